@@ -0,0 +1,148 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// Writes the sliding attack tables src/pext.rs and src/magic.rs include.
+// Const evaluation could build them too, but takes many seconds per compile
+// for the hundred thousand ray walks; here they cost a few milliseconds, once.
+
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const EDGE_FILES: u64 = 0x8181_8181_8181_8181;
+const EDGE_RANKS: u64 = 0xff00_0000_0000_00ff;
+
+fn ray_attacks(square: u8, occupied: u64, directions: &[(i8, i8)]) -> u64 {
+    let mut attacks = 0u64;
+    for &(dr, df) in directions {
+        let (mut rank, mut file) = ((square / 8) as i8 + dr, (square % 8) as i8 + df);
+        while (0..8).contains(&rank) && (0..8).contains(&file) {
+            let target = 1u64 << (rank * 8 + file);
+            attacks |= target;
+            if occupied & target != 0 {
+                break;
+            }
+            rank += dr;
+            file += df;
+        }
+    }
+    attacks
+}
+
+// The relevant-occupancy masks, where each square's entries start, and the
+// attacks for every subset of each mask, subsets in increasing order, which
+// is the order PEXT numbers them in
+fn write_table(out: &mut String, name: &str, directions: &[(i8, i8)]) {
+    let mut masks = Vec::new();
+    let mut offsets = Vec::new();
+    let mut attacks = Vec::new();
+    for square in 0..64u8 {
+        let mask = relevant_mask(square, directions);
+        masks.push(mask);
+        offsets.push(attacks.len());
+        attacks.extend(subsets(mask).into_iter().map(|subset| ray_attacks(square, subset, directions)));
+    }
+    let list = |values: &[u64]| values.iter().map(|value| format!("{:#x}", value)).collect::<Vec<_>>().join(",");
+    let offsets: Vec<String> = offsets.iter().map(usize::to_string).collect();
+    writeln!(out, "static {}_MASKS: [u64; 64] = [{}];", name, list(&masks)).unwrap();
+    writeln!(out, "static {}_OFFSETS: [usize; 64] = [{}];", name, offsets.join(",")).unwrap();
+    writeln!(out, "static {}_ATTACKS: [u64; {}] = [{}];", name, attacks.len(), list(&attacks)).unwrap();
+}
+
+// The squares whose occupancy decides a slider's attacks from square
+fn relevant_mask(square: u8, directions: &[(i8, i8)]) -> u64 {
+    let rank = 0xffu64 << (square / 8 * 8);
+    let file = 0x0101_0101_0101_0101u64 << (square % 8);
+    let edges = (EDGE_RANKS & !rank) | (EDGE_FILES & !file);
+    ray_attacks(square, 0, directions) & !edges
+}
+
+// Every subset of mask, the empty one first
+fn subsets(mask: u64) -> Vec<u64> {
+    let mut subsets = Vec::new();
+    let mut subset = 0u64;
+    loop {
+        subsets.push(subset);
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            return subsets;
+        }
+    }
+}
+
+// xorshift64*, seeded so the same magics come out of every build
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Magics with few bits set are found much sooner
+    fn sparse(&mut self) -> u64 {
+        self.next() & self.next() & self.next()
+    }
+}
+
+// A magic for each square, found by trial: multiplying any occupancy of the
+// mask by it must leave in the top bits an index shared only by occupancies
+// with the same attacks. Written as the masks, magics, shifts, where each
+// square's entries start, and the attacks at each index.
+fn write_magic_table(out: &mut String, name: &str, directions: &[(i8, i8)], random: &mut Random) {
+    let (mut masks, mut magics, mut shifts, mut offsets) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut attacks = Vec::new();
+    for square in 0..64u8 {
+        let mask = relevant_mask(square, directions);
+        let shift = 64 - mask.count_ones();
+        let occupancies = subsets(mask);
+        let reference: Vec<u64> = occupancies.iter().map(|&occupied| ray_attacks(square, occupied, directions)).collect();
+        let table = loop {
+            let magic = random.sparse();
+            // The mask's top bits must spread out, or too many indices collide
+            if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+                continue;
+            }
+            let mut table = vec![None; occupancies.len()];
+            let fits = occupancies.iter().zip(&reference).all(|(&occupied, &reach)| {
+                let entry = &mut table[(occupied.wrapping_mul(magic) >> shift) as usize];
+                *entry.get_or_insert(reach) == reach
+            });
+            if fits {
+                magics.push(magic);
+                break table;
+            }
+        };
+        masks.push(mask);
+        shifts.push(shift);
+        offsets.push(attacks.len());
+        attacks.extend(table.into_iter().map(|reach| reach.unwrap_or(0)));
+    }
+    let list = |values: &[u64]| values.iter().map(|value| format!("{:#x}", value)).collect::<Vec<_>>().join(",");
+    let shifts: Vec<String> = shifts.iter().map(u32::to_string).collect();
+    let offsets: Vec<String> = offsets.iter().map(usize::to_string).collect();
+    writeln!(out, "static {}_MASKS: [u64; 64] = [{}];", name, list(&masks)).unwrap();
+    writeln!(out, "static {}_MAGICS: [u64; 64] = [{}];", name, list(&magics)).unwrap();
+    writeln!(out, "static {}_SHIFTS: [u32; 64] = [{}];", name, shifts.join(",")).unwrap();
+    writeln!(out, "static {}_OFFSETS: [usize; 64] = [{}];", name, offsets.join(",")).unwrap();
+    writeln!(out, "static {}_ATTACKS: [u64; {}] = [{}];", name, attacks.len(), list(&attacks)).unwrap();
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    let mut out = String::new();
+    write_table(&mut out, "BISHOP", &BISHOP_DIRECTIONS);
+    write_table(&mut out, "ROOK", &ROOK_DIRECTIONS);
+    fs::write(Path::new(&out_dir).join("pext_tables.rs"), out).unwrap();
+
+    let mut out = String::new();
+    let mut random = Random(0x3a1e_5a17_0d5e_ed01);
+    write_magic_table(&mut out, "BISHOP", &BISHOP_DIRECTIONS, &mut random);
+    write_magic_table(&mut out, "ROOK", &ROOK_DIRECTIONS, &mut random);
+    fs::write(Path::new(&out_dir).join("magic_tables.rs"), out).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
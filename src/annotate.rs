@@ -0,0 +1,295 @@
+use crate::board::{Board, Color};
+use crate::engine::{Engine, SearchResult};
+use crate::game::{Annotation, Game, Nag};
+use crate::movegen::{GameState, Move, MoveGenerator};
+use crate::san::{format_line, to_san};
+use crate::search::{SearchLimits, MATE_SCORE, MATE_THRESHOLD};
+use crate::uci::format_move;
+use std::io::{self, Write};
+
+// Centipawns a move may lose against the engine's choice before it is marked ?!, ? and ??
+const INACCURACY_LOSS: i32 = 50;
+const MISTAKE_LOSS: i32 = 100;
+const BLUNDER_LOSS: i32 = 300;
+// Scores are capped here when measuring a loss, so choosing a slower mate, or
+// staying clearly winning instead of mating, doesn't count as a blunder
+const LOSS_CAP: i32 = 1000;
+// Plies of the engine's line suggested in place of a bad move
+const SUGGESTED_PLIES: usize = 4;
+
+pub fn classify(loss: i32) -> Option<Nag> {
+    if loss >= BLUNDER_LOSS {
+        Some(Nag::Blunder)
+    } else if loss >= MISTAKE_LOSS {
+        Some(Nag::Mistake)
+    } else if loss >= INACCURACY_LOSS {
+        Some(Nag::Inaccuracy)
+    } else {
+        None
+    }
+}
+
+// The searches of every position of a game, shared by the annotations and
+// the evaluation graph
+pub struct Analysis {
+    depth: u32,
+    // The search of each position, the one after the last move included
+    results: Vec<Option<SearchResult>>,
+    // Each position's score for its side to move
+    scores: Vec<i32>,
+    // What the played move scores for its mover whenever it wasn't the
+    // engine's choice. That score comes from a search one ply shallower so the
+    // two compared scores look equally far ahead.
+    played_scores: Vec<Option<i32>>,
+}
+
+pub fn analyse(engine: &mut Engine, game: &Game, depth: u32) -> Result<Analysis, String> {
+    let limits = SearchLimits { depth, ..SearchLimits::default() };
+    engine.set_variant(game.start.variant);
+    engine.new_game();
+    engine.set_position(Some(&game.start.to_fen()), &[])?;
+
+    let mut results = Vec::with_capacity(game.moves.len() + 1);
+    let mut scores = Vec::with_capacity(game.moves.len() + 1);
+    let mut played_scores = Vec::with_capacity(game.moves.len());
+    for &mv in &game.moves {
+        let result = engine.search(limits.clone());
+        scores.push(score(engine, result.as_ref()));
+        engine.play(&format_move(&mv))?;
+        let played = match &result {
+            Some(best) if best.best_move != mv => {
+                let reply = engine.search(SearchLimits { depth: depth.saturating_sub(1).max(1), ..limits.clone() });
+                Some(-score(engine, reply.as_ref()))
+            }
+            _ => None,
+        };
+        results.push(result);
+        played_scores.push(played);
+    }
+    let result = engine.search(limits.clone());
+    scores.push(score(engine, result.as_ref()));
+    results.push(result);
+    Ok(Analysis { depth, results, scores, played_scores })
+}
+
+// Analyses every position of the game to depth and returns a copy where each
+// move carries the evaluation after it, and a move that loses enough against
+// the engine's choice also carries a NAG and the line it should have played.
+// Clock annotations are kept.
+pub fn annotate(engine: &mut Engine, game: &Game, depth: u32) -> Result<Game, String> {
+    Ok(annotated(game, &analyse(engine, game, depth)?))
+}
+
+pub fn annotated(game: &Game, analysis: &Analysis) -> Game {
+    let Analysis { depth, results, scores, played_scores } = analysis;
+    let generator = MoveGenerator::new();
+    let mut annotations = Vec::with_capacity(game.moves.len());
+    for (i, (board, _)) in game.positions().into_iter().enumerate() {
+        // Scores are for the side to move, so the next position's is the mover's negated
+        let after = -scores[i + 1];
+        let white_eval = if board.side_to_move == Color::White { after } else { -after };
+        let mut annotation = Annotation {
+            // A finished game has no evaluation to give
+            eval: results[i + 1].is_some().then_some(white_eval),
+            depth: Some(*depth),
+            clock: game.annotations.get(i).and_then(|annotation| annotation.clock),
+            ..Annotation::default()
+        };
+
+        if let (Some(best), Some(played)) = (&results[i], played_scores[i]) {
+            let loss = scores[i].clamp(-LOSS_CAP, LOSS_CAP) - played.clamp(-LOSS_CAP, LOSS_CAP);
+            if let Some(nag) = classify(loss) {
+                annotation.nag = Some(nag);
+                annotation.comment = Some(suggestion(&generator, &board, nag, best));
+            }
+        }
+        annotations.push(annotation);
+    }
+
+    let mut annotated = game.clone();
+    annotated.annotations = annotations;
+    annotated.set_tag("Annotator", &format!("Three Salmons, depth {}", depth));
+    annotated
+}
+
+// One point of an evaluation graph: a position of the game, what it scores
+// for White and what the engine would play there
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphPoint {
+    pub move_number: u16,
+    pub side: Color,
+    // The move played from here in SAN, None after the last one
+    pub played: Option<String>,
+    // As in UCI, either centipawns or moves to mate, negative when Black
+    // mates; neither once the game is over
+    pub cp: Option<i32>,
+    pub mate: Option<i32>,
+    pub best_move: Option<String>,
+}
+
+pub fn eval_graph(game: &Game, analysis: &Analysis) -> Vec<GraphPoint> {
+    let generator = MoveGenerator::new();
+    let mut boards: Vec<(Board, Option<Move>)> =
+        game.positions().into_iter().map(|(board, mv)| (board, Some(mv))).collect();
+    boards.push((game.board(), None));
+
+    boards
+        .iter()
+        .zip(&analysis.results)
+        .zip(&analysis.scores)
+        .map(|(((board, played), result), &score)| {
+            let white_score = if board.side_to_move == Color::White { score } else { -score };
+            let (cp, mate) = match result {
+                None => (None, None),
+                Some(_) if white_score.abs() >= MATE_THRESHOLD => {
+                    let moves = (MATE_SCORE - white_score.abs() + 1) / 2;
+                    (None, Some(if white_score > 0 { moves } else { -moves }))
+                }
+                Some(_) => (Some(white_score), None),
+            };
+            GraphPoint {
+                move_number: board.fullmove_number,
+                side: board.side_to_move,
+                played: played.map(|mv| to_san(board, &generator, &mv)),
+                cp,
+                mate,
+                best_move: result.as_ref().map(|result| to_san(board, &generator, &result.best_move)),
+            }
+        })
+        .collect()
+}
+
+// One row per point, numbered by game: "game,move_number,side,move,cp,mate,best_move"
+pub fn write_graph_csv(out: &mut impl Write, games: &[Vec<GraphPoint>]) -> io::Result<()> {
+    let number = |value: Option<i32>| value.map_or(String::new(), |value| value.to_string());
+    writeln!(out, "game,move_number,side,move,cp,mate,best_move")?;
+    for (i, points) in games.iter().enumerate() {
+        for point in points {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                i + 1,
+                point.move_number,
+                side_name(point.side),
+                point.played.as_deref().unwrap_or(""),
+                number(point.cp),
+                number(point.mate),
+                point.best_move.as_deref().unwrap_or("")
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// A JSON array of games, each an array of points with the CSV's fields. SAN
+// needs no escaping, so strings are simply quoted.
+pub fn write_graph_json(out: &mut impl Write, games: &[Vec<GraphPoint>]) -> io::Result<()> {
+    let number = |value: Option<i32>| value.map_or("null".to_string(), |value| value.to_string());
+    let string = |value: Option<&str>| value.map_or("null".to_string(), |value| format!("\"{}\"", value));
+    let games: Vec<String> = games
+        .iter()
+        .map(|points| {
+            let points: Vec<String> = points
+                .iter()
+                .map(|point| {
+                    format!(
+                        "{{\"move_number\":{},\"side\":\"{}\",\"move\":{},\"cp\":{},\"mate\":{},\"best_move\":{}}}",
+                        point.move_number,
+                        side_name(point.side),
+                        string(point.played.as_deref()),
+                        number(point.cp),
+                        number(point.mate),
+                        string(point.best_move.as_deref())
+                    )
+                })
+                .collect();
+            format!("[{}]", points.join(","))
+        })
+        .collect();
+    writeln!(out, "[{}]", games.join(","))
+}
+
+fn side_name(side: Color) -> &'static str {
+    match side {
+        Color::White => "white",
+        Color::Black => "black",
+    }
+}
+
+// A position's score for its side to move; a finished game scores as won, lost or drawn
+fn score(engine: &Engine, result: Option<&SearchResult>) -> i32 {
+    if let Some(result) = result {
+        return result.score;
+    }
+    let board = engine.board();
+    match board.variant.result(&MoveGenerator::new(), board, false) {
+        Some(GameState::Checkmate(winner) | GameState::VariantWin(winner)) if winner == board.side_to_move => MATE_SCORE,
+        Some(GameState::Checkmate(_) | GameState::VariantWin(_)) => -MATE_SCORE,
+        _ => 0,
+    }
+}
+
+// "Mistake. Best was 12. Nf3 Nc6 13. O-O Bc5"
+fn suggestion(generator: &MoveGenerator, board: &Board, nag: Nag, best: &SearchResult) -> String {
+    let line = &best.pv[..best.pv.len().min(SUGGESTED_PLIES)];
+    format!("{}. Best was {}", nag, format_line(board, generator, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::read_games;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(20), None);
+        assert_eq!(classify(INACCURACY_LOSS), Some(Nag::Inaccuracy));
+        assert_eq!(classify(150), Some(Nag::Mistake));
+        assert_eq!(classify(900), Some(Nag::Blunder));
+    }
+
+    #[test]
+    fn test_annotate_marks_the_blunder() {
+        // Black leaves the queen en prise instead of saving it
+        let pgn = "[FEN \"4k3/8/8/3q4/8/8/3R4/4K3 b - - 0 1\"]\n\n1... Ke7 2. Rxd5 *\n";
+        let game = read_games(pgn).remove(0).unwrap();
+        let mut engine = Engine::new();
+        let annotated = annotate(&mut engine, &game, 3).unwrap();
+
+        assert_eq!(annotated.tag("Annotator"), Some("Three Salmons, depth 3"));
+        let blunder = &annotated.annotations[0];
+        assert_eq!(blunder.nag, Some(Nag::Blunder));
+        assert!(blunder.comment.as_deref().unwrap().starts_with("Blunder. Best was 1... Q"));
+        assert!(blunder.eval.unwrap() > 300);
+        // Taking the queen is what the engine would play too
+        assert_eq!(annotated.annotations[1].nag, None);
+        assert!(annotated.to_pgn().contains("1... Ke7?? {[%eval"));
+    }
+
+    #[test]
+    fn test_eval_graph() {
+        let pgn = "[FEN \"6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\"]\n\n1. Kf1 Kh8 2. Ra8# 1-0\n";
+        let game = read_games(pgn).remove(0).unwrap();
+        let mut engine = Engine::new();
+        let analysis = analyse(&mut engine, &game, 3).unwrap();
+        let points = eval_graph(&game, &analysis);
+
+        assert_eq!(points.len(), 4);
+        assert_eq!((points[0].move_number, points[0].side), (1, Color::White));
+        assert_eq!(points[0].played.as_deref(), Some("Kf1"));
+        assert_eq!((points[0].mate, points[0].best_move.as_deref()), (Some(1), Some("Ra8#")));
+        assert_eq!((points[1].side, points[1].cp.is_some()), (Color::Black, true));
+        assert_eq!((points[3].played.as_ref(), points[3].cp, points[3].mate), (None, None, None));
+
+        let games = [points];
+        let mut csv = Vec::new();
+        write_graph_csv(&mut csv, &games).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.lines().nth(1).unwrap().starts_with("1,1,white,Kf1,,1,Ra8#"));
+        let mut json = Vec::new();
+        write_graph_json(&mut json, &games).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[[{\"move_number\":1,\"side\":\"white\",\"move\":\"Kf1\",\"cp\":null,\"mate\":1,"));
+        assert!(json.trim_end().ends_with("\"best_move\":null}]]"));
+    }
+}
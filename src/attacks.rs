@@ -0,0 +1,143 @@
+use crate::board::{Color, Piece};
+use crate::{magic, pext};
+
+// Attack tables, all worked out at compile time so move generators share
+// them and cost nothing to create
+
+pub const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+pub const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (1, -2), (-1, 2), (-1, -2)];
+const KING_OFFSETS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+pub static KNIGHT_ATTACKS: [u64; 64] = step_attacks(&KNIGHT_OFFSETS);
+pub static KING_ATTACKS: [u64; 64] = step_attacks(&KING_OFFSETS);
+// The squares a pawn of each colour attacks, White's first
+pub static PAWN_ATTACKS: [[u64; 64]; 2] = [step_attacks(&[(1, -1), (1, 1)]), step_attacks(&[(-1, -1), (-1, 1)])];
+// The squares strictly between two squares on a rank, file or diagonal, and
+// the whole line through them; both empty for squares that don't share one
+pub static BETWEEN: [[u64; 64]; 64] = between_and_line().0;
+pub static LINE: [[u64; 64]; 64] = between_and_line().1;
+
+pub fn pawn_attacks(color: Color, square: u8) -> u64 {
+    PAWN_ATTACKS[color as usize][square as usize]
+}
+
+// Sliding attacks from the PEXT tables where the CPU has BMI2, from the
+// magic ones elsewhere
+pub fn bishop_attacks(square: u8, occupied: u64) -> u64 {
+    match pext::tables() {
+        Some(tables) => tables.bishop_attacks(square, occupied),
+        None => magic::bishop_attacks(square, occupied),
+    }
+}
+
+pub fn rook_attacks(square: u8, occupied: u64) -> u64 {
+    match pext::tables() {
+        Some(tables) => tables.rook_attacks(square, occupied),
+        None => magic::rook_attacks(square, occupied),
+    }
+}
+
+// The squares a piece of this colour on square attacks, given the occupied squares
+pub fn piece_attacks(piece: Piece, color: Color, square: u8, occupied: u64) -> u64 {
+    match piece {
+        Piece::Pawn => pawn_attacks(color, square),
+        Piece::Knight => KNIGHT_ATTACKS[square as usize],
+        Piece::Bishop => bishop_attacks(square, occupied),
+        Piece::Rook => rook_attacks(square, occupied),
+        Piece::Queen => bishop_attacks(square, occupied) | rook_attacks(square, occupied),
+        Piece::King => KING_ATTACKS[square as usize],
+    }
+}
+
+const fn offset(square: usize, (dr, df): (i8, i8)) -> Option<usize> {
+    let rank = (square / 8) as i8 + dr;
+    let file = (square % 8) as i8 + df;
+    if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+        Some((rank * 8 + file) as usize)
+    } else {
+        None
+    }
+}
+
+const fn step_attacks(offsets: &[(i8, i8)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            if let Some(target) = offset(square, offsets[i]) {
+                table[square] |= 1 << target;
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+// The squares a slider on square reaches along the directions, up to and
+// including the first occupied square on each
+pub const fn ray_attacks(square: u8, occupied: u64, directions: &[(i8, i8)]) -> u64 {
+    let mut attacks = 0u64;
+    let mut i = 0;
+    while i < directions.len() {
+        let mut current = square as usize;
+        while let Some(target) = offset(current, directions[i]) {
+            attacks |= 1 << target;
+            if occupied & (1 << target) != 0 {
+                break;
+            }
+            current = target;
+        }
+        i += 1;
+    }
+    attacks
+}
+
+const fn between_and_line() -> ([[u64; 64]; 64], [[u64; 64]; 64]) {
+    let mut between = [[0; 64]; 64];
+    let mut line = [[0; 64]; 64];
+    let directions = [BISHOP_DIRECTIONS, ROOK_DIRECTIONS];
+    let mut from = 0;
+    while from < 64 {
+        let mut d = 0;
+        while d < 8 {
+            let (dr, df) = directions[d / 4][d % 4];
+            let full = ray_attacks(from as u8, 0, &[(dr, df), (-dr, -df)]) | 1 << from;
+            let mut path = 0u64;
+            let mut current = from;
+            while let Some(to) = offset(current, (dr, df)) {
+                between[from][to] = path;
+                line[from][to] = full;
+                path |= 1 << to;
+                current = to;
+            }
+            d += 1;
+        }
+        from += 1;
+    }
+    (between, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables() {
+        // A knight in the corner, a king on the edge
+        assert_eq!(KNIGHT_ATTACKS[0], (1 << 10) | (1 << 17));
+        assert_eq!(KING_ATTACKS[4].count_ones(), 5);
+        // e4 pawns take on d5 and f5, or d3 and f3
+        assert_eq!(pawn_attacks(Color::White, 28), (1 << 35) | (1 << 37));
+        assert_eq!(pawn_attacks(Color::Black, 28), (1 << 19) | (1 << 21));
+        // a1 to h8: b2 up to g7 between, the long diagonal as the line
+        assert_eq!(BETWEEN[0][63], 0x0040_2010_0804_0200);
+        assert_eq!(LINE[0][63], 0x8040_2010_0804_0201);
+        assert_eq!(BETWEEN[63][0], BETWEEN[0][63]);
+        assert_eq!(BETWEEN[0][1], 0);
+        assert_eq!(LINE[0][10], 0);
+        assert_eq!(ray_attacks(0, 1 << 16, &ROOK_DIRECTIONS), 0xfe | (1 << 8) | (1 << 16));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::board::{square_name, Color};
+use crate::engine::Engine;
+use crate::evaluation::{Evaluator, KingDanger};
+use crate::puzzle::Puzzle;
+use crate::search::{SearchLimits, MATE_SCORE, MATE_THRESHOLD};
+use crate::uci::format_move;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    pub depth: u32,
+    pub movetime: Option<Duration>,
+    pub threads: usize,
+}
+
+// What the engine made of one position. Moves are in UCI notation and the
+// score, as in UCI, is for the side to move: centipawns or moves to mate,
+// negative when getting mated, neither when the game is already over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionAnalysis {
+    pub id: String,
+    pub fen: String,
+    pub best_move: Option<String>,
+    pub cp: Option<i32>,
+    pub mate: Option<i32>,
+    pub pv: Vec<String>,
+    pub nodes: u64,
+    // White's, then Black's
+    pub king_danger: [KingDanger; 2],
+}
+
+pub fn analyze_position(engine: &mut Engine, position: &Puzzle, limits: SearchLimits) -> Result<PositionAnalysis, String> {
+    let fen = position.board.to_fen();
+    engine.new_game();
+    engine.set_position(Some(&fen), &[])?;
+    let evaluator = Evaluator::new();
+    let king_danger = [Color::White, Color::Black].map(|color| evaluator.king_danger(engine.board(), color));
+    let result = engine.search(limits);
+    let (cp, mate) = match &result {
+        None => (None, None),
+        Some(result) if result.score.abs() >= MATE_THRESHOLD => {
+            let moves = (MATE_SCORE - result.score.abs() + 1) / 2;
+            (None, Some(if result.score > 0 { moves } else { -moves }))
+        }
+        Some(result) => (Some(result.score), None),
+    };
+    Ok(PositionAnalysis {
+        id: position.id.clone(),
+        fen,
+        best_move: result.as_ref().map(|result| format_move(&result.best_move)),
+        cp,
+        mate,
+        pv: result.as_ref().map_or(Vec::new(), |result| result.pv.iter().map(format_move).collect()),
+        nodes: result.map_or(0, |result| result.nodes),
+        king_danger,
+    })
+}
+
+// Analyses every position, spread over config.threads threads with an engine
+// each, and returns the results in the input's order
+pub fn analyze_all(positions: &[Puzzle], config: &BatchConfig) -> Vec<Result<PositionAnalysis, String>> {
+    let limits = SearchLimits { depth: config.depth, time: config.movetime, ..SearchLimits::default() };
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<PositionAnalysis, String>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut engine = Engine::new();
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(position) = positions.get(i) else { break };
+                        done.push((i, analyze_position(&mut engine, position, limits.clone())));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().expect("analysis worker panicked")).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// "id,fen,best_move,cp,mate,nodes,pv", the PV's moves separated by spaces.
+// FENs hold no commas and ids have theirs turned to spaces, so nothing is quoted.
+pub fn write_csv(out: &mut impl Write, results: &[PositionAnalysis]) -> io::Result<()> {
+    let number = |value: Option<i32>| value.map_or(String::new(), |value| value.to_string());
+    writeln!(out, "id,fen,best_move,cp,mate,nodes,pv")?;
+    for result in results {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            result.id.replace(',', " "),
+            result.fen,
+            result.best_move.as_deref().unwrap_or(""),
+            number(result.cp),
+            number(result.mate),
+            result.nodes,
+            result.pv.join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+fn king_danger_json(danger: &KingDanger) -> String {
+    let counts = &danger.counts;
+    let ring: Vec<String> =
+        (0..64).filter(|square| counts.ring >> square & 1 != 0).map(|square| format!("\"{}\"", square_name(square))).collect();
+    let attackers: Vec<String> = ["pawn", "knight", "bishop", "rook", "queen", "king"]
+        .iter()
+        .zip(counts.attackers)
+        .map(|(piece, count)| format!("\"{}\":{}", piece, count))
+        .collect();
+    format!(
+        "{{\"ring\":[{}],\"attackers\":{{{}}},\"attack_units\":{},\"shield_pawns\":{},\"open_file\":{},\"semi_open_file\":{},\"score\":{}}}",
+        ring.join(","),
+        attackers.join(","),
+        counts.attack_units(),
+        counts.shield_pawns,
+        counts.open_file,
+        counts.semi_open_file,
+        danger.score
+    )
+}
+
+// A JSON array with one object per position, the PV as an array of moves and
+// each side's king safety inputs under king_danger
+pub fn write_json(out: &mut impl Write, results: &[PositionAnalysis]) -> io::Result<()> {
+    let number = |value: Option<i32>| value.map_or("null".to_string(), |value| value.to_string());
+    let string = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+    let objects: Vec<String> = results
+        .iter()
+        .map(|result| {
+            let pv: Vec<String> = result.pv.iter().map(|mv| string(mv)).collect();
+            format!(
+                "{{\"id\":{},\"fen\":{},\"best_move\":{},\"cp\":{},\"mate\":{},\"nodes\":{},\"pv\":[{}],\"king_danger\":{{\"white\":{},\"black\":{}}}}}",
+                string(&result.id),
+                string(&result.fen),
+                result.best_move.as_deref().map_or("null".to_string(), string),
+                number(result.cp),
+                number(result.mate),
+                result.nodes,
+                pv.join(","),
+                king_danger_json(&result.king_danger[0]),
+                king_danger_json(&result.king_danger[1])
+            )
+        })
+        .collect();
+    writeln!(out, "[{}]", objects.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle::load_puzzles;
+
+    #[test]
+    fn test_analyze_all() {
+        let positions = load_puzzles(
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1; id \"back rank\";\n\
+             4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1\n\
+             7k/5Q2/6K1/8/8/8/8/8 b - - 0 1\n",
+        )
+        .unwrap();
+        let results: Vec<PositionAnalysis> = analyze_all(&positions, &BatchConfig { depth: 3, movetime: None, threads: 2 })
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!((results[0].id.as_str(), results[0].best_move.as_deref(), results[0].mate), ("back rank", Some("a1a8"), Some(1)));
+        assert_eq!(results[1].best_move.as_deref(), Some("d2d5"));
+        assert!(results[1].cp.unwrap() > 300);
+        // Stalemate: nothing to search
+        assert_eq!((results[2].best_move.as_ref(), results[2].cp, results[2].nodes), (None, None, 0));
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &results).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.lines().nth(1).unwrap().starts_with("back rank,6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1,a1a8,,1,"));
+        let mut json = Vec::new();
+        write_json(&mut json, &results).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[{\"id\":\"back rank\",\"fen\":\"6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\",\"best_move\":\"a1a8\",\"cp\":null,\"mate\":1,"));
+        assert!(json.contains("\"best_move\":null,\"cp\":null,\"mate\":null,\"nodes\":0,\"pv\":[],\"king_danger\":{\"white\":"));
+        // The queen on f7 bears down on the stalemated king
+        assert!(json.trim_end().ends_with(
+            "\"black\":{\"ring\":[\"g7\",\"h7\",\"g8\",\"h8\"],\"attackers\":{\"pawn\":0,\"knight\":0,\"bishop\":0,\"rook\":0,\"queen\":1,\"king\":1},\"attack_units\":5,\"shield_pawns\":0,\"open_file\":true,\"semi_open_file\":false,\"score\":-40}}}]"
+        ));
+    }
+}
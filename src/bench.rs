@@ -0,0 +1,143 @@
+use crate::board::Board;
+use crate::engine::{Engine, SearchResult};
+use crate::evaluation::Evaluator;
+use crate::movegen::MoveGenerator;
+use crate::perft::perft;
+use crate::search::{Search, SearchLimits};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+pub const BENCH_DEPTH: u32 = 6;
+
+// A spread of openings, middlegames and endgames, searched for a speed
+// measure that is comparable between builds and machines
+pub const BENCH_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "2r3k1/pp3pp1/4p2p/3pP3/3P4/P1R2N1P/1P3PP1/6K1 b - - 1 25",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/4k3/8/2p5/8/B2K4/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+];
+
+// Depth of the perft the movegen workload runs from each bench position
+const PROFILE_PERFT_DEPTH: u32 = 3;
+// The eval and qsearch workloads run on every position this many plies from a bench position
+const PROFILE_TREE_DEPTH: u32 = 2;
+// Evaluations are cheap enough to need a few rounds for a steady timing
+const PROFILE_EVAL_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchResult {
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn nps(&self) -> u64 {
+        (self.nodes as f64 / self.elapsed.as_secs_f64().max(0.001)) as u64
+    }
+}
+
+// Searches every bench position to depth from a cleared hash, handing each
+// result to on_position as it comes in
+pub fn run(engine: &mut Engine, depth: u32, on_position: impl FnMut(&str, &SearchResult)) -> Result<BenchResult, String> {
+    search_positions(engine, &BENCH_POSITIONS, depth, on_position)
+}
+
+fn search_positions(
+    engine: &mut Engine,
+    fens: &[&str],
+    depth: u32,
+    mut on_position: impl FnMut(&str, &SearchResult),
+) -> Result<BenchResult, String> {
+    let mut total = BenchResult { nodes: 0, elapsed: Duration::ZERO };
+    for &fen in fens {
+        engine.new_game();
+        engine.set_position(Some(fen), &[])?;
+        let result = engine.search(SearchLimits { depth, ..SearchLimits::default() }).ok_or("a bench position is already over")?;
+        total.nodes += result.nodes;
+        total.elapsed += result.elapsed;
+        on_position(fen, &result);
+    }
+    Ok(total)
+}
+
+// Times move generation, evaluation, quiescence search and the full search
+// apart, each over work drawn from the positions (the bench positions for the
+// profile command), so a change's effect on speed can be pinned on the part it
+// touched. Nodes are positions generated, evaluated or searched.
+pub fn profile(engine: &mut Engine, fens: &[&str], depth: u32) -> Result<Vec<(&'static str, BenchResult)>, String> {
+    let boards = fens.iter().map(|fen| Board::from_fen(fen)).collect::<Result<Vec<_>, _>>()?;
+    let generator = MoveGenerator::new();
+    let mut tree = boards.clone();
+    let mut frontier = boards.clone();
+    for _ in 0..PROFILE_TREE_DEPTH {
+        frontier = frontier
+            .iter()
+            .flat_map(|board| {
+                generator.generate_moves(board).into_iter().map(|mv| {
+                    let mut child = board.clone();
+                    child.make_move(mv);
+                    child
+                })
+            })
+            .collect();
+        tree.extend_from_slice(&frontier);
+    }
+
+    let movegen = timed(|| boards.iter().map(|board| perft(board, &generator, PROFILE_PERFT_DEPTH)).sum());
+    let evaluator = Evaluator::new();
+    let eval = timed(|| {
+        for _ in 0..PROFILE_EVAL_ROUNDS {
+            for board in &tree {
+                black_box(evaluator.evaluate(board));
+            }
+        }
+        (PROFILE_EVAL_ROUNDS * tree.len()) as u64
+    });
+    let mut search = Search::new();
+    let qsearch = timed(|| {
+        tree.iter()
+            .map(|board| {
+                search.quiescence(board);
+                search.get_nodes_searched()
+            })
+            .sum()
+    });
+    let full = search_positions(engine, fens, depth, |_, _| {})?;
+    Ok(vec![("movegen", movegen), ("eval", eval), ("qsearch", qsearch), ("search", full)])
+}
+
+fn timed(work: impl FnOnce() -> u64) -> BenchResult {
+    let start = Instant::now();
+    let nodes = work();
+    BenchResult { nodes, elapsed: start.elapsed() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_searches_every_position() {
+        let mut engine = Engine::new();
+        let mut nodes = 0;
+        let result = run(&mut engine, 2, |_, position| nodes += position.nodes).unwrap();
+        assert!(result.nodes > 0);
+        assert_eq!(result.nodes, nodes);
+        assert!(result.nps() > 0);
+    }
+
+    #[test]
+    fn test_profile_covers_each_component() {
+        let mut engine = Engine::new();
+        let components = profile(&mut engine, &BENCH_POSITIONS[5..], 1).unwrap();
+        let names: Vec<&str> = components.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["movegen", "eval", "qsearch", "search"]);
+        assert!(components.iter().all(|(_, result)| result.nodes > 0));
+        assert!(components[0].1.nodes > components[3].1.nodes);
+    }
+}
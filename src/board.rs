@@ -1,5 +1,29 @@
-use std::fmt;
-use crate::movegen::Move;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use crate::movegen::{MaterialRule, Move, MoveGenerator};
+use crate::psqt::{self, PsqtScore};
+use crate::variant::{Variant, STANDARD};
+use crate::zobrist;
+
+// A square index, a1 = 0 to h8 = 63, rank by rank
+pub type Square = u8;
+
+// Why a list of UCI moves stopped applying: which move, counted from 0, and
+// what was wrong with it, plus the moves played before it
+#[derive(Debug, Clone)]
+pub struct MoveListError {
+    pub index: usize,
+    pub reason: String,
+    pub played: Vec<(Board, Move)>,
+}
+
+impl fmt::Display for MoveListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, move {} of the list", self.reason, self.index + 1)
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Piece {
@@ -11,6 +35,11 @@ pub enum Piece {
     King,
 }
 
+impl Piece {
+    // In bitboard order
+    pub const ALL: [Piece; 6] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Color {
     White,
@@ -26,6 +55,60 @@ impl Color {
     }
 }
 
+// A piece put on or taken off a square
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PieceChange {
+    pub piece: Piece,
+    pub color: Color,
+    pub square: Square,
+}
+
+// The pieces a move took off and put on the board; castling and captures
+// touch two of either. Incrementally updated evaluations only need these.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DirtyPieces {
+    removed: [Option<PieceChange>; 2],
+    added: [Option<PieceChange>; 2],
+}
+
+impl DirtyPieces {
+    fn remove(&mut self, change: PieceChange) {
+        if let Some(slot) = self.removed.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(change);
+        }
+    }
+
+    fn add(&mut self, change: PieceChange) {
+        if let Some(slot) = self.added.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(change);
+        }
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = PieceChange> + '_ {
+        self.removed.iter().flatten().copied()
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = PieceChange> + '_ {
+        self.added.iter().flatten().copied()
+    }
+
+    // The changes that take these back
+    pub fn inverse(&self) -> Self {
+        Self { removed: self.added, added: self.removed }
+    }
+}
+
+// What make_move changed, for unmake_move to restore
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UndoState {
+    pub dirty: DirtyPieces,
+    pub castling_rights: u8,
+    pub en_passant_square: Option<u8>,
+    pub halfmove_clock: u8,
+    pub fullmove_number: u16,
+    pub hash: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Board {
     pub white_pieces: [u64; 6],  // Pawn, Knight, Bishop, Rook, Queen, King
@@ -35,11 +118,26 @@ pub struct Board {
     pub en_passant_square: Option<u8>,
     pub halfmove_clock: u8,
     pub fullmove_number: u16,
+    // The rules this position is played by
+    pub variant: &'static dyn Variant,
+    // Running material and piece-square totals, White's then Black's. Kept up
+    // by make_move and unmake_move; call refresh_psqt after setting bitboards
+    // by hand.
+    pub psqt: [PsqtScore; 2],
+    // Zobrist key of the position, kept up the same way; call refresh_hash
+    // after setting fields by hand.
+    pub hash: u64,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Board {
     pub fn new() -> Self {
-        Self {
+        let mut board = Self {
             white_pieces: [
                 0x000000000000FF00,  // Pawns
                 0x0000000000000042,  // Knights
@@ -61,23 +159,204 @@ impl Board {
             en_passant_square: None,
             halfmove_clock: 0,
             fullmove_number: 1,
-        }
+            variant: &STANDARD,
+            psqt: [PsqtScore::default(); 2],
+            hash: 0,
+        };
+        board.refresh_psqt();
+        board.refresh_hash();
+        board
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        Self::from_variant_fen(fen, &STANDARD)
     }
 
-    pub fn from_fen(_fen: &str) -> Result<Self, String> {
-        // TODO: Implement FEN parsing
-        Ok(Board::new())
+    // A FEN read as a position of the given variant, which decides what a valid position is
+    pub fn from_variant_fen(fen: &str, variant: &'static dyn Variant) -> Result<Self, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        // The move counters are optional; many tools omit them
+        if !(4..=6).contains(&fields.len()) {
+            return Err(format!("expected 4 to 6 fields, got {}", fields.len()));
+        }
+
+        let mut board = Board {
+            white_pieces: [0; 6],
+            black_pieces: [0; 6],
+            side_to_move: Color::White,
+            castling_rights: 0,
+            en_passant_square: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            variant,
+            psqt: [PsqtScore::default(); 2],
+            hash: 0,
+        };
+
+        // Piece placement, from rank 8 down to rank 1
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("expected 8 ranks, got {}", ranks.len()));
+        }
+        for (i, rank_str) in ranks.iter().enumerate() {
+            let rank = 7 - i as u8;
+            let mut file = 0u8;
+            for c in rank_str.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    if !(1..=8).contains(&skip) {
+                        return Err(format!("invalid empty-square count '{}'", c));
+                    }
+                    file += skip as u8;
+                    if file > 8 {
+                        return Err(format!("rank {} has more than 8 squares", rank + 1));
+                    }
+                } else {
+                    let piece_index = match c.to_ascii_lowercase() {
+                        'p' => 0,
+                        'n' => 1,
+                        'b' => 2,
+                        'r' => 3,
+                        'q' => 4,
+                        'k' => 5,
+                        _ => return Err(format!("invalid piece '{}'", c)),
+                    };
+                    if file >= 8 {
+                        return Err(format!("rank {} has more than 8 squares", rank + 1));
+                    }
+                    let mask = 1u64 << (rank * 8 + file);
+                    if c.is_ascii_uppercase() {
+                        board.white_pieces[piece_index] |= mask;
+                    } else {
+                        board.black_pieces[piece_index] |= mask;
+                    }
+                    file += 1;
+                }
+            }
+            if file != 8 {
+                return Err(format!("rank {} does not have 8 squares", rank + 1));
+            }
+        }
+        variant.validate(&board)?;
+
+        board.side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(format!("invalid side to move '{}'", other)),
+        };
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                board.castling_rights |= match c {
+                    'K' => 0b0001,
+                    'Q' => 0b0010,
+                    'k' => 0b0100,
+                    'q' => 0b1000,
+                    _ => return Err(format!("invalid castling rights '{}'", fields[2])),
+                };
+            }
+            // Handicap FENs often keep "KQkq" with a rook gone
+            board.clear_lost_castling_rights();
+        }
+
+        if fields[3] != "-" {
+            let square = parse_square(fields[3])
+                .ok_or_else(|| format!("invalid en passant square '{}'", fields[3]))?;
+            let expected_rank = if board.side_to_move == Color::White { 5 } else { 2 };
+            if square / 8 != expected_rank {
+                return Err(format!("invalid en passant square '{}'", fields[3]));
+            }
+            board.en_passant_square = Some(square);
+        }
+
+        if let Some(halfmove_clock) = fields.get(4) {
+            board.halfmove_clock = halfmove_clock
+                .parse()
+                .map_err(|_| format!("invalid halfmove clock '{}'", halfmove_clock))?;
+        }
+        if let Some(fullmove_number) = fields.get(5) {
+            board.fullmove_number = fullmove_number
+                .parse()
+                .map_err(|_| format!("invalid fullmove number '{}'", fullmove_number))?;
+        }
+
+        board.refresh_psqt();
+        board.refresh_hash();
+        Ok(board)
     }
 
     pub fn to_fen(&self) -> String {
-        // TODO: Implement FEN generation
-        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string()
+        let mut fen = String::new();
+
+        // Piece placement, from rank 8 down to rank 1
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.get_piece_at(rank * 8 + file) {
+                    Some((piece, color)) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let c = match piece {
+                            Piece::Pawn => 'p',
+                            Piece::Knight => 'n',
+                            Piece::Bishop => 'b',
+                            Piece::Rook => 'r',
+                            Piece::Queen => 'q',
+                            Piece::King => 'k',
+                        };
+                        fen.push(if color == Color::White { c.to_ascii_uppercase() } else { c });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.side_to_move == Color::White { " w " } else { " b " });
+
+        if self.castling_rights & 0b1111 == 0 {
+            fen.push('-');
+        } else {
+            for (bit, c) in [(0b0001, 'K'), (0b0010, 'Q'), (0b0100, 'k'), (0b1000, 'q')] {
+                if self.castling_rights & bit != 0 {
+                    fen.push(c);
+                }
+            }
+        }
+
+        match self.en_passant_square {
+            Some(square) => {
+                fen.push(' ');
+                fen.push((b'a' + square % 8) as char);
+                fen.push((b'1' + square / 8) as char);
+            }
+            None => fen.push_str(" -"),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
     }
 
-    pub fn make_move(&mut self, mv: Move) {
+    pub fn make_move(&mut self, mv: Move) -> UndoState {
+        let mut undo = UndoState {
+            dirty: DirtyPieces::default(),
+            castling_rights: self.castling_rights,
+            en_passant_square: self.en_passant_square,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
+        };
         let from_mask = 1u64 << mv.from;
         let to_mask = 1u64 << mv.to;
         let is_white = self.side_to_move == Color::White;
+        let color = self.side_to_move;
+        let them = color.opposite();
 
         // Remove piece from source square
         let pieces = if is_white {
@@ -85,16 +364,16 @@ impl Board {
         } else {
             &mut self.black_pieces
         };
-        for piece_bb in pieces.iter_mut() {
+        for (index, piece_bb) in pieces.iter_mut().enumerate() {
             if (*piece_bb & from_mask) != 0 {
                 *piece_bb &= !from_mask;
+                undo.dirty.remove(PieceChange { piece: Piece::ALL[index], color, square: mv.from });
                 break;
             }
         }
 
         // Handle captures
-        if mv.captured_piece.is_some() {
-            let captured_piece = mv.captured_piece.unwrap();
+        if let Some(captured_piece) = mv.captured_piece {
             let piece_index = match captured_piece {
                 Piece::Pawn => 0,
                 Piece::Knight => 1,
@@ -113,6 +392,7 @@ impl Board {
                 mv.to
             };
             let captured_mask = 1u64 << captured_square;
+            undo.dirty.remove(PieceChange { piece: captured_piece, color: them, square: captured_square });
             if is_white {
                 self.black_pieces[piece_index] &= !captured_mask;
             } else {
@@ -139,12 +419,14 @@ impl Board {
                 Piece::Knight => 1,
                 _ => unreachable!(),
             };
+            undo.dirty.add(PieceChange { piece: promotion, color, square: mv.to });
             if is_white {
                 self.white_pieces[promotion_index] |= to_mask;
             } else {
                 self.black_pieces[promotion_index] |= to_mask;
             }
         } else {
+            undo.dirty.add(PieceChange { piece: mv.piece, color, square: mv.to });
             if is_white {
                 self.white_pieces[piece_index] |= to_mask;
             } else {
@@ -167,6 +449,8 @@ impl Board {
                     (56, 59)  // a8 to d8
                 }
             };
+            undo.dirty.remove(PieceChange { piece: Piece::Rook, color, square: rook_from });
+            undo.dirty.add(PieceChange { piece: Piece::Rook, color, square: rook_to });
             let rook_from_mask = 1u64 << rook_from;
             let rook_to_mask = 1u64 << rook_to;
             if is_white {
@@ -178,25 +462,14 @@ impl Board {
             }
         }
 
-        // Update castling rights
-        if mv.piece == Piece::King {
-            if is_white {
-                self.castling_rights &= !0b0011;  // Clear white castling rights
-            } else {
-                self.castling_rights &= !0b1100;  // Clear black castling rights
-            }
-        } else if mv.piece == Piece::Rook {
-            match (self.side_to_move, mv.from) {
-                (Color::White, 0) => self.castling_rights &= !0b0010,  // White queenside
-                (Color::White, 7) => self.castling_rights &= !0b0001,  // White kingside
-                (Color::Black, 56) => self.castling_rights &= !0b1000, // Black queenside
-                (Color::Black, 63) => self.castling_rights &= !0b0100, // Black kingside
-                _ => {}
-            }
-        }
+        // Update castling rights: a move from or to a king's or rook's home
+        // square loses the rights that need it there, rook captures included
+        self.castling_rights &= castling_rights_kept(mv.from) & castling_rights_kept(mv.to);
 
         // Update en passant square
-        self.en_passant_square = if mv.piece == Piece::Pawn && (mv.to as i8 - mv.from as i8).abs() == 16 {
+        // Double pushes from the back rank, as in Horde, can't be taken en passant
+        let back_rank = mv.from / 8 == 0 || mv.from / 8 == 7;
+        self.en_passant_square = if mv.piece == Piece::Pawn && (mv.to as i8 - mv.from as i8).abs() == 16 && !back_rank {
             Some(if is_white { mv.from + 8 } else { mv.from - 8 })
         } else {
             None
@@ -206,14 +479,93 @@ impl Board {
         if mv.piece == Piece::Pawn || mv.captured_piece.is_some() {
             self.halfmove_clock = 0;
         } else {
-            self.halfmove_clock += 1;
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
         }
         if !is_white {
-            self.fullmove_number += 1;
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
         }
 
+        psqt::update(&mut self.psqt, &undo.dirty);
+
         // Switch side to move
         self.side_to_move = self.side_to_move.opposite();
+        self.hash = zobrist::update(undo.hash, &undo, self);
+        undo
+    }
+
+    // Takes back the move make_move returned undo for, handing back the
+    // pieces this puts back and takes off
+    pub fn unmake_move(&mut self, undo: &UndoState) -> DirtyPieces {
+        let dirty = undo.dirty.inverse();
+        for change in dirty.removed() {
+            self.pieces_mut(change.color)[change.piece as usize] &= !(1u64 << change.square);
+        }
+        for change in dirty.added() {
+            self.pieces_mut(change.color)[change.piece as usize] |= 1u64 << change.square;
+        }
+        self.castling_rights = undo.castling_rights;
+        self.en_passant_square = undo.en_passant_square;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.side_to_move = self.side_to_move.opposite();
+        self.hash = undo.hash;
+        psqt::update(&mut self.psqt, &dirty);
+        dirty
+    }
+
+    // Recomputes the running totals from the bitboards
+    pub fn refresh_psqt(&mut self) {
+        self.psqt = [PsqtScore::compute(&self.white_pieces), PsqtScore::compute(&self.black_pieces)];
+    }
+
+    // Recomputes the Zobrist key from scratch
+    pub fn refresh_hash(&mut self) {
+        self.hash = zobrist::hash(self);
+    }
+
+    fn pieces_mut(&mut self, color: Color) -> &mut [u64; 6] {
+        match color {
+            Color::White => &mut self.white_pieces,
+            Color::Black => &mut self.black_pieces,
+        }
+    }
+
+    // Drops castling rights whose king or rook is no longer on its home square
+    pub fn clear_lost_castling_rights(&mut self) {
+        let home = |pieces: &[u64; 6], index: usize, square: u8| pieces[index] & (1u64 << square) != 0;
+        for (right, king, rook, pieces) in [
+            (0b0001, 4, 7, &self.white_pieces),
+            (0b0010, 4, 0, &self.white_pieces),
+            (0b0100, 60, 63, &self.black_pieces),
+            (0b1000, 60, 56, &self.black_pieces),
+        ] {
+            if !home(pieces, 5, king) || !home(pieces, 3, rook) {
+                self.castling_rights &= !right;
+            }
+        }
+    }
+
+    // Plays a list of UCI moves, returning each position with the move made
+    // from it. At a move that isn't legal it stops, the board left after the
+    // moves before it.
+    pub fn apply_uci_moves<S: AsRef<str>>(&mut self, moves: &[S]) -> Result<Vec<(Board, Move)>, MoveListError> {
+        let generator = MoveGenerator::new();
+        let mut played = Vec::with_capacity(moves.len());
+        for (index, move_str) in moves.iter().enumerate() {
+            match generator.parse_uci_move(self, move_str.as_ref()) {
+                Ok(mv) => {
+                    played.push((self.clone(), mv));
+                    self.make_move(mv);
+                }
+                Err(reason) => return Err(MoveListError { index, reason, played }),
+            }
+        }
+        Ok(played)
+    }
+
+    // Whether neither side can win any more, by this board's variant
+    pub fn is_insufficient_material(&self, rule: MaterialRule) -> bool {
+        self.variant.is_insufficient_material(&MoveGenerator::new(), self, rule)
     }
 
     pub fn get_piece_at(&self, square: u8) -> Option<(Piece, Color)> {
@@ -253,6 +605,32 @@ impl Board {
     }
 }
 
+// The castling rights that survive a move touching this square
+fn castling_rights_kept(square: Square) -> u8 {
+    match square {
+        0 => !0b0010,   // a1, White queenside
+        4 => !0b0011,   // e1
+        7 => !0b0001,   // h1, White kingside
+        56 => !0b1000,  // a8, Black queenside
+        60 => !0b1100,  // e8
+        63 => !0b0100,  // h8, Black kingside
+        _ => !0,
+    }
+}
+
+// Parses a square name such as "e4"
+pub fn parse_square(name: &str) -> Option<Square> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    Some((bytes[1] - b'1') * 8 + (bytes[0] - b'a'))
+}
+
+pub fn square_name(square: Square) -> String {
+    format!("{}{}", (b'a' + square % 8) as char, square / 8 + 1)
+}
+
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut result = String::new();
@@ -301,4 +679,105 @@ impl fmt::Display for Board {
         }
         write!(f, "{}", result)
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_fen_startpos() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let start = Board::new();
+        assert_eq!(board.white_pieces, start.white_pieces);
+        assert_eq!(board.black_pieces, start.black_pieces);
+        assert_eq!(board.side_to_move, Color::White);
+        assert_eq!(board.castling_rights, 0b1111);
+        assert_eq!(board.en_passant_square, None);
+    }
+
+    #[test]
+    fn test_from_fen_fields() {
+        let board = Board::from_fen("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w Kq d6 0 3").unwrap();
+        assert_eq!(board.get_piece_at(36), Some((Piece::Pawn, Color::White)));
+        assert_eq!(board.get_piece_at(35), Some((Piece::Pawn, Color::Black)));
+        assert_eq!(board.castling_rights, 0b1001);
+        assert_eq!(board.en_passant_square, parse_square("d6"));
+        assert_eq!(board.fullmove_number, 3);
+
+        // Counters may be left out
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - -").unwrap();
+        assert_eq!(board.side_to_move, Color::Black);
+        assert_eq!(board.halfmove_clock, 0);
+        assert_eq!(board.fullmove_number, 1);
+        // Or just the fullmove number left out
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 7").unwrap();
+        assert_eq!((board.halfmove_clock, board.fullmove_number), (7, 1));
+
+        // Rights without their rook are dropped rather than kept or rejected
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(board.castling_rights, 0b1101);
+    }
+
+    #[test]
+    fn test_to_fen_round_trip() {
+        assert_eq!(Board::new().to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w Kq d6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_from_fen_rejects_malformed() {
+        assert!(Board::from_fen("").is_err());
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1 1").unwrap_err(), "expected 4 to 6 fields, got 7");
+        assert!(Board::from_fen("8/8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K2 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w X - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - e4 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - x 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4X3 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/88888888888888888888888888888888/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    }
+
+    #[test]
+    fn test_make_and_unmake_move() {
+        use crate::movegen::MoveGenerator;
+        let generator = MoveGenerator::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w Kq d6 0 3",
+            "4k3/1P6/8/8/8/8/6p1/4K2R b K - 3 30",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for mv in generator.generate_moves(&board) {
+                let mut after = board.clone();
+                let undo = after.make_move(mv);
+                // Applying the recorded changes to the old bitboards gives the new ones
+                let mut moved = board.clone();
+                for change in undo.dirty.removed() {
+                    moved.pieces_mut(change.color)[change.piece as usize] &= !(1u64 << change.square);
+                }
+                for change in undo.dirty.added() {
+                    moved.pieces_mut(change.color)[change.piece as usize] |= 1u64 << change.square;
+                }
+                assert_eq!((moved.white_pieces, moved.black_pieces), (after.white_pieces, after.black_pieces));
+                moved.refresh_psqt();
+                assert_eq!(after.psqt, moved.psqt);
+                assert_eq!(after.hash, zobrist::hash(&after));
+
+                assert_eq!(after.unmake_move(&undo), undo.dirty.inverse());
+                assert_eq!(after.to_fen(), fen);
+                assert_eq!((after.white_pieces, after.black_pieces), (board.white_pieces, board.black_pieces));
+                assert_eq!(after.psqt, board.psqt);
+                assert_eq!(after.hash, board.hash);
+            }
+        }
+    }
+}
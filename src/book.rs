@@ -0,0 +1,393 @@
+use crate::board::{Board, Color};
+use crate::movegen::{Move, MoveGenerator};
+use crate::game::{Game, GameResult};
+use crate::pgn::read_games;
+use crate::polyglot;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+
+// One 16-byte record of a Polyglot book, stored big-endian and sorted by key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+    // Books built by BookBuilder keep the number of games in the high 16 bits
+    // and the mover's score (0..=10000 for 0..100%) in the low 16 bits
+    pub learn: u32,
+}
+
+impl BookEntry {
+    pub fn games(&self) -> u32 {
+        self.learn >> 16
+    }
+
+    pub fn score_rate(&self) -> f64 {
+        (self.learn & 0xFFFF) as f64 / 10000.0
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+const ENTRY_SIZE: usize = 16;
+
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>,
+}
+
+impl PolyglotBook {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!("book size {} is not a multiple of {}", bytes.len(), ENTRY_SIZE));
+        }
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|chunk| BookEntry {
+                key: u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(chunk[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(chunk[10..12].try_into().unwrap()),
+                learn: u32::from_be_bytes(chunk[12..16].try_into().unwrap()),
+            })
+            .collect();
+        // Lookups binary search on the key; don't trust the file to be sorted
+        entries.sort_by_key(|entry| entry.key);
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = self.entries.partition_point(|entry| entry.key <= key);
+        &self.entries[start..end]
+    }
+
+    // The legal book moves for a position with their weights
+    pub fn moves(&self, board: &Board, generator: &MoveGenerator) -> Vec<(Move, u16)> {
+        self.lookup(polyglot::key(board))
+            .iter()
+            .filter_map(|entry| polyglot::decode_move(board, generator, entry.mv).map(|mv| (mv, entry.weight)))
+            .collect()
+    }
+
+    // Picks a book move according to the selection's policy, after dropping
+    // moves below its weight and score floors
+    pub fn pick<R: Rng>(&self, board: &Board, generator: &MoveGenerator, selection: &BookSelection, rng: &mut R) -> Option<Move> {
+        let mut candidates: Vec<(Move, u64)> = self
+            .lookup(polyglot::key(board))
+            .iter()
+            .filter(|entry| entry.weight >= selection.min_weight)
+            // Books without game statistics cannot be filtered by score
+            .filter(|entry| entry.games() == 0 || entry.score_rate() >= selection.min_score)
+            .filter_map(|entry| polyglot::decode_move(board, generator, entry.mv).map(|mv| (mv, entry.weight as u64)))
+            .collect();
+        candidates.sort_by_key(|&(_, weight)| Reverse(weight));
+        let best_weight = candidates.first()?.1;
+
+        match selection.policy {
+            BookPolicy::Best => Some(candidates[0].0),
+            BookPolicy::Weighted { variety } => {
+                // Variety 0 always plays the heaviest move; higher values (up to
+                // 100) let lighter moves in
+                let min_weight = best_weight * (100 - variety.min(100) as u64) / 100;
+                let weighted: Vec<(Move, f64)> = candidates
+                    .into_iter()
+                    .filter(|&(_, weight)| weight >= min_weight)
+                    .map(|(mv, weight)| (mv, weight as f64))
+                    .collect();
+                choose_weighted(&weighted, rng)
+            }
+            BookPolicy::TopN { n, temperature } => {
+                // Low temperatures sharpen the weights towards the best move,
+                // high ones flatten them towards a uniform choice
+                let weighted: Vec<(Move, f64)> = candidates
+                    .into_iter()
+                    .take(n.max(1))
+                    .map(|(mv, weight)| (mv, (weight as f64).powf(1.0 / temperature.max(0.01))))
+                    .collect();
+                choose_weighted(&weighted, rng)
+            }
+        }
+    }
+}
+
+// Books consulted in priority order, such as a narrow repertoire ahead of a
+// broad fallback, each with the last move number it applies to. The first
+// with a move for the position decides.
+pub fn pick_layered<R: Rng>(
+    layers: &[(&PolyglotBook, u16)],
+    board: &Board,
+    generator: &MoveGenerator,
+    selection: &BookSelection,
+    rng: &mut R,
+) -> Option<Move> {
+    layers
+        .iter()
+        .filter(|&&(_, depth)| board.fullmove_number <= depth)
+        .find_map(|(book, _)| book.pick(board, generator, selection, rng))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookPolicy {
+    Best,
+    Weighted { variety: u32 },
+    TopN { n: usize, temperature: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookSelection {
+    pub policy: BookPolicy,
+    pub min_weight: u16,
+    // Minimum score for the side to move, 0.0..=1.0
+    pub min_score: f64,
+}
+
+impl Default for BookSelection {
+    fn default() -> Self {
+        Self {
+            policy: BookPolicy::Weighted { variety: 0 },
+            min_weight: 0,
+            min_score: 0.0,
+        }
+    }
+}
+
+fn choose_weighted<R: Rng>(candidates: &[(Move, f64)], rng: &mut R) -> Option<Move> {
+    let total: f64 = candidates.iter().map(|&(_, weight)| weight).sum();
+    if total <= 0.0 {
+        return candidates.first().map(|&(mv, _)| mv);
+    }
+    let mut choice = rng.gen_range(0.0..total);
+    for &(mv, weight) in candidates {
+        if choice < weight {
+            return Some(mv);
+        }
+        choice -= weight;
+    }
+    candidates.last().map(|&(mv, _)| mv)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+// Collects the moves played in a set of games, up to a ply limit, into a Polyglot book
+pub struct BookBuilder {
+    max_ply: usize,
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+impl BookBuilder {
+    pub fn new(max_ply: usize) -> Self {
+        Self {
+            max_ply,
+            stats: HashMap::new(),
+        }
+    }
+
+    // Adds every game in a PGN text and returns how many were usable
+    pub fn add_pgn(&mut self, text: &str) -> usize {
+        read_games(text).into_iter().flatten().filter(|game| self.add_game(game).is_ok()).count()
+    }
+
+    pub fn add_game(&mut self, game: &Game) -> Result<(), String> {
+        let white_score = match game.result {
+            GameResult::WhiteWins => 2,
+            GameResult::Draw => 1,
+            GameResult::BlackWins => 0,
+            // Unfinished games say nothing about how good a move is
+            GameResult::Unfinished => return Err("game has no result".to_string()),
+        };
+
+        for (board, mv) in game.positions().into_iter().take(self.max_ply) {
+            let score = if board.side_to_move == Color::White { white_score } else { 2 - white_score };
+            let stats = self.stats.entry((polyglot::key(&board), polyglot::encode_move(&mv))).or_default();
+            stats.games += 1;
+            match score {
+                2 => stats.wins += 1,
+                1 => stats.draws += 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Weight is 2 per win and 1 per draw, scaled down to fit if needed
+    pub fn entries(&self, min_games: u32) -> Vec<BookEntry> {
+        let points = |stats: &MoveStats| 2 * stats.wins as u64 + stats.draws as u64;
+        let max_points = self.stats.values().map(points).max().unwrap_or(0);
+        let scale = max_points.div_ceil(u16::MAX as u64).max(1);
+
+        let mut entries: Vec<BookEntry> = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| stats.games >= min_games)
+            .map(|(&(key, mv), stats)| BookEntry {
+                key,
+                mv,
+                weight: (points(stats) / scale) as u16,
+                learn: (stats.games.min(0xFFFF) << 16) | (points(stats) * 5000 / stats.games as u64) as u32,
+            })
+            .collect();
+        entries.sort_by_key(|entry| (entry.key, Reverse(entry.weight), entry.mv));
+        entries
+    }
+
+    pub fn write(&self, path: &str, min_games: u32) -> Result<usize, String> {
+        let entries = self.entries(min_games);
+        let bytes: Vec<u8> = entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+        fs::write(path, bytes).map_err(|err| format!("cannot write {}: {}", path, err))?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn entry_bytes(key: u64, mv: u16, weight: u16) -> Vec<u8> {
+        let mut bytes = key.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&mv.to_be_bytes());
+        bytes.extend_from_slice(&weight.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes
+    }
+
+    // e2e4 and d2d4 from the start position, plus an unrelated entry
+    fn start_book() -> PolyglotBook {
+        let start = polyglot::key(&Board::new());
+        let mut bytes = entry_bytes(start, 28 | 12 << 6, 100);
+        bytes.extend(entry_bytes(1, 0, 1));
+        bytes.extend(entry_bytes(start, 27 | 11 << 6, 10));
+        PolyglotBook::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_and_decode() {
+        let book = start_book();
+        let generator = MoveGenerator::new();
+        assert_eq!(book.len(), 3);
+        assert_eq!(book.lookup(1).len(), 1);
+        assert!(book.lookup(2).is_empty());
+
+        let moves = book.moves(&Board::new(), &generator);
+        assert_eq!(moves.len(), 2);
+        assert!(moves.iter().any(|&(mv, weight)| mv.from == 12 && mv.to == 28 && weight == 100));
+
+        assert!(PolyglotBook::from_bytes(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn test_build_from_pgn() {
+        let pgn = "1. e4 e5 2. Nf3 1-0\n\n1. e4 c5 0-1\n\n1. d4 d5 1/2-1/2\n\n1. e4 e5 *\n";
+        let mut builder = BookBuilder::new(2);
+        assert_eq!(builder.add_pgn(pgn), 3);
+
+        let entries = builder.entries(1);
+        let bytes: Vec<u8> = entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+        let book = PolyglotBook::from_bytes(&bytes).unwrap();
+        let generator = MoveGenerator::new();
+
+        // e4: one win, one loss; d4: one draw. Nf3 is past the ply limit.
+        let start = book.lookup(polyglot::key(&Board::new()));
+        assert_eq!(start.len(), 2);
+        let e4 = start.iter().find(|entry| entry.mv == 28 | 12 << 6).unwrap();
+        assert_eq!((e4.weight, e4.games(), e4.score_rate()), (2, 2, 0.5));
+        let d4 = start.iter().find(|entry| entry.mv == 27 | 11 << 6).unwrap();
+        assert_eq!((d4.weight, d4.games()), (1, 1));
+        assert_eq!(book.len(), 5);
+        let selection = BookSelection::default();
+        assert_eq!(book.pick(&Board::new(), &generator, &selection, &mut StdRng::seed_from_u64(1)).unwrap().to, 28);
+
+        // Only moves seen in at least two games
+        assert_eq!(builder.entries(2).len(), 1);
+    }
+
+    fn weighted(variety: u32) -> BookSelection {
+        BookSelection { policy: BookPolicy::Weighted { variety }, ..BookSelection::default() }
+    }
+
+    #[test]
+    fn test_pick_variety() {
+        let book = start_book();
+        let generator = MoveGenerator::new();
+        let board = Board::new();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        // No variety: always the heaviest move
+        for _ in 0..20 {
+            assert_eq!(book.pick(&board, &generator, &weighted(0), &mut rng).unwrap().to, 28);
+        }
+        // Full variety: the light move comes up too
+        let picks: Vec<u8> = (0..200).map(|_| book.pick(&board, &generator, &weighted(100), &mut rng).unwrap().to).collect();
+        assert!(picks.contains(&27));
+        assert!(picks.iter().filter(|&&to| to == 28).count() > picks.len() / 2);
+    }
+
+    #[test]
+    fn test_pick_policies_and_filters() {
+        let book = start_book();
+        let generator = MoveGenerator::new();
+        let board = Board::new();
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut count_d4 = |selection: BookSelection| {
+            (0..400).filter(|_| book.pick(&board, &generator, &selection, &mut rng).unwrap().to == 27).count()
+        };
+
+        let best = BookSelection { policy: BookPolicy::Best, ..BookSelection::default() };
+        assert_eq!(count_d4(best), 0);
+        let top_one = BookSelection { policy: BookPolicy::TopN { n: 1, temperature: 5.0 }, ..BookSelection::default() };
+        assert_eq!(count_d4(top_one), 0);
+        // Weights 100 and 10: a high temperature plays d4 far more often than a low one
+        let cold = count_d4(BookSelection { policy: BookPolicy::TopN { n: 2, temperature: 0.5 }, ..BookSelection::default() });
+        let hot = count_d4(BookSelection { policy: BookPolicy::TopN { n: 2, temperature: 10.0 }, ..BookSelection::default() });
+        assert!(cold < 20 && hot > 100, "cold {} hot {}", cold, hot);
+
+        // Filtering out e4 leaves only d4, and filtering out everything leaves nothing
+        let min_weight = BookSelection { policy: BookPolicy::Best, min_weight: 5, ..BookSelection::default() };
+        assert_eq!(book.pick(&board, &generator, &min_weight, &mut rng).unwrap().to, 28);
+        let too_heavy = BookSelection { min_weight: 101, ..BookSelection::default() };
+        assert!(book.pick(&board, &generator, &too_heavy, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_pick_min_score() {
+        // e4 won two of its three games for White, d4 drew its only one
+        let mut builder = BookBuilder::new(1);
+        builder.add_pgn("1. e4 1-0\n\n1. e4 0-1\n\n1. e4 1-0\n\n1. d4 1/2-1/2\n");
+        let bytes: Vec<u8> = builder.entries(1).iter().flat_map(|entry| entry.to_bytes()).collect();
+        let book = PolyglotBook::from_bytes(&bytes).unwrap();
+        let generator = MoveGenerator::new();
+        let mut rng = StdRng::seed_from_u64(5);
+
+        let selection = BookSelection { policy: BookPolicy::Best, min_score: 0.6, ..BookSelection::default() };
+        assert_eq!(book.pick(&Board::new(), &generator, &selection, &mut rng).unwrap().to, 28);
+        let selection = BookSelection { min_score: 0.7, ..BookSelection::default() };
+        assert!(book.pick(&Board::new(), &generator, &selection, &mut rng).is_none());
+    }
+}
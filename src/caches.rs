@@ -0,0 +1,152 @@
+#[cfg(feature = "search")]
+use crate::transposition::{TranspositionEntry, TranspositionTable};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+// The main table gets most of the Hash budget; the pawn hash and eval cache
+// take these fractions of it
+#[cfg(feature = "search")]
+const PAWN_HASH_DIVISOR: usize = 16;
+#[cfg(feature = "search")]
+const EVAL_CACHE_DIVISOR: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CacheEntry<V: Copy + Default> {
+    key: u64,
+    value: V,
+}
+
+// A fixed-size, always-replace table keyed by a full 64-bit hash
+pub struct CacheTable<V: Copy + Default> {
+    entries: Vec<CacheEntry<V>>,
+}
+
+// Pawn structure score, keyed by the pawn-only hash
+pub type PawnHashTable = CacheTable<i32>;
+// Static evaluation, keyed by the full position hash
+pub type EvalCache = CacheTable<i32>;
+
+impl<V: Copy + Default> CacheTable<V> {
+    pub fn new(size: usize) -> Self {
+        Self {
+            entries: vec![CacheEntry::default(); size.max(1)],
+        }
+    }
+
+    pub fn with_bytes(bytes: usize) -> Self {
+        Self::new(bytes / size_of::<CacheEntry<V>>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn get(&self, key: u64) -> Option<V> {
+        let entry = &self.entries[self.index(key)];
+        // Key 0 marks an empty slot
+        if key != 0 && entry.key == key {
+            Some(entry.value)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, key: u64, value: V) {
+        let index = self.index(key);
+        self.entries[index] = CacheEntry { key, value };
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(CacheEntry::default());
+    }
+}
+
+// All of the engine's hash tables, sized from the Hash option's budget and cleared
+// together by "Clear Hash" and ucinewgame
+#[cfg(feature = "search")]
+pub struct Caches {
+    pub tt: TranspositionTable,
+    pub pawn_hash: PawnHashTable,
+    pub eval_cache: EvalCache,
+}
+
+#[cfg(feature = "search")]
+impl Default for Caches {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
+    }
+}
+
+#[cfg(feature = "search")]
+impl Caches {
+    pub fn new(hash_mb: usize) -> Self {
+        Self::with_huge_pages(hash_mb, false)
+    }
+
+    // Huge pages only for the main table: it is the one probed all over
+    pub fn with_huge_pages(hash_mb: usize, huge_pages: bool) -> Self {
+        let bytes = hash_mb.max(1) * 1024 * 1024;
+        let pawn_bytes = bytes / PAWN_HASH_DIVISOR;
+        let eval_bytes = bytes / EVAL_CACHE_DIVISOR;
+        let tt_bytes = bytes - pawn_bytes - eval_bytes;
+
+        Self {
+            tt: TranspositionTable::with_huge_pages(tt_bytes / size_of::<TranspositionEntry>(), huge_pages),
+            pawn_hash: PawnHashTable::with_bytes(pawn_bytes),
+            eval_cache: EvalCache::with_bytes(eval_bytes),
+        }
+    }
+
+    // Reallocate every table for a new Hash budget; contents are lost
+    pub fn resize(&mut self, hash_mb: usize, huge_pages: bool) {
+        *self = Self::with_huge_pages(hash_mb, huge_pages);
+    }
+
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.pawn_hash.clear();
+        self.eval_cache.clear();
+    }
+}
+
+#[cfg(all(test, feature = "search"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_table_get_insert_clear() {
+        let mut cache = EvalCache::new(64);
+        let key = 0x1234_5678_9ABC_DEF0;
+
+        assert_eq!(cache.get(key), None);
+        cache.insert(key, -25);
+        assert_eq!(cache.get(key), Some(-25));
+        // Same slot, different key
+        assert_eq!(cache.get(key + 64), None);
+
+        cache.clear();
+        assert_eq!(cache.get(key), None);
+    }
+
+    #[test]
+    fn test_hash_budget_is_shared() {
+        let caches = Caches::new(16);
+        let total = caches.tt.len() * size_of::<TranspositionEntry>()
+            + caches.pawn_hash.len() * 16
+            + caches.eval_cache.len() * 16;
+
+        assert!(total <= 16 * 1024 * 1024);
+        assert!(total > 15 * 1024 * 1024);
+    }
+}
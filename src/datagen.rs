@@ -0,0 +1,210 @@
+use crate::board::{Board, Color};
+use crate::game::GameResult;
+use crate::movegen::{GameState, Move, MoveGenerator};
+use crate::repetition::RepetitionHistory;
+use crate::search::{Search, SearchLimits, MATE_THRESHOLD};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+// A game is called once one side has been this far ahead for this many plies in a row
+const WIN_ADJUDICATION_SCORE: i32 = 2000;
+const WIN_ADJUDICATION_PLIES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DatagenConfig {
+    pub games: usize,
+    pub threads: usize,
+    // Node budget for every move, which keeps games fast and reproducible
+    pub nodes: u64,
+    // Random moves played before the engine takes over, so games differ
+    pub random_plies: usize,
+    // Games still running after this many plies are scored as draws
+    pub max_plies: usize,
+    pub seed: u64,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        Self {
+            games: 100,
+            threads: 1,
+            nodes: 5000,
+            random_plies: 8,
+            max_plies: 400,
+            seed: 0,
+        }
+    }
+}
+
+// One training position with the search score, in centipawns from White's
+// side, and the final result of its game
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub fen: String,
+    pub score: i32,
+    pub result: GameResult,
+}
+
+// "<fen> | <score> | <result>", result being 1.0, 0.5 or 0.0 for White
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let result = match self.result {
+            GameResult::WhiteWins => "1.0",
+            GameResult::BlackWins => "0.0",
+            _ => "0.5",
+        };
+        write!(f, "{} | {} | {}", self.fen, self.score, result)
+    }
+}
+
+// Plays one self-play game and returns its quiet positions. None when the
+// random opening already ended the game.
+pub fn play_game(search: &mut Search, config: &DatagenConfig, rng: &mut StdRng) -> Option<Vec<Record>> {
+    let generator = MoveGenerator::new();
+    let mut board = Board::new();
+    let mut history: Vec<(Board, Move)> = Vec::new();
+
+    for _ in 0..config.random_plies {
+        let &mv = generator.generate_moves(&board).choose(rng)?;
+        history.push((board.clone(), mv));
+        board.make_move(mv);
+    }
+    if generator.generate_moves(&board).is_empty() {
+        return None;
+    }
+
+    search.clear();
+    search.set_limits(SearchLimits { nodes: Some(config.nodes), ..SearchLimits::default() });
+    let mut records = Vec::new();
+    let mut winning_streak = (Color::White, 0);
+
+    let result = loop {
+        match generator.get_game_state(&board, &history) {
+            GameState::Ongoing => {}
+            GameState::Checkmate(Color::White) | GameState::VariantWin(Color::White) => break GameResult::WhiteWins,
+            GameState::Checkmate(Color::Black) | GameState::VariantWin(Color::Black) => break GameResult::BlackWins,
+            _ => break GameResult::Draw,
+        }
+        if history.len() >= config.max_plies {
+            break GameResult::Draw;
+        }
+
+        search.set_history(RepetitionHistory::from_moves(&history, &board));
+        let mv = search.find_best_move(&board)?;
+        let score = search.root_moves().best()?.score;
+        let white_score = if board.side_to_move == Color::White { score } else { -score };
+
+        // Positions in check, with a tactical best move or a mate score teach
+        // an evaluation little
+        let quiet = mv.captured_piece.is_none() && mv.promotion.is_none();
+        if quiet && score.abs() < MATE_THRESHOLD && !generator.is_king_in_check(&board, board.side_to_move) {
+            records.push(Record { fen: board.to_fen(), score: white_score, result: GameResult::Unfinished });
+        }
+
+        if white_score.abs() >= WIN_ADJUDICATION_SCORE {
+            let leader = if white_score > 0 { Color::White } else { Color::Black };
+            winning_streak = if winning_streak.0 == leader { (leader, winning_streak.1 + 1) } else { (leader, 1) };
+            if winning_streak.1 >= WIN_ADJUDICATION_PLIES {
+                break if leader == Color::White { GameResult::WhiteWins } else { GameResult::BlackWins };
+            }
+        } else {
+            winning_streak.1 = 0;
+        }
+
+        history.push((board.clone(), mv));
+        board.make_move(mv);
+    };
+
+    for record in &mut records {
+        record.result = result;
+    }
+    Some(records)
+}
+
+// Plays config.games games over config.threads threads, writing one record per
+// line as games finish. Returns the number of games and records written.
+pub fn generate<W: Write>(config: &DatagenConfig, out: &mut W) -> io::Result<(usize, usize)> {
+    let next_game = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..config.threads.max(1))
+        .map(|_| {
+            let next_game = Arc::clone(&next_game);
+            let sender = sender.clone();
+            let config = *config;
+            thread::spawn(move || {
+                let mut search = Search::new();
+                loop {
+                    let game = next_game.fetch_add(1, Ordering::Relaxed);
+                    if game >= config.games {
+                        break;
+                    }
+                    // Seeding per game keeps the output independent of the thread count
+                    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(game as u64));
+                    if sender.send(play_game(&mut search, &config, &mut rng)).is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let (mut games, mut records) = (0, 0);
+    for game in receiver.into_iter().flatten() {
+        for record in &game {
+            writeln!(out, "{}", record)?;
+        }
+        games += 1;
+        records += game.len();
+    }
+    for worker in workers {
+        worker.join().expect("datagen worker panicked");
+    }
+    Ok((games, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick_config() -> DatagenConfig {
+        DatagenConfig { games: 3, threads: 2, nodes: 1000, random_plies: 6, max_plies: 16, seed: 11 }
+    }
+
+    #[test]
+    fn test_play_game_records() {
+        let mut search = Search::new();
+        let records = play_game(&mut search, &quick_config(), &mut StdRng::seed_from_u64(3)).unwrap();
+
+        assert!(!records.is_empty());
+        let result = records[0].result;
+        for record in &records {
+            assert!(Board::from_fen(&record.fen).is_ok());
+            assert_eq!(record.result, result);
+            assert!(record.score.abs() < MATE_THRESHOLD);
+        }
+    }
+
+    #[test]
+    fn test_generate_writes_lines() {
+        let mut out = Vec::new();
+        let (games, records) = generate(&quick_config(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert_eq!(games, 3);
+        assert_eq!(text.lines().count(), records);
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split(" | ").collect();
+            assert_eq!(fields.len(), 3);
+            assert!(fields[1].parse::<i32>().is_ok());
+            assert!(["1.0", "0.5", "0.0"].contains(&fields[2]));
+        }
+    }
+}
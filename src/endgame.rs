@@ -0,0 +1,282 @@
+use crate::board::{Board, Color};
+
+// Knowledge of particular endgames that piece-square tables can't express,
+// looked up by material signature such as "KRPvKR", the stronger side first.
+// Each function gets the generic evaluation from the strong side's point of
+// view and returns its replacement: a score of its own for an endgame it knows
+// to be won, or the generic one scaled towards zero for a drawish one.
+type EndgameFn = fn(&Board, Color, i32) -> i32;
+
+static ENDGAMES: [(u64, EndgameFn); 11] = [
+    (signature_key("KQvK"), lone_king),
+    (signature_key("KRvK"), lone_king),
+    (signature_key("KBNvK"), bishop_and_knight),
+    (signature_key("KNNvK"), two_knights),
+    (signature_key("KPvK"), king_and_pawn),
+    (signature_key("KBPvK"), rook_pawn_and_bishop),
+    (signature_key("KQvKP"), queen_against_pawn),
+    (signature_key("KRPvKR"), rook_and_pawn_against_rook),
+    (signature_key("KRvKB"), rook_against_minor),
+    (signature_key("KRvKN"), rook_against_minor),
+    (signature_key("KBPvKB"), opposite_bishops),
+];
+
+// No signature above has more pieces than this, kings included
+const MAX_PIECES: u32 = 6;
+
+// Above anything the generic evaluation gives these endgames, and far below mate
+pub const KNOWN_WIN: i32 = 2000;
+
+// Scale factors are out of this
+const SCALE_NORMAL: i32 = 64;
+
+const DARK_SQUARES: u64 = 0xaa55_aa55_aa55_aa55;
+
+// Four bits per piece type, pawn to queen, the strong side in the low half
+const fn signature_key(signature: &str) -> u64 {
+    let bytes = signature.as_bytes();
+    let mut key = 0;
+    let mut side = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let piece = match bytes[i] {
+            b'P' => 0,
+            b'N' => 1,
+            b'B' => 2,
+            b'R' => 3,
+            b'Q' => 4,
+            b'v' => {
+                side = 32;
+                i += 1;
+                continue;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        key += 1 << (side + 4 * piece);
+        i += 1;
+    }
+    key
+}
+
+fn material_key(strong: &[u64; 6], weak: &[u64; 6]) -> u64 {
+    let side = |pieces: &[u64; 6]| (0..5).map(|piece| (pieces[piece].count_ones() as u64) << (4 * piece)).sum::<u64>();
+    side(strong) | side(weak) << 32
+}
+
+// The specialised evaluation of the position from White's point of view, or
+// None when its material has none. total is the generic evaluation.
+pub fn evaluate(board: &Board, total: i32) -> Option<i32> {
+    let pieces: u32 = board.white_pieces.iter().chain(&board.black_pieces).map(|bb| bb.count_ones()).sum();
+    if pieces > MAX_PIECES {
+        return None;
+    }
+    for strong in [Color::White, Color::Black] {
+        let key = material_key(side_pieces(board, strong), side_pieces(board, strong.opposite()));
+        if let Some((_, endgame)) = ENDGAMES.iter().find(|(signature, _)| *signature == key) {
+            return Some(match strong {
+                Color::White => endgame(board, strong, total),
+                Color::Black => -endgame(board, strong, -total),
+            });
+        }
+    }
+    None
+}
+
+fn side_pieces(board: &Board, color: Color) -> &[u64; 6] {
+    match color {
+        Color::White => &board.white_pieces,
+        Color::Black => &board.black_pieces,
+    }
+}
+
+fn square_of(bitboard: u64) -> u8 {
+    bitboard.trailing_zeros() as u8
+}
+
+fn distance(a: u8, b: u8) -> i32 {
+    let (a_file, a_rank) = ((a % 8) as i32, (a / 8) as i32);
+    let (b_file, b_rank) = ((b % 8) as i32, (b / 8) as i32);
+    (a_file - b_file).abs().max((a_rank - b_rank).abs())
+}
+
+// 0 on the four centre squares up to 3 on the edge
+fn centre_distance(square: u8) -> i32 {
+    let (file, rank) = ((square % 8) as i32, (square / 8) as i32);
+    ((2 * file - 7).abs().max((2 * rank - 7).abs())) / 2
+}
+
+fn scaled(generic: i32, factor: i32) -> i32 {
+    generic * factor / SCALE_NORMAL
+}
+
+// The rank a pawn of this colour has reached, counted from its own side
+fn relative_rank(square: u8, color: Color) -> i32 {
+    let rank = (square / 8) as i32;
+    if color == Color::White {
+        rank
+    } else {
+        7 - rank
+    }
+}
+
+fn is_rook_pawn(pawn: u8) -> bool {
+    matches!(pawn % 8, 0 | 7)
+}
+
+fn promotion_square(pawn: u8, color: Color) -> u8 {
+    if color == Color::White {
+        56 + pawn % 8
+    } else {
+        pawn % 8
+    }
+}
+
+// Mating the bare king: drive it to the edge and bring the kings together
+fn lone_king(board: &Board, strong: Color, _generic: i32) -> i32 {
+    let strong_king = square_of(side_pieces(board, strong)[5]);
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    KNOWN_WIN + 40 * centre_distance(weak_king) + 10 * (7 - distance(strong_king, weak_king))
+}
+
+// The mate needs a corner the bishop covers
+fn bishop_and_knight(board: &Board, strong: Color, _generic: i32) -> i32 {
+    let pieces = side_pieces(board, strong);
+    let strong_king = square_of(pieces[5]);
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    let corners = if pieces[2] & DARK_SQUARES != 0 { [0, 63] } else { [7, 56] };
+    let corner_distance = corners.iter().map(|&corner| distance(weak_king, corner)).min().unwrap_or(0);
+    KNOWN_WIN + 10 * centre_distance(weak_king) + 30 * (7 - corner_distance) + 10 * (7 - distance(strong_king, weak_king))
+}
+
+// Can't be forced
+fn two_knights(_board: &Board, _strong: Color, generic: i32) -> i32 {
+    scaled(generic, 4)
+}
+
+fn king_and_pawn(board: &Board, strong: Color, generic: i32) -> i32 {
+    let pawn = square_of(side_pieces(board, strong)[0]);
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    let queening = promotion_square(pawn, strong);
+    // A rook pawn is a draw once the defending king reaches the corner
+    if is_rook_pawn(pawn) && distance(weak_king, queening) <= 1 {
+        return scaled(generic, 0);
+    }
+    // The rule of the square: the defending king can't catch the pawn
+    let pawn_distance = (7 - relative_rank(pawn, strong)).min(5);
+    let tempo = i32::from(board.side_to_move != strong);
+    if distance(weak_king, queening) - tempo > pawn_distance {
+        return generic + KNOWN_WIN;
+    }
+    generic
+}
+
+// A rook pawn whose promotion square the bishop can't cover draws when the
+// defending king gets to the corner
+fn rook_pawn_and_bishop(board: &Board, strong: Color, generic: i32) -> i32 {
+    let pieces = side_pieces(board, strong);
+    let pawn = square_of(pieces[0]);
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    let queening = promotion_square(pawn, strong);
+    let bishop_is_dark = pieces[2] & DARK_SQUARES != 0;
+    let queening_is_dark = DARK_SQUARES & (1 << queening) != 0;
+    if is_rook_pawn(pawn) && bishop_is_dark != queening_is_dark && distance(weak_king, queening) <= 1 {
+        return scaled(generic, 0);
+    }
+    generic
+}
+
+// A bishop or rook pawn one step from promoting, its king beside it, holds
+// the draw by stalemate when the attacking king is too far away to help
+fn queen_against_pawn(board: &Board, strong: Color, generic: i32) -> i32 {
+    let weak = strong.opposite();
+    let pawn = square_of(side_pieces(board, weak)[0]);
+    let weak_king = square_of(side_pieces(board, weak)[5]);
+    let strong_king = square_of(side_pieces(board, strong)[5]);
+    let file = pawn % 8;
+    if relative_rank(pawn, weak) == 6
+        && matches!(file, 0 | 2 | 5 | 7)
+        && distance(weak_king, pawn) <= 1
+        && distance(strong_king, pawn) > 3
+    {
+        return scaled(generic, 8);
+    }
+    generic
+}
+
+// The defending king in front of the pawn draws
+fn rook_and_pawn_against_rook(board: &Board, strong: Color, generic: i32) -> i32 {
+    let pawn = square_of(side_pieces(board, strong)[0]);
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    let ahead = relative_rank(weak_king, strong) > relative_rank(pawn, strong);
+    if ahead && (weak_king % 8).abs_diff(pawn % 8) <= 1 {
+        return scaled(generic, 16);
+    }
+    generic
+}
+
+// Usually drawn, though the defender's king shouldn't stray to the edge
+fn rook_against_minor(board: &Board, strong: Color, generic: i32) -> i32 {
+    let weak_king = square_of(side_pieces(board, strong.opposite())[5]);
+    scaled(generic, 16) + 10 * centre_distance(weak_king)
+}
+
+// Bishops on opposite colours draw with a single pawn
+fn opposite_bishops(board: &Board, strong: Color, generic: i32) -> i32 {
+    let strong_dark = side_pieces(board, strong)[2] & DARK_SQUARES != 0;
+    let weak_dark = side_pieces(board, strong.opposite())[2] & DARK_SQUARES != 0;
+    if strong_dark != weak_dark {
+        return scaled(generic, 8);
+    }
+    generic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specialised(fen: &str) -> Option<i32> {
+        evaluate(&Board::from_fen(fen).unwrap(), 100)
+    }
+
+    #[test]
+    fn test_signatures() {
+        assert_eq!(signature_key("KRPvKR"), 0x1000_0000_1001);
+        let board = Board::from_fen("8/8/4k3/8/3KP3/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(material_key(&board.white_pieces, &board.black_pieces), signature_key("KPvK"));
+        // Too much material for any table entry
+        assert_eq!(specialised("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), None);
+        assert_eq!(specialised("4k3/8/8/8/8/8/8/R2QK3 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn test_mating_the_bare_king() {
+        // Either colour can be the strong side, and the edge is better for it
+        let centre = specialised("8/8/8/3k4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let edge = specialised("3k4/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert!(centre >= KNOWN_WIN && edge > centre);
+        assert_eq!(specialised("r3k3/8/8/8/8/8/8/3K4 b - - 0 1"), Some(-edge));
+
+        // Bishop and knight mate in the bishop's corner: a1 for a dark-squared one
+        let right = specialised("8/8/8/8/8/8/3BN3/k3K3 w - - 0 1").unwrap();
+        let wrong = specialised("8/8/8/8/8/8/3BN3/4K2k w - - 0 1").unwrap();
+        assert!(right > wrong);
+    }
+
+    #[test]
+    fn test_drawish_endgames() {
+        // Wrong rook pawn with the defender in the corner
+        assert_eq!(specialised("7k/8/8/8/8/8/7P/3BK3 w - - 0 1"), Some(0));
+        assert_eq!(specialised("7k/8/8/8/8/8/7P/2B1K3 w - - 0 1"), Some(100));
+        // The defending king in front of the pawn
+        assert_eq!(specialised("3k4/8/8/3P4/8/8/r7/R3K3 w - - 0 1"), Some(25));
+        assert_eq!(specialised("8/8/8/3P4/8/k7/r7/R3K3 w - - 0 1"), Some(100));
+        // Opposite-coloured bishops
+        assert_eq!(specialised("4k3/8/4b3/8/3P4/8/8/2B1K3 w - - 0 1"), Some(12));
+        // A pawn outside the king's square runs home
+        assert!(specialised("8/k7/8/8/6P1/8/8/4K3 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert_eq!(specialised("8/8/8/5k2/6P1/8/8/4K3 w - - 0 1"), Some(100));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::board::Board;
+use crate::evaluation::Evaluator;
+use crate::level::{self, Level};
+use crate::movegen::{GameState, Move, MoveGenerator};
+use crate::repetition::RepetitionHistory;
+use crate::search::{Algorithm, Search, SearchLimits, SearchSignals};
+use crate::uci::parse_uci_move;
+use crate::variant::{self, Variant, STANDARD};
+use std::sync::Arc;
+use std::time::Duration;
+
+// What a finished search found
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub ponder: Option<Move>,
+    // Centipawns from the side to move's point of view
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+// One game's worth of engine: the position with its history and a search.
+// Front ends other than the UCI loop drive the engine through this.
+pub struct Engine {
+    board: Board,
+    history: Vec<(Board, Move)>,
+    generator: MoveGenerator,
+    search: Search,
+    variant: &'static dyn Variant,
+    level: Level,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            board: Board::new(),
+            history: Vec::new(),
+            generator: MoveGenerator::new(),
+            search: Search::new(),
+            variant: &STANDARD,
+            level: Level::Full,
+        }
+    }
+
+    // Back to the start position with nothing remembered from earlier games
+    pub fn new_game(&mut self) {
+        self.board = variant::start_position(self.variant);
+        self.history.clear();
+        self.search.clear();
+    }
+
+    // A FEN (None for the start position) followed by moves in coordinate notation
+    pub fn set_position(&mut self, fen: Option<&str>, moves: &[&str]) -> Result<(), String> {
+        let mut board = match fen {
+            Some(fen) => Board::from_variant_fen(fen, self.variant)?,
+            None => variant::start_position(self.variant),
+        };
+        let history = board.apply_uci_moves(moves).map_err(|err| err.to_string())?;
+        self.board = board;
+        self.history = history;
+        Ok(())
+    }
+
+    // The rules of the games to come; takes effect with the next new game or position
+    pub fn set_variant(&mut self, variant: &'static dyn Variant) {
+        self.variant = variant;
+    }
+
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+        self.search.set_variety(level.variety().unwrap_or_default());
+    }
+
+    pub fn set_evaluator(&mut self, evaluator: Evaluator) {
+        self.search.set_evaluator(evaluator);
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.search.set_algorithm(algorithm);
+    }
+
+    pub fn play(&mut self, move_str: &str) -> Result<Move, String> {
+        let mv = parse_uci_move(&self.board, &self.generator, move_str)?;
+        self.history.push((self.board.clone(), mv));
+        self.board.make_move(mv);
+        Ok(mv)
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn history(&self) -> &[(Board, Move)] {
+        &self.history
+    }
+
+    pub fn game_state(&self) -> GameState {
+        self.generator.get_game_state(&self.board, &self.history)
+    }
+
+    // Searches the current position, or picks a move without searching at the
+    // levels that don't; None when there is no legal move. A move picked
+    // without a search scores the material it leaves.
+    pub fn search(&mut self, mut limits: SearchLimits) -> Option<SearchResult> {
+        if let Some(mv) = self.level.quick_move(&self.board, &mut rand::thread_rng()) {
+            return Some(SearchResult {
+                best_move: mv,
+                ponder: None,
+                score: level::material_after(&self.board, &mv),
+                pv: vec![mv],
+                nodes: 0,
+                elapsed: Duration::ZERO,
+            });
+        }
+        if let Some(depth) = self.level.max_depth() {
+            limits.depth = limits.depth.min(depth);
+        }
+        self.search.set_limits(limits);
+        self.search.set_history(RepetitionHistory::from_moves(&self.history, &self.board));
+        let best_move = self.search.find_best_move(&self.board)?;
+        let ponder = self.search.ponder_move(&self.board);
+        let best = self.search.root_moves().best()?;
+        let stats = self.search.stats();
+        Some(SearchResult {
+            best_move,
+            ponder,
+            score: best.score,
+            pv: best.pv.clone(),
+            nodes: stats.nodes,
+            elapsed: stats.elapsed,
+        })
+    }
+
+    // Shared handle for stopping a search from another thread
+    pub fn signals(&self) -> Arc<SearchSignals> {
+        self.search.signals()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_and_search() {
+        let mut engine = Engine::new();
+        engine.set_position(None, &["e2e4", "e7e5"]).unwrap();
+        assert_eq!(engine.history().len(), 2);
+        assert!(engine.set_position(None, &["e2e5"]).is_err());
+        // A failed update leaves the previous position
+        assert_eq!(engine.history().len(), 2);
+
+        let result = engine.search(SearchLimits { depth: 2, ..SearchLimits::default() }).unwrap();
+        assert_eq!(result.pv[0], result.best_move);
+        assert!(result.nodes > 0);
+        engine.play("g1f3").unwrap();
+        assert_eq!(engine.game_state(), GameState::Ongoing);
+
+        engine.set_position(Some("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1"), &[]).unwrap();
+        assert!(engine.search(SearchLimits::default()).is_none());
+    }
+}
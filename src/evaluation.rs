@@ -1,6 +1,80 @@
+use crate::attacks::{self, KING_ATTACKS};
 use crate::board::{Board, Color, Piece};
+use crate::caches::PawnHashTable;
+use crate::endgame;
 use crate::movegen::MoveGenerator;
+use crate::psqt;
+use crate::simd;
+use crate::zobrist;
+use core::fmt;
+
+// Per-term breakdown of a static evaluation, all from White's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalTrace {
+    pub material: i32,
+    pub piece_squares: i32,
+    pub mobility: i32,
+    pub pawn_structure: i32,
+    pub king_safety: i32,
+    // What the specialised endgame knowledge changed, if the material has any
+    pub known_endgame: i32,
+    pub is_endgame: bool,
+    pub total: i32,
+}
+
+// How often each pawn pattern the evaluation scores occurs for one side
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PawnStructureCounts {
+    pub doubled: i32,
+    pub isolated: i32,
+    pub passed: i32,
+    pub connected: i32,
+}
+
+// How much an attacker of each piece type adds to the danger of a king
+const KING_ATTACK_WEIGHTS: [i32; 6] = [1, 2, 2, 3, 5, 0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct KingSafetyCounts {
+    // Own pawns on the three squares in front of the king
+    pub shield_pawns: i32,
+    pub open_file: bool,
+    pub semi_open_file: bool,
+    // The king's square and those next to it
+    pub ring: u64,
+    // Enemy pieces attacking the ring, by piece type
+    pub attackers: [i32; 6],
+}
+
+impl KingSafetyCounts {
+    pub fn attack_units(&self) -> i32 {
+        self.attackers.iter().zip(KING_ATTACK_WEIGHTS).map(|(count, weight)| count * weight).sum()
+    }
+}
+
+// One side's king safety with the inputs it came from, for showing why the
+// evaluation thinks a king is in danger
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct KingDanger {
+    pub counts: KingSafetyCounts,
+    // The side's own king safety term, negative when the king is unsafe
+    pub score: i32,
+}
 
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Material       {:>6}", self.material)?;
+        writeln!(f, "Piece squares  {:>6}", self.piece_squares)?;
+        writeln!(f, "Mobility       {:>6}", self.mobility)?;
+        writeln!(f, "Pawn structure {:>6}", self.pawn_structure)?;
+        writeln!(f, "King safety    {:>6}", self.king_safety)?;
+        writeln!(f, "Known endgame  {:>6}", self.known_endgame)?;
+        writeln!(f, "Phase          {:>6}", if self.is_endgame { "endgame" } else { "middlegame" })?;
+        write!(f, "Total          {:>6} (white side)", self.total)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Evaluator {
     // Piece values
     pub pawn_value: i32,
@@ -38,101 +112,36 @@ pub struct Evaluator {
     pub open_file_penalty: i32,
     pub semi_open_file_penalty: i32,
     pub king_attack_bonus: i32,
+
+    // Read material and piece squares off the board's running totals, which
+    // use the default values and tables; turn off after changing either
+    pub use_board_psqt: bool,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Evaluator {
     pub fn new() -> Self {
         Self {
-            pawn_value: 100,
-            knight_value: 320,
-            bishop_value: 330,
-            rook_value: 500,
-            queen_value: 900,
-            king_value: 20000,
-
-            // Pawn position bonuses (encourages central control and advancement)
-            pawn_position_bonus: [
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [50, 50, 50, 50, 50, 50, 50, 50],
-                [10, 10, 20, 30, 30, 20, 10, 10],
-                [5, 5, 10, 25, 25, 10, 5, 5],
-                [0, 0, 0, 20, 20, 0, 0, 0],
-                [5, -5, -10, 0, 0, -10, -5, 5],
-                [5, 10, 10, -20, -20, 10, 10, 5],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-            ],
-
-            // Knight position bonuses (encourages central control)
-            knight_position_bonus: [
-                [-50, -40, -30, -30, -30, -30, -40, -50],
-                [-40, -20, 0, 0, 0, 0, -20, -40],
-                [-30, 0, 10, 15, 15, 10, 0, -30],
-                [-30, 5, 15, 20, 20, 15, 5, -30],
-                [-30, 0, 15, 20, 20, 15, 0, -30],
-                [-30, 5, 10, 15, 15, 10, 5, -30],
-                [-40, -20, 0, 5, 5, 0, -20, -40],
-                [-50, -40, -30, -30, -30, -30, -40, -50],
-            ],
-
-            // Bishop position bonuses (encourages central control and long diagonals)
-            bishop_position_bonus: [
-                [-20, -10, -10, -10, -10, -10, -10, -20],
-                [-10, 0, 0, 0, 0, 0, 0, -10],
-                [-10, 0, 5, 10, 10, 5, 0, -10],
-                [-10, 5, 5, 10, 10, 5, 5, -10],
-                [-10, 0, 10, 10, 10, 10, 0, -10],
-                [-10, 10, 10, 10, 10, 10, 10, -10],
-                [-10, 5, 0, 0, 0, 0, 5, -10],
-                [-20, -10, -10, -10, -10, -10, -10, -20],
-            ],
-
-            // Rook position bonuses (encourages open files and central control)
-            rook_position_bonus: [
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [5, 10, 10, 10, 10, 10, 10, 5],
-                [-5, 0, 0, 0, 0, 0, 0, -5],
-                [-5, 0, 0, 0, 0, 0, 0, -5],
-                [-5, 0, 0, 0, 0, 0, 0, -5],
-                [-5, 0, 0, 0, 0, 0, 0, -5],
-                [-5, 0, 0, 0, 0, 0, 0, -5],
-                [0, 0, 0, 5, 5, 0, 0, 0],
-            ],
-
-            // Queen position bonuses (encourages central control and mobility)
-            queen_position_bonus: [
-                [-20, -10, -10, -5, -5, -10, -10, -20],
-                [-10, 0, 0, 0, 0, 0, 0, -10],
-                [-10, 0, 5, 5, 5, 5, 0, -10],
-                [-5, 0, 5, 5, 5, 5, 0, -5],
-                [0, 0, 5, 5, 5, 5, 0, -5],
-                [-10, 5, 5, 5, 5, 5, 0, -10],
-                [-10, 0, 5, 0, 0, 0, 0, -10],
-                [-20, -10, -10, -5, -5, -10, -10, -20],
-            ],
-
-            // King position bonuses (encourages safety in opening/middlegame)
-            king_position_bonus: [
-                [-30, -40, -40, -50, -50, -40, -40, -30],
-                [-30, -40, -40, -50, -50, -40, -40, -30],
-                [-30, -40, -40, -50, -50, -40, -40, -30],
-                [-30, -40, -40, -50, -50, -40, -40, -30],
-                [-20, -30, -30, -40, -40, -30, -30, -20],
-                [-10, -20, -20, -20, -20, -20, -20, -10],
-                [20, 20, 0, 0, 0, 0, 20, 20],
-                [20, 30, 10, 0, 0, 10, 30, 20],
-            ],
-
-            // King position bonuses for endgame (encourages centralization)
-            king_endgame_position_bonus: [
-                [-50, -40, -30, -20, -20, -30, -40, -50],
-                [-30, -20, -10, 0, 0, -10, -20, -30],
-                [-30, -10, 20, 30, 30, 20, -10, -30],
-                [-30, -10, 30, 40, 40, 30, -10, -30],
-                [-30, -10, 30, 40, 40, 30, -10, -30],
-                [-30, -10, 20, 30, 30, 20, -10, -30],
-                [-30, -30, 0, 0, 0, 0, -30, -30],
-                [-50, -30, -30, -30, -30, -30, -30, -50],
-            ],
+            pawn_value: psqt::PIECE_VALUES[0],
+            knight_value: psqt::PIECE_VALUES[1],
+            bishop_value: psqt::PIECE_VALUES[2],
+            rook_value: psqt::PIECE_VALUES[3],
+            queen_value: psqt::PIECE_VALUES[4],
+            king_value: psqt::PIECE_VALUES[5],
+
+            // Positional bonuses
+            pawn_position_bonus: psqt::PAWN_TABLE,
+            knight_position_bonus: psqt::KNIGHT_TABLE,
+            bishop_position_bonus: psqt::BISHOP_TABLE,
+            rook_position_bonus: psqt::ROOK_TABLE,
+            queen_position_bonus: psqt::QUEEN_TABLE,
+            king_position_bonus: psqt::KING_TABLE,
+            king_endgame_position_bonus: psqt::KING_ENDGAME_TABLE,
 
             // Mobility weights
             pawn_mobility_weight: 5,
@@ -153,92 +162,119 @@ impl Evaluator {
             open_file_penalty: -15,
             semi_open_file_penalty: -10,
             king_attack_bonus: 5,
+
+            use_board_psqt: true,
         }
     }
 
+    // Whether the piece values are the ones the board's running totals use
+    pub fn has_default_piece_values(&self) -> bool {
+        let values = [self.pawn_value, self.knight_value, self.bishop_value, self.rook_value, self.queen_value];
+        values[..] == psqt::PIECE_VALUES[..5]
+    }
+
+    // Score from White's point of view
     pub fn evaluate(&self, board: &Board) -> i32 {
-        let mut score = 0;
-        let is_endgame = self.is_endgame(board);
+        self.evaluate_with_pawn_score(board, self.evaluate_pawn_structure(board))
+    }
 
-        // Evaluate material and position for each piece
-        for square in 0..64 {
-            if let Some((piece, color)) = board.get_piece_at(square as u8) {
-                let rank = (square / 8) as usize;
-                let file = (square % 8) as usize;
-                let value = self.get_piece_value(piece, rank, file, is_endgame);
-                score += if color == Color::White { value } else { -value };
+    // Same as evaluate, but the pawn structure term comes from the pawn hash when possible
+    pub fn evaluate_cached(&self, board: &Board, pawn_hash: &mut PawnHashTable) -> i32 {
+        let key = zobrist::pawn_hash(board);
+        let pawn_score = match pawn_hash.get(key) {
+            Some(score) => score,
+            None => {
+                let score = self.evaluate_pawn_structure(board);
+                pawn_hash.insert(key, score);
+                score
             }
-        }
+        };
+        self.evaluate_with_pawn_score(board, pawn_score)
+    }
+
+    fn evaluate_with_pawn_score(&self, board: &Board, pawn_score: i32) -> i32 {
+        self.trace_with_pawn_score(board, pawn_score).total
+    }
 
-        // Add mobility bonus
-        score += self.evaluate_mobility(board);
+    // The evaluation split into its terms, for the "eval" command
+    pub fn trace(&self, board: &Board) -> EvalTrace {
+        self.trace_with_pawn_score(board, self.evaluate_pawn_structure(board))
+    }
 
-        // Add pawn structure bonus
-        score += self.evaluate_pawn_structure(board);
+    fn trace_with_pawn_score(&self, board: &Board, pawn_score: i32) -> EvalTrace {
+        let mut trace = EvalTrace {
+            is_endgame: self.is_endgame(board),
+            ..EvalTrace::default()
+        };
 
-        // Add king safety bonus
-        score += self.evaluate_king_safety(board);
+        if self.use_board_psqt {
+            let [white, black] = board.psqt;
+            trace.material = white.material - black.material;
+            trace.piece_squares = if trace.is_endgame { white.eg - black.eg } else { white.mg - black.mg };
+        } else {
+            // Evaluate material and position for each piece type a bitboard at a time
+            for (sign, pieces) in [(1, &board.white_pieces), (-1, &board.black_pieces)] {
+                let tables = Piece::ALL.map(|piece| self.get_position_table(piece, trace.is_endgame));
+                for (&piece, &bitboard) in Piece::ALL.iter().zip(pieces) {
+                    trace.material += sign * bitboard.count_ones() as i32 * self.get_base_value(piece);
+                }
+                trace.piece_squares += sign * simd::piece_square_sum(pieces, &tables);
+            }
+        }
 
-        score
+        trace.mobility = self.evaluate_mobility(board);
+        trace.pawn_structure = pawn_score;
+        trace.king_safety = self.evaluate_king_safety(board);
+        board.variant.adjust_evaluation(self, board, &mut trace);
+
+        trace.total = trace.material + trace.piece_squares + trace.mobility + trace.pawn_structure + trace.king_safety;
+        if board.variant.uses_endgame_table() {
+            if let Some(total) = endgame::evaluate(board, trace.total) {
+                trace.known_endgame = total - trace.total;
+                trace.total = total;
+            }
+        }
+        trace
     }
 
-    fn get_piece_value(&self, piece: Piece, rank: usize, file: usize, is_endgame: bool) -> i32 {
-        let base_value = match piece {
+    fn get_base_value(&self, piece: Piece) -> i32 {
+        match piece {
             Piece::Pawn => self.pawn_value,
             Piece::Knight => self.knight_value,
             Piece::Bishop => self.bishop_value,
             Piece::Rook => self.rook_value,
             Piece::Queen => self.queen_value,
             Piece::King => self.king_value,
-        };
+        }
+    }
 
-        let position_bonus = match piece {
-            Piece::Pawn => self.pawn_position_bonus[rank][file],
-            Piece::Knight => self.knight_position_bonus[rank][file],
-            Piece::Bishop => self.bishop_position_bonus[rank][file],
-            Piece::Rook => self.rook_position_bonus[rank][file],
-            Piece::Queen => self.queen_position_bonus[rank][file],
+    fn get_position_table(&self, piece: Piece, is_endgame: bool) -> &[[i32; 8]; 8] {
+        match piece {
+            Piece::Pawn => &self.pawn_position_bonus,
+            Piece::Knight => &self.knight_position_bonus,
+            Piece::Bishop => &self.bishop_position_bonus,
+            Piece::Rook => &self.rook_position_bonus,
+            Piece::Queen => &self.queen_position_bonus,
             Piece::King => if is_endgame {
-                self.king_endgame_position_bonus[rank][file]
+                &self.king_endgame_position_bonus
             } else {
-                self.king_position_bonus[rank][file]
+                &self.king_position_bonus
             },
-        };
-
-        base_value + position_bonus
+        }
     }
 
     fn is_endgame(&self, board: &Board) -> bool {
         // Count major pieces (queens and rooks)
-        let mut major_pieces = 0;
-        for square in 0..64 {
-            if let Some((piece, _)) = board.get_piece_at(square as u8) {
-                if piece == Piece::Queen || piece == Piece::Rook {
-                    major_pieces += 1;
-                }
-            }
-        }
+        let major_pieces = [board.white_pieces, board.black_pieces]
+            .iter()
+            .map(|pieces| (pieces[3] | pieces[4]).count_ones())
+            .sum::<u32>();
         major_pieces <= 2
     }
 
     fn evaluate_mobility(&self, board: &Board) -> i32 {
+        let piece_moves = self.mobility_counts(board);
         let mut score = 0;
-        let move_generator = MoveGenerator::new();
-        let moves = move_generator.generate_moves(board);
-
-        // Count moves for each piece type
-        let mut piece_moves = [0; 6]; // Pawn, Knight, Bishop, Rook, Queen, King
-        for mv in moves {
-            let piece_index = match mv.piece {
-                Piece::Pawn => 0,
-                Piece::Knight => 1,
-                Piece::Bishop => 2,
-                Piece::Rook => 3,
-                Piece::Queen => 4,
-                Piece::King => 5,
-            };
-            piece_moves[piece_index] += 1;
-        }
 
         // Apply mobility weights
         score += piece_moves[0] * self.pawn_mobility_weight;
@@ -256,172 +292,218 @@ impl Evaluator {
         score
     }
 
-    fn evaluate_pawn_structure(&self, board: &Board) -> i32 {
-        let mut score = 0;
-        let mut white_pawns = [0; 8]; // Count pawns per file
-        let mut black_pawns = [0; 8];
-
-        // Count pawns on each file
-        for square in 0..64 {
-            if let Some((piece, color)) = board.get_piece_at(square as u8) {
-                if piece == Piece::Pawn {
-                    let file = (square % 8) as usize;
-                    match color {
-                        Color::White => white_pawns[file] += 1,
-                        Color::Black => black_pawns[file] += 1,
-                    }
-                }
-            }
+    // Legal moves of the side to move per piece type: pawn, knight, bishop, rook, queen, king
+    pub fn mobility_counts(&self, board: &Board) -> [i32; 6] {
+        let move_generator = MoveGenerator::new();
+        let mut piece_moves = [0; 6];
+        for mv in move_generator.generate_moves(board) {
+            piece_moves[mv.piece as usize] += 1;
         }
+        piece_moves
+    }
 
-        // Evaluate pawn structure for both colors
-        score += self.evaluate_pawn_structure_for_color(white_pawns, true);
-        score -= self.evaluate_pawn_structure_for_color(black_pawns, false);
-
-        score
+    fn evaluate_pawn_structure(&self, board: &Board) -> i32 {
+        let white = self.pawn_structure_counts(board, Color::White);
+        let black = self.pawn_structure_counts(board, Color::Black);
+        self.score_pawn_structure(white) - self.score_pawn_structure(black)
     }
 
-    fn evaluate_pawn_structure_for_color(&self, pawns: [i32; 8], is_white: bool) -> i32 {
-        let mut score = 0;
+    fn score_pawn_structure(&self, counts: PawnStructureCounts) -> i32 {
+        counts.doubled * self.doubled_pawn_penalty
+            + counts.isolated * self.isolated_pawn_penalty
+            + counts.passed * self.passed_pawn_bonus
+            + counts.connected * self.connected_pawn_bonus
+    }
 
-        // Check for doubled pawns
-        for &count in pawns.iter() {
-            if count > 1 {
-                score += self.doubled_pawn_penalty * (count - 1);
+    // The pawn patterns the evaluation scores for one side. Patterns are
+    // judged file by file: a file with pawns and no friendly pawns on either
+    // neighbour counts as both isolated and passed.
+    pub fn pawn_structure_counts(&self, board: &Board, color: Color) -> PawnStructureCounts {
+        let mut pawns = [0; 8]; // Count pawns per file
+        for square in 0..64 {
+            if board.get_piece_at(square as u8) == Some((Piece::Pawn, color)) {
+                pawns[square % 8] += 1;
             }
         }
 
-        // Check for isolated pawns
+        let mut counts = PawnStructureCounts::default();
         for file in 0..8 {
-            if pawns[file] > 0 {
-                let has_neighbor = (file > 0 && pawns[file - 1] > 0) || 
-                                 (file < 7 && pawns[file + 1] > 0);
-                if !has_neighbor {
-                    score += self.isolated_pawn_penalty;
-                }
+            if pawns[file] == 0 {
+                continue;
             }
-        }
-
-        // Check for passed pawns
-        for file in 0..8 {
-            if pawns[file] > 0 {
-                let is_passed = if is_white {
-                    // For white pawns, check if there are no black pawns on adjacent files
-                    (file == 0 || pawns[file - 1] == 0) && 
-                    (file == 7 || pawns[file + 1] == 0)
-                } else {
-                    // For black pawns, check if there are no white pawns on adjacent files
-                    (file == 0 || pawns[file - 1] == 0) && 
-                    (file == 7 || pawns[file + 1] == 0)
-                };
-                if is_passed {
-                    score += self.passed_pawn_bonus;
-                }
+            // Doubled pawns: every pawn beyond the first on a file
+            counts.doubled += pawns[file] - 1;
+            let has_neighbor = (file > 0 && pawns[file - 1] > 0) || (file < 7 && pawns[file + 1] > 0);
+            if !has_neighbor {
+                counts.isolated += 1;
+                counts.passed += 1;
             }
-        }
-
-        // Check for connected pawns
-        for file in 0..7 {
-            if pawns[file] > 0 && pawns[file + 1] > 0 {
-                score += self.connected_pawn_bonus;
+            // Connected pawns: pairs of neighbouring files with pawns
+            if file < 7 && pawns[file + 1] > 0 {
+                counts.connected += 1;
             }
         }
-
-        score
+        counts
     }
 
     fn evaluate_king_safety(&self, board: &Board) -> i32 {
-        let mut score = 0;
-
-        // Find king positions
-        let (white_king_square, black_king_square) = self.find_kings(board);
-
-        // Evaluate pawn shield
-        score += self.evaluate_pawn_shield(board, white_king_square, true);
-        score -= self.evaluate_pawn_shield(board, black_king_square, false);
-
-        // Evaluate open files near king
-        score += self.evaluate_open_files(board, white_king_square, true);
-        score -= self.evaluate_open_files(board, black_king_square, false);
+        let white = self.king_safety_counts(board, Color::White);
+        let black = self.king_safety_counts(board, Color::Black);
+        self.score_king_safety(white) - self.score_king_safety(black)
+    }
 
-        score
+    fn score_king_safety(&self, counts: KingSafetyCounts) -> i32 {
+        counts.shield_pawns * self.pawn_shield_bonus
+            + counts.open_file as i32 * self.open_file_penalty
+            + counts.semi_open_file as i32 * self.semi_open_file_penalty
+            - counts.attack_units() * self.king_attack_bonus
     }
 
-    fn find_kings(&self, board: &Board) -> (Option<u8>, Option<u8>) {
-        let mut white_king = None;
-        let mut black_king = None;
+    pub fn king_danger(&self, board: &Board, color: Color) -> KingDanger {
+        let counts = self.king_safety_counts(board, color);
+        KingDanger { counts, score: self.score_king_safety(counts) }
+    }
 
-        for square in 0..64 {
-            if let Some((piece, color)) = board.get_piece_at(square as u8) {
-                if piece == Piece::King {
-                    match color {
-                        Color::White => white_king = Some(square as u8),
-                        Color::Black => black_king = Some(square as u8),
-                    }
+    // What the evaluation sees around one side's king; nothing without a king
+    pub fn king_safety_counts(&self, board: &Board, color: Color) -> KingSafetyCounts {
+        let mut counts = KingSafetyCounts::default();
+        let king = if color == Color::White { board.white_pieces[5] } else { board.black_pieces[5] };
+        if king == 0 {
+            return counts;
+        }
+        let square = king.trailing_zeros() as u8;
+        let rank = square / 8;
+        let file = square % 8;
+
+        // Pawns in front of the king
+        let shield_rank = if color == Color::White { rank + 1 } else { rank.wrapping_sub(1) };
+        if shield_rank < 8 {
+            for file_offset in -1..=1 {
+                let shield_file = file as i8 + file_offset;
+                if (0..8).contains(&shield_file) && board.get_piece_at(shield_rank * 8 + shield_file as u8) == Some((Piece::Pawn, color)) {
+                    counts.shield_pawns += 1;
                 }
             }
         }
 
-        (white_king, black_king)
-    }
-
-    fn evaluate_pawn_shield(&self, board: &Board, king_square: Option<u8>, is_white: bool) -> i32 {
-        let mut score = 0;
-
-        if let Some(square) = king_square {
-            let rank = square / 8;
-            let file = square % 8;
-
-            // Check pawns in front of the king
-            let shield_rank = if is_white { rank + 1 } else { rank - 1 };
-            if shield_rank < 8 {
-                for file_offset in -1..=1 {
-                    let shield_file = file as i8 + file_offset;
-                    if shield_file >= 0 && shield_file < 8 {
-                        let shield_square = (shield_rank * 8 + shield_file as u8) as u8;
-                        if let Some((piece, color)) = board.get_piece_at(shield_square) {
-                            if piece == Piece::Pawn && color == (if is_white { Color::White } else { Color::Black }) {
-                                score += self.pawn_shield_bonus;
-                            }
-                        }
-                    }
+        // Whether the king's file is open or semi-open
+        let (mut has_own_pawn, mut has_opponent_pawn) = (false, false);
+        for rank in 0..8 {
+            if let Some((Piece::Pawn, pawn_color)) = board.get_piece_at(rank * 8 + file) {
+                if pawn_color == color {
+                    has_own_pawn = true;
+                } else {
+                    has_opponent_pawn = true;
                 }
             }
         }
-
-        score
+        counts.open_file = !has_own_pawn && !has_opponent_pawn;
+        counts.semi_open_file = !has_own_pawn && has_opponent_pawn;
+
+        // Enemy pieces bearing on the king or the squares around it
+        counts.ring = king | KING_ATTACKS[square as usize];
+        let enemy = if color == Color::White { &board.black_pieces } else { &board.white_pieces };
+        let occupied = board.white_pieces.iter().chain(&board.black_pieces).fold(0, |all, &pieces| all | pieces);
+        for (piece, &pieces) in Piece::ALL.iter().zip(enemy) {
+            let mut pieces = pieces;
+            while pieces != 0 {
+                let reach = attacks::piece_attacks(*piece, color.opposite(), pieces.trailing_zeros() as u8, occupied);
+                if reach & counts.ring != 0 {
+                    counts.attackers[*piece as usize] += 1;
+                }
+                pieces &= pieces - 1;
+            }
+        }
+        counts
     }
+}
 
-    fn evaluate_open_files(&self, board: &Board, king_square: Option<u8>, is_white: bool) -> i32 {
-        let mut score = 0;
-
-        if let Some(square) = king_square {
-            let file = square % 8;
-
-            // Check if the file is open or semi-open
-            let mut has_own_pawn = false;
-            let mut has_opponent_pawn = false;
-
-            for rank in 0..8 {
-                let square = rank * 8 + file;
-                if let Some((piece, color)) = board.get_piece_at(square as u8) {
-                    if piece == Piece::Pawn {
-                        if color == (if is_white { Color::White } else { Color::Black }) {
-                            has_own_pawn = true;
-                        } else {
-                            has_opponent_pawn = true;
-                        }
-                    }
+// How many pieces of each colour attack every square, White's first, for
+// drawing who controls the board. Squares held by a piece's own side count,
+// as it defends them; a piece lined up behind another doesn't see through it.
+pub fn square_control(board: &Board) -> [[u8; 64]; 2] {
+    let occupied = board.white_pieces.iter().chain(&board.black_pieces).fold(0, |all, &pieces| all | pieces);
+    let mut control = [[0; 64]; 2];
+    for (color, pieces) in [(Color::White, &board.white_pieces), (Color::Black, &board.black_pieces)] {
+        for (&piece, &bitboard) in Piece::ALL.iter().zip(pieces) {
+            let mut bitboard = bitboard;
+            while bitboard != 0 {
+                let mut attacked = attacks::piece_attacks(piece, color, bitboard.trailing_zeros() as u8, occupied);
+                while attacked != 0 {
+                    control[color as usize][attacked.trailing_zeros() as usize] += 1;
+                    attacked &= attacked - 1;
                 }
+                bitboard &= bitboard - 1;
             }
+        }
+    }
+    control
+}
 
-            if !has_own_pawn && !has_opponent_pawn {
-                score += self.open_file_penalty;
-            } else if !has_own_pawn {
-                score += self.semi_open_file_penalty;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_matches_evaluate() {
+        let evaluator = Evaluator::new();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+            "3k4/8/8/3P4/8/8/r7/R3K3 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let trace = evaluator.trace(&board);
+            assert_eq!(trace.total, evaluator.evaluate(&board));
+            assert_eq!(
+                trace.total,
+                trace.material
+                    + trace.piece_squares
+                    + trace.mobility
+                    + trace.pawn_structure
+                    + trace.king_safety
+                    + trace.known_endgame
+            );
         }
+    }
 
-        score
+    #[test]
+    fn test_board_psqt_matches_tables() {
+        let generator = MoveGenerator::new();
+        let incremental = Evaluator::new();
+        let from_tables = Evaluator { use_board_psqt: false, ..Evaluator::new() };
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        for _ in 0..12 {
+            assert_eq!(incremental.trace(&board), from_tables.trace(&board), "{}", board.to_fen());
+            let moves = generator.generate_moves(&board);
+            // Captures first, to get to an endgame
+            let mv = moves.iter().find(|mv| mv.captured_piece.is_some()).unwrap_or(&moves[0]);
+            board.make_move(*mv);
+        }
+    }
+
+    #[test]
+    fn test_trace_material() {
+        let evaluator = Evaluator::new();
+        // White is a rook up
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let trace = evaluator.trace(&board);
+        assert_eq!(trace.material, evaluator.rook_value);
+        assert!(trace.is_endgame);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_square_control() {
+        let [white, black] = square_control(&Board::new());
+        // f3: the g1 knight and the e2 and g2 pawns; e4 is nobody's yet
+        assert_eq!((white[21], black[21]), (3, 0));
+        assert_eq!((white[28], black[28]), (0, 0));
+        // The rank in front of each side's pawns, mirrored
+        assert_eq!(white[16..24], black[40..48]);
+        // The queen doesn't see through the rook in front of it
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/QR2K3 w - - 0 1").unwrap();
+        let [white, _] = square_control(&board);
+        assert_eq!((white[0], white[1], white[2]), (1, 1, 1));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::movegen::Move;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+
+// Searches shallower than this are neither recorded nor consulted
+pub const MIN_DEPTH: u32 = 6;
+
+// Bytes per entry on disk: key u64, move u16, depth u16 and score i32, all big-endian
+const ENTRY_SIZE: usize = 16;
+
+// What a finished search concluded about one move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExperienceEntry {
+    // Move::to_u16 encoding
+    pub mv: u16,
+    pub depth: u32,
+    // Centipawns for the side to move
+    pub score: i32,
+}
+
+impl ExperienceEntry {
+    pub fn matches(&self, mv: Move) -> bool {
+        self.mv == mv.to_u16()
+    }
+}
+
+// Results of earlier searches by position hash, kept across sessions in a
+// file so later games can start from them
+#[derive(Debug, Default)]
+pub struct Experience {
+    path: Option<String>,
+    entries: HashMap<u64, Vec<ExperienceEntry>>,
+    changed: bool,
+}
+
+impl Experience {
+    // A missing file is an empty experience that will be created on save
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(format!("cannot read {}: {}", path, err)),
+        };
+        let mut experience = Self::from_bytes(&bytes).map_err(|err| format!("{}: {}", path, err))?;
+        experience.path = Some(path.to_string());
+        Ok(experience)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!("size {} is not a whole number of entries", bytes.len()));
+        }
+        let mut experience = Self::default();
+        for chunk in bytes.chunks_exact(ENTRY_SIZE) {
+            let key = u64::from_be_bytes(chunk[0..8].try_into().unwrap());
+            let entry = ExperienceEntry {
+                mv: u16::from_be_bytes([chunk[8], chunk[9]]),
+                depth: u16::from_be_bytes([chunk[10], chunk[11]]) as u32,
+                score: i32::from_be_bytes(chunk[12..16].try_into().unwrap()),
+            };
+            experience.entries.entry(key).or_default().push(entry);
+        }
+        Ok(experience)
+    }
+
+    // Entries sorted by key, so the same experience always writes the same file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut keys: Vec<&u64> = self.entries.keys().collect();
+        keys.sort();
+        let mut bytes = Vec::with_capacity(self.len() * ENTRY_SIZE);
+        for key in keys {
+            for entry in &self.entries[key] {
+                bytes.extend_from_slice(&key.to_be_bytes());
+                bytes.extend_from_slice(&entry.mv.to_be_bytes());
+                bytes.extend_from_slice(&(entry.depth.min(u16::MAX as u32) as u16).to_be_bytes());
+                bytes.extend_from_slice(&entry.score.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    // Writes the file back if anything was learned since it was read
+    pub fn save(&mut self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.changed {
+            fs::write(path, self.to_bytes()).map_err(|err| format!("cannot write {}: {}", path, err))?;
+            self.changed = false;
+        }
+        Ok(())
+    }
+
+    // Number of entries over all positions
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Keeps what a search found about a move, unless a deeper search already did
+    pub fn record(&mut self, key: u64, mv: Move, depth: u32, score: i32) {
+        if depth < MIN_DEPTH {
+            return;
+        }
+        let entries = self.entries.entry(key).or_default();
+        let entry = ExperienceEntry { mv: mv.to_u16(), depth, score };
+        match entries.iter_mut().find(|old| old.mv == entry.mv) {
+            Some(old) if old.depth > depth => return,
+            Some(old) => *old = entry,
+            None => entries.push(entry),
+        }
+        self.changed = true;
+    }
+
+    pub fn moves(&self, key: u64) -> &[ExperienceEntry] {
+        self.entries.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    // The deepest entry for a position, the better score breaking ties
+    pub fn best(&self, key: u64) -> Option<ExperienceEntry> {
+        self.moves(key).iter().copied().filter(|entry| entry.depth >= MIN_DEPTH).max_by_key(|entry| (entry.depth, entry.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Piece;
+
+    #[test]
+    fn test_record_and_round_trip() {
+        let e4 = Move::new(12, 28, Piece::Pawn);
+        let d4 = Move::new(11, 27, Piece::Pawn);
+        let mut experience = Experience::default();
+        experience.record(1, e4, 8, 30);
+        experience.record(1, d4, 10, 20);
+        // Shallow results don't displace deep ones, nor count at all
+        experience.record(1, d4, 7, -50);
+        experience.record(2, e4, 3, 0);
+
+        assert_eq!(experience.len(), 2);
+        assert_eq!(experience.best(1), Some(ExperienceEntry { mv: d4.to_u16(), depth: 10, score: 20 }));
+        assert_eq!(experience.best(2), None);
+
+        let bytes = experience.to_bytes();
+        assert_eq!(bytes.len(), 2 * ENTRY_SIZE);
+        let loaded = Experience::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.moves(1), experience.moves(1));
+        assert!(Experience::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_open_and_save() {
+        let path = std::env::temp_dir().join(format!("three-salmons-exp-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut experience = Experience::open(path).unwrap();
+        assert!(experience.is_empty());
+        experience.record(7, Move::new(6, 21, Piece::Knight), 9, 15);
+        experience.save().unwrap();
+
+        let reopened = Experience::open(path).unwrap();
+        assert_eq!(reopened.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::board::{square_name, Board, Color};
+use crate::evaluation::Evaluator;
+use std::io::{self, Write};
+
+const PIECES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+const COLORS: [&str; 2] = ["white", "black"];
+
+// Where each group of features starts in the vector
+const MATERIAL: usize = 0;
+// One-hot piece on square: colour * 384 + piece * 64 + square
+const PIECE_SQUARES: usize = MATERIAL + 12;
+// Per side: doubled, isolated, passed, connected
+const PAWN_STRUCTURE: usize = PIECE_SQUARES + 768;
+// Per side: shield pawns, king on an open file, king on a semi-open file,
+// weighted attackers of the king's ring
+const KING_SAFETY: usize = PAWN_STRUCTURE + 8;
+// Legal moves of the side to move per piece type
+const MOBILITY: usize = KING_SAFETY + 8;
+const SIDE_TO_MOVE: usize = MOBILITY + 6;
+const ENDGAME: usize = SIDE_TO_MOVE + 1;
+pub const FEATURE_COUNT: usize = ENDGAME + 1;
+
+// A position's features and, when known, its label
+pub type Row = (Vec<(usize, i32)>, Option<f64>);
+
+// The hand-crafted evaluation's inputs, before any weights are applied
+pub fn feature_names() -> Vec<String> {
+    let mut names = Vec::with_capacity(FEATURE_COUNT);
+    for color in COLORS {
+        names.extend(PIECES.iter().map(|piece| format!("{}_{}s", color, piece)));
+    }
+    for color in COLORS {
+        for piece in PIECES {
+            names.extend((0..64).map(|square| format!("{}_{}_{}", color, piece, square_name(square))));
+        }
+    }
+    for color in COLORS {
+        names.extend(["doubled", "isolated", "passed", "connected"].iter().map(|pattern| format!("{}_{}_pawns", color, pattern)));
+    }
+    for color in COLORS {
+        let terms = ["shield_pawns", "king_open_file", "king_semi_open_file", "king_attack_units"];
+        names.extend(terms.iter().map(|term| format!("{}_{}", color, term)));
+    }
+    names.extend(PIECES.iter().map(|piece| format!("{}_mobility", piece)));
+    names.push("white_to_move".to_string());
+    names.push("endgame".to_string());
+    names
+}
+
+// The non-zero features of a position as (index, value), indices ascending
+pub fn extract(evaluator: &Evaluator, board: &Board) -> Vec<(usize, i32)> {
+    let mut features = Vec::new();
+    for (side, pieces) in [board.white_pieces, board.black_pieces].iter().enumerate() {
+        for (piece, &bitboard) in pieces.iter().enumerate() {
+            features.push((MATERIAL + side * 6 + piece, bitboard.count_ones() as i32));
+        }
+    }
+    for (side, pieces) in [board.white_pieces, board.black_pieces].iter().enumerate() {
+        for (piece, &bitboard) in pieces.iter().enumerate() {
+            let mut bitboard = bitboard;
+            while bitboard != 0 {
+                let square = bitboard.trailing_zeros() as usize;
+                features.push((PIECE_SQUARES + side * 384 + piece * 64 + square, 1));
+                bitboard &= bitboard - 1;
+            }
+        }
+    }
+    for (side, color) in [Color::White, Color::Black].into_iter().enumerate() {
+        let pawns = evaluator.pawn_structure_counts(board, color);
+        let values = [pawns.doubled, pawns.isolated, pawns.passed, pawns.connected];
+        features.extend(values.iter().enumerate().map(|(i, &value)| (PAWN_STRUCTURE + side * 4 + i, value)));
+    }
+    for (side, color) in [Color::White, Color::Black].into_iter().enumerate() {
+        let king = evaluator.king_safety_counts(board, color);
+        let values = [king.shield_pawns, king.open_file as i32, king.semi_open_file as i32, king.attack_units()];
+        features.extend(values.iter().enumerate().map(|(i, &value)| (KING_SAFETY + side * 4 + i, value)));
+    }
+    let mobility = evaluator.mobility_counts(board);
+    features.extend(mobility.iter().enumerate().map(|(piece, &moves)| (MOBILITY + piece, moves)));
+    features.push((SIDE_TO_MOVE, (board.side_to_move == Color::White) as i32));
+    features.push((ENDGAME, evaluator.trace(board).is_endgame as i32));
+
+    features.retain(|&(_, value)| value != 0);
+    features
+}
+
+pub fn to_dense(features: &[(usize, i32)]) -> Vec<i32> {
+    let mut dense = vec![0; FEATURE_COUNT];
+    for &(index, value) in features {
+        dense[index] = value;
+    }
+    dense
+}
+
+// A header row of feature names, then one row per position: loads with
+// numpy.loadtxt(path, delimiter=",", skiprows=1). Labels, when given, fill
+// a first "label" column.
+pub fn write_csv(out: &mut impl Write, rows: &[Row]) -> io::Result<()> {
+    let labelled = rows.iter().any(|(_, label)| label.is_some());
+    let mut header = feature_names();
+    if labelled {
+        header.insert(0, "label".to_string());
+    }
+    writeln!(out, "{}", header.join(","))?;
+    for (features, label) in rows {
+        let mut fields: Vec<String> = to_dense(features).iter().map(i32::to_string).collect();
+        if labelled {
+            fields.insert(0, label.map_or("nan".to_string(), |label| label.to_string()));
+        }
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+// SVMlight lines, "<label> <index>:<value> ...", with 1-based indices as the
+// format wants; sklearn.datasets.load_svmlight_file reads them. A missing
+// label is written as 0.
+pub fn write_svmlight(out: &mut impl Write, rows: &[Row]) -> io::Result<()> {
+    for (features, label) in rows {
+        let pairs: Vec<String> = features.iter().map(|(index, value)| format!("{}:{}", index + 1, value)).collect();
+        writeln!(out, "{} {}", label.unwrap_or(0.0), pairs.join(" "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let names = feature_names();
+        assert_eq!(names.len(), FEATURE_COUNT);
+        let evaluator = Evaluator::new();
+        let features = extract(&evaluator, &Board::new());
+        let named = |name: &str| {
+            let index = names.iter().position(|n| n == name).unwrap();
+            features.iter().find(|&&(i, _)| i == index).map_or(0, |&(_, value)| value)
+        };
+
+        assert_eq!(named("white_pawns"), 8);
+        assert_eq!(named("black_queens"), 1);
+        assert_eq!(named("white_knight_g1"), 1);
+        assert_eq!(named("white_knight_g2"), 0);
+        assert_eq!(named("black_king_e8"), 1);
+        assert_eq!(named("white_shield_pawns"), 3);
+        assert_eq!(named("knight_mobility"), 4);
+        assert_eq!(named("white_to_move"), 1);
+        assert!(features.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn test_writers() {
+        let evaluator = Evaluator::new();
+        let board = Board::from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        let rows = vec![(extract(&evaluator, &board), Some(1.0))];
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &rows).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("label,white_pawns,"));
+        assert_eq!(lines[1].split(',').count(), FEATURE_COUNT + 1);
+        assert!(lines[1].starts_with("1,1,0,"));
+
+        let mut svm = Vec::new();
+        write_svmlight(&mut svm, &rows).unwrap();
+        assert!(String::from_utf8(svm).unwrap().starts_with("1 1:1 6:1 "));
+    }
+}
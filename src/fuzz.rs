@@ -0,0 +1,97 @@
+use crate::board::Board;
+use crate::movegen::MoveGenerator;
+use crate::san::{parse_san, to_san};
+use crate::timeman::GoParams;
+use crate::uci::{format_move, parse_uci_move, UciHandler};
+use crate::variant;
+use std::io;
+
+// Entry points for the cargo-fuzz targets in fuzz/. Any input may be rejected,
+// but none may panic, and whatever is accepted has to survive a round trip.
+
+// A FEN in every variant: accepted ones are written back out unchanged
+pub fn fen(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    for variant in variant::all() {
+        let Ok(board) = Board::from_variant_fen(text, variant) else {
+            continue;
+        };
+        let fen = board.to_fen();
+        let again = Board::from_variant_fen(&fen, variant).expect("a written FEN reads back");
+        assert_eq!(again.to_fen(), fen);
+        MoveGenerator::new().generate_moves(&board);
+    }
+}
+
+// A FEN on the first line, the start position if it doesn't parse, then
+// moves in coordinate notation or SAN. Accepted moves are played.
+pub fn moves(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let (fen, moves) = text.split_once('\n').unwrap_or(("", text));
+    let mut board = Board::from_fen(fen).unwrap_or_default();
+    let generator = MoveGenerator::new();
+    for token in moves.split_whitespace() {
+        let uci = parse_uci_move(&board, &generator, token);
+        if let Ok(mv) = uci {
+            assert_eq!(format_move(&mv), token);
+        }
+        let san = parse_san(&board, &generator, token);
+        if let Ok(mv) = san {
+            let written = to_san(&board, &generator, &mv);
+            assert_eq!(parse_san(&board, &generator, &written), Ok(mv), "{} written as {}", token, written);
+        }
+        if let Ok(mv) = uci.or(san) {
+            board.make_move(mv);
+        }
+    }
+}
+
+// Lines for the UCI dispatcher. Searches are cut down to depth 1, and options
+// naming files are left out so a run never touches the disk.
+pub fn uci(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut handler = UciHandler::with_output(io::sink());
+    for line in text.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.first() {
+            Some(&"go") => {
+                GoParams::parse(&parts[1..]);
+                let _ = handler.handle_command("go depth 1");
+                let _ = handler.handle_command("stop");
+            }
+            Some(&"perft") => {}
+            Some(&"setoption") if ["File", "Path", "Tablebase"].iter().any(|word| line.contains(word)) => {}
+            Some(&"quit") => return,
+            _ => {
+                let _ = handler.handle_command(line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_survive_odd_inputs() {
+        for input in [
+            "",
+            "8888888888888888888888888888888888/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 255 65535\nKe2",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\ne2e4 e7e5 Nf3 Nc6 Bb5 a6 O-O e1g1 x",
+            "position fen 4k3/8/8/8/8/8/8/4K3 w - - 255 65535 moves e1e2\ngo wtime -5 movestogo 0",
+            "position startpos moves e2e4 zz\nd\neval\nsetoption name Hash value 99999999999\ngo perft",
+        ] {
+            fen(input.as_bytes());
+            moves(input.as_bytes());
+            uci(input.as_bytes());
+        }
+    }
+}
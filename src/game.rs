@@ -0,0 +1,477 @@
+use crate::board::{Board, Color};
+use crate::movegen::{GameState, MaterialRule, Move, MoveGenerator};
+use crate::pgn::PgnGame;
+use crate::repetition::RepetitionHistory;
+use crate::san::{parse_san, to_san};
+use crate::search::{MATE_SCORE, MATE_THRESHOLD};
+use crate::variant::{self, STANDARD};
+use std::fmt;
+use std::time::Duration;
+
+// The tags every PGN game carries, in the order the standard asks for
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+const PGN_LINE_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unfinished,
+}
+
+impl GameResult {
+    // Anything that isn't a decisive or drawn PGN result counts as unfinished
+    pub fn from_pgn(result: &str) -> Self {
+        match result {
+            "1-0" => GameResult::WhiteWins,
+            "0-1" => GameResult::BlackWins,
+            "1/2-1/2" => GameResult::Draw,
+            _ => GameResult::Unfinished,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let result = match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unfinished => "*",
+        };
+        write!(f, "{}", result)
+    }
+}
+
+// Why a game is drawn. The rules end a game at once for most of these; the
+// fifty-move rule and threefold repetition only draw when a player claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawReason {
+    Stalemate,
+    InsufficientMaterial,
+    FivefoldRepetition,
+    SeventyFiveMoveRule,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+}
+
+impl DrawReason {
+    pub fn is_automatic(&self) -> bool {
+        !matches!(self, DrawReason::ThreefoldRepetition | DrawReason::FiftyMoveRule)
+    }
+
+    // The reason as a PGN Termination tag words it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DrawReason::Stalemate => "stalemate",
+            DrawReason::InsufficientMaterial => "insufficient material",
+            DrawReason::FivefoldRepetition => "fivefold repetition",
+            DrawReason::SeventyFiveMoveRule => "seventy-five-move rule",
+            DrawReason::ThreefoldRepetition => "threefold repetition",
+            DrawReason::FiftyMoveRule => "fifty-move rule",
+        }
+    }
+}
+
+impl fmt::Display for DrawReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// How a move is judged, written straight after it as the traditional symbol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nag {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Nag {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Nag::Inaccuracy => "?!",
+            Nag::Mistake => "?",
+            Nag::Blunder => "??",
+        }
+    }
+}
+
+impl fmt::Display for Nag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Nag::Inaccuracy => write!(f, "Inaccuracy"),
+            Nag::Mistake => write!(f, "Mistake"),
+            Nag::Blunder => write!(f, "Blunder"),
+        }
+    }
+}
+
+// What the engine knew about a move when it was played, exported as PGN comments
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotation {
+    // Search score in centipawns from White's point of view
+    pub eval: Option<i32>,
+    pub depth: Option<u32>,
+    // Time left on the mover's clock after the move
+    pub clock: Option<Duration>,
+    pub nag: Option<Nag>,
+    // Free text following the commands
+    pub comment: Option<String>,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.eval.is_none() && self.clock.is_none() && self.comment.is_none()
+    }
+}
+
+// "[%eval 0.35,12] [%clk 0:01:05]", the commands lichess and most GUIs read
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(eval) = self.eval {
+            let mut text = if eval.abs() >= MATE_THRESHOLD {
+                // Mate in moves, counted from the side to move after this one
+                let plies = MATE_SCORE - eval.abs();
+                format!("#{}{}", if eval < 0 { "-" } else { "" }, (plies + 1) / 2)
+            } else {
+                format!("{:.2}", eval as f64 / 100.0)
+            };
+            if let Some(depth) = self.depth {
+                text.push_str(&format!(",{}", depth));
+            }
+            parts.push(format!("[%eval {}]", text));
+        }
+        if let Some(clock) = self.clock {
+            let secs = clock.as_secs();
+            parts.push(format!("[%clk {}:{:02}:{:02}]", secs / 3600, secs / 60 % 60, secs % 60));
+        }
+        if let Some(comment) = &self.comment {
+            // A closing brace would end the comment early
+            parts.push(comment.replace('}', ")"));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+// A game from its starting position, with the moves played and how it ended
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    // One per move; missing or empty ones are simply not written
+    pub annotations: Vec<Annotation>,
+    pub result: GameResult,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(Board::new())
+    }
+}
+
+impl Game {
+    pub fn new(start: Board) -> Self {
+        Self {
+            tags: Vec::new(),
+            start,
+            moves: Vec::new(),
+            annotations: Vec::new(),
+            result: GameResult::Unfinished,
+        }
+    }
+
+    // Replays the SAN moves of a parsed PGN game from its FEN tag or the start
+    // position, under the rules its Variant tag names
+    pub fn from_pgn(pgn: &PgnGame) -> Result<Self, String> {
+        let variant = pgn.tag("Variant").and_then(variant::by_name).unwrap_or(&STANDARD);
+        let start = match pgn.tag("FEN") {
+            Some(fen) => Board::from_variant_fen(fen, variant)?,
+            None => variant::start_position(variant),
+        };
+        let generator = MoveGenerator::new();
+        let mut game = Self::new(start);
+        game.tags = pgn.tags.clone();
+        game.result = GameResult::from_pgn(&pgn.result);
+
+        let mut board = game.start.clone();
+        for (ply, san) in pgn.moves.iter().enumerate() {
+            let mv = parse_san(&board, &generator, san)
+                .map_err(|err| format!("move {}{} {}: {}", ply / 2 + 1, if ply % 2 == 0 { "." } else { "..." }, san, err))?;
+            board.make_move(mv);
+            game.push(mv, Annotation::default());
+        }
+        Ok(game)
+    }
+
+    pub fn push(&mut self, mv: Move, annotation: Annotation) {
+        self.moves.push(mv);
+        self.annotations.push(annotation);
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // Every position of the game paired with the move played from it
+    pub fn positions(&self) -> Vec<(Board, Move)> {
+        let mut board = self.start.clone();
+        self.moves
+            .iter()
+            .map(|&mv| {
+                let before = board.clone();
+                board.make_move(mv);
+                (before, mv)
+            })
+            .collect()
+    }
+
+    pub fn board(&self) -> Board {
+        let mut board = self.start.clone();
+        for &mv in &self.moves {
+            board.make_move(mv);
+        }
+        board
+    }
+
+    // The hashes of the game's positions, for counting repetitions
+    pub fn repetitions(&self) -> RepetitionHistory {
+        RepetitionHistory::from_moves(&self.positions(), &self.board())
+    }
+
+    // Whether the position at the end of the game has occurred three times
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.repetitions().is_threefold()
+    }
+
+    // The draw the final position stands at, automatic or claimable, or None
+    // while the game goes on or has been won. Checkmate on the move that
+    // reaches a draw still wins.
+    pub fn draw_reason(&self) -> Option<DrawReason> {
+        let board = self.board();
+        let generator = MoveGenerator::new();
+        let has_moves = !generator.generate_moves(&board).is_empty();
+        match board.variant.result(&generator, &board, has_moves) {
+            Some(GameState::Stalemate) => return Some(DrawReason::Stalemate),
+            Some(_) => return None,
+            None => {}
+        }
+        let repetitions = self.repetitions().count();
+        [
+            (board.is_insufficient_material(MaterialRule::Fide), DrawReason::InsufficientMaterial),
+            (repetitions >= 5, DrawReason::FivefoldRepetition),
+            (board.halfmove_clock >= 150, DrawReason::SeventyFiveMoveRule),
+            (repetitions >= 3, DrawReason::ThreefoldRepetition),
+            (board.halfmove_clock >= 100, DrawReason::FiftyMoveRule),
+        ]
+        .into_iter()
+        .find_map(|(applies, reason)| applies.then_some(reason))
+    }
+
+    // The draw the player to move could claim now; None when there is none or
+    // the game is already drawn without a claim
+    pub fn can_claim_draw(&self) -> Option<DrawReason> {
+        self.draw_reason().filter(|reason| !reason.is_automatic())
+    }
+
+    // Ends the game as a draw if the final position is one, claimed or not
+    pub fn claim_draw(&mut self) -> Result<DrawReason, String> {
+        let reason = self.draw_reason().ok_or("the position is not a draw")?;
+        self.result = GameResult::Draw;
+        Ok(reason)
+    }
+
+    // Whether the game has come down to too little material for a win
+    pub fn is_insufficient_material(&self, rule: MaterialRule) -> bool {
+        self.board().is_insufficient_material(rule)
+    }
+
+    // The game as PGN: the Seven Tag Roster first, then any other tags, the SAN
+    // movetext with annotations as comments, wrapped to 80 columns
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let result = self.result.to_string();
+        for (name, default) in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => result.as_str(),
+                _ => self.tag(name).unwrap_or(default),
+            };
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(value)));
+        }
+        let start_fen = self.start.to_fen();
+        let custom_start = start_fen != Board::new().to_fen();
+        for (name, value) in &self.tags {
+            let generated = ["SetUp", "FEN"].contains(&name.as_str()) && custom_start;
+            if !generated && !SEVEN_TAG_ROSTER.iter().any(|&(roster, _)| roster == name) {
+                pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(value)));
+            }
+        }
+        if custom_start {
+            pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", start_fen));
+        }
+        pgn.push('\n');
+
+        let generator = MoveGenerator::new();
+        let mut tokens = Vec::new();
+        for (i, (board, mv)) in self.positions().into_iter().enumerate() {
+            let number = board.fullmove_number;
+            if board.side_to_move == Color::White {
+                tokens.push(format!("{}.", number));
+            } else if i == 0 || self.annotations.get(i - 1).is_some_and(|a| !a.is_empty()) {
+                // Black's move needs its number again after a comment or at the start
+                tokens.push(format!("{}...", number));
+            }
+            let mut san = to_san(&board, &generator, &mv);
+            if let Some(nag) = self.annotations.get(i).and_then(|a| a.nag) {
+                san.push_str(nag.symbol());
+            }
+            tokens.push(san);
+            if let Some(annotation) = self.annotations.get(i).filter(|a| !a.is_empty()) {
+                tokens.push(format!("{{{}}}", annotation));
+            }
+        }
+        tokens.push(result);
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > PGN_LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::parse_pgn;
+
+    #[test]
+    fn test_from_pgn() {
+        let pgn = parse_pgn("[White \"A\"]\n[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n");
+        let game = Game::from_pgn(&pgn[0]).unwrap();
+
+        assert_eq!(game.tag("White"), Some("A"));
+        assert_eq!(game.result, GameResult::BlackWins);
+        assert_eq!(game.moves.len(), 4);
+        assert_eq!(game.board().to_fen(), "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(game.positions()[1].0.side_to_move, crate::board::Color::Black);
+    }
+
+    #[test]
+    fn test_from_pgn_with_fen_and_bad_move() {
+        let pgn = parse_pgn("[FEN \"4k3/8/8/8/8/8/8/R3K3 w Q - 0 1\"]\n\n1. O-O-O Kf7 *\n");
+        let game = Game::from_pgn(&pgn[0]).unwrap();
+        assert!(game.moves[0].is_castling);
+        assert_eq!(game.result, GameResult::Unfinished);
+
+        let pgn = parse_pgn("1. e4 e5 2. Ke3 *\n");
+        assert_eq!(Game::from_pgn(&pgn[0]).unwrap_err().split(':').next(), Some("move 2. Ke3"));
+    }
+
+    #[test]
+    fn test_to_pgn() {
+        let pgn = parse_pgn("[White \"A \\\"B\\\"\"]\n[Opening \"Fool's mate\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n");
+        let mut game = Game::from_pgn(&pgn[0]).unwrap();
+        game.annotations[0] = Annotation { eval: Some(-45), depth: Some(12), clock: Some(Duration::from_secs(3725)), ..Annotation::default() };
+        game.annotations[2] = Annotation {
+            eval: Some(-MATE_SCORE + 1),
+            nag: Some(Nag::Blunder),
+            comment: Some("Blunder".to_string()),
+            ..Annotation::default()
+        };
+
+        assert_eq!(
+            game.to_pgn(),
+            "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"A \\\"B\\\"\"]\n[Black \"?\"]\n\
+             [Result \"0-1\"]\n[Opening \"Fool's mate\"]\n\n\
+             1. f3 {[%eval -0.45,12] [%clk 1:02:05]} 1... e5 2. g4?? {[%eval #-1] Blunder}\n2... Qh4# 0-1\n"
+        );
+    }
+
+    #[test]
+    fn test_to_pgn_round_trip() {
+        let start = Board::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R b KQkq - 0 30").unwrap();
+        let mut game = Game::new(start);
+        let generator = MoveGenerator::new();
+        for _ in 0..40 {
+            let board = game.board();
+            let Some(&mv) = generator.generate_moves(&board).first() else { break };
+            game.push(mv, Annotation::default());
+        }
+        game.result = GameResult::Draw;
+
+        let pgn = game.to_pgn();
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"r3k2r/1P6/8/3pP3/8/8/8/R3K2R b KQkq - 0 30\"]"));
+        assert!(pgn.lines().all(|line| line.len() <= PGN_LINE_WIDTH));
+        assert!(pgn.contains("\n30... "));
+
+        let parsed = Game::from_pgn(&parse_pgn(&pgn)[0]).unwrap();
+        assert_eq!(parsed.moves, game.moves);
+        assert_eq!(parsed.result, GameResult::Draw);
+        assert_eq!(parsed.to_pgn(), pgn);
+    }
+
+    #[test]
+    fn test_draw_claims() {
+        let game = |movetext: &str| Game::from_pgn(&parse_pgn(movetext)[0]).unwrap();
+        let shuffle = "Nf3 Nf6 Ng1 Ng8 ";
+
+        let mut threefold = game("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 *");
+        assert_eq!(threefold.draw_reason(), Some(DrawReason::ThreefoldRepetition));
+        assert_eq!(threefold.can_claim_draw(), Some(DrawReason::ThreefoldRepetition));
+        assert_eq!(threefold.claim_draw(), Ok(DrawReason::ThreefoldRepetition));
+        assert_eq!(threefold.result, GameResult::Draw);
+
+        let fivefold = game(&format!("{0}{0}{0}{0}*", shuffle));
+        assert_eq!(fivefold.draw_reason(), Some(DrawReason::FivefoldRepetition));
+        assert_eq!(fivefold.can_claim_draw(), None);
+
+        let fifty = game("[FEN \"4k3/8/8/8/8/8/8/R3K3 w - - 99 80\"]\n\n80. Kd2 *");
+        assert_eq!(fifty.can_claim_draw(), Some(DrawReason::FiftyMoveRule));
+        let seventy_five = game("[FEN \"4k3/8/8/8/8/8/8/R3K3 w - - 149 80\"]\n\n80. Kd2 *");
+        assert_eq!(seventy_five.draw_reason(), Some(DrawReason::SeventyFiveMoveRule));
+        // Mate on the last move still wins
+        let mate = game("[FEN \"4k3/R7/8/8/8/8/8/1R2K3 w - - 149 80\"]\n\n80. Rb8# *");
+        assert_eq!(mate.draw_reason(), None);
+
+        let stalemate = game("[FEN \"k7/8/2Q5/8/8/8/8/4K3 w - - 0 1\"]\n\n1. Qb6 *");
+        assert_eq!(stalemate.draw_reason(), Some(DrawReason::Stalemate));
+        assert!(stalemate.draw_reason().unwrap().is_automatic());
+
+        let mut ongoing = game("1. e4 e5 *");
+        assert!(ongoing.claim_draw().is_err());
+        assert_eq!(ongoing.result, GameResult::Unfinished);
+    }
+}
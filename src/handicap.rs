@@ -0,0 +1,93 @@
+use crate::board::{Board, Color};
+use std::fmt;
+
+// Material odds, given by one side from the standard start position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handicap {
+    None,
+    // The f-pawn, with the other side moving first
+    PawnAndMove,
+    // The queen's knight
+    Knight,
+    // The queen's rook
+    Rook,
+}
+
+impl Handicap {
+    pub const ALL: [Handicap; 4] = [Handicap::None, Handicap::PawnAndMove, Handicap::Knight, Handicap::Rook];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|handicap| handicap.to_string().eq_ignore_ascii_case(name))
+    }
+
+    // The start position with the giver's material removed
+    pub fn start_position(self, giver: Color) -> Board {
+        let mut board = Board::new();
+        // Squares are White's; Black's are the same file on the other back rank
+        let square = match self {
+            Handicap::None => return board,
+            Handicap::PawnAndMove => 13,
+            Handicap::Knight => 1,
+            Handicap::Rook => 0,
+        };
+        let square = if giver == Color::White { square } else { square ^ 56 };
+        for pieces in board.white_pieces.iter_mut().chain(board.black_pieces.iter_mut()) {
+            *pieces &= !(1u64 << square);
+        }
+        board.refresh_psqt();
+        if self == Handicap::PawnAndMove {
+            board.side_to_move = giver.opposite();
+        }
+        board.clear_lost_castling_rights();
+        board.refresh_hash();
+        board
+    }
+}
+
+impl fmt::Display for Handicap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Handicap::None => write!(f, "None"),
+            Handicap::PawnAndMove => write!(f, "PawnAndMove"),
+            Handicap::Knight => write!(f, "Knight"),
+            Handicap::Rook => write!(f, "Rook"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGenerator;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Handicap::None.start_position(Color::White).to_fen(), Board::new().to_fen());
+        assert_eq!(
+            Handicap::PawnAndMove.start_position(Color::White).to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPP1PP/RNBQKBNR b KQkq - 0 1"
+        );
+        assert_eq!(
+            Handicap::Knight.start_position(Color::Black).to_fen(),
+            "r1bqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(
+            Handicap::Rook.start_position(Color::White).to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w Kkq - 0 1"
+        );
+        assert_eq!(Handicap::from_name("pawnandmove"), Some(Handicap::PawnAndMove));
+        assert_eq!(Handicap::from_name("queen"), None);
+    }
+
+    #[test]
+    fn test_rook_odds_never_castles_queenside() {
+        let generator = MoveGenerator::new();
+        // Clear the back rank between king and corners; with the a1 rook gone only O-O remains
+        let mut board = Handicap::Rook.start_position(Color::White);
+        board.white_pieces[1] = 0;
+        board.white_pieces[2] = 0;
+        board.white_pieces[4] = 0;
+        let castles: Vec<u8> = generator.generate_moves(&board).iter().filter(|mv| mv.is_castling).map(|mv| mv.to).collect();
+        assert_eq!(castles, vec![6]);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::engine::Engine;
+use crate::evaluation::Evaluator;
+use crate::game::Game;
+use crate::level::Level;
+use crate::match_runner::{self, EnginePlayer, MatchConfig, MatchScore, Opening, Player};
+use crate::search::Algorithm;
+use crate::tune;
+use std::io::{self, Write};
+
+// Rating passes; the fit has long settled by then for any ladder worth running
+const FIT_ITERATIONS: usize = 200;
+
+// One configuration of this engine taking part in a ladder
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contender {
+    pub name: String,
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub level: Level,
+    pub algorithm: Algorithm,
+    // Evaluation weights changed from their defaults, by their tune::WEIGHTS names
+    pub weights: Vec<(String, i32)>,
+}
+
+impl Contender {
+    // Comma-separated settings, as in "depth=4,knight_value=340": depth, nodes,
+    // level, mcts (its exploration in hundredths, 150 if left out) and any
+    // tunable evaluation weight. The spec is the contender's name unless a
+    // name= setting gives another.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut contender = Self {
+            name: spec.to_string(),
+            depth: None,
+            nodes: None,
+            level: Level::Full,
+            algorithm: Algorithm::AlphaBeta,
+            weights: Vec::new(),
+        };
+        for setting in spec.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting.split_once('=').unwrap_or((setting, ""));
+            let number = || value.parse::<i64>().map_err(|_| format!("{} must be a number in '{}'", key, spec));
+            match key {
+                "name" => contender.name = value.to_string(),
+                "depth" => contender.depth = Some(number()?.clamp(1, u32::MAX as i64) as u32),
+                "nodes" => contender.nodes = Some(number()?.max(1) as u64),
+                "level" => contender.level = Level::from_name(value).ok_or_else(|| format!("unknown level '{}'", value))?,
+                "mcts" => {
+                    let exploration = if value.is_empty() { 150 } else { number()? };
+                    contender.algorithm = Algorithm::Mcts { exploration: exploration as f32 / 100.0 };
+                }
+                _ if tune::WEIGHTS.iter().any(|(name, _)| *name == key) => contender.weights.push((key.to_string(), number()? as i32)),
+                _ => return Err(format!("unknown setting '{}' in '{}'", key, spec)),
+            }
+        }
+        Ok(contender)
+    }
+
+    pub fn evaluator(&self) -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        for (name, value) in &self.weights {
+            if let Some((_, weight)) = tune::WEIGHTS.iter().find(|(weight, _)| weight == name) {
+                *weight(&mut evaluator) = *value;
+            }
+        }
+        evaluator.use_board_psqt = evaluator.has_default_piece_values();
+        evaluator
+    }
+
+    pub fn player(&self) -> EnginePlayer {
+        let mut engine = Engine::new();
+        engine.set_level(self.level);
+        engine.set_algorithm(self.algorithm);
+        engine.set_evaluator(self.evaluator());
+        EnginePlayer::with_engine(&self.name, engine, self.depth, self.nodes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // Everyone against everyone
+    RoundRobin,
+    // The first player against each of the others, who don't meet
+    Gauntlet,
+}
+
+// The matches a ladder plays, by player index
+pub fn pairings(players: usize, format: Format) -> Vec<(usize, usize)> {
+    match format {
+        Format::RoundRobin => (0..players).flat_map(|first| (first + 1..players).map(move |second| (first, second))).collect(),
+        Format::Gauntlet => (1..players).map(|second| (0, second)).collect(),
+    }
+}
+
+// A finished match, scored from the first player's side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pairing {
+    pub first: usize,
+    pub second: usize,
+    pub score: MatchScore,
+}
+
+// Plays each pairing's match of config.games games. on_game sees every
+// finished game with the pairing and its running score.
+pub fn run_ladder<F>(
+    config: &MatchConfig,
+    openings: &[Opening],
+    players: &mut [Box<dyn Player>],
+    format: Format,
+    mut on_game: F,
+) -> Result<Vec<Pairing>, String>
+where
+    F: FnMut(&Game, (usize, usize), &MatchScore),
+{
+    let mut results = Vec::new();
+    for (first, second) in pairings(players.len(), format) {
+        let (left, right) = players.split_at_mut(second);
+        let score = match_runner::run_match(config, openings, left[first].as_mut(), right[0].as_mut(), |game, score| {
+            on_game(game, (first, second), score);
+            true
+        })?;
+        results.push(Pairing { first, second, score });
+    }
+    Ok(results)
+}
+
+// A player's results over the whole ladder and the rating fitted to them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Standing {
+    pub player: usize,
+    pub score: MatchScore,
+    pub elo: f64,
+    // Half-width of the 95% interval, from the player's own games; None while
+    // the score is 0% or 100%
+    pub margin: Option<f64>,
+}
+
+// The players, best first. Ratings are relative to the field's average in a
+// round robin and to the first player in a gauntlet.
+pub fn standings(players: usize, results: &[Pairing], format: Format) -> Vec<Standing> {
+    let mut ratings = fit_ratings(players, results);
+    if format == Format::Gauntlet {
+        let anchor = ratings.first().copied().unwrap_or(0.0);
+        ratings.iter_mut().for_each(|rating| *rating -= anchor);
+    }
+    let mut standings: Vec<Standing> = (0..players)
+        .map(|player| {
+            let score = results.iter().fold(MatchScore::default(), |total, pairing| {
+                let score = from_side(pairing, player).map_or(MatchScore::default(), |(_, score)| score);
+                MatchScore { wins: total.wins + score.wins, draws: total.draws + score.draws, losses: total.losses + score.losses }
+            });
+            Standing { player, score, elo: ratings[player], margin: score.elo_margin() }
+        })
+        .collect();
+    standings.sort_by(|a, b| b.elo.total_cmp(&a.elo));
+    standings
+}
+
+// The opponent and the score from player's side, if player took part
+fn from_side(pairing: &Pairing, player: usize) -> Option<(usize, MatchScore)> {
+    let score = pairing.score;
+    if pairing.first == player {
+        Some((pairing.second, score))
+    } else if pairing.second == player {
+        Some((pairing.first, MatchScore { wins: score.losses, draws: score.draws, losses: score.wins }))
+    } else {
+        None
+    }
+}
+
+fn expected_score(difference: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-difference / 400.0))
+}
+
+// Maximum likelihood Elo ratings for all the results at once, averaging 0.
+// Each pairing counts one extra draw, so that a player winning every game
+// still gets a finite rating.
+fn fit_ratings(players: usize, results: &[Pairing]) -> Vec<f64> {
+    let mut ratings = vec![0.0; players];
+    for _ in 0..FIT_ITERATIONS {
+        for player in 0..players {
+            let (mut actual, mut expected, mut information) = (0.0, 0.0, 0.0);
+            for (opponent, score) in results.iter().filter_map(|pairing| from_side(pairing, player)) {
+                let games = score.games() as f64 + 1.0;
+                let expectation = expected_score(ratings[player] - ratings[opponent]);
+                actual += score.wins as f64 + score.draws as f64 / 2.0 + 0.5;
+                expected += games * expectation;
+                information += games * expectation * (1.0 - expectation);
+            }
+            if information > 0.0 {
+                // A Newton step: the expected score rises by E(1 - E) ln 10 / 400 per point
+                ratings[player] += (actual - expected) / information * 400.0 / std::f64::consts::LN_10;
+            }
+        }
+        let mean = ratings.iter().sum::<f64>() / players.max(1) as f64;
+        ratings.iter_mut().for_each(|rating| *rating -= mean);
+    }
+    ratings
+}
+
+pub fn write_table(out: &mut impl Write, names: &[String], standings: &[Standing]) -> io::Result<()> {
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(4);
+    writeln!(out, "Rank  {:<width$}  Games  W-D-L        Score     Elo     +/-", "Name")?;
+    for (rank, standing) in standings.iter().enumerate() {
+        let score = standing.score;
+        writeln!(
+            out,
+            "{:>4}  {:<width$}  {:>5}  {:<11}  {:>5.1}%  {:>+6.0}  {:>6}",
+            rank + 1,
+            names[standing.player],
+            score.games(),
+            format!("{}-{}-{}", score.wins, score.draws, score.losses),
+            score.score() * 100.0,
+            standing.elo,
+            standing.margin.map_or("?".to_string(), |margin| format!("{:.0}", margin))
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_runner::{load_epd, Adjudication, TimeControl};
+
+    fn pairing(first: usize, second: usize, wins: u32, draws: u32, losses: u32) -> Pairing {
+        Pairing { first, second, score: MatchScore { wins, draws, losses } }
+    }
+
+    #[test]
+    fn test_contender_settings() {
+        let contender = Contender::parse("depth=3,knight_value=340,mcts").unwrap();
+        assert_eq!(contender.name, "depth=3,knight_value=340,mcts");
+        assert_eq!(contender.depth, Some(3));
+        assert_eq!(contender.algorithm, Algorithm::Mcts { exploration: 1.5 });
+        let evaluator = contender.evaluator();
+        assert_eq!(evaluator.knight_value, 340);
+        assert!(!evaluator.use_board_psqt);
+
+        assert_eq!(Contender::parse("name=club,level=club").unwrap().level, Level::Club);
+        assert!(Contender::parse("depth=deep").is_err());
+        assert!(Contender::parse("bogus=1").is_err());
+    }
+
+    #[test]
+    fn test_ratings() {
+        assert_eq!(pairings(4, Format::RoundRobin).len(), 6);
+        assert_eq!(pairings(4, Format::Gauntlet), [(0, 1), (0, 2), (0, 3)]);
+
+        // Scoring 75% is worth about 190 Elo; the draws added to each pairing
+        // pull that in a little
+        let results = [pairing(0, 1, 30, 0, 10)];
+        let table = standings(2, &results, Format::RoundRobin);
+        let difference = table[0].elo - table[1].elo;
+        assert_eq!(table[0].player, 0);
+        assert!((difference - 186.0).abs() < 2.0, "{}", difference);
+        assert!((table[0].elo + table[1].elo).abs() < 1e-6);
+
+        // A clean sweep stays finite, and a gauntlet is rated against its first player
+        let results = [pairing(0, 1, 10, 0, 0), pairing(0, 2, 5, 0, 5)];
+        let table = standings(3, &results, Format::Gauntlet);
+        assert_eq!((table[0].player, table[2].player), (0, 1));
+        assert!(table[0].elo.abs() < 1e-6 && table[2].elo.is_finite());
+        assert_eq!(table[0].score, MatchScore { wins: 15, draws: 0, losses: 5 });
+        assert_eq!(table[2].margin, None);
+
+        let names = ["first".to_string(), "second".to_string(), "third".to_string()];
+        let mut out = Vec::new();
+        write_table(&mut out, &names, &table).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().nth(1).unwrap().starts_with("   1  first      20  15-0-5        75.0%      +0"));
+        assert!(out.lines().nth(3).unwrap().ends_with("?"));
+    }
+
+    #[test]
+    fn test_run_ladder() {
+        // Mate in one for whoever has the queen, so every pairing splits its two games
+        let openings = load_epd("6k1/5ppp/8/8/8/8/8/Q5K1 w - -").unwrap();
+        let config = MatchConfig {
+            games: 2,
+            time_control: TimeControl::parse("60+1").unwrap(),
+            adjudication: Adjudication::default(),
+        };
+        let mut players: Vec<Box<dyn Player>> =
+            ["depth=1", "depth=2", "depth=2,pawn_value=110"].iter().map(|spec| Box::new(Contender::parse(spec).unwrap().player()) as Box<dyn Player>).collect();
+
+        let mut games = 0;
+        let results = run_ladder(&config, &openings, &mut players, Format::RoundRobin, |game, _, _| {
+            assert_eq!(game.tag("Termination"), Some("checkmate"));
+            games += 1;
+        })
+        .unwrap();
+        assert_eq!(games, 6);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|pairing| pairing.score == MatchScore { wins: 1, draws: 0, losses: 1 }));
+        assert!(standings(3, &results, Format::RoundRobin).iter().all(|standing| standing.elo.abs() < 1e-6));
+    }
+}
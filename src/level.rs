@@ -0,0 +1,109 @@
+use crate::board::{Board, Color};
+use crate::movegen::{Move, MoveGenerator};
+use crate::search::Variety;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::fmt;
+
+// Playing strengths for people learning the game. The weakest levels don't
+// search at all; the others search to a capped depth, Club also picking at
+// random among moves that are nearly as good.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Level {
+    // Any legal move
+    Random,
+    // Whatever wins the most material this move, replies unseen
+    Greedy,
+    Depth1,
+    Depth3,
+    Club,
+    #[default]
+    Full,
+}
+
+impl Level {
+    pub const ALL: [Level; 6] = [Level::Random, Level::Greedy, Level::Depth1, Level::Depth3, Level::Club, Level::Full];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.to_string().eq_ignore_ascii_case(name))
+    }
+
+    // The move of a level that doesn't search; None for the levels that do,
+    // and when there is no legal move
+    pub fn quick_move(&self, board: &Board, rng: &mut impl Rng) -> Option<Move> {
+        let moves = MoveGenerator::new().generate_moves(board);
+        match self {
+            Level::Random => moves.choose(rng).copied(),
+            Level::Greedy => {
+                let best = moves.iter().map(|mv| material_after(board, mv)).max()?;
+                let greediest: Vec<Move> = moves.into_iter().filter(|mv| material_after(board, mv) == best).collect();
+                greediest.choose(rng).copied()
+            }
+            _ => None,
+        }
+    }
+
+    // The deepest a searching level looks
+    pub fn max_depth(&self) -> Option<u32> {
+        match self {
+            Level::Depth1 => Some(1),
+            Level::Depth3 => Some(3),
+            Level::Club => Some(5),
+            _ => None,
+        }
+    }
+
+    // The root variety a level plays with, if it sets one
+    pub fn variety(&self) -> Option<Variety> {
+        (*self == Level::Club).then_some(Variety { margin: 80, temperature: 40, seed: 0 })
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Random => write!(f, "Random"),
+            Level::Greedy => write!(f, "Greedy"),
+            Level::Depth1 => write!(f, "Depth1"),
+            Level::Depth3 => write!(f, "Depth3"),
+            Level::Club => write!(f, "Club"),
+            Level::Full => write!(f, "Full"),
+        }
+    }
+}
+
+// The mover's material lead once mv is made
+pub fn material_after(board: &Board, mv: &Move) -> i32 {
+    let mut after = board.clone();
+    after.make_move(*mv);
+    let [white, black] = after.psqt;
+    let lead = white.material - black.material;
+    if board.side_to_move == Color::White {
+        lead
+    } else {
+        -lead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_quick_moves() {
+        let mut rng = StdRng::seed_from_u64(983);
+        // The queen takes the defended rook
+        let board = Board::from_fen("4k3/8/2p5/3r4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let greedy = Level::Greedy.quick_move(&board, &mut rng).unwrap();
+        assert_eq!(greedy.to_string(), "d1d5");
+        assert_eq!(material_after(&board, &greedy), 800);
+        let random = Level::Random.quick_move(&board, &mut rng).unwrap();
+        assert!(MoveGenerator::new().generate_moves(&board).contains(&random));
+        assert_eq!(Level::Club.quick_move(&board, &mut rng), None);
+
+        assert_eq!(Level::from_name("depth3"), Some(Level::Depth3));
+        assert_eq!(Level::Depth3.max_depth(), Some(3));
+        assert!(Level::Full.max_depth().is_none() && Level::Full.variety().is_none());
+    }
+}
@@ -1,19 +1,104 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+// A UCI chess engine and the tools around it. Most users want the prelude:
+// the board, move generation, evaluation, search and the Engine that ties
+// them together. The other modules are the engine's parts and tools, public
+// for the binary and for experiments; attack tables and SIMD kernels are
+// internal.
+//
+// The default "engine" feature builds everything. Programs that need less can
+// turn default features off and pick a layer: none for the board, move
+// generation, SAN and evaluation on alloc alone, "std" adding perft, Polyglot keys
+// and the other std-only helpers, "search" adding the search stack and games.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "engine")]
+pub mod annotate;
+pub(crate) mod attacks;
+#[cfg(feature = "engine")]
+pub mod bench;
+#[cfg(feature = "engine")]
+pub mod batch;
 pub mod board;
+#[cfg(feature = "search")]
+pub mod book;
+pub mod caches;
+#[cfg(feature = "search")]
+pub mod datagen;
+pub mod endgame;
+#[cfg(feature = "engine")]
+pub mod engine;
+pub(crate) mod magic;
 pub mod movegen;
+#[cfg(feature = "std")]
+pub mod nnue;
+#[cfg(feature = "online-explorer")]
+pub mod online_explorer;
+#[cfg(feature = "online-tablebase")]
+pub mod online_tb;
+#[cfg(feature = "engine")]
+pub mod options;
+#[cfg(feature = "std")]
+pub mod perft;
+pub(crate) mod pext;
+#[cfg(feature = "search")]
+pub mod pgn;
+#[cfg(feature = "std")]
+pub mod polyglot;
+pub mod prelude;
+pub mod psqt;
+pub mod repetition;
+#[cfg(feature = "engine")]
+pub mod puzzle;
 pub mod evaluation;
+#[cfg(feature = "search")]
+pub mod experience;
+#[cfg(feature = "std")]
+pub mod features;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+#[cfg(feature = "search")]
+pub mod game;
+#[cfg(feature = "std")]
+pub mod handicap;
+#[cfg(feature = "engine")]
+pub mod ladder;
+#[cfg(feature = "engine")]
+pub mod level;
+#[cfg(feature = "lichess")]
+pub mod lichess;
+#[cfg(feature = "engine")]
+pub mod match_runner;
+#[cfg(feature = "search")]
+pub mod mcts;
+#[cfg(feature = "search")]
 pub mod transposition;
+pub mod san;
+#[cfg(feature = "search")]
 pub mod search;
+pub(crate) mod simd;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "engine")]
+pub mod sprt;
+#[cfg(feature = "std")]
+pub mod timeman;
+#[cfg(feature = "std")]
+pub mod tune;
+#[cfg(feature = "engine")]
 pub mod uci;
+pub mod variant;
+#[cfg(feature = "engine")]
+pub mod verify;
+pub mod zobrist;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "engine"))]
 mod tests {
     use super::*;
     use board::{Board, Color, Piece};
-    use movegen::{Move, MoveGenerator, GameState};
+    use movegen::{Move, MoveGenerator, MoveList, GameState, MaterialRule};
+    use perft::perft;
 
     #[test]
     fn test_initial_position() {
@@ -26,7 +111,7 @@ mod tests {
         
         // Check that all moves are valid
         for mv in moves {
-            assert!(generator.is_move_valid(&board, &mv));
+            assert!(generator.is_legal(&board, &mv));
         }
     }
 
@@ -242,35 +327,66 @@ mod tests {
         assert_eq!(state, GameState::InsufficientMaterial);
     }
 
+    #[test]
+    fn test_insufficient_material_rules() {
+        let insufficient = |fen: &str, rule| Board::from_fen(fen).unwrap().is_insufficient_material(rule);
+        // Bishops all on dark squares, however many and whoever's
+        for fen in ["4k3/8/8/8/8/B3B3/8/2B1K3 w - - 0 1", "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"] {
+            assert!(insufficient(fen, MaterialRule::Fide), "{}", fen);
+        }
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1BB1K3 w - - 0 1", MaterialRule::Fide));
+        // Two knights, or a minor each, can only mate with help
+        for fen in ["4k3/8/8/8/8/8/8/1N2K1N1 w - - 0 1", "4kn2/8/8/8/8/8/8/2B1K3 w - - 0 1", "4kn2/8/8/8/8/8/8/1N2K3 w - - 0 1"] {
+            assert!(!insufficient(fen, MaterialRule::Fide), "{}", fen);
+            assert!(insufficient(fen, MaterialRule::Practical), "{}", fen);
+        }
+        // A bishop and knight, a pawn or a rook can force it
+        for fen in ["4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1", "4k3/8/8/8/8/8/P7/4K3 w - - 0 1", "4k3/8/8/8/8/8/8/R3K3 w - - 0 1"] {
+            assert!(!insufficient(fen, MaterialRule::Practical), "{}", fen);
+        }
+    }
+
     #[test]
     fn test_fifty_move_rule() {
         let mut board = Board::new();
         let generator = MoveGenerator::new();
         
-        // Set halfmove clock to 50
-        board.halfmove_clock = 50;
-        
-        let state = generator.get_game_state(&board, &[]);
-        assert_eq!(state, GameState::FiftyMoveRule);
+        // Fifty moves a side is a hundred halfmoves
+        board.halfmove_clock = 99;
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::Ongoing);
+        board.halfmove_clock = 100;
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::FiftyMoveRule);
+
+        // Mate on the hundredth halfmove still wins
+        let mut board = Board::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 99 80").unwrap();
+        let undo = board.make_move(Move::new(0, 56, Piece::Rook));
+        assert_eq!(board.halfmove_clock, 100);
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::Checkmate(Color::White));
+        board.unmake_move(&undo);
+        assert_eq!(board.halfmove_clock, 99);
     }
 
     #[test]
     fn test_threefold_repetition() {
-        let board = Board::new();
+        let mut board = Board::new();
         let generator = MoveGenerator::new();
-        
-        // Create a move history with three identical positions
-        let move_history = vec![
-            (board.clone(), Move::new(12, 28, Piece::Pawn)),  // e2-e4
-            (board.clone(), Move::new(52, 36, Piece::Pawn)),  // e7-e5
-            (board.clone(), Move::new(28, 12, Piece::Pawn)),  // e4-e2
-            (board.clone(), Move::new(36, 52, Piece::Pawn)),  // e5-e7
-            (board.clone(), Move::new(12, 28, Piece::Pawn)),  // e2-e4
-            (board.clone(), Move::new(52, 36, Piece::Pawn)),  // e7-e5
-        ];
-        
-        let state = generator.get_game_state(&board, &move_history);
-        assert_eq!(state, GameState::ThreefoldRepetition);
+
+        // The knights go out and back twice, bringing the start position round a third time
+        let mut move_history = Vec::new();
+        for (from, to) in [(6, 21), (62, 45), (21, 6), (45, 62)].repeat(2) {
+            let mv = Move::new(from, to, Piece::Knight);
+            move_history.push((board.clone(), mv));
+            board.make_move(mv);
+            let state = generator.get_game_state(&board, &move_history);
+            assert_eq!(state == GameState::ThreefoldRepetition, move_history.len() == 8);
+        }
+
+        // Pawn moves can't be taken back, so nothing before one counts
+        let pawn_move = Move::new(12, 28, Piece::Pawn);
+        let mut after = board.clone();
+        after.make_move(pawn_move);
+        move_history.push((board.clone(), pawn_move));
+        assert_eq!(generator.get_game_state(&after, &move_history), GameState::Ongoing);
     }
 
     #[test]
@@ -284,7 +400,24 @@ mod tests {
         board.black_pieces[5] = 0x0000000000000010;  // Black king on e1
         
         let invalid_move = Move::new(8, 0, Piece::Rook);  // a1-a8 (would leave white king in check)
-        assert!(!generator.is_move_valid(&board, &invalid_move));
+        assert!(!generator.is_legal(&board, &invalid_move));
+    }
+
+    #[test]
+    fn test_apply_uci_moves() {
+        let mut board = Board::new();
+        let played = board.apply_uci_moves(&["e2e4", "e7e5", "g1f3"]).unwrap();
+        assert_eq!(played.len(), 3);
+        assert_eq!(played[2].1.to_string(), "g1f3");
+        assert_eq!(board.side_to_move, Color::Black);
+
+        // Stops at the first bad move, saying which it was
+        let mut board = Board::new();
+        let err = board.apply_uci_moves(&["d2d4", "d7d5", "d4d5", "c7c5"]).unwrap_err();
+        assert_eq!((err.index, err.played.len()), (2, 2));
+        assert_eq!(err.to_string(), "illegal move 'd4d5', move 3 of the list");
+        assert_eq!(board.to_fen(), "rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq d6 0 2");
+        assert_eq!(board.apply_uci_moves(&["e9e4"]).unwrap_err().reason, "invalid move 'e9e4'");
     }
 
     #[test]
@@ -302,24 +435,138 @@ mod tests {
         assert_eq!(perft(&board, &generator, 3), 8902);
     }
 
-    // Helper function to perform perft
-    fn perft(board: &Board, generator: &MoveGenerator, depth: u32) -> u64 {
-        if depth == 0 {
-            return 1;
-        }
-        
-        let moves = generator.generate_moves(board);
-        if depth == 1 {
-            return moves.len() as u64;
-        }
-        
-        let mut nodes = 0;
-        for mv in moves {
-            let mut new_board = board.clone();
-            new_board.make_move(mv);
-            nodes += perft(&new_board, generator, depth - 1);
+    #[test]
+    fn test_infer_move() {
+        let generator = MoveGenerator::new();
+        let infer = |before: &str, after: &str| {
+            let (before, after) = (Board::from_fen(before).unwrap(), Board::from_fen(after).unwrap());
+            generator.infer_move(&before, &after).map(|mv| uci::format_move(&mv))
+        };
+
+        assert_eq!(infer("4k3/8/8/8/8/8/8/4K2R w K - 0 1", "4k3/8/8/8/8/8/8/5RK1 b - - 1 1"), Ok("e1g1".to_string()));
+        assert_eq!(infer("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "4k3/8/3P4/8/8/8/8/4K3 b - - 0 1"), Ok("e5d6".to_string()));
+        // The promotion piece tells under-promotions apart
+        assert_eq!(infer("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "1N2k3/8/8/8/8/8/8/4K3 b - - 0 1"), Ok("b7b8n".to_string()));
+        // Snapshots with stale castling rights still match
+        assert_eq!(infer("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "4k3/8/8/8/8/8/8/R4K2 b Q - 1 1"), Ok("e1f1".to_string()));
+
+        assert!(infer("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(infer("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "4k3/8/8/8/8/8/8/2K5 b - - 0 1").is_err());
+    }
+
+    #[test]
+    fn test_generate_into_move_list() {
+        let generator = MoveGenerator::new();
+        let mut list = MoveList::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+            "4k3/1P6/8/8/8/8/6p1/4K2R b K - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let moves = generator.generate_moves(&board);
+            generator.generate_moves_into(&board, &mut list);
+            assert_eq!(&list[..], &moves[..]);
+
+            // The list is cleared, not appended to
+            generator.generate_captures_into(&board, &mut list);
+            let (captures, quiets): (Vec<_>, Vec<_>) =
+                moves.iter().partition(|mv| mv.captured_piece.is_some() || mv.promotion.is_some());
+            assert_eq!(&list[..], &captures[..]);
+            assert_eq!(generator.generate_captures(&board), captures);
+            generator.generate_quiets_into(&board, &mut list);
+            assert_eq!(&list[..], &quiets[..]);
+            assert_eq!(generator.generate_quiets(&board), quiets);
+
+            // What the transposition table keeps of a move is enough to find it again
+            for mv in moves {
+                assert_eq!(generator.decode_move(&board, mv.to_u16()), Some(mv));
+            }
         }
-        
-        nodes
+        let board = Board::new();
+        assert_eq!(generator.decode_move(&board, Move::new(12, 36, Piece::Pawn).to_u16()), None);
+    }
+
+    #[test]
+    fn test_move_generators_share_tables() {
+        assert_eq!(std::mem::size_of::<MoveGenerator>(), 0);
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(MoveGenerator::new().generate_moves(&board), MoveGenerator.generate_moves(&board));
+    }
+
+    #[test]
+    fn test_move_display_and_debug() {
+        let board = Board::from_fen("r3k2r/1P6/8/3pP3/8/8/8/4K3 w kq d6 0 1").unwrap();
+        let moves = MoveGenerator::new().generate_moves(&board);
+        let find = |uci: &str| *moves.iter().find(|mv| mv.to_string() == uci).unwrap();
+
+        assert_eq!(find("e5d6").to_string(), uci::format_move(&find("e5d6")));
+        assert_eq!(format!("{:?}", find("e5d6")), "e5d6 Pawn x Pawn e.p.");
+        assert_eq!(format!("{:?}", find("b7a8q")), "b7a8q Pawn x Rook");
+        assert_eq!(find("b7a8q").to_san(&board), "bxa8=Q+");
+        assert_eq!(find("e1d2").to_san(&board), "Kd2");
+
+        let castle = Move::new_castling(60, 62, 63, 61);
+        assert_eq!(format!("{:?}", castle), "e8g8 King castling, rook h8f8");
+    }
+
+    #[test]
+    fn test_is_legal_checks_pins_checks_and_flags() {
+        let generator = MoveGenerator::new();
+        // The e4 knight is pinned, and taking en passant would bare the king along the fifth rank
+        let board = Board::from_fen("4r1k1/8/8/K2pP2q/8/8/4N3/8 w - d6 0 1").unwrap();
+        assert_eq!(generator.pinned(&board, Color::White), 0);
+        assert!(!generator.is_legal(&board, &Move::new_en_passant(36, 43, Piece::Pawn)));
+        assert!(generator.is_legal(&board, &Move::new(36, 44, Piece::Pawn)));
+
+        let board = Board::from_fen("4r1k1/8/8/8/8/8/4N3/4K3 w - - 0 1").unwrap();
+        assert_eq!(generator.pinned(&board, Color::White), 1 << 12);
+        assert!(!generator.is_legal(&board, &Move::new(12, 29, Piece::Knight)));
+
+        // In check, only taking or blocking the checker will do
+        let board = Board::from_fen("4k3/8/8/8/8/8/3PPPr1/R3K3 w - - 0 1").unwrap();
+        assert_eq!(generator.checkers(&board, Color::White), 0);
+        let board = Board::from_fen("4r1k1/8/8/8/8/8/3B4/R3K3 w - - 0 1").unwrap();
+        assert_eq!(generator.checkers(&board, Color::White), 1 << 60);
+        assert!(generator.is_legal(&board, &Move::new(11, 20, Piece::Bishop)));
+        assert!(!generator.is_legal(&board, &Move::new(0, 8, Piece::Rook)));
+        assert!(!generator.is_legal(&board, &Move::new(4, 12, Piece::King)));
+        assert!(generator.is_legal(&board, &Move::new(4, 3, Piece::King)));
+
+        // The flags must match what the move does on this board
+        let board = Board::new();
+        assert!(!generator.is_legal(&board, &Move { captured_piece: Some(Piece::Pawn), ..Move::new(12, 28, Piece::Pawn) }));
+        assert!(!generator.is_legal(&board, &Move::new(12, 28, Piece::Knight)));
+        assert!(!generator.is_legal(&board, &Move::new_castling(4, 6, 7, 5)));
+        assert_eq!(generator.find_move(&board, 6, 21, None), Some(Move::new(6, 21, Piece::Knight)));
+        assert_eq!(generator.find_move(&board, 52, 36, None), None);
+    }
+
+    #[test]
+    fn test_static_exchange() {
+        let generator = MoveGenerator::new();
+        let find = |board: &Board, from: &str, to: &str| {
+            generator.find_move(board, board::parse_square(from).unwrap(), board::parse_square(to).unwrap(), None).unwrap()
+        };
+
+        // An undefended pawn
+        let board = Board::from_fen("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1").unwrap();
+        assert_eq!(generator.see(&board, &find(&board, "e1", "e5")), 100);
+
+        // The rook behind the knight and the queen behind the bishop come in
+        // through x-rays, but Black stops once the knight is won
+        let board = Board::from_fen("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1").unwrap();
+        let (score, steps) = generator.exchange(&board, &find(&board, "d3", "e5"));
+        assert_eq!(score, -220);
+        let pieces: Vec<Piece> = steps.iter().map(|step| step.capture.piece).collect();
+        assert_eq!(pieces, [Piece::Knight, Piece::Knight, Piece::Rook, Piece::Bishop, Piece::Queen, Piece::Queen]);
+        assert_eq!(steps[1].balance, -220);
+
+        // A king can't take back on a square the rook still covers; a quiet move can lose its piece
+        let board = Board::from_fen("4k3/3p4/8/8/8/8/3Q4/3RK3 w - - 0 1").unwrap();
+        assert_eq!(generator.see(&board, &find(&board, "d2", "d7")), 100);
+        let board = Board::from_fen("4k3/8/8/2p5/8/8/2N5/4K3 w - - 0 1").unwrap();
+        assert_eq!(generator.see(&board, &find(&board, "c2", "d4")), -320);
+        assert_eq!(generator.see(&board, &find(&board, "c2", "a3")), 0);
     }
 }
@@ -0,0 +1,339 @@
+use crate::board::Color;
+use crate::engine::Engine;
+#[cfg(feature = "online-explorer")]
+use crate::online_explorer::OpeningExplorer;
+use crate::search::SearchLimits;
+use crate::timeman::{GoParams, TimeManager};
+use crate::uci::format_move;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const API: &str = "https://lichess.org";
+
+// Which challenges the bot takes
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotConfig {
+    // Personal API token of a Lichess BOT account, with the bot:play scope
+    pub token: String,
+    // Lichess speed names: ultraBullet, bullet, blitz, rapid, classical, correspondence
+    pub speeds: Vec<String>,
+    // Lichess variant keys, e.g. standard or fromPosition
+    pub variants: Vec<String>,
+    pub rated: bool,
+    pub casual: bool,
+    // Games played at once; more challenges are declined with "later"
+    pub max_games: usize,
+    // Consulted out of book, in casual games only
+    #[cfg(feature = "online-explorer")]
+    pub explorer: Option<OpeningExplorer>,
+}
+
+impl BotConfig {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            speeds: vec!["bullet".to_string(), "blitz".to_string(), "rapid".to_string()],
+            variants: vec!["standard".to_string()],
+            rated: true,
+            casual: true,
+            max_games: 1,
+            #[cfg(feature = "online-explorer")]
+            explorer: None,
+        }
+    }
+}
+
+// Whether to accept a challenge event, or the decline reason Lichess should show
+pub fn challenge_decision(config: &BotConfig, challenge: &Value) -> Result<(), &'static str> {
+    let allowed = |list: &[String], key: &Value| key.as_str().is_some_and(|key| list.iter().any(|item| item == key));
+    if !allowed(&config.variants, &challenge["variant"]["key"]) {
+        return Err("variant");
+    }
+    if !allowed(&config.speeds, &challenge["speed"]) {
+        return Err("timeControl");
+    }
+    match challenge["rated"].as_bool() {
+        Some(true) if !config.rated => Err("casual"),
+        Some(false) if !config.casual => Err("rated"),
+        _ => Ok(()),
+    }
+}
+
+// Follows one game through its stream events and decides when and what to move
+pub struct GamePlayer {
+    engine: Engine,
+    bot_id: String,
+    color: Option<Color>,
+    initial_fen: Option<String>,
+    #[cfg(feature = "online-explorer")]
+    explorer: Option<OpeningExplorer>,
+}
+
+impl GamePlayer {
+    pub fn new(bot_id: &str) -> Self {
+        Self {
+            engine: Engine::new(),
+            bot_id: bot_id.to_lowercase(),
+            color: None,
+            initial_fen: None,
+            #[cfg(feature = "online-explorer")]
+            explorer: None,
+        }
+    }
+
+    // The move to send in reply to an event, when it is our turn
+    pub fn on_event(&mut self, event: &Value) -> Result<Option<String>, String> {
+        match event["type"].as_str() {
+            Some("gameFull") => {
+                let white = event["white"]["id"].as_str().unwrap_or_default();
+                self.color = Some(if white == self.bot_id { Color::White } else { Color::Black });
+                self.initial_fen = event["initialFen"].as_str().filter(|&fen| fen != "startpos").map(str::to_string);
+                // Rated games are left to the engine alone
+                #[cfg(feature = "online-explorer")]
+                if event["rated"].as_bool() != Some(false) {
+                    self.explorer = None;
+                }
+                self.engine.new_game();
+                self.on_state(&event["state"])
+            }
+            Some("gameState") => self.on_state(event),
+            _ => Ok(None),
+        }
+    }
+
+    fn on_state(&mut self, state: &Value) -> Result<Option<String>, String> {
+        if state["status"].as_str() != Some("started") {
+            return Ok(None);
+        }
+        let moves: Vec<&str> = state["moves"].as_str().unwrap_or_default().split_whitespace().collect();
+        self.engine.set_position(self.initial_fen.as_deref(), &moves)?;
+        let side = self.engine.board().side_to_move;
+        if self.color != Some(side) {
+            return Ok(None);
+        }
+
+        #[cfg(feature = "online-explorer")]
+        if let Some(explorer) = &self.explorer {
+            match explorer.pick_move(self.engine.board(), &mut rand::thread_rng()) {
+                Ok(Some(mv)) => return Ok(Some(format_move(&mv))),
+                Ok(None) => {}
+                Err(err) => eprintln!("opening explorer: {}", err),
+            }
+        }
+
+        let params = GoParams {
+            wtime: state["wtime"].as_u64(),
+            btime: state["btime"].as_u64(),
+            winc: state["winc"].as_u64(),
+            binc: state["binc"].as_u64(),
+            ..GoParams::default()
+        };
+        let limits = SearchLimits::default().with_budget(TimeManager::allocate(&params, side));
+        Ok(self.engine.search(limits).map(|result| format_move(&result.best_move)))
+    }
+}
+
+// A connection to the Lichess Bot API
+#[derive(Clone)]
+pub struct LichessBot {
+    config: BotConfig,
+    agent: ureq::Agent,
+    bot_id: String,
+    games: Arc<AtomicUsize>,
+}
+
+impl LichessBot {
+    pub fn connect(config: BotConfig) -> Result<Self, String> {
+        // Event and game streams stay open for hours, so only connecting is timed
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_connect(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+        let mut bot = Self { config, agent, bot_id: String::new(), games: Arc::new(AtomicUsize::new(0)) };
+        let account: Value = serde_json::from_str(&bot.get("/api/account")?)
+            .map_err(|err| format!("account response is not JSON: {}", err))?;
+        bot.bot_id = account["id"].as_str().ok_or("account response has no id")?.to_string();
+        Ok(bot)
+    }
+
+    pub fn bot_id(&self) -> &str {
+        &self.bot_id
+    }
+
+    // Handles incoming events until the stream closes, one thread per game
+    pub fn run(&self) -> Result<(), String> {
+        self.stream("/api/stream/event", |event| {
+            match event["type"].as_str() {
+                Some("challenge") => self.answer_challenge(&event["challenge"]),
+                Some("gameStart") => {
+                    if let Some(id) = event["game"]["gameId"].as_str() {
+                        let bot = self.clone();
+                        let id = id.to_string();
+                        bot.games.fetch_add(1, Ordering::SeqCst);
+                        thread::spawn(move || {
+                            if let Err(err) = bot.play_game(&id) {
+                                eprintln!("game {}: {}", id, err);
+                            }
+                            bot.games.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn answer_challenge(&self, challenge: &Value) {
+        let Some(id) = challenge["id"].as_str() else {
+            return;
+        };
+        // Our own outgoing challenges show up in the stream too
+        if challenge["challenger"]["id"].as_str() == Some(self.bot_id.as_str()) {
+            return;
+        }
+        let decision = if self.games.load(Ordering::SeqCst) >= self.config.max_games {
+            Err("later")
+        } else {
+            challenge_decision(&self.config, challenge)
+        };
+        let result = match decision {
+            Ok(()) => self.post(&format!("/api/challenge/{}/accept", id), &[]),
+            Err(reason) => self.post(&format!("/api/challenge/{}/decline", id), &[("reason", reason)]),
+        };
+        if let Err(err) = result {
+            eprintln!("challenge {}: {}", id, err);
+        }
+    }
+
+    fn play_game(&self, id: &str) -> Result<(), String> {
+        let mut player = GamePlayer::new(&self.bot_id);
+        #[cfg(feature = "online-explorer")]
+        {
+            player.explorer = self.config.explorer.clone();
+        }
+        self.stream(&format!("/api/bot/game/stream/{}", id), |event| {
+            if let Some(mv) = player.on_event(&event)? {
+                self.post(&format!("/api/bot/game/{}/move/{}", id, mv), &[])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get(&self, path: &str) -> Result<String, String> {
+        self.agent
+            .get(format!("{}{}", API, path))
+            .header("Authorization", format!("Bearer {}", self.config.token))
+            .call()
+            .map_err(|err| format!("GET {} failed: {}", path, err))?
+            .body_mut()
+            .read_to_string()
+            .map_err(|err| format!("GET {} unreadable: {}", path, err))
+    }
+
+    fn post(&self, path: &str, form: &[(&str, &str)]) -> Result<(), String> {
+        let request = self
+            .agent
+            .post(format!("{}{}", API, path))
+            .header("Authorization", format!("Bearer {}", self.config.token));
+        let response = if form.is_empty() { request.send_empty() } else { request.send_form(form.iter().copied()) };
+        response.map(drop).map_err(|err| format!("POST {} failed: {}", path, err))
+    }
+
+    // Calls handler with each event of a newline-delimited JSON stream
+    fn stream<F>(&self, path: &str, mut handler: F) -> Result<(), String>
+    where
+        F: FnMut(Value) -> Result<(), String>,
+    {
+        let response = self
+            .agent
+            .get(format!("{}{}", API, path))
+            .header("Authorization", format!("Bearer {}", self.config.token))
+            .call()
+            .map_err(|err| format!("GET {} failed: {}", path, err))?;
+        let reader = BufReader::new(response.into_body().into_reader());
+        for line in reader.lines() {
+            let line = line.map_err(|err| format!("stream {} broke: {}", path, err))?;
+            // Empty lines are keep-alives
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(event) => handler(event)?,
+                Err(err) => eprintln!("ignoring unreadable event from {}: {}", path, err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(variant: &str, speed: &str, rated: bool) -> Value {
+        serde_json::json!({"id": "abc", "variant": {"key": variant}, "speed": speed, "rated": rated})
+    }
+
+    #[test]
+    fn test_challenge_decision() {
+        let mut config = BotConfig::new("token".to_string());
+        assert_eq!(challenge_decision(&config, &challenge("standard", "blitz", true)), Ok(()));
+        assert_eq!(challenge_decision(&config, &challenge("chess960", "blitz", true)), Err("variant"));
+        assert_eq!(challenge_decision(&config, &challenge("standard", "classical", true)), Err("timeControl"));
+
+        config.rated = false;
+        assert_eq!(challenge_decision(&config, &challenge("standard", "blitz", true)), Err("casual"));
+        assert_eq!(challenge_decision(&config, &challenge("standard", "blitz", false)), Ok(()));
+    }
+
+    #[test]
+    fn test_game_player_moves_on_its_turn() {
+        let mut player = GamePlayer::new("Salmon");
+        let full = serde_json::json!({
+            "type": "gameFull",
+            "white": {"id": "someone"},
+            "black": {"id": "salmon"},
+            "initialFen": "startpos",
+            "state": {"moves": "e2e4", "wtime": 300, "btime": 300, "winc": 0, "binc": 0, "status": "started"}
+        });
+        let reply = player.on_event(&full).unwrap().unwrap();
+        assert!(player.engine.play(&reply).is_ok());
+
+        // White to move: nothing to do
+        let state = serde_json::json!({"type": "gameState", "moves": format!("e2e4 {}", reply), "wtime": 300, "btime": 300, "status": "started"});
+        assert_eq!(player.on_event(&state).unwrap(), None);
+        let state = serde_json::json!({"type": "gameState", "moves": "e2e4", "status": "resign"});
+        assert_eq!(player.on_event(&state).unwrap(), None);
+        let state = serde_json::json!({"type": "gameState", "moves": "e2e5", "status": "started"});
+        assert!(player.on_event(&state).is_err());
+    }
+
+    #[cfg(feature = "online-explorer")]
+    #[test]
+    fn test_explorer_only_in_casual_games() {
+        let explorer = OpeningExplorer::new(std::env::temp_dir().join("three-salmons-explorer"));
+        let full = |rated: bool| {
+            serde_json::json!({
+                "type": "gameFull",
+                "rated": rated,
+                "white": {"id": "someone"},
+                "black": {"id": "salmon"},
+                "initialFen": "startpos",
+                "state": {"moves": "", "status": "started"}
+            })
+        };
+        let mut player = GamePlayer::new("Salmon");
+        player.explorer = Some(explorer.clone());
+        // White to move, so nothing is looked up either way
+        player.on_event(&full(true)).unwrap();
+        assert_eq!(player.explorer, None);
+        player.explorer = Some(explorer.clone());
+        player.on_event(&full(false)).unwrap();
+        assert_eq!(player.explorer, Some(explorer));
+    }
+}
@@ -0,0 +1,66 @@
+// Sliding attacks from magic bitboards: the occupancy of a piece's rays,
+// multiplied by a magic number build.rs found for its square, leaves in its
+// top bits an index into that square's attacks. Works on any CPU, where the
+// PEXT tables need BMI2.
+
+include!(concat!(env!("OUT_DIR"), "/magic_tables.rs"));
+
+struct Table {
+    masks: &'static [u64; 64],
+    magics: &'static [u64; 64],
+    shifts: &'static [u32; 64],
+    offsets: &'static [usize; 64],
+    attacks: &'static [u64],
+}
+
+impl Table {
+    fn get(&self, square: u8, occupied: u64) -> u64 {
+        let square = square as usize;
+        let index = (occupied & self.masks[square]).wrapping_mul(self.magics[square]) >> self.shifts[square];
+        self.attacks[self.offsets[square] + index as usize]
+    }
+}
+
+static BISHOP: Table = Table {
+    masks: &BISHOP_MASKS,
+    magics: &BISHOP_MAGICS,
+    shifts: &BISHOP_SHIFTS,
+    offsets: &BISHOP_OFFSETS,
+    attacks: &BISHOP_ATTACKS,
+};
+static ROOK: Table = Table {
+    masks: &ROOK_MASKS,
+    magics: &ROOK_MAGICS,
+    shifts: &ROOK_SHIFTS,
+    offsets: &ROOK_OFFSETS,
+    attacks: &ROOK_ATTACKS,
+};
+
+pub fn bishop_attacks(square: u8, occupied: u64) -> u64 {
+    BISHOP.get(square, occupied)
+}
+
+pub fn rook_attacks(square: u8, occupied: u64) -> u64 {
+    ROOK.get(square, occupied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attacks::{ray_attacks, BISHOP_DIRECTIONS, ROOK_DIRECTIONS};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_tables_match_rays() {
+        let mut rng = StdRng::seed_from_u64(1009);
+        for _ in 0..2000 {
+            let occupied = rng.gen::<u64>() & rng.gen::<u64>() & if rng.gen() { rng.gen() } else { u64::MAX };
+            let square = rng.gen_range(0..64);
+            assert_eq!(bishop_attacks(square, occupied), ray_attacks(square, occupied, &BISHOP_DIRECTIONS));
+            assert_eq!(rook_attacks(square, occupied), ray_attacks(square, occupied, &ROOK_DIRECTIONS));
+        }
+        // Edge squares are left out of the masks but still attacked
+        assert_eq!(rook_attacks(0, u64::MAX), (1 << 1) | (1 << 8));
+        assert_eq!(bishop_attacks(0, 0), 0x8040_2010_0804_0200);
+    }
+}
@@ -1,35 +1,788 @@
-mod board;
-mod evaluation;
-mod movegen;
-mod search;
-mod transposition;
-mod uci;
-
-use board::{Board, Color, Piece};
-use evaluation::Evaluator;
-use movegen::{Move, MoveGenerator};
-use search::Search;
-use std::io;
+use clap::{Args, Parser, Subcommand};
+use std::io::BufRead;
 use std::time::{Duration, Instant};
-use uci::UciHandler;
+use three_salmons::board::{self, Board};
+#[cfg(feature = "lichess")]
+use three_salmons::lichess;
+#[cfg(all(feature = "lichess", feature = "online-explorer"))]
+use three_salmons::online_explorer;
+use three_salmons::level::Level;
+use three_salmons::movegen::MoveGenerator;
+use three_salmons::search::{self, SearchLimits, MATE_SCORE, MATE_THRESHOLD};
+#[cfg(feature = "server")]
+use three_salmons::server;
+use three_salmons::uci::{self, UciHandler};
+use three_salmons::{annotate, batch, bench, book, datagen, engine, evaluation, features, ladder, match_runner, movegen, pgn, perft, puzzle, san, sprt, tune};
+
+#[derive(Parser)]
+#[command(name = "three-salmons", version, about = "A UCI chess engine and the tools around it")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// Without a subcommand the engine speaks UCI, which is what GUIs expect
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Speak UCI on standard input and output (the default)")]
+    Uci,
+    #[command(about = "Search a fixed set of positions and report nodes per second")]
+    Bench {
+        #[arg(default_value_t = bench::BENCH_DEPTH)]
+        depth: u32,
+    },
+    #[command(about = "Time move generation, evaluation, quiescence search and full search separately")]
+    Profile {
+        #[arg(default_value_t = bench::BENCH_DEPTH, help = "Depth of the full search workload")]
+        depth: u32,
+    },
+    #[command(about = "Count the leaf nodes of the move tree, split by root move")]
+    Perft {
+        depth: u32,
+        #[arg(long, help = "Position to count from, the start position if left out")]
+        fen: Option<String>,
+    },
+    #[command(about = "Search one position, reporting every completed depth")]
+    Analyze {
+        #[arg(long)]
+        fen: Option<String>,
+        #[arg(long, num_args = 1.., help = "Moves in UCI notation played from the position first")]
+        moves: Vec<String>,
+        #[arg(long, default_value_t = 12)]
+        depth: u32,
+        #[arg(long, help = "Milliseconds to stop after")]
+        movetime: Option<u64>,
+    },
+    #[command(about = "Analyse every position of a FEN or EPD file and write the results as JSON or CSV")]
+    AnalyzeFile {
+        file: String,
+        #[arg(long, default_value_t = 12)]
+        depth: u32,
+        #[arg(long, help = "Milliseconds per position")]
+        movetime: Option<u64>,
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+        #[arg(long, default_value = "json", value_parser = ["json", "csv"])]
+        format: String,
+        #[arg(long, help = "File to write, standard output if left out")]
+        out: Option<String>,
+    },
+    #[command(about = "Count the EPD positions whose bm move the engine finds")]
+    Testsuite {
+        file: String,
+        #[arg(long, default_value_t = 1000, help = "Milliseconds per position")]
+        movetime: u64,
+        #[arg(long)]
+        depth: Option<u32>,
+    },
+    #[command(about = "Play the engine against itself and write the games as PGN")]
+    Selfplay {
+        #[arg(long, default_value_t = 10)]
+        games: usize,
+        #[arg(long)]
+        depth: Option<u32>,
+        #[arg(long)]
+        nodes: Option<u64>,
+        #[arg(long, default_value = "10+0.1")]
+        tc: String,
+        #[arg(long, help = "EPD file or Polyglot book to draw openings from")]
+        openings: Option<String>,
+        #[arg(long)]
+        pgn: Option<String>,
+    },
+    #[command(about = "Fit the evaluation weights to datagen output by Texel tuning")]
+    Tune {
+        data: String,
+        #[arg(long, default_value_t = 50)]
+        passes: usize,
+    },
+    #[command(about = "Write the evaluation's features for a batch of positions as CSV or SVMlight")]
+    Features {
+        #[arg(help = "One FEN per line, or datagen output whose results become labels")]
+        input: String,
+        #[arg(long, default_value = "csv", value_parser = ["csv", "svmlight"])]
+        format: String,
+        #[arg(long, help = "File to write, standard output if left out")]
+        out: Option<String>,
+    },
+    #[command(
+        about = "Play a match between two engines",
+        long_about = "Play a match between two engines. A player is \"internal\", \"internal:depth=N\", \
+                      \"internal:nodes=N\" or \"uci:<command>[,Option=value...]\" for an external engine. \
+                      With --sprt the match stops once the test decides, --games then being the most it plays."
+    )]
+    Match(MatchArgs),
+    #[command(about = "Play engine configurations against each other and rate them")]
+    Ladder(LadderArgs),
+    #[command(about = "Play a game against the engine in the terminal")]
+    Play {
+        #[arg(long, help = "Take the black pieces")]
+        black: bool,
+        #[arg(long, default_value_t = 8)]
+        depth: u32,
+        #[arg(long, help = "Milliseconds the engine may think per move")]
+        movetime: Option<u64>,
+        #[arg(long)]
+        fen: Option<String>,
+        #[arg(long, default_value = "Full", help = "Random, Greedy, Depth1, Depth3, Club or Full")]
+        level: String,
+    },
+    #[command(about = "Build a Polyglot opening book from a PGN collection")]
+    Makebook {
+        pgn: String,
+        book: String,
+        #[arg(default_value_t = 16)]
+        max_ply: usize,
+        #[arg(default_value_t = 1)]
+        min_games: u32,
+    },
+    #[command(about = "Generate training positions by self-play")]
+    Datagen {
+        out: String,
+        games: Option<usize>,
+        nodes: Option<u64>,
+        threads: Option<usize>,
+    },
+    #[command(about = "Solve puzzles given as a FEN, an EPD file or a PGN file")]
+    Solve {
+        #[arg(long, default_value_t = 10)]
+        depth: u32,
+        #[arg(required = true, num_args = 1..)]
+        source: Vec<String>,
+    },
+    #[command(about = "Annotate the games of a PGN file with evaluations and mistakes")]
+    Annotate {
+        #[arg(long, default_value_t = 12)]
+        depth: u32,
+        pgn: String,
+        out: Option<String>,
+        #[arg(long, help = "File to write every position's evaluation and best move to, for plotting")]
+        graph: Option<String>,
+        #[arg(long, default_value = "json", value_parser = ["json", "csv"])]
+        graph_format: String,
+    },
+    #[cfg(feature = "lichess")]
+    #[command(about = "Play on Lichess through the Bot API, with the token in LICHESS_BOT_TOKEN")]
+    Lichess {
+        #[arg(help = "Comma-separated speeds to accept, bullet,blitz,rapid if left out")]
+        speeds: Option<String>,
+        #[arg(help = "Comma-separated variants to accept, standard if left out")]
+        variants: Option<String>,
+        #[arg(long, help = "Play moves from the Lichess opening explorer in casual games (needs the online-explorer feature)")]
+        explorer: bool,
+    },
+    #[cfg(feature = "server")]
+    #[command(about = "Serve the HTTP/JSON analysis API")]
+    Serve {
+        #[arg(default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+}
+
+#[derive(Args)]
+struct MatchArgs {
+    #[arg(long, default_value_t = 100)]
+    games: usize,
+    #[arg(long, default_value = "10+0.1")]
+    tc: String,
+    #[arg(long, help = "EPD file or Polyglot book to draw openings from")]
+    openings: Option<String>,
+    #[arg(long)]
+    pgn: Option<String>,
+    #[arg(long, default_value = "internal")]
+    p1: String,
+    #[arg(long, default_value = "internal")]
+    p2: String,
+    #[arg(long, help = "Elo bounds, as in 0,5")]
+    sprt: Option<String>,
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+    #[arg(long, default_value_t = 0.05)]
+    beta: f64,
+}
+
+#[derive(Args)]
+struct LadderArgs {
+    #[arg(
+        required = true,
+        num_args = 2..,
+        help = "Configurations, such as depth=4,knight_value=340 or level=club, or uci:<command> for an external engine"
+    )]
+    players: Vec<String>,
+    #[arg(long, help = "Play the first configuration against each of the others instead of everyone against everyone")]
+    gauntlet: bool,
+    #[arg(long, default_value_t = 20, help = "Games per pairing")]
+    games: usize,
+    #[arg(long, default_value = "10+0.1")]
+    tc: String,
+    #[arg(long, help = "EPD file or Polyglot book to draw openings from")]
+    openings: Option<String>,
+    #[arg(long)]
+    pgn: Option<String>,
+}
+
+// Ctrl-C runs handler once; a second one exits at once, for when shutting
+// down gracefully hangs
+fn on_interrupt(handler: impl FnOnce() + Send + 'static) {
+    use signal_hook::consts::SIGINT;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // Registered first so it sees the flag as it was before this Ctrl-C
+    let _ = signal_hook::flag::register_conditional_shutdown(SIGINT, 130, Arc::clone(&interrupted));
+    if signal_hook::flag::register(SIGINT, Arc::clone(&interrupted)).is_err() {
+        return;
+    }
+    std::thread::spawn(move || {
+        while !interrupted.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        handler();
+    });
+}
 
 fn main() {
-    let mut uci = UciHandler::new();
-    uci.run().unwrap();
+    let command = Cli::parse().command.unwrap_or(Command::Uci);
+    let result = match command {
+        Command::Uci => {
+            let mut uci = UciHandler::new();
+            let interrupter = uci.interrupter();
+            on_interrupt(move || interrupter.interrupt());
+            uci.run(std::io::BufReader::new(std::io::stdin())).map_err(|err| err.to_string())
+        }
+        Command::Bench { depth } => run_bench(depth),
+        Command::Profile { depth } => run_profile(depth),
+        Command::Perft { depth, fen } => run_perft(depth, fen.as_deref()),
+        Command::Analyze { fen, moves, depth, movetime } => analyze(fen.as_deref(), &moves, depth, movetime),
+        Command::AnalyzeFile { file, depth, movetime, threads, format, out } => {
+            analyze_file(&file, depth, movetime, threads, &format, out.as_deref())
+        }
+        Command::Testsuite { file, movetime, depth } => run_test_suite(&file, movetime, depth),
+        Command::Selfplay { games, depth, nodes, tc, openings, pgn } => {
+            self_play(games, depth, nodes, &tc, openings.as_deref(), pgn.as_deref())
+        }
+        Command::Tune { data, passes } => tune_evaluation(&data, passes),
+        Command::Features { input, format, out } => extract_features(&input, &format, out.as_deref()),
+        Command::Match(args) => play_match(args),
+        Command::Ladder(args) => run_ladder(args),
+        Command::Play { black, depth, movetime, fen, level } => play(black, depth, movetime, fen.as_deref(), &level),
+        Command::Makebook { pgn, book, max_ply, min_games } => make_book(&pgn, &book, max_ply, min_games),
+        Command::Datagen { out, games, nodes, threads } => generate_data(&out, games, nodes, threads),
+        Command::Solve { depth, source } => solve_puzzles(depth, &source.join(" ")),
+        Command::Annotate { depth, pgn, out, graph, graph_format } => {
+            annotate_games(depth, &pgn, out.as_deref(), graph.as_deref().map(|path| (path, graph_format.as_str())))
+        }
+        #[cfg(feature = "lichess")]
+        Command::Lichess { speeds, variants, explorer } => lichess_bot(speeds.as_deref(), variants.as_deref(), explorer),
+        #[cfg(feature = "server")]
+        Command::Serve { address } => serve(&address),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run_bench(depth: u32) -> Result<(), String> {
+    let mut engine = engine::Engine::new();
+    let result = bench::run(&mut engine, depth, |fen, result| {
+        println!("{:<72} {:>6} {:>10}", fen, uci::format_move(&result.best_move), result.nodes);
+    })?;
+    println!("{} nodes {} nps", result.nodes, result.nps());
+    Ok(())
+}
+
+fn run_profile(depth: u32) -> Result<(), String> {
+    let mut engine = engine::Engine::new();
+    println!("{:<10} {:>12} {:>10} {:>12}", "component", "nodes", "time ms", "nps");
+    for (name, result) in bench::profile(&mut engine, &bench::BENCH_POSITIONS, depth)? {
+        println!("{:<10} {:>12} {:>10} {:>12}", name, result.nodes, result.elapsed.as_millis(), result.nps());
+    }
+    Ok(())
+}
+
+fn run_perft(depth: u32, fen: Option<&str>) -> Result<(), String> {
+    let board = match fen {
+        Some(fen) => Board::from_fen(fen)?,
+        None => Board::new(),
+    };
+    let start = Instant::now();
+    let split = perft::divide(&board, &MoveGenerator::new(), depth);
+    for (mv, count) in &split {
+        println!("{}: {}", uci::format_move(mv), count);
+    }
+    let nodes: u64 = split.iter().map(|(_, nodes)| nodes).sum();
+    let elapsed = start.elapsed();
+    println!("\nNodes searched: {}\nTime: {} ms ({} nps)", nodes, elapsed.as_millis(), (nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64);
+    Ok(())
+}
+
+// Iterative deepening from the outside: each depth is a search of its own,
+// the hash table carrying what the shallower ones found
+fn analyze(fen: Option<&str>, moves: &[String], depth: u32, movetime: Option<u64>) -> Result<(), String> {
+    let mut engine = engine::Engine::new();
+    let moves: Vec<&str> = moves.iter().map(String::as_str).collect();
+    engine.set_position(fen, &moves)?;
+    let board = engine.board().clone();
+    let generator = MoveGenerator::new();
+    let deadline = movetime.map(|ms| Instant::now() + Duration::from_millis(ms));
+
+    for current in 1..=depth {
+        let time = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if time == Some(Duration::ZERO) {
+            break;
+        }
+        let Some(result) = engine.search(SearchLimits { depth: current, time, ..SearchLimits::default() }) else {
+            println!("game over: {}", engine.game_state());
+            return Ok(());
+        };
+        println!(
+            "depth {:>2} {:>7} nodes {:>9} time {:>6} ms  {}",
+            current,
+            format_score(result.score),
+            result.nodes,
+            result.elapsed.as_millis(),
+            san::format_line(&board, &generator, &result.pv)
+        );
+    }
+    Ok(())
+}
+
+// "+0.35", "-1.20", "#3" or "#-2", for the side to move
+fn format_score(score: i32) -> String {
+    if score.abs() >= MATE_THRESHOLD {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        format!("#{}{}", if score < 0 { "-" } else { "" }, moves)
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
+
+fn analyze_file(path: &str, depth: u32, movetime: Option<u64>, threads: usize, format: &str, out_path: Option<&str>) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let positions = puzzle::load_puzzles(&text)?;
+    let config = batch::BatchConfig { depth, movetime: movetime.map(Duration::from_millis), threads };
+
+    let mut results = Vec::new();
+    for (position, result) in positions.iter().zip(batch::analyze_all(&positions, &config)) {
+        match result {
+            Ok(result) => results.push(result),
+            Err(err) => eprintln!("{}: {}", position.id, err),
+        }
+    }
+    let mut out: Box<dyn std::io::Write> = match out_path {
+        Some(out_path) => {
+            let file = std::fs::File::create(out_path).map_err(|err| format!("cannot create {}: {}", out_path, err))?;
+            Box::new(std::io::BufWriter::new(file))
+        }
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        "csv" => batch::write_csv(&mut out, &results),
+        _ => batch::write_json(&mut out, &results),
+    }
+    .and_then(|_| out.flush())
+    .map_err(|err| err.to_string())
+}
+
+fn run_test_suite(path: &str, movetime: u64, depth: Option<u32>) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let puzzles = puzzle::load_puzzles(&text)?;
+    let limits = SearchLimits {
+        depth: depth.unwrap_or(search::MAX_DEPTH),
+        time: Some(Duration::from_millis(movetime)),
+        ..SearchLimits::default()
+    };
+
+    let mut engine = engine::Engine::new();
+    let generator = MoveGenerator::new();
+    let (mut tested, mut passed) = (0, 0);
+    for puzzle in puzzles.iter().filter(|puzzle| !puzzle.best_moves.is_empty()) {
+        tested += 1;
+        engine.new_game();
+        engine.set_position(Some(&puzzle.board.to_fen()), &[])?;
+        let expected: Vec<String> = puzzle.best_moves.iter().map(|mv| san::to_san(&puzzle.board, &generator, mv)).collect();
+        match engine.search(limits.clone()).map(|result| result.best_move) {
+            Some(mv) if puzzle.best_moves.contains(&mv) => {
+                passed += 1;
+                println!("{}: ok, {}", puzzle.id, san::to_san(&puzzle.board, &generator, &mv));
+            }
+            Some(mv) => println!("{}: FAIL, {} instead of {}", puzzle.id, san::to_san(&puzzle.board, &generator, &mv), expected.join(" or ")),
+            None => println!("{}: FAIL, no move", puzzle.id),
+        }
+    }
+    println!("passed {} of {}", passed, tested);
+    Ok(())
+}
+
+fn self_play(games: usize, depth: Option<u32>, nodes: Option<u64>, tc: &str, openings: Option<&str>, pgn: Option<&str>) -> Result<(), String> {
+    let config = match_runner::MatchConfig {
+        games,
+        time_control: match_runner::TimeControl::parse(tc)?,
+        adjudication: Default::default(),
+    };
+    let mut first = match_runner::EnginePlayer::new("salmon-1", depth, nodes);
+    let mut second = match_runner::EnginePlayer::new("salmon-2", depth, nodes);
+    play_games(&config, openings, pgn, &mut first, &mut second, None)
+}
+
+fn tune_evaluation(path: &str, passes: usize) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let positions = tune::load_positions(&text)?;
+    if positions.is_empty() {
+        return Err(format!("no positions in {}", path));
+    }
+
+    let mut evaluator = evaluation::Evaluator::new();
+    let k = tune::best_k(&evaluator, &positions);
+    println!("{} positions, K {:.2}, error {:.6}", positions.len(), k, tune::mean_error(&evaluator, &positions, k));
+    tune::tune(&mut evaluator, &positions, k, passes, |pass, error| println!("pass {}: error {:.6}", pass, error));
+    for (name, weight) in tune::WEIGHTS {
+        println!("{}: {}", name, weight(&mut evaluator));
+    }
+    Ok(())
+}
+
+fn extract_features(path: &str, format: &str, out_path: Option<&str>) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let evaluator = evaluation::Evaluator::new();
+    let mut rows = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        // Datagen lines are "<fen> | <score> | <result>"
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        let board = Board::from_fen(fields[0]).map_err(|err| format!("'{}': {}", fields[0], err))?;
+        let label = match fields.get(2) {
+            Some(result) => Some(result.parse().map_err(|_| format!("bad result in '{}'", line))?),
+            None => None,
+        };
+        rows.push((features::extract(&evaluator, &board), label));
+    }
+
+    let mut out: Box<dyn std::io::Write> = match out_path {
+        Some(out_path) => Box::new(std::io::BufWriter::new(
+            std::fs::File::create(out_path).map_err(|err| format!("cannot create {}: {}", out_path, err))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        "svmlight" => features::write_svmlight(&mut out, &rows),
+        _ => features::write_csv(&mut out, &rows),
+    }
+    .and_then(|_| out.flush())
+    .map_err(|err| err.to_string())
+}
+
+fn play_match(args: MatchArgs) -> Result<(), String> {
+    let config = match_runner::MatchConfig {
+        games: args.games,
+        time_control: match_runner::TimeControl::parse(&args.tc)?,
+        adjudication: Default::default(),
+    };
+    let sprt = match &args.sprt {
+        Some(bounds) => Some(sprt::Sprt { alpha: args.alpha, beta: args.beta, ..sprt::Sprt::parse(bounds)? }),
+        None => None,
+    };
+    let mut first = match_player(&args.p1, "salmon-1")?;
+    let mut second = match_player(&args.p2, "salmon-2")?;
+    play_games(&config, args.openings.as_deref(), args.pgn.as_deref(), first.as_mut(), second.as_mut(), sprt)
+}
+
+// Runs the games, printing each result and the running score, and appending
+// each game to the PGN file if there is one. With an SPRT the match ends when
+// the test decides.
+fn play_games(
+    config: &match_runner::MatchConfig,
+    openings: Option<&str>,
+    pgn: Option<&str>,
+    first: &mut dyn match_runner::Player,
+    second: &mut dyn match_runner::Player,
+    sprt: Option<sprt::Sprt>,
+) -> Result<(), String> {
+    let openings = load_openings(openings, config.games)?;
+    let mut pgn = create_pgn(pgn)?;
+
+    let mut write_error = None;
+    let score = match_runner::run_match(config, &openings, first, second, |game, score| {
+        println!("game {}: {} ({}), {}", score.games(), game.result, game.tag("Termination").unwrap_or("?"), score);
+        if let Some(file) = &mut pgn {
+            if let Err(err) = std::io::Write::write_all(file, format!("{}\n", game.to_pgn()).as_bytes()) {
+                write_error = Some(err.to_string());
+                return false;
+            }
+        }
+        match &sprt {
+            Some(sprt) => {
+                let (lower, upper) = sprt.bounds();
+                println!("LLR {:.2} ({:.2}, {:.2})", sprt.llr(score), lower, upper);
+                sprt.verdict(score) == sprt::Verdict::Continue
+            }
+            None => true,
+        }
+    })?;
+    if let Some(err) = write_error {
+        return Err(format!("cannot write PGN: {}", err));
+    }
+    println!("{} vs {}: {}", first.name(), second.name(), score);
+    if let Some(sprt) = &sprt {
+        println!("SPRT [{}, {}]: LLR {:.2}, {}", sprt.elo0, sprt.elo1, sprt.llr(&score), sprt.verdict(&score));
+    }
+    Ok(())
 }
 
-fn parse_move(input: &str) -> Option<Move> {
-    // TODO: Implement move parsing from algebraic notation
-    None
+// Openings from an EPD file or a Polyglot book, enough book walks for games
+// played two to an opening; none without a file
+fn load_openings(path: Option<&str>, games: usize) -> Result<Vec<match_runner::Opening>, String> {
+    match path {
+        Some(path) if path.ends_with(".bin") => {
+            let book = book::PolyglotBook::open(path)?;
+            Ok(match_runner::book_openings(&book, games.div_ceil(2), 8, &mut rand::thread_rng()))
+        }
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+            match_runner::load_epd(&text)
+        }
+        None => Ok(Vec::new()),
+    }
 }
 
-fn format_move(mv: &Move) -> String {
-    // TODO: Implement move formatting to algebraic notation
-    String::new()
+fn create_pgn(path: Option<&str>) -> Result<Option<std::fs::File>, String> {
+    path.map(|path| std::fs::File::create(path).map_err(|err| format!("cannot create {}: {}", path, err))).transpose()
 }
 
-fn is_move_legal(board: &Board, mv: &Move) -> bool {
+// Plays the ladder's matches, printing each game and each finished pairing,
+// then the players rated best first
+fn run_ladder(args: LadderArgs) -> Result<(), String> {
+    let config = match_runner::MatchConfig {
+        games: args.games,
+        time_control: match_runner::TimeControl::parse(&args.tc)?,
+        adjudication: Default::default(),
+    };
+    let format = if args.gauntlet { ladder::Format::Gauntlet } else { ladder::Format::RoundRobin };
+    let mut players = args
+        .players
+        .iter()
+        .map(|spec| {
+            if spec.starts_with("uci:") {
+                match_player(spec, spec)
+            } else {
+                Ok(Box::new(ladder::Contender::parse(spec)?.player()) as Box<dyn match_runner::Player>)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let names: Vec<String> = players.iter().map(|player| player.name().to_string()).collect();
+    let openings = load_openings(args.openings.as_deref(), config.games)?;
+    let mut pgn = create_pgn(args.pgn.as_deref())?;
+
+    let mut write_error = None;
+    let results = ladder::run_ladder(&config, &openings, &mut players, format, |game, (first, second), score| {
+        println!("{} vs {}, game {}: {} ({})", names[first], names[second], score.games(), game.result, game.tag("Termination").unwrap_or("?"));
+        if score.games() as usize == config.games {
+            println!("{} vs {}: {}", names[first], names[second], score);
+        }
+        if let Some(file) = &mut pgn {
+            if let Err(err) = std::io::Write::write_all(file, format!("{}\n", game.to_pgn()).as_bytes()) {
+                write_error.get_or_insert(err.to_string());
+            }
+        }
+    })?;
+    if let Some(err) = write_error {
+        return Err(format!("cannot write PGN: {}", err));
+    }
+    println!();
+    ladder::write_table(&mut std::io::stdout(), &names, &ladder::standings(players.len(), &results, format)).map_err(|err| err.to_string())
+}
+
+fn play(black: bool, depth: u32, movetime: Option<u64>, fen: Option<&str>, level: &str) -> Result<(), String> {
+    let mut engine = engine::Engine::new();
+    engine.set_level(Level::from_name(level).ok_or_else(|| format!("unknown level '{}'", level))?);
+    engine.set_position(fen, &[])?;
+    let human = if black { board::Color::Black } else { board::Color::White };
+    let limits = SearchLimits { depth, time: movetime.map(Duration::from_millis), ..SearchLimits::default() };
     let generator = MoveGenerator::new();
-    let legal_moves = generator.generate_moves(board);
-    legal_moves.contains(mv)
-} 
\ No newline at end of file
+    let mut lines = std::io::stdin().lock().lines();
+
+    println!("Moves in SAN or UCI notation; \"quit\" ends the game");
+    loop {
+        let board = engine.board().clone();
+        println!("{}", board);
+        let state = engine.game_state();
+        if state != movegen::GameState::Ongoing {
+            println!("{}", state);
+            return Ok(());
+        }
+
+        if board.side_to_move != human {
+            let result = engine.search(limits.clone()).ok_or("the engine has no move")?;
+            println!("Three Salmons plays {} ({})", san::to_san(&board, &generator, &result.best_move), format_score(result.score));
+            engine.play(&uci::format_move(&result.best_move))?;
+            continue;
+        }
+        print!("Your move: ");
+        std::io::Write::flush(&mut std::io::stdout()).map_err(|err| err.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|err| err.to_string())?;
+        let input = line.trim();
+        if input == "quit" {
+            return Ok(());
+        }
+        match san::parse_san(&board, &generator, input).or_else(|_| uci::parse_uci_move(&board, &generator, input)) {
+            Ok(mv) => {
+                engine.play(&uci::format_move(&mv))?;
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+fn make_book(pgn_path: &str, book_path: &str, max_ply: usize, min_games: u32) -> Result<(), String> {
+    let text = std::fs::read_to_string(pgn_path).map_err(|err| format!("cannot read {}: {}", pgn_path, err))?;
+    let mut builder = book::BookBuilder::new(max_ply);
+    let games = builder.add_pgn(&text);
+    let entries = builder.write(book_path, min_games)?;
+    println!("{} games, {} book entries written to {}", games, entries, book_path);
+    Ok(())
+}
+
+fn generate_data(out_path: &str, games: Option<usize>, nodes: Option<u64>, threads: Option<usize>) -> Result<(), String> {
+    let defaults = datagen::DatagenConfig::default();
+    let config = datagen::DatagenConfig {
+        games: games.unwrap_or(defaults.games),
+        nodes: nodes.unwrap_or(defaults.nodes),
+        threads: threads.unwrap_or(defaults.threads),
+        // A fresh seed per run, so repeated runs add new games
+        seed: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+        ..defaults
+    };
+
+    let file = std::fs::File::create(out_path).map_err(|err| format!("cannot create {}: {}", out_path, err))?;
+    let mut out = std::io::BufWriter::new(file);
+    let (games, records) = datagen::generate(&config, &mut out).map_err(|err| err.to_string())?;
+    println!("{} games, {} positions written to {}", games, records, out_path);
+    Ok(())
+}
+
+fn match_player(spec: &str, name: &str) -> Result<Box<dyn match_runner::Player>, String> {
+    if let Some(command) = spec.strip_prefix("uci:") {
+        let mut parts = command.split(',');
+        let command = parts.next().unwrap_or_default();
+        let options = parts
+            .map(|option| {
+                option
+                    .split_once('=')
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .ok_or_else(|| format!("engine option '{}' is not Name=value", option))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Box::new(match_runner::UciPlayer::spawn(command, &options)?));
+    }
+
+    let (mut depth, mut nodes) = (None, None);
+    match spec.strip_prefix("internal").map(|rest| rest.trim_start_matches(':')) {
+        Some("") => {}
+        Some(limit) => match limit.split_once('=') {
+            Some(("depth", value)) => depth = Some(value.parse().map_err(|_| "depth must be a number")?),
+            Some(("nodes", value)) => nodes = Some(value.parse().map_err(|_| "nodes must be a number")?),
+            _ => return Err(format!("unknown limit '{}'", limit)),
+        },
+        None => return Err(format!("unknown player '{}'", spec)),
+    }
+    Ok(Box::new(match_runner::EnginePlayer::new(name, depth, nodes)))
+}
+
+fn solve_puzzles(depth: u32, source: &str) -> Result<(), String> {
+    let puzzles = match std::fs::read_to_string(source) {
+        Ok(text) => puzzle::load_puzzles(&text)?,
+        Err(_) => puzzle::load_puzzles(source)?,
+    };
+
+    let mut engine = engine::Engine::new();
+    let mut solved = 0;
+    for puzzle in &puzzles {
+        match puzzle::solve(&mut engine, &puzzle.board, depth) {
+            Ok(solution) if puzzle.best_moves.is_empty() || puzzle.best_moves.contains(&solution.line[0]) => {
+                println!("{}: {}", puzzle.id, puzzle::describe(&puzzle.board, &solution));
+                solved += 1;
+            }
+            Ok(solution) => {
+                let expected: Vec<String> = puzzle.best_moves.iter().map(uci::format_move).collect();
+                println!("{}: {}, expected {}", puzzle.id, puzzle::describe(&puzzle.board, &solution), expected.join(" or "));
+            }
+            Err(err) => println!("{}: unsolved, {}", puzzle.id, err),
+        }
+    }
+    println!("solved {} of {}", solved, puzzles.len());
+    Ok(())
+}
+
+// graph is the file and format ("json" or "csv") of the evaluation graph, if one is wanted
+fn annotate_games(depth: u32, pgn_path: &str, out_path: Option<&str>, graph: Option<(&str, &str)>) -> Result<(), String> {
+    let text = std::fs::read_to_string(pgn_path).map_err(|err| format!("cannot read {}: {}", pgn_path, err))?;
+
+    let mut engine = engine::Engine::new();
+    let mut output = String::new();
+    let mut graphs = Vec::new();
+    for (i, game) in pgn::read_games(&text).into_iter().enumerate() {
+        let analysed = game.and_then(|game| annotate::analyse(&mut engine, &game, depth).map(|analysis| (game, analysis)));
+        match analysed {
+            Ok((game, analysis)) => {
+                output.push_str(&annotate::annotated(&game, &analysis).to_pgn());
+                output.push('\n');
+                graphs.push(annotate::eval_graph(&game, &analysis));
+            }
+            Err(err) => eprintln!("game {}: {}", i + 1, err),
+        }
+    }
+    if let Some((graph_path, format)) = graph {
+        let file = std::fs::File::create(graph_path).map_err(|err| format!("cannot create {}: {}", graph_path, err))?;
+        let mut out = std::io::BufWriter::new(file);
+        match format {
+            "csv" => annotate::write_graph_csv(&mut out, &graphs),
+            _ => annotate::write_graph_json(&mut out, &graphs),
+        }
+        .and_then(|_| std::io::Write::flush(&mut out))
+        .map_err(|err| err.to_string())?;
+    }
+    match out_path {
+        Some(out_path) => std::fs::write(out_path, output).map_err(|err| format!("cannot write {}: {}", out_path, err)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "lichess")]
+fn lichess_bot(speeds: Option<&str>, variants: Option<&str>, explorer: bool) -> Result<(), String> {
+    let token = std::env::var("LICHESS_BOT_TOKEN").map_err(|_| "set LICHESS_BOT_TOKEN to the bot's API token")?;
+    let mut config = lichess::BotConfig::new(token);
+    let list = |arg: &str| arg.split(',').map(str::to_string).collect();
+    if let Some(speeds) = speeds {
+        config.speeds = list(speeds);
+    }
+    if let Some(variants) = variants {
+        config.variants = list(variants);
+    }
+    if explorer {
+        #[cfg(feature = "online-explorer")]
+        {
+            let mut explorer = online_explorer::OpeningExplorer::new(std::env::temp_dir().join("three-salmons-explorer"));
+            explorer.token = Some(config.token.clone());
+            config.explorer = Some(explorer);
+        }
+        #[cfg(not(feature = "online-explorer"))]
+        return Err("built without the online-explorer feature".to_string());
+    }
+
+    let bot = lichess::LichessBot::connect(config)?;
+    println!("connected to Lichess as {}", bot.bot_id());
+    bot.run()
+}
+
+#[cfg(feature = "server")]
+fn serve(address: &str) -> Result<(), String> {
+    println!("listening on http://{}", address);
+    server::serve(address)
+}
@@ -0,0 +1,595 @@
+use crate::board::{Board, Color};
+use crate::book::{BookPolicy, BookSelection, PolyglotBook};
+use crate::engine::Engine;
+use crate::game::{Annotation, Game, GameResult};
+use crate::movegen::{GameState, MoveGenerator};
+use crate::search::{SearchLimits, MATE_SCORE};
+use crate::timeman::{GoParams, TimeManager};
+use crate::uci::{format_move, parse_uci_move};
+use rand::Rng;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Slack allowed past zero on the clock before a player loses on time, to
+// absorb process and pipe latency
+const TIME_MARGIN: Duration = Duration::from_millis(50);
+// How long an external engine gets to answer anything but a search
+const UCI_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What a player wants to play, and how it rates the position for itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerMove {
+    pub uci: String,
+    // Centipawns from the mover's point of view, when the player reports one
+    pub score: Option<i32>,
+}
+
+pub trait Player {
+    fn name(&self) -> &str;
+    fn new_game(&mut self) -> Result<(), String>;
+    // The position is the start FEN (None for the start position) and the moves since
+    fn play(&mut self, fen: Option<&str>, moves: &[String], clocks: &GoParams) -> Result<PlayerMove, String>;
+}
+
+// This engine, run in-process, optionally held to a fixed depth or node count
+pub struct EnginePlayer {
+    name: String,
+    engine: Engine,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+}
+
+impl EnginePlayer {
+    pub fn new(name: &str, depth: Option<u32>, nodes: Option<u64>) -> Self {
+        Self::with_engine(name, Engine::new(), depth, nodes)
+    }
+
+    // An engine already set up with its own evaluation or search settings
+    pub fn with_engine(name: &str, engine: Engine, depth: Option<u32>, nodes: Option<u64>) -> Self {
+        Self {
+            name: name.to_string(),
+            engine,
+            depth,
+            nodes,
+        }
+    }
+}
+
+impl Player for EnginePlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        self.engine.new_game();
+        Ok(())
+    }
+
+    fn play(&mut self, fen: Option<&str>, moves: &[String], clocks: &GoParams) -> Result<PlayerMove, String> {
+        let moves: Vec<&str> = moves.iter().map(String::as_str).collect();
+        self.engine.set_position(fen, &moves)?;
+        let limits = SearchLimits {
+            depth: self.depth.unwrap_or(SearchLimits::default().depth),
+            nodes: self.nodes,
+            ..SearchLimits::default()
+        }
+        .with_budget(TimeManager::allocate(clocks, self.engine.board().side_to_move));
+        let result = self.engine.search(limits).ok_or("no legal move to play")?;
+        Ok(PlayerMove { uci: format_move(&result.best_move), score: Some(result.score) })
+    }
+}
+
+// An external engine spoken to over UCI on its standard input and output
+pub struct UciPlayer {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl UciPlayer {
+    pub fn spawn(command: &str, options: &[(String, String)]) -> Result<Self, String> {
+        let mut child = Command::new(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("cannot start {}: {}", command, err))?;
+        let stdin = child.stdin.take().ok_or("engine has no stdin")?;
+        let stdout = child.stdout.take().ok_or("engine has no stdout")?;
+
+        // Lines are read on their own thread so a silent engine can be timed out
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut player = Self { name: command.to_string(), child, stdin, lines };
+        player.send("uci")?;
+        for line in player.read_until("uciok", UCI_TIMEOUT)? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                player.name = name.to_string();
+            }
+        }
+        for (name, value) in options {
+            player.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        player.send("isready")?;
+        player.read_until("readyok", UCI_TIMEOUT)?;
+        Ok(player)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|err| format!("{} stopped reading: {}", self.name, err))
+    }
+
+    // Every line up to and including the first starting with prefix
+    fn read_until(&mut self, prefix: &str, timeout: Duration) -> Result<Vec<String>, String> {
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let line = self
+                .lines
+                .recv_timeout(remaining)
+                .map_err(|_| format!("{} did not answer with '{}'", self.name, prefix))?;
+            let done = line.starts_with(prefix);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+// The score of the last "info ... score cp X" or "score mate N" line, mates
+// scored the way our own search scores them
+fn last_score(lines: &[String]) -> Option<i32> {
+    lines.iter().rev().find_map(|line| {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let at = parts.iter().position(|&part| part == "score")?;
+        let value: i32 = parts.get(at + 2)?.parse().ok()?;
+        match *parts.get(at + 1)? {
+            "cp" => Some(value),
+            "mate" => Some(if value > 0 { MATE_SCORE - (2 * value - 1) } else { -MATE_SCORE - 2 * value }),
+            _ => None,
+        }
+    })
+}
+
+impl Player for UciPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.read_until("readyok", UCI_TIMEOUT).map(drop)
+    }
+
+    fn play(&mut self, fen: Option<&str>, moves: &[String], clocks: &GoParams) -> Result<PlayerMove, String> {
+        let mut position = match fen {
+            Some(fen) => format!("position fen {}", fen),
+            None => "position startpos".to_string(),
+        };
+        if !moves.is_empty() {
+            position.push_str(&format!(" moves {}", moves.join(" ")));
+        }
+        self.send(&position)?;
+
+        let mut go = "go".to_string();
+        for (name, value) in [("wtime", clocks.wtime), ("btime", clocks.btime), ("winc", clocks.winc), ("binc", clocks.binc)] {
+            if let Some(value) = value {
+                go.push_str(&format!(" {} {}", name, value));
+            }
+        }
+        self.send(&go)?;
+
+        // The caller flags a player that overstays its clock; this only catches a hung engine
+        let budget = Duration::from_millis(clocks.wtime.max(clocks.btime).unwrap_or(0)) + UCI_TIMEOUT;
+        let lines = self.read_until("bestmove", budget)?;
+        let uci = lines
+            .last()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .ok_or_else(|| format!("{} sent an empty bestmove", self.name))?
+            .to_string();
+        Ok(PlayerMove { uci, score: last_score(&lines) })
+    }
+}
+
+impl Drop for UciPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        thread::sleep(Duration::from_millis(50));
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    // "60+0.5": seconds on the clock plus seconds added per move
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+        let seconds = |s: &str| {
+            s.parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| format!("invalid time control '{}'", text))
+        };
+        Ok(Self { base: seconds(base)?, increment: seconds(increment)? })
+    }
+}
+
+// When a game is called before it ends on the board. Scores are from White's
+// side, taken from whichever player just moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjudication {
+    // A side this far ahead for resign_plies plies in a row wins
+    pub resign_score: i32,
+    pub resign_plies: usize,
+    // After draw_after_plies, draw_plies plies in a row within draw_score is a draw
+    pub draw_score: i32,
+    pub draw_plies: usize,
+    pub draw_after_plies: usize,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Self {
+            resign_score: 1000,
+            resign_plies: 6,
+            draw_score: 10,
+            draw_plies: 16,
+            draw_after_plies: 80,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MatchConfig {
+    pub games: usize,
+    pub time_control: TimeControl,
+    pub adjudication: Adjudication,
+}
+
+// A starting position: a FEN (None for the start position) and moves played from it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Opening {
+    pub fen: Option<String>,
+    pub moves: Vec<String>,
+}
+
+// One opening per EPD line; only the first four fields (the position) are used
+pub fn load_epd(text: &str) -> Result<Vec<Opening>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            let fen = format!("{} 0 1", fields.join(" "));
+            Board::from_fen(&fen).map_err(|err| format!("bad EPD line '{}': {}", line, err))?;
+            Ok(Opening { fen: Some(fen), moves: Vec::new() })
+        })
+        .collect()
+}
+
+// Random walks of up to plies book moves from the start position
+pub fn book_openings<R: Rng>(book: &PolyglotBook, count: usize, plies: usize, rng: &mut R) -> Vec<Opening> {
+    let generator = MoveGenerator::new();
+    let selection = BookSelection { policy: BookPolicy::Weighted { variety: 100 }, ..BookSelection::default() };
+    (0..count)
+        .map(|_| {
+            let mut board = Board::new();
+            let mut moves = Vec::new();
+            while moves.len() < plies {
+                let Some(mv) = book.pick(&board, &generator, &selection, rng) else {
+                    break;
+                };
+                moves.push(format_move(&mv));
+                board.make_move(mv);
+            }
+            Opening { fen: None, moves }
+        })
+        .collect()
+}
+
+// Wins, draws and losses from the first player's side
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // Elo difference implied by the score; None while it is 0% or 100%
+    pub fn elo(&self) -> Option<f64> {
+        elo_from_score(self.score())
+    }
+
+    // Half-width of the 95% confidence interval around elo()
+    pub fn elo_margin(&self) -> Option<f64> {
+        let n = self.games() as f64;
+        let score = self.score();
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / n;
+        let deviation = 1.96 * (variance / n).sqrt();
+        Some((elo_from_score(score + deviation)? - elo_from_score(score - deviation)?) / 2.0)
+    }
+
+    fn add(&mut self, result: GameResult, first_player: Color) {
+        match (result, first_player) {
+            (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => self.wins += 1,
+            (GameResult::WhiteWins, Color::Black) | (GameResult::BlackWins, Color::White) => self.losses += 1,
+            _ => self.draws += 1,
+        }
+    }
+}
+
+fn elo_from_score(score: f64) -> Option<f64> {
+    (score > 0.0 && score < 1.0).then(|| 400.0 * (score / (1.0 - score)).log10())
+}
+
+impl fmt::Display for MatchScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "W-D-L {}-{}-{}, score {:.1}%", self.wins, self.draws, self.losses, self.score() * 100.0)?;
+        match (self.elo(), self.elo_margin()) {
+            (Some(elo), Some(margin)) => write!(f, ", Elo {:+.1} +/- {:.1}", elo, margin),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Plays one game between two players from an opening and returns it with
+// White, Black, Result and Termination tags and per-move eval/clock comments
+pub fn play_game(white: &mut dyn Player, black: &mut dyn Player, opening: &Opening, config: &MatchConfig) -> Result<Game, String> {
+    let generator = MoveGenerator::new();
+    let start = match &opening.fen {
+        Some(fen) => Board::from_fen(fen)?,
+        None => Board::new(),
+    };
+    let mut game = Game::new(start.clone());
+    game.set_tag("White", white.name());
+    game.set_tag("Black", black.name());
+
+    let mut board = start;
+    let mut history = board.apply_uci_moves(&opening.moves).map_err(|err| format!("opening: {}", err))?;
+    for (_, mv) in &history {
+        game.push(*mv, Annotation::default());
+    }
+    let mut moves = opening.moves.clone();
+
+    white.new_game()?;
+    black.new_game()?;
+    let tc = config.time_control;
+    let mut clocks = [tc.base, tc.base];
+    let (mut resign_streak, mut draw_streak) = (0, 0);
+    let mut last_leader = None;
+
+    let (result, termination) = loop {
+        match generator.get_game_state(&board, &history) {
+            GameState::Ongoing => {}
+            GameState::Checkmate(Color::White) => break (GameResult::WhiteWins, "checkmate"),
+            GameState::Checkmate(Color::Black) => break (GameResult::BlackWins, "checkmate"),
+            GameState::VariantWin(Color::White) => break (GameResult::WhiteWins, "variant win"),
+            GameState::VariantWin(Color::Black) => break (GameResult::BlackWins, "variant win"),
+            _ => {}
+        }
+        // Claimable draws end the game too, as either player would claim them
+        if let Ok(reason) = game.claim_draw() {
+            break (GameResult::Draw, reason.as_str());
+        }
+
+        let side = board.side_to_move;
+        let (player, index): (&mut dyn Player, usize) = match side {
+            Color::White => (&mut *white, 0),
+            Color::Black => (&mut *black, 1),
+        };
+        let loses = if side == Color::White { GameResult::BlackWins } else { GameResult::WhiteWins };
+        let params = GoParams {
+            wtime: Some(clocks[0].as_millis() as u64),
+            btime: Some(clocks[1].as_millis() as u64),
+            winc: Some(tc.increment.as_millis() as u64),
+            binc: Some(tc.increment.as_millis() as u64),
+            ..GoParams::default()
+        };
+
+        let started = Instant::now();
+        let reply = match player.play(opening.fen.as_deref(), &moves, &params) {
+            Ok(reply) => reply,
+            Err(_) => break (loses, "abandoned"),
+        };
+        let elapsed = started.elapsed();
+        if elapsed > clocks[index] + TIME_MARGIN {
+            break (loses, "time forfeit");
+        }
+        clocks[index] = clocks[index].saturating_sub(elapsed) + tc.increment;
+
+        let Ok(mv) = parse_uci_move(&board, &generator, &reply.uci) else {
+            break (loses, "illegal move");
+        };
+        let white_score = reply.score.map(|score| if side == Color::White { score } else { -score });
+        history.push((board.clone(), mv));
+        board.make_move(mv);
+        moves.push(reply.uci);
+        game.push(mv, Annotation { eval: white_score, clock: Some(clocks[index]), ..Annotation::default() });
+
+        // Score adjudication
+        let adjudication = config.adjudication;
+        match white_score {
+            Some(score) if score.abs() >= adjudication.resign_score => {
+                let leader = score > 0;
+                resign_streak = if last_leader == Some(leader) { resign_streak + 1 } else { 1 };
+                last_leader = Some(leader);
+            }
+            _ => resign_streak = 0,
+        }
+        draw_streak = match white_score {
+            Some(score) if score.abs() <= adjudication.draw_score && game.moves.len() >= adjudication.draw_after_plies => {
+                draw_streak + 1
+            }
+            _ => 0,
+        };
+        if resign_streak >= adjudication.resign_plies {
+            let result = if last_leader == Some(true) { GameResult::WhiteWins } else { GameResult::BlackWins };
+            break (result, "score adjudication");
+        }
+        if draw_streak >= adjudication.draw_plies {
+            break (GameResult::Draw, "score adjudication");
+        }
+    };
+
+    game.result = result;
+    game.set_tag("Result", &result.to_string());
+    game.set_tag("Termination", termination);
+    Ok(game)
+}
+
+// Plays config.games games, the players swapping colours on each opening,
+// which is used for two games in a row. on_game sees every finished game with
+// the running score and returns false to stop the match early.
+pub fn run_match<F>(
+    config: &MatchConfig,
+    openings: &[Opening],
+    first: &mut dyn Player,
+    second: &mut dyn Player,
+    mut on_game: F,
+) -> Result<MatchScore, String>
+where
+    F: FnMut(&Game, &MatchScore) -> bool,
+{
+    let mut score = MatchScore::default();
+    let default_opening = [Opening::default()];
+    let openings = if openings.is_empty() { &default_opening[..] } else { openings };
+
+    for round in 0..config.games {
+        let opening = &openings[(round / 2) % openings.len()];
+        let (game, first_color) = if round % 2 == 0 {
+            (play_game(first, second, opening, config)?, Color::White)
+        } else {
+            (play_game(second, first, opening, config)?, Color::Black)
+        };
+        score.add(game.result, first_color);
+        if !on_game(&game, &score) {
+            break;
+        }
+    }
+    Ok(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(games: usize) -> MatchConfig {
+        MatchConfig {
+            games,
+            time_control: TimeControl::parse("60+1").unwrap(),
+            adjudication: Adjudication::default(),
+        }
+    }
+
+    #[test]
+    fn test_time_control_and_epd() {
+        assert_eq!(
+            TimeControl::parse("10+0.1").unwrap(),
+            TimeControl { base: Duration::from_secs(10), increment: Duration::from_millis(100) }
+        );
+        assert_eq!(TimeControl::parse("5").unwrap().increment, Duration::ZERO);
+        assert!(TimeControl::parse("fast").is_err());
+
+        let openings = load_epd("# comment\nrnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - id \"e4\";\n\n").unwrap();
+        assert_eq!(openings.len(), 1);
+        assert_eq!(openings[0].fen.as_deref(), Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"));
+        assert!(load_epd("not a position").is_err());
+
+        let lines = ["info depth 3 score cp 25 pv e2e4", "info depth 4 score mate -2 pv e2e4", "bestmove e2e4"];
+        assert_eq!(last_score(&lines.map(String::from)), Some(-MATE_SCORE + 4));
+    }
+
+    #[test]
+    fn test_match_score() {
+        let even = MatchScore { wins: 3, draws: 4, losses: 3 };
+        assert_eq!(even.elo(), Some(0.0));
+        let ahead = MatchScore { wins: 30, draws: 0, losses: 10 };
+        assert!((ahead.elo().unwrap() - 190.8).abs() < 0.1);
+        assert!((ahead.elo_margin().unwrap() - 135.3).abs() < 1.0);
+        // Too few games for the interval to stay inside (0, 1)
+        assert_eq!(MatchScore { wins: 3, draws: 0, losses: 1 }.elo_margin(), None);
+        assert_eq!(MatchScore { wins: 2, draws: 0, losses: 0 }.elo(), None);
+        assert_eq!(even.to_string(), "W-D-L 3-4-3, score 50.0%, Elo +0.0 +/- 181.7");
+    }
+
+    #[test]
+    fn test_run_match() {
+        // Mate in one for whoever has the queen; colours swap, so each side mates once
+        let openings = load_epd("6k1/5ppp/8/8/8/8/8/Q5K1 w - -").unwrap();
+        let mut first = EnginePlayer::new("first", Some(2), None);
+        let mut second = EnginePlayer::new("second", Some(2), None);
+
+        let mut games = Vec::new();
+        let score = run_match(&config(2), &openings, &mut first, &mut second, |game, _| {
+            games.push(game.clone());
+            true
+        })
+        .unwrap();
+
+        assert_eq!(score, MatchScore { wins: 1, draws: 0, losses: 1 });
+        assert_eq!(games[0].tag("White"), Some("first"));
+        assert_eq!(games[1].tag("White"), Some("second"));
+        assert_eq!(games[0].tag("Termination"), Some("checkmate"));
+        assert!(games[0].to_pgn().contains("1. Qa8# {[%eval"));
+    }
+
+    #[test]
+    fn test_adjudication_and_early_stop() {
+        let mut first = EnginePlayer::new("first", Some(1), None);
+        let mut second = EnginePlayer::new("second", Some(1), None);
+        let mut config = config(10);
+        config.adjudication.draw_after_plies = 0;
+        config.adjudication.draw_plies = 2;
+        config.adjudication.draw_score = 10_000;
+
+        let mut played = 0;
+        let score = run_match(&config, &[], &mut first, &mut second, |game, _| {
+            assert_eq!(game.tag("Termination"), Some("score adjudication"));
+            assert_eq!(game.moves.len(), 2);
+            played += 1;
+            played < 3
+        })
+        .unwrap();
+        assert_eq!(score.games(), 3);
+        assert_eq!(score.draws, 3);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::board::Board;
+use crate::movegen::{GameState, MaterialRule, Move, MoveGenerator};
+
+// Centipawns mapped onto values in (-1, 1): tanh(cp / VALUE_SCALE)
+const VALUE_SCALE: f32 = 400.0;
+// Softmax temperature, in centipawns, turning static evaluations of the
+// children into move priors
+const PRIOR_TEMPERATURE: f32 = 100.0;
+
+#[derive(Debug)]
+struct Node {
+    // The move leading here; None only at the root
+    mv: Option<Move>,
+    children: Vec<usize>,
+    prior: f32,
+    visits: u32,
+    // Sum of the values backed up through this node, for the side that made its move
+    value_sum: f32,
+    // Value for the side to move once the game is known to be over here
+    terminal: Option<f32>,
+    expanded: bool,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, prior: f32) -> Self {
+        Self {
+            mv,
+            children: Vec::new(),
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            terminal: None,
+            expanded: false,
+        }
+    }
+
+    // Mean value for the side that made this node's move
+    fn q(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.value_sum / self.visits as f32
+        }
+    }
+}
+
+// What the tree thinks of one root move
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootStats {
+    pub mv: Move,
+    pub visits: u32,
+    // Mean value in (-1, 1) for the side to move at the root
+    pub value: f32,
+    // The game ends with this move: 1 a win, -1 a loss, 0 a draw
+    pub terminal: Option<f32>,
+    // The most visited line starting with this move
+    pub pv: Vec<Move>,
+}
+
+// A PUCT search tree: each playout walks down by the mean value plus an
+// exploration bonus weighted by the move's prior, expands the leaf it reaches,
+// and backs the leaf's static evaluation up the path. Positions are replayed
+// from the root, so the tree only stores moves.
+pub struct Tree {
+    nodes: Vec<Node>,
+    generator: MoveGenerator,
+    exploration: f32,
+}
+
+impl Tree {
+    // The root's children are the given moves, with equal priors until the root is expanded
+    pub fn new(root_moves: &[Move], exploration: f32) -> Self {
+        let mut nodes = vec![Node::new(None, 1.0)];
+        let prior = 1.0 / root_moves.len().max(1) as f32;
+        for &mv in root_moves {
+            nodes.push(Node::new(Some(mv), prior));
+        }
+        nodes[0].children = (1..nodes.len()).collect();
+        Self {
+            nodes,
+            generator: MoveGenerator::new(),
+            exploration,
+        }
+    }
+
+    // One playout from the root. evaluate scores a position in centipawns for
+    // its side to move; the number of positions it was asked for is returned.
+    pub fn playout(&mut self, root: &Board, evaluate: &mut impl FnMut(&Board) -> i32) -> u64 {
+        let mut board = root.clone();
+        let mut path = vec![0];
+        let mut node = 0;
+        while self.nodes[node].expanded && self.nodes[node].terminal.is_none() {
+            node = self.select(node);
+            board.make_move(self.nodes[node].mv.expect("only the root has no move"));
+            path.push(node);
+        }
+
+        let mut evaluations = 0;
+        let value = match self.nodes[node].terminal {
+            Some(value) => value,
+            None => {
+                evaluations = self.expand(node, &board, evaluate);
+                self.nodes[node].terminal.unwrap_or_else(|| to_value(evaluate(&board)))
+            }
+        };
+        self.backup(&path, value);
+        evaluations + 1
+    }
+
+    fn select(&self, node: usize) -> usize {
+        let parent = &self.nodes[node];
+        let scale = self.exploration * (parent.visits.max(1) as f32).sqrt();
+        let score = |child: &Node| child.q() + scale * child.prior / (1 + child.visits) as f32;
+        *parent
+            .children
+            .iter()
+            .max_by(|&&a, &&b| score(&self.nodes[a]).total_cmp(&score(&self.nodes[b])))
+            .expect("expanded nodes that aren't terminal have children")
+    }
+
+    // Adds the children of a leaf, with priors from their static evaluations,
+    // or marks the leaf terminal. The root keeps the moves it was given.
+    fn expand(&mut self, node: usize, board: &Board, evaluate: &mut impl FnMut(&Board) -> i32) -> u64 {
+        self.nodes[node].expanded = true;
+        let moves = if node == 0 {
+            self.nodes[0]
+                .children
+                .iter()
+                .map(|&child| self.nodes[child].mv.unwrap())
+                .collect()
+        } else {
+            self.generator.generate_moves(board)
+        };
+        if let Some(value) = terminal_value(&self.generator, board, !moves.is_empty()) {
+            self.nodes[node].terminal = Some(value);
+            return 0;
+        }
+
+        // Children are scored for the side choosing between them
+        let scores: Vec<f32> = moves
+            .iter()
+            .map(|&mv| {
+                let mut child = board.clone();
+                child.make_move(mv);
+                -evaluate(&child) as f32 / PRIOR_TEMPERATURE
+            })
+            .collect();
+        let max = scores.iter().copied().fold(f32::MIN, f32::max);
+        let weights: Vec<f32> = scores.iter().map(|score| (score - max).exp()).collect();
+        let total: f32 = weights.iter().sum();
+
+        if node == 0 {
+            for (i, &child) in self.nodes[0].children.clone().iter().enumerate() {
+                self.nodes[child].prior = weights[i] / total;
+            }
+        } else {
+            let first = self.nodes.len();
+            for (&mv, weight) in moves.iter().zip(&weights) {
+                self.nodes.push(Node::new(Some(mv), weight / total));
+            }
+            self.nodes[node].children = (first..self.nodes.len()).collect();
+        }
+        moves.len() as u64
+    }
+
+    // value is for the side to move at the end of the path, so it changes sign every ply
+    fn backup(&mut self, path: &[usize], value: f32) {
+        let mut value = -value;
+        for &node in path.iter().rev() {
+            self.nodes[node].visits += 1;
+            self.nodes[node].value_sum += value;
+            value = -value;
+        }
+    }
+
+    pub fn playouts(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    // Root moves, most visited first
+    pub fn root_stats(&self) -> Vec<RootStats> {
+        let mut stats: Vec<RootStats> = self.nodes[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &self.nodes[child];
+                RootStats {
+                    mv: node.mv.unwrap(),
+                    visits: node.visits,
+                    value: node.q(),
+                    terminal: node.terminal.map(|value| -value),
+                    pv: self.pv(child),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| b.visits.cmp(&a.visits).then(b.value.total_cmp(&a.value)));
+        stats
+    }
+
+    fn pv(&self, mut node: usize) -> Vec<Move> {
+        let mut pv = vec![self.nodes[node].mv.unwrap()];
+        while let Some(&child) = self.nodes[node]
+            .children
+            .iter()
+            .filter(|&&child| self.nodes[child].visits > 0)
+            .max_by_key(|&&child| self.nodes[child].visits)
+        {
+            pv.push(self.nodes[child].mv.unwrap());
+            node = child;
+        }
+        pv
+    }
+}
+
+// The game's value for the side to move when it is over: won, lost or drawn
+fn terminal_value(generator: &MoveGenerator, board: &Board, has_moves: bool) -> Option<f32> {
+    match board.variant.result(generator, board, has_moves) {
+        Some(GameState::Checkmate(winner) | GameState::VariantWin(winner)) => {
+            Some(if winner == board.side_to_move { 1.0 } else { -1.0 })
+        }
+        Some(_) => Some(0.0),
+        None if board.halfmove_clock >= 100 || board.is_insufficient_material(MaterialRule::Fide) => Some(0.0),
+        None => None,
+    }
+}
+
+pub fn to_value(centipawns: i32) -> f32 {
+    (centipawns as f32 / VALUE_SCALE).tanh()
+}
+
+pub fn to_centipawns(value: f32) -> i32 {
+    (value.clamp(-0.999, 0.999).atanh() * VALUE_SCALE).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Color;
+    use crate::evaluation::Evaluator;
+
+    fn run(fen: &str, playouts: u32) -> Vec<RootStats> {
+        let board = Board::from_fen(fen).unwrap();
+        let evaluator = Evaluator::new();
+        // The tree wants scores for the side to move
+        let mut evaluate = |board: &Board| match board.side_to_move {
+            Color::White => evaluator.evaluate(board),
+            Color::Black => -evaluator.evaluate(board),
+        };
+        let moves = MoveGenerator::new().generate_moves(&board);
+        let mut tree = Tree::new(&moves, 1.5);
+        while tree.playouts() < playouts {
+            tree.playout(&board, &mut evaluate);
+        }
+        tree.root_stats()
+    }
+
+    #[test]
+    fn test_finds_the_mate_and_the_free_queen() {
+        let stats = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 200);
+        assert_eq!(stats[0].mv.to_string(), "a1a8");
+        assert_eq!(stats[0].terminal, Some(1.0));
+
+        let stats = run("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", 300);
+        assert_eq!(stats[0].mv.to_string(), "d2d5");
+        assert!(stats[0].value > 0.5);
+        assert_eq!(stats[0].pv[0], stats[0].mv);
+        assert!(stats[0].pv.len() > 1);
+    }
+
+    #[test]
+    fn test_value_conversion() {
+        assert_eq!(to_value(0), 0.0);
+        assert_eq!(to_centipawns(to_value(150)), 150);
+        assert!(to_centipawns(1.0) < 10000);
+    }
+}
@@ -1,6 +1,18 @@
-use crate::board::{Board, Color, Piece};
-
-#[derive(Debug, Clone, Copy, PartialEq)]
+use crate::board::{parse_square, Board, Color, Piece};
+use crate::attacks::{self, BETWEEN, KING_ATTACKS, KNIGHT_ATTACKS, LINE};
+use crate::psqt::PIECE_VALUES;
+use crate::repetition::RepetitionHistory;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+// No position has more legal moves than this
+pub const MAX_MOVES: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
 pub struct Move {
     pub from: u8,
     pub to: u8,
@@ -83,451 +95,477 @@ impl Move {
             castling_rook_to: None,
         }
     }
+
+    // Pack into 16 bits: from (6 bits) | to (6 bits) | promotion (3 bits).
+    // A zero value never describes a real move, so it can stand for "no move".
+    pub fn to_u16(self) -> u16 {
+        let promo = match self.promotion {
+            Some(Piece::Knight) => 1,
+            Some(Piece::Bishop) => 2,
+            Some(Piece::Rook) => 3,
+            Some(Piece::Queen) => 4,
+            _ => 0,
+        };
+        (self.from as u16) | ((self.to as u16) << 6) | (promo << 12)
+    }
+
+    // Standard algebraic notation, with check marks, for the move played on board
+    pub fn to_san(&self, board: &Board) -> String {
+        crate::san::to_san(board, &MoveGenerator, self)
+    }
 }
 
-pub struct MoveGenerator {
-    pub bishop_magics: [u64; 64],
-    pub rook_magics: [u64; 64],
+// UCI coordinate notation, "e2e4" or "e7e8q"
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let square = |square: u8| [(b'a' + square % 8) as char, (b'1' + square / 8) as char];
+        let [from_file, from_rank] = square(self.from);
+        let [to_file, to_rank] = square(self.to);
+        write!(f, "{}{}{}{}", from_file, from_rank, to_file, to_rank)?;
+        match self.promotion {
+            Some(Piece::Queen) => write!(f, "q"),
+            Some(Piece::Rook) => write!(f, "r"),
+            Some(Piece::Bishop) => write!(f, "b"),
+            Some(Piece::Knight) => write!(f, "n"),
+            _ => Ok(()),
+        }
+    }
 }
 
-impl MoveGenerator {
-    pub fn new() -> Self {
-        Self {
-            bishop_magics: [0; 64],
-            rook_magics: [0; 64],
+// The UCI move with what moves and what it takes, "e5d6 Pawn x Pawn e.p."
+impl fmt::Debug for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?}", self, self.piece)?;
+        if let Some(captured) = self.captured_piece {
+            write!(f, " x {:?}", captured)?;
+        }
+        if self.is_en_passant {
+            write!(f, " e.p.")?;
         }
+        if let (true, Some(rook_from), Some(rook_to)) = (self.is_castling, self.castling_rook_from, self.castling_rook_to) {
+            write!(f, " castling, rook {}", Move::new(rook_from, rook_to, Piece::Rook))?;
+        }
+        Ok(())
+    }
+}
+
+// One capture of a static exchange, with the material balance for the side
+// that began the exchange once it is made
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExchangeStep {
+    pub capture: Move,
+    pub balance: i32,
+}
+
+// A handle on the attack tables, which are process-wide statics, so making
+// one costs nothing and any number can share them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MoveGenerator;
+
+// A move buffer that keeps its allocation when cleared, so a search can hold
+// one per ply and generate into it at every node
+#[derive(Debug, Clone, Default)]
+pub struct MoveList {
+    moves: Vec<Move>,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self { moves: Vec::with_capacity(MAX_MOVES) }
+    }
+
+    pub fn clear(&mut self) {
+        self.moves.clear();
+    }
+
+    pub fn push(&mut self, mv: Move) {
+        self.moves.push(mv);
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves
+    }
+}
+
+// Which of the legal moves to generate: captures take in promotions, so
+// quiets are everything else
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Selection {
+    All,
+    Captures,
+    Quiets,
+}
+
+// How generation tells the legal moves from the rest. Under the standard rule
+// the checks and pins are worked out once for the position; a variant with
+// rules of its own has each move made on a scratch copy and judged whole.
+enum Legality {
+    NoKing,
+    Standard {
+        king: u8,
+        // Where a move other than the king's must land: anywhere, the checker
+        // or the squares between it and the king, or nowhere in double check
+        check_mask: u64,
+        pinned: u64,
+        occupied: u64,
+    },
+    Variant(Board),
+}
+
+impl MoveGenerator {
+    pub const fn new() -> Self {
+        Self
     }
 
+    // PEXT tables where the CPU has BMI2, magic bitboards otherwise
     fn get_bishop_attacks(&self, square: u8, occupied: u64) -> u64 {
-        let mut attacks = 0u64;
-        let rank = (square / 8) as i8;
-        let file = (square % 8) as i8;
-        
-        // Generate attacks in all four diagonal directions
-        for &(dr, df) in &[(1, 1), (1, -1), (-1, 1), (-1, -1)] {
-            let mut r = rank + dr;
-            let mut f = file + df;
-            while r >= 0 && r < 8 && f >= 0 && f < 8 {
-                let target = (r * 8 + f) as u8;
-                let target_mask = 1u64 << target;
-                attacks |= target_mask;
-                if (occupied & target_mask) != 0 {
-                    break;
-                }
-                r += dr;
-                f += df;
-            }
-        }
-        attacks
+        attacks::bishop_attacks(square, occupied)
     }
 
     fn get_rook_attacks(&self, square: u8, occupied: u64) -> u64 {
-        let mut attacks = 0u64;
-        let rank = (square / 8) as i8;
-        let file = (square % 8) as i8;
-        
-        // Generate attacks in all four orthogonal directions
-        for &(dr, df) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
-            let mut r = rank + dr;
-            let mut f = file + df;
-            while r >= 0 && r < 8 && f >= 0 && f < 8 {
-                let target = (r * 8 + f) as u8;
-                let target_mask = 1u64 << target;
-                attacks |= target_mask;
-                if (occupied & target_mask) != 0 {
-                    break;
-                }
-                r += dr;
-                f += df;
-            }
-        }
-        attacks
+        attacks::rook_attacks(square, occupied)
     }
 
-    pub fn is_square_under_attack(&self, board: &Board, square: u8, attacker_color: Color) -> bool {
-        let square_mask = 1u64 << square;
-        let square_rank = square / 8;
-        let square_file = square % 8;
-        let attacker_pieces = match attacker_color {
+    // The pieces of attacker_color attacking square, with sliders blocked by
+    // occupied, which need not be the board's own occupancy
+    fn attackers(&self, board: &Board, square: u8, occupied: u64, attacker_color: Color) -> u64 {
+        let pieces = match attacker_color {
             Color::White => &board.white_pieces,
             Color::Black => &board.black_pieces,
         };
-        let defender_pieces = match attacker_color {
-            Color::White => &board.black_pieces,
-            Color::Black => &board.white_pieces,
-        };
+        let square_index = square as usize;
+        // A pawn of the other colour on the square would attack the attacking pawns
+        let attackers = (attacks::pawn_attacks(attacker_color.opposite(), square) & pieces[0])
+            | (KNIGHT_ATTACKS[square_index] & pieces[1])
+            | (KING_ATTACKS[square_index] & pieces[5])
+            | (self.get_bishop_attacks(square, occupied) & (pieces[2] | pieces[4]))
+            | (self.get_rook_attacks(square, occupied) & (pieces[3] | pieces[4]));
+        attackers & occupied
+    }
 
-        // Check pawn attacks
-        let pawn_attacks = match attacker_color {
-            Color::White => {
-                let mut attacks = 0u64;
-                if square_rank < 7 {
-                    if square_file > 0 {
-                        attacks |= 1u64 << (square + 7);
-                    }
-                    if square_file < 7 {
-                        attacks |= 1u64 << (square + 9);
-                    }
-                }
-                attacks
-            }
-            Color::Black => {
-                let mut attacks = 0u64;
-                if square_rank > 0 {
-                    if square_file > 0 {
-                        attacks |= 1u64 << (square - 9);
-                    }
-                    if square_file < 7 {
-                        attacks |= 1u64 << (square - 7);
-                    }
-                }
-                attacks
-            }
-        };
-        if (pawn_attacks & attacker_pieces[0]) != 0 {
-            return true;
+    pub fn is_square_under_attack(&self, board: &Board, square: u8, attacker_color: Color) -> bool {
+        let occupied = board.white_pieces.iter().chain(board.black_pieces.iter()).fold(0, |acc, &bb| acc | bb);
+        self.attackers(board, square, occupied, attacker_color) != 0
+    }
+
+    // The enemy pieces giving check to color's king; none without a king
+    pub fn checkers(&self, board: &Board, color: Color) -> u64 {
+        let king = if color == Color::White { board.white_pieces[5] } else { board.black_pieces[5] };
+        if king == 0 {
+            return 0;
         }
+        let occupied = board.white_pieces.iter().chain(board.black_pieces.iter()).fold(0, |acc, &bb| acc | bb);
+        self.attackers(board, king.trailing_zeros() as u8, occupied, color.opposite())
+    }
 
-        // Check knight attacks
-        let knight_attacks = {
-            let mut attacks = 0u64;
-            let knight_moves = [
-                (-2, -1), (-2, 1), (-1, -2), (-1, 2),
-                (1, -2), (1, 2), (2, -1), (2, 1)
-            ];
-            for &(dr, df) in &knight_moves {
-                let rank = square_rank as i8 + dr;
-                let file = square_file as i8 + df;
-                if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
-                    attacks |= 1u64 << (rank * 8 + file);
-                }
-            }
-            attacks
+    // The pieces of color that are the only thing between its king and an
+    // enemy slider, so may only move along that line
+    pub fn pinned(&self, board: &Board, color: Color) -> u64 {
+        let (ours, theirs) = match color {
+            Color::White => (&board.white_pieces, &board.black_pieces),
+            Color::Black => (&board.black_pieces, &board.white_pieces),
         };
-        if (knight_attacks & attacker_pieces[1]) != 0 {
-            return true;
+        if ours[5] == 0 {
+            return 0;
         }
-
-        // Check king attacks
-        let king_attacks = {
-            let mut attacks = 0u64;
-            let king_moves = [
-                (-1, -1), (-1, 0), (-1, 1),
-                (0, -1), (0, 1),
-                (1, -1), (1, 0), (1, 1)
-            ];
-            for &(dr, df) in &king_moves {
-                let rank = square_rank as i8 + dr;
-                let file = square_file as i8 + df;
-                if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
-                    attacks |= 1u64 << (rank * 8 + file);
-                }
+        let king = ours[5].trailing_zeros() as u8;
+        let our_occupied = ours.iter().fold(0, |acc, &bb| acc | bb);
+        let their_occupied = theirs.iter().fold(0, |acc, &bb| acc | bb);
+        // Enemy sliders that would see the king through our own pieces
+        let mut snipers = (self.get_bishop_attacks(king, their_occupied) & (theirs[2] | theirs[4]))
+            | (self.get_rook_attacks(king, their_occupied) & (theirs[3] | theirs[4]));
+        let mut pinned = 0;
+        while snipers != 0 {
+            let sniper = snipers.trailing_zeros() as usize;
+            let blockers = BETWEEN[king as usize][sniper] & (our_occupied | their_occupied);
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & our_occupied;
             }
-            attacks
-        };
-        if (king_attacks & attacker_pieces[5]) != 0 {
-            return true;
+            snipers &= snipers - 1;
         }
+        pinned
+    }
 
-        // Check bishop/queen attacks (diagonals)
-        for &(dr, df) in &[(1, 1), (1, -1), (-1, 1), (-1, -1)] {
-            let mut rank = square_rank as i8;
-            let mut file = square_file as i8;
-            loop {
-                rank += dr;
-                file += df;
-                if rank < 0 || rank >= 8 || file < 0 || file >= 8 {
-                    break;
-                }
-                let target = 1u64 << (rank * 8 + file);
-                // If we hit a piece, check if it's an attacker's bishop or queen
-                if (attacker_pieces[2] | attacker_pieces[4]) & target != 0 {
-                    return true;
-                }
-                // If we hit any other piece, stop looking in this direction
-                if defender_pieces.iter().any(|&bb| bb & target != 0) ||
-                   attacker_pieces.iter().any(|&bb| bb & target != 0) {
-                    break;
-                }
+    pub fn is_king_in_check(&self, board: &Board, color: Color) -> bool {
+        self.checkers(board, color) != 0
+    }
+
+    // Whether mv is one of the legal moves in board, flags and all. Pins and
+    // checks are read off the attack tables instead of making the move, so
+    // this is cheap enough for validating single moves from outside.
+    pub fn is_legal(&self, board: &Board, mv: &Move) -> bool {
+        let us = board.side_to_move;
+        let (ours, theirs) = match us {
+            Color::White => (&board.white_pieces, &board.black_pieces),
+            Color::Black => (&board.black_pieces, &board.white_pieces),
+        };
+        let from_mask = 1u64 << mv.from;
+        let to_mask = 1u64 << mv.to;
+        let our_occupied = ours.iter().fold(0, |acc, &bb| acc | bb);
+        let occupied = our_occupied | theirs.iter().fold(0, |acc, &bb| acc | bb);
+
+        // The piece must be ours and the flags must say what the move does
+        if mv.from >= 64 || mv.to >= 64 || ours[mv.piece as usize] & from_mask == 0 || our_occupied & to_mask != 0 {
+            return false;
+        }
+        if mv.is_castling {
+            return self.is_legal_castling(board, mv);
+        }
+        if mv.castling_rook_from.is_some() || mv.castling_rook_to.is_some() {
+            return false;
+        }
+        let target = Piece::ALL.into_iter().find(|&piece| theirs[piece as usize] & to_mask != 0);
+        let forward = |mask: u64| if us == Color::White { mask << 8 } else { mask >> 8 };
+        let victim_mask = if us == Color::White { to_mask >> 8 } else { to_mask << 8 };
+        if mv.is_en_passant {
+            if mv.piece != Piece::Pawn || board.en_passant_square != Some(mv.to) || target.is_some()
+                || mv.captured_piece != Some(Piece::Pawn) || theirs[0] & victim_mask == 0 {
+                return false;
             }
+        } else if mv.captured_piece != target {
+            return false;
+        }
+        let last_rank = if us == Color::White { 7 } else { 0 };
+        let promotes = mv.piece == Piece::Pawn && mv.to / 8 == last_rank;
+        if promotes != mv.promotion.is_some() || matches!(mv.promotion, Some(Piece::Pawn | Piece::King)) {
+            return false;
         }
 
-        // Check rook/queen attacks (orthogonals)
-        for &(dr, df) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
-            let mut rank = square_rank as i8;
-            let mut file = square_file as i8;
-            loop {
-                rank += dr;
-                file += df;
-                if rank < 0 || rank >= 8 || file < 0 || file >= 8 {
-                    break;
-                }
-                let target = 1u64 << (rank * 8 + file);
-                // If we hit a piece, check if it's an attacker's rook or queen
-                if (attacker_pieces[3] | attacker_pieces[4]) & target != 0 {
-                    return true;
-                }
-                // If we hit any other piece, stop looking in this direction
-                if defender_pieces.iter().any(|&bb| bb & target != 0) ||
-                   attacker_pieces.iter().any(|&bb| bb & target != 0) {
-                    break;
-                }
+        // The piece must reach the square
+        let reachable = match mv.piece {
+            Piece::Pawn => {
+                let captures = attacks::pawn_attacks(us, mv.from) & if mv.is_en_passant { to_mask } else { occupied };
+                let single = forward(from_mask) & !occupied;
+                let double = if board.variant.can_double_push(us, mv.from / 8) { forward(single) & !occupied } else { 0 };
+                captures | single | double
             }
+            Piece::Knight => KNIGHT_ATTACKS[mv.from as usize],
+            Piece::Bishop => self.get_bishop_attacks(mv.from, occupied),
+            Piece::Rook => self.get_rook_attacks(mv.from, occupied),
+            Piece::Queen => self.get_bishop_attacks(mv.from, occupied) | self.get_rook_attacks(mv.from, occupied),
+            Piece::King => KING_ATTACKS[mv.from as usize],
+        };
+        if reachable & to_mask == 0 {
+            return false;
         }
 
-        false
-    }
-
-    pub fn is_king_in_check(&self, board: &Board, color: Color) -> bool {
-        let king_pieces = match color {
-            Color::White => &board.white_pieces[5],
-            Color::Black => &board.black_pieces[5],
-        };
-        
-        // Find the king's square
-        let mut king_square = None;
-        for square in 0..64 {
-            if (king_pieces >> square) & 1 != 0 {
-                king_square = Some(square as u8);
-                break;
-            }
+        // A variant with rules of its own has the move made and judged whole
+        if !board.variant.standard_legality() {
+            let mut board_copy = board.clone();
+            board_copy.make_move(*mv);
+            return board.variant.is_legal(self, board, &board_copy);
         }
-        
-        if let Some(king_square) = king_square {
-            self.is_square_under_attack(board, king_square, color.opposite())
-        } else {
-            false  // No king found (shouldn't happen in a valid position)
+        if ours[5] == 0 {
+            return true;
+        }
+        let king = ours[5].trailing_zeros() as u8;
+        let them = us.opposite();
+        if mv.piece == Piece::King {
+            // Off the king's old square, so it can't hide behind itself from a slider
+            return self.attackers(board, mv.to, occupied ^ from_mask, them) == 0;
+        }
+        if mv.is_en_passant {
+            // Two pawns leave one rank at once, which can uncover the king along it
+            return self.attackers(board, king, occupied ^ from_mask ^ to_mask ^ victim_mask, them) == 0;
         }
+        let checkers = self.attackers(board, king, occupied, them);
+        if checkers.count_ones() > 1 {
+            return false;
+        }
+        // Take the checker or block it
+        if checkers != 0 && (BETWEEN[king as usize][checkers.trailing_zeros() as usize] | checkers) & to_mask == 0 {
+            return false;
+        }
+        // A pinned piece stays on the line through its king
+        self.pinned(board, us) & from_mask == 0 || LINE[king as usize][mv.from as usize] & to_mask != 0
     }
 
-    pub fn is_move_valid(&self, board: &Board, mv: &Move) -> bool {
-        // First verify that the piece at the source square matches the move's piece and color
-        let from_mask = 1u64 << mv.from;
-        let pieces = if board.side_to_move == Color::White {
-            &board.white_pieces
-        } else {
-            &board.black_pieces
+    // Castling is legal with the right still held, the rook in its corner, the
+    // squares between them empty, and none the king crosses attacked
+    fn is_legal_castling(&self, board: &Board, mv: &Move) -> bool {
+        let (home, pieces) = match board.side_to_move {
+            Color::White => (0, &board.white_pieces),
+            Color::Black => (56, &board.black_pieces),
         };
-        
-        // Check if the piece at the source square matches the move's piece
-        let piece_index = match mv.piece {
-            Piece::Pawn => 0,
-            Piece::Knight => 1,
-            Piece::Bishop => 2,
-            Piece::Rook => 3,
-            Piece::Queen => 4,
-            Piece::King => 5,
+        let kingside = mv.to > mv.from;
+        let (right, rook_from, rook_to, king_to) = match (board.side_to_move, kingside) {
+            (Color::White, true) => (0b0001, home + 7, home + 5, home + 6),
+            (Color::White, false) => (0b0010, home, home + 3, home + 2),
+            (Color::Black, true) => (0b0100, home + 7, home + 5, home + 6),
+            (Color::Black, false) => (0b1000, home, home + 3, home + 2),
         };
-        if (pieces[piece_index] & from_mask) == 0 {
+        if *mv != Move::new_castling(home + 4, king_to, rook_from, rook_to)
+            || board.castling_rights & right == 0
+            || pieces[3] & (1 << rook_from) == 0 {
             return false;
         }
-
-        // Check if the destination square is occupied by our own piece
-        let to_mask = 1u64 << mv.to;
-        if pieces.iter().any(|&p| (p & to_mask) != 0) {
+        let occupied = board.white_pieces.iter().chain(board.black_pieces.iter()).fold(0u64, |acc, &p| acc | p);
+        if BETWEEN[(home + 4) as usize][rook_from as usize] & occupied != 0 {
             return false;
         }
+        let them = board.side_to_move.opposite();
+        let mut crossed = if kingside { home + 4..=home + 6 } else { home + 2..=home + 4 };
+        if crossed.any(|square| self.is_square_under_attack(board, square, them)) {
+            return false;
+        }
+        if board.variant.standard_legality() {
+            return true;
+        }
+        let mut board_copy = board.clone();
+        board_copy.make_move(*mv);
+        board.variant.is_legal(self, board, &board_copy)
+    }
 
-        // Check if the move is legal for the piece type
-        let from_rank = (mv.from / 8) as i8;
-        let from_file = (mv.from % 8) as i8;
-        let to_rank = (mv.to / 8) as i8;
-        let to_file = (mv.to % 8) as i8;
+    // Static exchange evaluation: the material mv wins once both sides have
+    // taken back on its target square, least valuable piece first, for as
+    // long as it pays. X-rays count; pins and checks are ignored.
+    pub fn see(&self, board: &Board, mv: &Move) -> i32 {
+        self.exchange(board, mv).0
+    }
 
-        let is_legal = match mv.piece {
-            Piece::Pawn => {
-                // Pawns can only move forward
-                let direction = if board.side_to_move == Color::White { 1 } else { -1 };
-                let rank_diff = to_rank - from_rank;
-                let file_diff = to_file - from_file;
+    // The SEE score with the whole exchange behind it: every capture either
+    // side has, mv first, until one runs out of attackers or a king would be
+    // taken back. Where the sides would rather stop shows only in the score.
+    pub fn exchange(&self, board: &Board, mv: &Move) -> (i32, Vec<ExchangeStep>) {
+        let value = |piece: Piece| PIECE_VALUES[piece as usize];
+        let pieces = |color: Color| if color == Color::White { &board.white_pieces } else { &board.black_pieces };
+        let mut occupied = board.white_pieces.iter().chain(board.black_pieces.iter()).fold(0, |acc, &bb| acc | bb);
+        occupied &= !(1u64 << mv.from);
+        if mv.is_en_passant {
+            occupied &= !(1u64 << (mv.to ^ 8));
+        }
 
-                // Single push
-                if file_diff == 0 && rank_diff == direction {
-                    // Check if the destination square is empty
-                    let to_mask = 1u64 << mv.to;
-                    board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                        .all(|&p| (p & to_mask) == 0)
-                }
-                // Double push from starting rank
-                else if file_diff == 0 && rank_diff == 2 * direction && 
-                        ((board.side_to_move == Color::White && from_rank == 1) || 
-                         (board.side_to_move == Color::Black && from_rank == 6)) {
-                    // Check if both squares are empty
-                    let to_mask = 1u64 << mv.to;
-                    let intermediate = if board.side_to_move == Color::White {
-                        mv.from + 8
-                    } else {
-                        mv.from - 8
-                    };
-                    let intermediate_mask = 1u64 << intermediate;
-                    board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                        .all(|&p| (p & to_mask) == 0 && (p & intermediate_mask) == 0)
-                }
-                // Capture
-                else if file_diff.abs() == 1 && rank_diff == direction {
-                    // Check if there's a piece to capture
-                    let to_mask = 1u64 << mv.to;
-                    let opponent_pieces = if board.side_to_move == Color::White {
-                        &board.black_pieces
-                    } else {
-                        &board.white_pieces
-                    };
-                    if mv.is_en_passant {
-                        if board.en_passant_square != Some(mv.to) {
-                            false
-                        } else {
-                            // Check if there's a pawn to capture
-                            let captured_pawn_square = if board.side_to_move == Color::White {
-                                mv.to - 8
-                            } else {
-                                mv.to + 8
-                            };
-                            let captured_pawn_mask = 1u64 << captured_pawn_square;
-                            opponent_pieces[0] & captured_pawn_mask != 0
-                        }
-                    } else {
-                        opponent_pieces.iter().any(|&p| (p & to_mask) != 0)
-                    }
-                }
-                else {
-                    false
-                }
-            }
-            Piece::Knight => {
-                let rank_diff = (to_rank - from_rank).abs();
-                let file_diff = (to_file - from_file).abs();
-                (rank_diff == 2 && file_diff == 1) || (rank_diff == 1 && file_diff == 2)
-            }
-            Piece::Bishop => {
-                let rank_diff = (to_rank - from_rank).abs();
-                let file_diff = (to_file - from_file).abs();
-                if rank_diff != file_diff {
-                    false
-                } else {
-                    // Check if the path is clear
-                    let rank_step = if to_rank > from_rank { 1 } else { -1 };
-                    let file_step = if to_file > from_file { 1 } else { -1 };
-                    let mut rank = from_rank + rank_step;
-                    let mut file = from_file + file_step;
-                    while rank != to_rank && file != to_file {
-                        let square = (rank * 8 + file) as u8;
-                        let square_mask = 1u64 << square;
-                        if board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                            .any(|&p| (p & square_mask) != 0) {
-                            return false;
-                        }
-                        rank += rank_step;
-                        file += file_step;
-                    }
-                    true
-                }
-            }
-            Piece::Rook => {
-                let rank_diff = (to_rank - from_rank).abs();
-                let file_diff = (to_file - from_file).abs();
-                if rank_diff != 0 && file_diff != 0 {
-                    false
-                } else {
-                    // Check if the path is clear
-                    let rank_step = if to_rank > from_rank { 1 } else if to_rank < from_rank { -1 } else { 0 };
-                    let file_step = if to_file > from_file { 1 } else if to_file < from_file { -1 } else { 0 };
-                    let mut rank = from_rank + rank_step;
-                    let mut file = from_file + file_step;
-                    while rank != to_rank || file != to_file {
-                        let square = (rank * 8 + file) as u8;
-                        let square_mask = 1u64 << square;
-                        if board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                            .any(|&p| (p & square_mask) != 0) {
-                            return false;
-                        }
-                        rank += rank_step;
-                        file += file_step;
-                    }
-                    true
-                }
-            }
-            Piece::Queen => {
-                let rank_diff = (to_rank - from_rank).abs();
-                let file_diff = (to_file - from_file).abs();
-                if rank_diff != 0 && file_diff != 0 && rank_diff != file_diff {
-                    false
-                } else {
-                    // Check if the path is clear
-                    let rank_step = if to_rank > from_rank { 1 } else if to_rank < from_rank { -1 } else { 0 };
-                    let file_step = if to_file > from_file { 1 } else if to_file < from_file { -1 } else { 0 };
-                    let mut rank = from_rank + rank_step;
-                    let mut file = from_file + file_step;
-                    while rank != to_rank || file != to_file {
-                        let square = (rank * 8 + file) as u8;
-                        let square_mask = 1u64 << square;
-                        if board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                            .any(|&p| (p & square_mask) != 0) {
-                            return false;
-                        }
-                        rank += rank_step;
-                        file += file_step;
-                    }
-                    true
-                }
+        // What each capture takes, the first one's promotion included
+        let captured = if mv.is_en_passant { Some(Piece::Pawn) } else { mv.captured_piece };
+        let mut gains = vec![captured.map_or(0, value) + mv.promotion.map_or(0, |piece| value(piece) - value(Piece::Pawn))];
+        let mut steps = vec![ExchangeStep { capture: *mv, balance: gains[0] }];
+        let mut on_square = mv.promotion.unwrap_or(mv.piece);
+        let mut side = board.side_to_move.opposite();
+        loop {
+            let attackers = self.attackers(board, mv.to, occupied, side);
+            let Some(piece) = Piece::ALL.into_iter().find(|&piece| attackers & pieces(side)[piece as usize] != 0) else {
+                break;
+            };
+            let from = (attackers & pieces(side)[piece as usize]).trailing_zeros() as u8;
+            if piece == Piece::King && self.attackers(board, mv.to, occupied & !(1u64 << from), side.opposite()) != 0 {
+                break;
             }
-            Piece::King => {
-                let rank_diff = (to_rank - from_rank).abs();
-                let file_diff = (to_file - from_file).abs();
-                if mv.is_castling {
-                    // Check if castling is still allowed
-                    let castling_mask = if board.side_to_move == Color::White {
-                        if mv.to > mv.from { 0b0001 } else { 0b0010 }  // White kingside or queenside
-                    } else {
-                        if mv.to > mv.from { 0b0100 } else { 0b1000 }  // Black kingside or queenside
-                    };
-                    if board.castling_rights & castling_mask == 0 {
-                        false
-                    } else {
-                        // Check if the path is clear
-                        let rank = if board.side_to_move == Color::White { 0 } else { 7 };
-                        let (start_file, end_file) = if mv.to > mv.from {
-                            (4, 7)  // Kingside
-                        } else {
-                            (0, 4)  // Queenside
-                        };
-                        for file in start_file..=end_file {
-                            let square = rank * 8 + file;
-                            let square_mask = 1u64 << square;
-                            if file != start_file && file != end_file &&  // Skip king and rook squares
-                                board.white_pieces[0..6].iter().chain(board.black_pieces[0..6].iter())
-                                .any(|&p| (p & square_mask) != 0) {
-                                return false;
-                            }
-                        }
+            occupied &= !(1u64 << from);
+            gains.push(value(on_square));
+            let sign = if side == board.side_to_move { 1 } else { -1 };
+            let balance = steps[steps.len() - 1].balance + sign * value(on_square);
+            steps.push(ExchangeStep { capture: Move { captured_piece: Some(on_square), ..Move::new(from, mv.to, piece) }, balance });
+            on_square = piece;
+            side = side.opposite();
+        }
 
-                        // Check if any of the squares the king moves through are under attack
-                        let attacker_color = board.side_to_move.opposite();
-                        for file in if mv.to > mv.from { 4..=6 } else { 2..=4 } {
-                            let square = rank * 8 + file;
-                            if self.is_square_under_attack(board, square as u8, attacker_color) {
-                                return false;
-                            }
-                        }
-                        true
-                    }
-                } else {
-                    rank_diff <= 1 && file_diff <= 1
-                }
-            }
+        // Folded from the end: each side only takes back when it gains by it
+        let replies = gains[1..].iter().rev().fold(0, |score, &gain| (gain - score).max(0));
+        (gains[0] - replies, steps)
+    }
+
+    // The legal move from one square to another with the given promotion, with
+    // its capture, castling and en passant details filled in from the board
+    pub fn find_move(&self, board: &Board, from: u8, to: u8, promotion: Option<Piece>) -> Option<Move> {
+        let (piece, color) = board.get_piece_at(from)?;
+        if color != board.side_to_move {
+            return None;
+        }
+        let captured = board.get_piece_at(to).map(|(piece, _)| piece);
+        let mv = if piece == Piece::King && from % 8 == 4 && from.abs_diff(to) == 2 {
+            let (rook_from, rook_to) = if to > from { (from + 3, from + 1) } else { (from - 4, from - 1) };
+            Move { promotion, ..Move::new_castling(from, to, rook_from, rook_to) }
+        } else if piece == Piece::Pawn && board.en_passant_square == Some(to) && from % 8 != to % 8 && captured.is_none() {
+            Move { promotion, ..Move::new_en_passant(from, to, piece) }
+        } else {
+            Move { captured_piece: captured, promotion, ..Move::new(from, to, piece) }
         };
+        self.is_legal(board, &mv).then_some(mv)
+    }
 
-        if !is_legal {
-            return false;
+    // The legal move a UCI string such as "e2e4" or "e7e8q" names
+    pub fn parse_uci_move(&self, board: &Board, move_str: &str) -> Result<Move, String> {
+        let invalid = || format!("invalid move '{}'", move_str);
+        if move_str.len() != 4 && move_str.len() != 5 {
+            return Err(invalid());
         }
 
-        // Make the move and check if the king is in check
-        let mut board_copy = board.clone();
-        board_copy.make_move(*mv);
-        !self.is_king_in_check(&board_copy, board.side_to_move)
+        let from = move_str.get(0..2).and_then(parse_square).ok_or_else(invalid)?;
+        let to = move_str.get(2..4).and_then(parse_square).ok_or_else(invalid)?;
+        let promotion = match move_str.get(4..) {
+            Some("") => None,
+            Some("q") => Some(Piece::Queen),
+            Some("r") => Some(Piece::Rook),
+            Some("b") => Some(Piece::Bishop),
+            Some("n") => Some(Piece::Knight),
+            _ => return Err(invalid()),
+        };
+
+        self.find_move(board, from, to, promotion).ok_or_else(|| format!("illegal move '{}'", move_str))
     }
 
     pub fn generate_moves(&self, board: &Board) -> Vec<Move> {
         let mut moves = Vec::new();
+        self.generate(board, Selection::All, &mut moves);
+        moves
+    }
+
+    // Only the legal captures and promotions, as the quiescence search wants
+    pub fn generate_captures(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.generate(board, Selection::Captures, &mut moves);
+        moves
+    }
+
+    // The rest of the legal moves: neither captures nor promotions
+    pub fn generate_quiets(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.generate(board, Selection::Quiets, &mut moves);
+        moves
+    }
+
+    // The legal moves into a buffer that is reused from call to call
+    pub fn generate_moves_into(&self, board: &Board, list: &mut MoveList) {
+        list.clear();
+        self.generate(board, Selection::All, &mut list.moves);
+    }
+
+    pub fn generate_captures_into(&self, board: &Board, list: &mut MoveList) {
+        list.clear();
+        self.generate(board, Selection::Captures, &mut list.moves);
+    }
+
+    pub fn generate_quiets_into(&self, board: &Board, list: &mut MoveList) {
+        list.clear();
+        self.generate(board, Selection::Quiets, &mut list.moves);
+    }
+
+    // The legal move a transposition table entry packed with Move::to_u16,
+    // if there is one in board
+    pub fn decode_move(&self, board: &Board, packed: u16) -> Option<Move> {
+        let promotion = match packed >> 12 {
+            0 => None,
+            1 => Some(Piece::Knight),
+            2 => Some(Piece::Bishop),
+            3 => Some(Piece::Rook),
+            4 => Some(Piece::Queen),
+            _ => return None,
+        };
+        self.find_move(board, (packed & 63) as u8, (packed >> 6 & 63) as u8, promotion)
+    }
+
+    fn generate(&self, board: &Board, selection: Selection, moves: &mut Vec<Move>) {
+        let mut legality = self.legality(board);
         let pieces = if board.side_to_move == Color::White {
             &board.white_pieces
         } else {
@@ -559,19 +597,13 @@ impl MoveGenerator {
                             (board.side_to_move == Color::Black && to < 8) {
                             for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
                                 let mv = Move::new_promotion(from as u8, to as u8, promotion);
-                                // Make the move and check if the king is in check
-                                let mut board_copy = board.clone();
-                                board_copy.make_move(mv);
-                                if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                                if selection != Selection::Quiets && self.passes(board, &mut legality, mv) {
                                     moves.push(mv);
                                 }
                             }
-                        } else {
+                        } else if selection != Selection::Captures {
                             let mv = Move::new(from as u8, to as u8, Piece::Pawn);
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -579,10 +611,12 @@ impl MoveGenerator {
                 }
 
                 // Double push
-                let to = if board.side_to_move == Color::White {
-                    (from as i8).checked_add(16).filter(|&x| x < 64 && from / 8 == 1)
+                let to = if selection == Selection::Captures {
+                    None
+                } else if board.side_to_move == Color::White {
+                    (from as i8).checked_add(16).filter(|&x| x < 64 && board.variant.can_double_push(Color::White, (from / 8) as u8))
                 } else {
-                    (from as i8).checked_sub(16).filter(|&x| x >= 0 && from / 8 == 6)
+                    (from as i8).checked_sub(16).filter(|&x| x >= 0 && board.variant.can_double_push(Color::Black, (from / 8) as u8))
                 };
                 if let Some(to) = to {
                     let intermediate = if board.side_to_move == Color::White {
@@ -598,10 +632,7 @@ impl MoveGenerator {
                         .all(|&p| (p & intermediate_mask) == 0);
                     if is_empty {
                         let mv = Move::new(from as u8, to as u8, Piece::Pawn);
-                        // Make the move and check if the king is in check
-                        let mut board_copy = board.clone();
-                        board_copy.make_move(mv);
-                        if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                        if self.passes(board, &mut legality, mv) {
                             moves.push(mv);
                         }
                     }
@@ -622,21 +653,18 @@ impl MoveGenerator {
                     ]
                 };
                 for &(rank, file) in &capture_squares {
-                    if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+                    if (0..8).contains(&rank) && (0..8).contains(&file) {
                         let to = (rank * 8 + file) as u8;
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture {
+                        if is_capture && selection != Selection::Quiets {
                             let captured_piece = self.get_piece_at(board, to);
                             // Check for promotion
                             if (board.side_to_move == Color::White && rank == 7) ||
                                 (board.side_to_move == Color::Black && rank == 0) {
                                 for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
                                     let mv = Move::new_promotion_capture(from as u8, to, captured_piece, promotion);
-                                    // Make the move and check if the king is in check
-                                    let mut board_copy = board.clone();
-                                    board_copy.make_move(mv);
-                                    if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                                    if self.passes(board, &mut legality, mv) {
                                         moves.push(mv);
                                     }
                                 }
@@ -652,10 +680,7 @@ impl MoveGenerator {
                                     castling_rook_from: None,
                                     castling_rook_to: None,
                                 };
-                                // Make the move and check if the king is in check
-                                let mut board_copy = board.clone();
-                                board_copy.make_move(mv);
-                                if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                                if self.passes(board, &mut legality, mv) {
                                     moves.push(mv);
                                 }
                             }
@@ -664,34 +689,30 @@ impl MoveGenerator {
                 }
 
                 // En passant
-                if let Some(ep_square) = board.en_passant_square {
+                if let Some(ep_square) = board.en_passant_square.filter(|_| selection != Selection::Quiets) {
                     let ep_rank = ep_square / 8;
                     let from_rank = from / 8;
                     let from_file = from % 8;
                     let ep_file = ep_square % 8;
-                    if (board.side_to_move == Color::White && ep_rank == 5 && from_rank == 4) ||
-                        (board.side_to_move == Color::Black && ep_rank == 2 && from_rank == 3) {
-                        if (ep_file as i8 - from_file as i8).abs() == 1 {
-                            let captured_pawn_square = if board.side_to_move == Color::White {
-                                ep_square - 8
-                            } else {
-                                ep_square + 8
-                            };
-                            let captured_pawn_mask = 1u64 << captured_pawn_square;
-                            let has_pawn_to_capture = if board.side_to_move == Color::White {
-                                (board.black_pieces[0] & captured_pawn_mask) != 0
-                            } else {
-                                (board.white_pieces[0] & captured_pawn_mask) != 0
-                            };
-                            if has_pawn_to_capture {
-                                let mut mv = Move::new_en_passant(from as u8, ep_square, Piece::Pawn);
-                                mv.captured_piece = Some(Piece::Pawn);
-                                // Make the move and check if the king is in check
-                                let mut board_copy = board.clone();
-                                board_copy.make_move(mv);
-                                if !self.is_king_in_check(&board_copy, board.side_to_move) {
-                                    moves.push(mv);
-                                }
+                    if ((board.side_to_move == Color::White && ep_rank == 5 && from_rank == 4) ||
+                        (board.side_to_move == Color::Black && ep_rank == 2 && from_rank == 3)) &&
+                        (ep_file as i8 - from_file as i8).abs() == 1 {
+                        let captured_pawn_square = if board.side_to_move == Color::White {
+                            ep_square - 8
+                        } else {
+                            ep_square + 8
+                        };
+                        let captured_pawn_mask = 1u64 << captured_pawn_square;
+                        let has_pawn_to_capture = if board.side_to_move == Color::White {
+                            (board.black_pieces[0] & captured_pawn_mask) != 0
+                        } else {
+                            (board.white_pieces[0] & captured_pawn_mask) != 0
+                        };
+                        if has_pawn_to_capture {
+                            let mut mv = Move::new_en_passant(from as u8, ep_square, Piece::Pawn);
+                            mv.captured_piece = Some(Piece::Pawn);
+                            if self.passes(board, &mut legality, mv) {
+                                moves.push(mv);
                             }
                         }
                     }
@@ -701,31 +722,19 @@ impl MoveGenerator {
 
         // Generate knight moves
         let knights = pieces[1];
-        for from in 0..64 {
+        for (from, &attacks) in KNIGHT_ATTACKS.iter().enumerate() {
             if (knights >> from) & 1 != 0 {
-                let from_rank = (from / 8) as i8;
-                let from_file = (from % 8) as i8;
-                let knight_moves = [
-                    (2, 1), (2, -1), (-2, 1), (-2, -1),
-                    (1, 2), (1, -2), (-1, 2), (-1, -2)
-                ];
-                for &(dr, df) in &knight_moves {
-                    let rank = from_rank + dr;
-                    let file = from_file + df;
-                    if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
-                        let to = (rank * 8 + file) as u8;
+                for to in 0..64 {
+                    if (attacks >> to) & 1 != 0 {
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
                         let is_empty = !pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture || is_empty {
+                        if (is_capture && selection != Selection::Quiets) || (is_empty && !is_capture && selection != Selection::Captures) {
                             let mut mv = Move::new(from as u8, to, Piece::Knight);
                             if is_capture {
                                 mv.captured_piece = Some(self.get_piece_at(board, to));
                             }
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -746,15 +755,12 @@ impl MoveGenerator {
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
                         let is_empty = !pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture || is_empty {
-                            let mut mv = Move::new(from as u8, to as u8, Piece::Bishop);
+                        if (is_capture && selection != Selection::Quiets) || (is_empty && !is_capture && selection != Selection::Captures) {
+                            let mut mv = Move::new(from as u8, to, Piece::Bishop);
                             if is_capture {
                                 mv.captured_piece = Some(self.get_piece_at(board, to));
                             }
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -775,15 +781,12 @@ impl MoveGenerator {
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
                         let is_empty = !pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture || is_empty {
-                            let mut mv = Move::new(from as u8, to as u8, Piece::Rook);
+                        if (is_capture && selection != Selection::Quiets) || (is_empty && !is_capture && selection != Selection::Captures) {
+                            let mut mv = Move::new(from as u8, to, Piece::Rook);
                             if is_capture {
                                 mv.captured_piece = Some(self.get_piece_at(board, to));
                             }
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -805,15 +808,12 @@ impl MoveGenerator {
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
                         let is_empty = !pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture || is_empty {
-                            let mut mv = Move::new(from as u8, to as u8, Piece::Queen);
+                        if (is_capture && selection != Selection::Quiets) || (is_empty && !is_capture && selection != Selection::Captures) {
+                            let mut mv = Move::new(from as u8, to, Piece::Queen);
                             if is_capture {
                                 mv.captured_piece = Some(self.get_piece_at(board, to));
                             }
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -824,31 +824,19 @@ impl MoveGenerator {
 
         // Generate king moves
         let king = pieces[5];
-        for from in 0..64 {
+        for (from, &attacks) in KING_ATTACKS.iter().enumerate() {
             if (king >> from) & 1 != 0 {
-                let from_rank = (from / 8) as i8;
-                let from_file = (from % 8) as i8;
-                let king_moves = [
-                    (1, 0), (1, 1), (0, 1), (-1, 1),
-                    (-1, 0), (-1, -1), (0, -1), (1, -1)
-                ];
-                for &(dr, df) in &king_moves {
-                    let rank = from_rank + dr;
-                    let file = from_file + df;
-                    if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
-                        let to = (rank * 8 + file) as u8;
+                for to in 0..64 {
+                    if (attacks >> to) & 1 != 0 {
                         let to_mask = 1u64 << to;
                         let is_capture = opponent_pieces.iter().any(|&p| (p & to_mask) != 0);
                         let is_empty = !pieces.iter().any(|&p| (p & to_mask) != 0);
-                        if is_capture || is_empty {
-                            let mut mv = Move::new(from as u8, to as u8, Piece::King);
+                        if (is_capture && selection != Selection::Quiets) || (is_empty && !is_capture && selection != Selection::Captures) {
+                            let mut mv = Move::new(from as u8, to, Piece::King);
                             if is_capture {
                                 mv.captured_piece = Some(self.get_piece_at(board, to));
                             }
-                            // Make the move and check if the king is in check
-                            let mut board_copy = board.clone();
-                            board_copy.make_move(mv);
-                            if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                            if self.passes(board, &mut legality, mv) {
                                 moves.push(mv);
                             }
                         }
@@ -856,6 +844,9 @@ impl MoveGenerator {
                 }
 
                 // Castling
+                if selection == Selection::Captures {
+                    continue;
+                }
                 let occupied = board.white_pieces.iter().chain(board.black_pieces.iter())
                     .fold(0u64, |acc, &p| acc | p);
                 if board.side_to_move == Color::White {
@@ -867,10 +858,7 @@ impl MoveGenerator {
                         !self.is_square_under_attack(board, 5, Color::Black) && // f1 not attacked
                         !self.is_square_under_attack(board, 6, Color::Black) { // g1 not attacked
                         let mv = Move::new_castling(4, 6, 7, 5);
-                        // Make the move and check if the king is in check
-                        let mut board_copy = board.clone();
-                        board_copy.make_move(mv);
-                        if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                        if self.passes(board, &mut legality, mv) {
                             moves.push(mv);
                         }
                     }
@@ -882,10 +870,7 @@ impl MoveGenerator {
                         !self.is_square_under_attack(board, 3, Color::Black) && // d1 not attacked
                         !self.is_square_under_attack(board, 2, Color::Black) { // c1 not attacked
                         let mv = Move::new_castling(4, 2, 0, 3);
-                        // Make the move and check if the king is in check
-                        let mut board_copy = board.clone();
-                        board_copy.make_move(mv);
-                        if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                        if self.passes(board, &mut legality, mv) {
                             moves.push(mv);
                         }
                     }
@@ -898,10 +883,7 @@ impl MoveGenerator {
                         !self.is_square_under_attack(board, 61, Color::White) && // f8 not attacked
                         !self.is_square_under_attack(board, 62, Color::White) { // g8 not attacked
                         let mv = Move::new_castling(60, 62, 63, 61);
-                        // Make the move and check if the king is in check
-                        let mut board_copy = board.clone();
-                        board_copy.make_move(mv);
-                        if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                        if self.passes(board, &mut legality, mv) {
                             moves.push(mv);
                         }
                     }
@@ -913,18 +895,63 @@ impl MoveGenerator {
                         !self.is_square_under_attack(board, 59, Color::White) && // d8 not attacked
                         !self.is_square_under_attack(board, 58, Color::White) { // c8 not attacked
                         let mv = Move::new_castling(60, 58, 56, 59);
-                        // Make the move and check if the king is in check
-                        let mut board_copy = board.clone();
-                        board_copy.make_move(mv);
-                        if !self.is_king_in_check(&board_copy, board.side_to_move) {
+                        if self.passes(board, &mut legality, mv) {
                             moves.push(mv);
                         }
                     }
                 }
             }
         }
+    }
 
-        moves
+    fn legality(&self, board: &Board) -> Legality {
+        if !board.variant.standard_legality() {
+            return Legality::Variant(board.clone());
+        }
+        let us = board.side_to_move;
+        let ours = if us == Color::White { &board.white_pieces } else { &board.black_pieces };
+        if ours[5] == 0 {
+            return Legality::NoKing;
+        }
+        let king = ours[5].trailing_zeros() as u8;
+        let occupied = board.white_pieces.iter().chain(board.black_pieces.iter()).fold(0, |acc, &bb| acc | bb);
+        let checkers = self.attackers(board, king, occupied, us.opposite());
+        let check_mask = match checkers.count_ones() {
+            0 => !0,
+            1 => BETWEEN[king as usize][checkers.trailing_zeros() as usize] | checkers,
+            _ => 0,
+        };
+        Legality::Standard { king, check_mask, pinned: self.pinned(board, us), occupied }
+    }
+
+    // Whether a generated move, pseudo-legal in board, is legal. Castling only
+    // gets here once the squares the king crosses are known to be safe.
+    fn passes(&self, board: &Board, legality: &mut Legality, mv: Move) -> bool {
+        match legality {
+            Legality::NoKing => true,
+            Legality::Standard { king, check_mask, pinned, occupied } => {
+                let (king, from_mask, to_mask) = (*king, 1u64 << mv.from, 1u64 << mv.to);
+                let them = board.side_to_move.opposite();
+                if mv.is_castling {
+                    true
+                } else if mv.piece == Piece::King {
+                    // Off the king's old square, so it can't hide behind itself from a slider
+                    self.attackers(board, mv.to, *occupied ^ from_mask, them) == 0
+                } else if mv.is_en_passant {
+                    // Two pawns leave one rank at once, which can uncover the king along it
+                    let victim_mask = if them == Color::Black { to_mask >> 8 } else { to_mask << 8 };
+                    self.attackers(board, king, *occupied ^ from_mask ^ to_mask ^ victim_mask, them) == 0
+                } else {
+                    *check_mask & to_mask != 0 && (*pinned & from_mask == 0 || LINE[king as usize][mv.from as usize] & to_mask != 0)
+                }
+            }
+            Legality::Variant(scratch) => {
+                let undo = scratch.make_move(mv);
+                let legal = board.variant.is_legal(self, board, scratch);
+                scratch.unmake_move(&undo);
+                legal
+            }
+        }
     }
 
     fn get_piece_at(&self, board: &Board, square: u8) -> Piece {
@@ -976,164 +1003,115 @@ impl MoveGenerator {
 
     pub fn get_game_state(&self, board: &Board, move_history: &[(Board, Move)]) -> GameState {
         // Check for insufficient material
-        if self.is_insufficient_material(board) {
+        if board.variant.is_insufficient_material(self, board, MaterialRule::Fide) {
             return GameState::InsufficientMaterial;
         }
 
-        // Check for fifty-move rule
-        if board.halfmove_clock >= 50 {
-            return GameState::FiftyMoveRule;
+        // Check for the fifty-move rule: a hundred halfmoves without a capture
+        // or pawn move, unless the last of them won the game outright
+        if board.halfmove_clock >= 100 {
+            let has_moves = !self.generate_moves(board).is_empty();
+            return match board.variant.result(self, board, has_moves) {
+                Some(state @ (GameState::Checkmate(_) | GameState::VariantWin(_))) => state,
+                _ => GameState::FiftyMoveRule,
+            };
         }
 
         // Check for threefold repetition
-        if self.is_threefold_repetition(board, move_history) {
+        if RepetitionHistory::from_moves(move_history, board).is_threefold() {
             return GameState::ThreefoldRepetition;
         }
 
-        // Generate all legal moves
-        let moves = self.generate_moves(board);
-
-        // If there are no legal moves
-        if moves.is_empty() {
-            // Check if the king is in check
-            if self.is_king_in_check(board, board.side_to_move) {
-                // Checkmate - the side to move is in check and has no legal moves
-                return GameState::Checkmate(board.side_to_move.opposite());
-            } else {
-                // Stalemate - the side to move is not in check but has no legal moves
-                return GameState::Stalemate;
-            }
-        }
-
-        GameState::Ongoing
+        // Checkmate, stalemate or whatever else ends a game in this variant
+        let has_moves = !self.generate_moves(board).is_empty();
+        board.variant.result(self, board, has_moves).unwrap_or(GameState::Ongoing)
     }
 
-    fn is_threefold_repetition(&self, board: &Board, move_history: &[(Board, Move)]) -> bool {
-        let current_hash = self.get_position_hash(board);
-        let mut repetition_count = 1;
-
-        for (past_board, _) in move_history {
-            if self.get_position_hash(past_board) == current_hash {
-                repetition_count += 1;
-                if repetition_count >= 3 {
-                    return true;
-                }
-            }
+    // The legal move that turns before into after, for callers that only see
+    // board snapshots. Only the pieces and the side to move are compared, as
+    // snapshots often get castling rights, en passant and clocks wrong.
+    pub fn infer_move(&self, before: &Board, after: &Board) -> Result<Move, String> {
+        if after.side_to_move == before.side_to_move {
+            return Err("the same side is to move in both positions".to_string());
         }
-
-        false
+        self.generate_moves(before)
+            .into_iter()
+            .find(|&mv| {
+                let mut board = before.clone();
+                board.make_move(mv);
+                board.white_pieces == after.white_pieces && board.black_pieces == after.black_pieces
+            })
+            .ok_or_else(|| "no legal move leads from one position to the other".to_string())
     }
 
-    fn get_position_hash(&self, board: &Board) -> u64 {
-        let mut hash = 0u64;
-
-        // Hash white pieces
-        for (piece_type, &bitboard) in board.white_pieces.iter().enumerate() {
-            hash = hash.wrapping_mul(PRIME_NUMBERS[piece_type]);
-            hash = hash.wrapping_add(bitboard);
-        }
-
-        // Hash black pieces
-        for (piece_type, &bitboard) in board.black_pieces.iter().enumerate() {
-            hash = hash.wrapping_mul(PRIME_NUMBERS[piece_type + 6]);
-            hash = hash.wrapping_add(bitboard);
-        }
-
-        // Hash game state
-        hash = hash.wrapping_mul(PRIME_NUMBERS[12]);
-        hash = hash.wrapping_add(board.castling_rights as u64);
-
-        if let Some(ep_square) = board.en_passant_square {
-            hash = hash.wrapping_mul(PRIME_NUMBERS[13]);
-            hash = hash.wrapping_add(ep_square as u64);
-        }
-
-        hash = hash.wrapping_mul(PRIME_NUMBERS[14]);
-        hash = hash.wrapping_add(if board.side_to_move == Color::White { 0 } else { 1 });
-
-        hash
+    // The standard chess rule; variants decide through Variant::is_insufficient_material
+    pub fn is_insufficient_material(&self, board: &Board) -> bool {
+        self.insufficient_material(board, MaterialRule::Fide)
     }
 
-    fn is_insufficient_material(&self, board: &Board) -> bool {
-        let (white_pieces, black_pieces) = self.count_pieces(board);
-        let (white_minors, black_minors) = self.count_minor_pieces(board);
-        let (white_bishops, black_bishops) = self.count_bishops(board);
-
-        // King vs King
-        if white_pieces == 1 && black_pieces == 1 {
-            return true;
-        }
-
-        // King and Bishop vs King
-        if (white_pieces == 2 && white_bishops == 1 && black_pieces == 1) ||
-           (black_pieces == 2 && black_bishops == 1 && white_pieces == 1) {
-            return true;
+    // Whether the material left is too little under the given reading of the rule
+    pub fn insufficient_material(&self, board: &Board, rule: MaterialRule) -> bool {
+        let (white, black) = (&board.white_pieces, &board.black_pieces);
+        if white[0] | white[3] | white[4] | black[0] | black[3] | black[4] != 0 {
+            return false;
         }
-
-        // King and Knight vs King
-        if (white_pieces == 2 && white_minors == 1 && black_pieces == 1) ||
-           (black_pieces == 2 && black_minors == 1 && white_pieces == 1) {
+        // Bishops all on one colour of square can never cover both a king's
+        // square and its flight squares, and a lone knight can't either
+        let knights = white[1] | black[1];
+        let bishops = white[2] | black[2];
+        if (knights == 0 && same_coloured(bishops)) || (knights.count_ones() == 1 && bishops == 0) {
             return true;
         }
-
-        // King and Bishop vs King and Bishop (same colored squares)
-        if white_pieces == 2 && black_pieces == 2 &&
-           white_bishops == 1 && black_bishops == 1 {
-            let white_bishop_square = self.find_bishop_square(board, Color::White);
-            let black_bishop_square = self.find_bishop_square(board, Color::Black);
-            if let (Some(white_sq), Some(black_sq)) = (white_bishop_square, black_bishop_square) {
-                let white_is_dark = (white_sq / 8 + white_sq % 8) % 2 == 1;
-                let black_is_dark = (black_sq / 8 + black_sq % 8) % 2 == 1;
-                if white_is_dark == black_is_dark {
-                    return true;
-                }
-            }
-        }
-
-        false
-    }
-
-    fn count_pieces(&self, board: &Board) -> (u8, u8) {
-        let white_count = board.white_pieces.iter().map(|&bb| bb.count_ones() as u8).sum();
-        let black_count = board.black_pieces.iter().map(|&bb| bb.count_ones() as u8).sum();
-        (white_count, black_count)
-    }
-
-    fn count_minor_pieces(&self, board: &Board) -> (u8, u8) {
-        let white_count = (board.white_pieces[1] | board.white_pieces[2]).count_ones() as u8;
-        let black_count = (board.black_pieces[1] | board.black_pieces[2]).count_ones() as u8;
-        (white_count, black_count)
-    }
-
-    fn count_bishops(&self, board: &Board) -> (u8, u8) {
-        let white_count = board.white_pieces[2].count_ones() as u8;
-        let black_count = board.black_pieces[2].count_ones() as u8;
-        (white_count, black_count)
-    }
-
-    fn find_bishop_square(&self, board: &Board, color: Color) -> Option<u8> {
-        let bishops = match color {
-            Color::White => board.white_pieces[2],
-            Color::Black => board.black_pieces[2],
+        // Without help, one minor, two knights or same-coloured bishops can't mate
+        let cannot_force_mate = |pieces: &[u64; 6]| {
+            let (knights, bishops) = (pieces[1], pieces[2]);
+            (knights.count_ones() + bishops.count_ones() <= 1)
+                || (bishops == 0 && knights.count_ones() == 2)
+                || (knights == 0 && same_coloured(bishops))
         };
-        if bishops != 0 {
-            Some(bishops.trailing_zeros() as u8)
-        } else {
-            None
-        }
+        rule == MaterialRule::Practical && cannot_force_mate(white) && cannot_force_mate(black)
     }
 }
 
-const PRIME_NUMBERS: [u64; 15] = [
-    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47,
-];
+const DARK_SQUARES: u64 = 0xaa55_aa55_aa55_aa55;
+
+fn same_coloured(bishops: u64) -> bool {
+    bishops & DARK_SQUARES == 0 || bishops & !DARK_SQUARES == 0
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GameState {
     Ongoing,
     Checkmate(Color),  // Color is the winner
+    VariantWin(Color),  // Won by a variant's own win condition
     Stalemate,
     ThreefoldRepetition,
     FiftyMoveRule,
     InsufficientMaterial,
-} 
\ No newline at end of file
+} 
+// How much material counts as too little to go on. Fide is the dead-position
+// rule, no sequence of legal moves could mate; Practical also ends games
+// neither side can win by force, such as two knights against a king, as some
+// GUIs adjudicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaterialRule {
+    #[default]
+    Fide,
+    Practical,
+}
+
+impl fmt::Display for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameState::Ongoing => write!(f, "ongoing"),
+            GameState::Checkmate(Color::White) => write!(f, "checkmate, white wins"),
+            GameState::Checkmate(Color::Black) => write!(f, "checkmate, black wins"),
+            GameState::VariantWin(Color::White) => write!(f, "white wins by the variant rules"),
+            GameState::VariantWin(Color::Black) => write!(f, "black wins by the variant rules"),
+            GameState::Stalemate => write!(f, "stalemate"),
+            GameState::ThreefoldRepetition => write!(f, "draw by threefold repetition"),
+            GameState::FiftyMoveRule => write!(f, "draw by the fifty-move rule"),
+            GameState::InsufficientMaterial => write!(f, "draw by insufficient material"),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::board::{Board, Color, DirtyPieces, Piece, PieceChange};
+
+// One input per (own or enemy, piece, square), seen from each side in turn
+pub const INPUTS: usize = 768;
+// Hidden values are clipped to [0, QA] before the output layer, whose weights
+// are scaled by QB; SCALE turns the result into centipawns
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+// The input index of a piece for one perspective. Black sees the board
+// mirrored, so both perspectives share one set of weights.
+pub fn feature_index(perspective: Color, change: PieceChange) -> usize {
+    let (square, own) = match perspective {
+        Color::White => (change.square as usize, change.color == Color::White),
+        Color::Black => (change.square as usize ^ 56, change.color == Color::Black),
+    };
+    let side = if own { 0 } else { 384 };
+    side + change.piece as usize * 64 + square
+}
+
+// The first layer: the hidden values are the biases plus the weight rows of
+// every active input. Being a sum, it can follow a move by adding and
+// subtracting the rows of the pieces that changed.
+#[derive(Debug, Clone)]
+pub struct FeatureTransformer {
+    pub hidden: usize,
+    // INPUTS rows of hidden weights each
+    pub weights: Vec<i16>,
+    pub biases: Vec<i16>,
+}
+
+impl FeatureTransformer {
+    pub fn new(hidden: usize, weights: Vec<i16>, biases: Vec<i16>) -> Result<Self, String> {
+        if weights.len() != INPUTS * hidden || biases.len() != hidden {
+            return Err(format!(
+                "a feature transformer with {} hidden values needs {} weights and {} biases, got {} and {}",
+                hidden,
+                INPUTS * hidden,
+                hidden,
+                weights.len(),
+                biases.len()
+            ));
+        }
+        Ok(Self { hidden, weights, biases })
+    }
+
+    fn row(&self, index: usize) -> &[i16] {
+        &self.weights[index * self.hidden..(index + 1) * self.hidden]
+    }
+
+    // Computes both perspectives from scratch
+    pub fn refresh(&self, board: &Board, accumulator: &mut Accumulator) {
+        for (perspective, values) in [(Color::White, &mut accumulator.white), (Color::Black, &mut accumulator.black)] {
+            values.clear();
+            values.extend_from_slice(&self.biases);
+            for (color, pieces) in [(Color::White, &board.white_pieces), (Color::Black, &board.black_pieces)] {
+                for (piece, &bitboard) in Piece::ALL.iter().zip(pieces) {
+                    let mut bitboard = bitboard;
+                    while bitboard != 0 {
+                        let square = bitboard.trailing_zeros() as u8;
+                        let change = PieceChange { piece: *piece, color, square };
+                        for (value, &weight) in values.iter_mut().zip(self.row(feature_index(perspective, change))) {
+                            *value += weight;
+                        }
+                        bitboard &= bitboard - 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Brings an accumulator one move forward
+    pub fn update(&self, dirty: &DirtyPieces, accumulator: &mut Accumulator) {
+        for (perspective, values) in [(Color::White, &mut accumulator.white), (Color::Black, &mut accumulator.black)] {
+            for change in dirty.removed() {
+                for (value, &weight) in values.iter_mut().zip(self.row(feature_index(perspective, change))) {
+                    *value -= weight;
+                }
+            }
+            for change in dirty.added() {
+                for (value, &weight) in values.iter_mut().zip(self.row(feature_index(perspective, change))) {
+                    *value += weight;
+                }
+            }
+        }
+    }
+}
+
+// The hidden values of a position from White's and from Black's side
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Accumulator {
+    pub white: Vec<i16>,
+    pub black: Vec<i16>,
+}
+
+// A network of a feature transformer and a single output layer over the
+// clipped hidden values of both perspectives, the side to move's first
+#[derive(Debug, Clone)]
+pub struct Network {
+    pub transformer: FeatureTransformer,
+    pub output_weights: Vec<i16>,
+    pub output_bias: i32,
+}
+
+impl Network {
+    pub fn new(transformer: FeatureTransformer, output_weights: Vec<i16>, output_bias: i32) -> Result<Self, String> {
+        if output_weights.len() != 2 * transformer.hidden {
+            return Err(format!(
+                "the output layer needs {} weights, got {}",
+                2 * transformer.hidden,
+                output_weights.len()
+            ));
+        }
+        Ok(Self { transformer, output_weights, output_bias })
+    }
+
+    // Centipawns for the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, side_to_move: Color) -> i32 {
+        let (us, them) = match side_to_move {
+            Color::White => (&accumulator.white, &accumulator.black),
+            Color::Black => (&accumulator.black, &accumulator.white),
+        };
+        let sum: i32 = us
+            .iter()
+            .chain(them.iter())
+            .zip(&self.output_weights)
+            .map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32)
+            .sum();
+        (sum + self.output_bias) * SCALE / (QA * QB)
+    }
+}
+
+struct Entry {
+    accumulator: Accumulator,
+    // The move that led here from the entry below
+    dirty: DirtyPieces,
+    computed: bool,
+}
+
+// One accumulator per ply of the line being searched. Pushing a move only
+// records what it changed; the values are worked out when an evaluation asks
+// for them, from the nearest computed entry below, so the many interior nodes
+// that are never evaluated cost nothing. Entries are kept between searches to
+// reuse their allocations.
+#[derive(Default)]
+pub struct AccumulatorStack {
+    entries: Vec<Entry>,
+    len: usize,
+    refreshes: u64,
+    updates: u64,
+}
+
+impl AccumulatorStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts over at a new root, which is computed from its board when first needed
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.push(DirtyPieces::default());
+    }
+
+    pub fn push(&mut self, dirty: DirtyPieces) {
+        if self.len == self.entries.len() {
+            self.entries.push(Entry { accumulator: Accumulator::default(), dirty, computed: false });
+        } else {
+            let entry = &mut self.entries[self.len];
+            entry.dirty = dirty;
+            entry.computed = false;
+        }
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) {
+        self.len = self.len.saturating_sub(1);
+    }
+
+    // Forgets the top entry's values, for a board that wasn't reached by pushes
+    pub fn invalidate(&mut self) {
+        if let Some(entry) = self.entries[..self.len].last_mut() {
+            entry.computed = false;
+        }
+    }
+
+    // The accumulator of board, which must be the position at the top
+    pub fn current(&mut self, transformer: &FeatureTransformer, board: &Board) -> &Accumulator {
+        if self.len == 0 {
+            self.reset();
+        }
+        let top = self.len - 1;
+        match self.entries[..=top].iter().rposition(|entry| entry.computed) {
+            Some(base) => {
+                for i in base + 1..=top {
+                    let (below, above) = self.entries.split_at_mut(i);
+                    let entry = &mut above[0];
+                    entry.accumulator.clone_from(&below[i - 1].accumulator);
+                    transformer.update(&entry.dirty, &mut entry.accumulator);
+                    entry.computed = true;
+                    self.updates += 1;
+                }
+            }
+            None => {
+                let entry = &mut self.entries[top];
+                transformer.refresh(board, &mut entry.accumulator);
+                entry.computed = true;
+                self.refreshes += 1;
+            }
+        }
+        &self.entries[top].accumulator
+    }
+
+    // Accumulators computed from scratch and by following moves so far
+    pub fn counts(&self) -> (u64, u64) {
+        (self.refreshes, self.updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGenerator;
+
+    // Small made-up weights, all different, so a wrong index shows
+    fn network() -> Network {
+        let hidden = 4;
+        let weights = (0..INPUTS * hidden).map(|i| (i % 97) as i16 - 48).collect();
+        let transformer = FeatureTransformer::new(hidden, weights, vec![10; hidden]).unwrap();
+        Network::new(transformer, vec![3, -2, 5, 1, -4, 2, -1, 6], 0).unwrap()
+    }
+
+    #[test]
+    fn test_incremental_matches_refresh() {
+        let network = network();
+        let generator = MoveGenerator::new();
+        let mut stack = AccumulatorStack::new();
+        stack.reset();
+        // Castling, a capture, en passant and a promotion along the way
+        let mut board = Board::from_fen("r3k2r/P7/8/8/3pP3/8/8/R3K2R b KQkq e3 0 1").unwrap();
+        for uci in ["d4e3", "e1g1", "e8c8", "a7a8q", "c8d7", "a8d8"] {
+            let mv = generator.generate_moves(&board).into_iter().find(|mv| mv.to_string() == uci).unwrap();
+            let undo = board.make_move(mv);
+            stack.push(undo.dirty);
+
+            let mut fresh = Accumulator::default();
+            network.transformer.refresh(&board, &mut fresh);
+            assert_eq!(stack.current(&network.transformer, &board), &fresh, "after {}", uci);
+        }
+        // Only the first position was computed from scratch
+        assert_eq!(stack.counts(), (1, 5));
+    }
+
+    #[test]
+    fn test_evaluation_is_lazy_and_symmetric() {
+        let network = network();
+        let mut stack = AccumulatorStack::new();
+        stack.reset();
+        let board = Board::new();
+        let generator = MoveGenerator::new();
+        // Pushing without evaluating computes nothing
+        for mv in generator.generate_moves(&board) {
+            let mut child = board.clone();
+            stack.push(child.make_move(mv).dirty);
+            stack.pop();
+        }
+        assert_eq!(stack.counts(), (0, 0));
+
+        // The start position looks the same from both sides
+        let accumulator = stack.current(&network.transformer, &board);
+        assert_eq!(accumulator.white, accumulator.black);
+        assert_eq!(network.evaluate(accumulator, Color::White), network.evaluate(accumulator, Color::Black));
+        assert_eq!(stack.counts(), (1, 0));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::board::Board;
+use crate::movegen::{Move, MoveGenerator};
+use rand::Rng;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ENDPOINT: &str = "https://explorer.lichess.ovh/lichess";
+// Lichess asks for one request at a time, and a minute's rest after a 429
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+const BACKOFF: Duration = Duration::from_secs(60);
+
+// When the next request may go out, shared by every explorer in the process
+static NEXT_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplorerMove {
+    pub uci: String,
+    pub white: u64,
+    pub draws: u64,
+    pub black: u64,
+}
+
+impl ExplorerMove {
+    pub fn games(&self) -> u64 {
+        self.white + self.draws + self.black
+    }
+}
+
+// Opening moves from the games played on Lichess, for when the book has run
+// out. Answers are kept on disk so a position is only ever fetched once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpeningExplorer {
+    cache_dir: PathBuf,
+    // Moves played in fewer games than this are left to the search
+    pub min_games: u64,
+    // No lookups after this move number
+    pub max_moves: u16,
+    // Sent as a bearer token when set
+    pub token: Option<String>,
+}
+
+impl OpeningExplorer {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self { cache_dir, min_games: 100, max_moves: 20, token: None }
+    }
+
+    pub fn applies(&self, board: &Board) -> bool {
+        board.fullmove_number <= self.max_moves
+    }
+
+    // A legal move picked with probability proportional to how often it was
+    // played, among those played often enough
+    pub fn pick_move(&self, board: &Board, rng: &mut impl Rng) -> Result<Option<Move>, String> {
+        if !self.applies(board) {
+            return Ok(None);
+        }
+        let legal = MoveGenerator::new().generate_moves(board);
+        let candidates: Vec<(Move, u64)> = self
+            .probe(board)?
+            .into_iter()
+            .filter(|mv| mv.games() >= self.min_games.max(1))
+            .filter_map(|mv| Some((*legal.iter().find(|legal| legal.to_string() == mv.uci)?, mv.games())))
+            .collect();
+
+        let total: u64 = candidates.iter().map(|(_, games)| games).sum();
+        if total == 0 {
+            return Ok(None);
+        }
+        let mut pick = rng.gen_range(0..total);
+        for (mv, games) in candidates {
+            if pick < games {
+                return Ok(Some(mv));
+            }
+            pick -= games;
+        }
+        unreachable!("the pick is below the total")
+    }
+
+    pub fn probe(&self, board: &Board) -> Result<Vec<ExplorerMove>, String> {
+        let fen = board.to_fen();
+        let cache_file = self.cache_dir.join(format!("{}.json", fen.replace('/', "_").replace(' ', "+")));
+
+        let body = match fs::read_to_string(&cache_file) {
+            Ok(body) => body,
+            Err(_) => {
+                let body = self.fetch(&fen)?;
+                // A cache that can't be written only costs another request later
+                let _ = fs::create_dir_all(&self.cache_dir);
+                let _ = fs::write(&cache_file, &body);
+                body
+            }
+        };
+        parse_response(&body)
+    }
+
+    // Waits out the interval since the last request, but gives up at once
+    // while backing off: the clock is running
+    fn fetch(&self, fen: &str) -> Result<String, String> {
+        let mut next = NEXT_REQUEST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(wait) = next.and_then(|next| next.checked_duration_since(Instant::now())) {
+            if wait > MIN_INTERVAL {
+                return Err("opening explorer rate limited, backing off".to_string());
+            }
+            thread::sleep(wait);
+        }
+
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(5)))
+            .build()
+            .into();
+        let mut request = agent.get(ENDPOINT).query("fen", fen).query("variant", "standard");
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let response = request.call();
+        *next = Some(Instant::now() + MIN_INTERVAL);
+        match response {
+            Ok(mut response) => {
+                response.body_mut().read_to_string().map_err(|err| format!("explorer response unreadable: {}", err))
+            }
+            Err(ureq::Error::StatusCode(429)) => {
+                *next = Some(Instant::now() + BACKOFF);
+                Err("opening explorer rate limited, backing off".to_string())
+            }
+            Err(err) => Err(format!("explorer request failed: {}", err)),
+        }
+    }
+}
+
+fn parse_response(body: &str) -> Result<Vec<ExplorerMove>, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(body).map_err(|err| format!("explorer response is not JSON: {}", err))?;
+    let moves = json["moves"].as_array().ok_or("explorer response has no moves")?;
+
+    Ok(moves
+        .iter()
+        .filter_map(|mv| {
+            Some(ExplorerMove {
+                uci: mv["uci"].as_str()?.to_string(),
+                white: mv["white"].as_u64()?,
+                draws: mv["draws"].as_u64()?,
+                black: mv["black"].as_u64()?,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const AFTER_E4: &str = r#"{"white":100,"draws":10,"black":90,"moves":[
+        {"uci":"c7c5","san":"c5","white":60,"draws":5,"black":55},
+        {"uci":"e7e5","san":"e5","white":40,"draws":5,"black":35},
+        {"uci":"a7a5","san":"a5","white":1,"draws":0,"black":0},
+        {"uci":"e2e4","san":"e4","white":100,"draws":0,"black":0},
+        {"san":"??"}
+    ]}"#;
+
+    #[test]
+    fn test_parse_response() {
+        let moves = parse_response(AFTER_E4).unwrap();
+        assert_eq!(moves.len(), 4);
+        assert_eq!(moves[0], ExplorerMove { uci: "c7c5".to_string(), white: 60, draws: 5, black: 55 });
+        assert_eq!(moves[1].games(), 80);
+
+        assert!(parse_response("not json").is_err());
+        assert!(parse_response("{}").is_err());
+    }
+
+    #[test]
+    fn test_pick_move_from_cache() {
+        let cache_dir = std::env::temp_dir().join(format!("three-salmons-explorer-{}", std::process::id()));
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        let cache_file = cache_dir.join(format!("{}.json", board.to_fen().replace('/', "_").replace(' ', "+")));
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(&cache_file, AFTER_E4).unwrap();
+
+        // Rare and illegal moves are never picked, and no request is made
+        let mut explorer = OpeningExplorer::new(cache_dir.clone());
+        explorer.min_games = 10;
+        let mut rng = StdRng::seed_from_u64(988);
+        for _ in 0..20 {
+            let mv = explorer.pick_move(&board, &mut rng).unwrap().unwrap().to_string();
+            assert!(mv == "c7c5" || mv == "e7e5", "{}", mv);
+        }
+        explorer.min_games = 1000;
+        assert_eq!(explorer.pick_move(&board, &mut rng).unwrap(), None);
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+        let late = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 40").unwrap();
+        assert!(!explorer.applies(&late));
+    }
+}
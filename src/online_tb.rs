@@ -0,0 +1,156 @@
+use crate::board::Board;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const ENDPOINT: &str = "https://tablebase.lichess.ovh/standard";
+// The server only knows positions with up to 7 pieces, kings included
+pub const MAX_PIECES: u32 = 7;
+
+// Outcome of a move for the side playing it, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Win,
+    // Wins, but not within the fifty-move rule
+    CursedWin,
+    Draw,
+    // Loses, but the fifty-move rule saves it
+    BlessedLoss,
+    Loss,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TablebaseMove {
+    pub uci: String,
+    pub outcome: Outcome,
+    pub dtz: Option<i32>,
+}
+
+// Probes the Lichess tablebase server, keeping every answer on disk so a
+// position is only ever fetched once
+pub struct OnlineTablebase {
+    cache_dir: PathBuf,
+}
+
+impl OnlineTablebase {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self { cache_dir }
+    }
+
+    pub fn applies(board: &Board) -> bool {
+        let pieces: u32 = board.white_pieces.iter().chain(&board.black_pieces).map(|bb| bb.count_ones()).sum();
+        pieces <= MAX_PIECES
+    }
+
+    // The best move by outcome, then distance to zeroing: win fast, lose slowly
+    pub fn best_move(&self, board: &Board) -> Result<Option<TablebaseMove>, String> {
+        let mut moves = self.probe(board)?;
+        moves.sort_by_key(|mv| {
+            let dtz = mv.dtz.map_or(i32::MAX, |dtz| dtz.abs());
+            let dtz = if mv.outcome >= Outcome::BlessedLoss { -dtz } else { dtz };
+            (mv.outcome, dtz)
+        });
+        Ok(moves.into_iter().next())
+    }
+
+    pub fn probe(&self, board: &Board) -> Result<Vec<TablebaseMove>, String> {
+        let fen = board.to_fen();
+        let cache_file = self.cache_dir.join(format!("{}.json", fen.replace('/', "_").replace(' ', "+")));
+
+        let body = match fs::read_to_string(&cache_file) {
+            Ok(body) => body,
+            Err(_) => {
+                let body = fetch(&fen)?;
+                // A cache that can't be written only costs another request later
+                let _ = fs::create_dir_all(&self.cache_dir);
+                let _ = fs::write(&cache_file, &body);
+                body
+            }
+        };
+        parse_response(&body)
+    }
+}
+
+fn fetch(fen: &str) -> Result<String, String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(5)))
+        .build()
+        .into();
+    agent
+        .get(ENDPOINT)
+        .query("fen", fen)
+        .call()
+        .map_err(|err| format!("tablebase request failed: {}", err))?
+        .body_mut()
+        .read_to_string()
+        .map_err(|err| format!("tablebase response unreadable: {}", err))
+}
+
+// The server reports each move's category for the side to move after it
+fn outcome_for_mover(category: &str) -> Option<Outcome> {
+    match category {
+        "loss" | "syzygy-loss" | "maybe-loss" => Some(Outcome::Win),
+        "blessed-loss" => Some(Outcome::CursedWin),
+        "draw" => Some(Outcome::Draw),
+        "cursed-win" => Some(Outcome::BlessedLoss),
+        "win" | "syzygy-win" | "maybe-win" => Some(Outcome::Loss),
+        _ => None,
+    }
+}
+
+fn parse_response(body: &str) -> Result<Vec<TablebaseMove>, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(body).map_err(|err| format!("tablebase response is not JSON: {}", err))?;
+    let moves = json["moves"].as_array().ok_or("tablebase response has no moves")?;
+
+    Ok(moves
+        .iter()
+        .filter_map(|mv| {
+            Some(TablebaseMove {
+                uci: mv["uci"].as_str()?.to_string(),
+                outcome: outcome_for_mover(mv["category"].as_str()?)?,
+                dtz: mv["dtz"].as_i64().map(|dtz| dtz as i32),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KQK: &str = r#"{"category":"win","dtz":19,"moves":[
+        {"uci":"e1d1","category":"draw","dtz":0},
+        {"uci":"a1a8","category":"loss","dtz":-12},
+        {"uci":"a1a7","category":"loss","dtz":-6},
+        {"uci":"e1e2","category":"unknown","dtz":null}
+    ]}"#;
+
+    #[test]
+    fn test_parse_response() {
+        let moves = parse_response(KQK).unwrap();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0], TablebaseMove { uci: "e1d1".to_string(), outcome: Outcome::Draw, dtz: Some(0) });
+        assert_eq!(moves[1].outcome, Outcome::Win);
+
+        assert!(parse_response("not json").is_err());
+        assert!(parse_response("{}").is_err());
+    }
+
+    #[test]
+    fn test_best_move_from_cache() {
+        let cache_dir = std::env::temp_dir().join(format!("three-salmons-tb-{}", std::process::id()));
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/Q3K3 w - - 0 1").unwrap();
+        let cache_file = cache_dir.join(format!("{}.json", board.to_fen().replace('/', "_").replace(' ', "+")));
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(&cache_file, KQK).unwrap();
+
+        let tablebase = OnlineTablebase::new(cache_dir.clone());
+        assert!(OnlineTablebase::applies(&board));
+        // The quickest win, served without touching the network
+        assert_eq!(tablebase.best_move(&board).unwrap().unwrap().uci, "a1a7");
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+        assert!(!OnlineTablebase::applies(&Board::new()));
+    }
+}
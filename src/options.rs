@@ -0,0 +1,224 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionKind {
+    Check { default: bool },
+    Spin { default: i64, min: i64, max: i64 },
+    Combo { default: String, choices: Vec<String> },
+    Button,
+    String { default: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Check(bool),
+    Spin(i64),
+    Combo(String),
+    Button,
+    String(String),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionValue::Check(value) => write!(f, "{}", value),
+            OptionValue::Spin(value) => write!(f, "{}", value),
+            OptionValue::Combo(value) | OptionValue::String(value) => write!(f, "{}", value),
+            OptionValue::Button => write!(f, "pressed"),
+        }
+    }
+}
+
+impl OptionKind {
+    fn default_value(&self) -> OptionValue {
+        match self {
+            OptionKind::Check { default } => OptionValue::Check(*default),
+            OptionKind::Spin { default, .. } => OptionValue::Spin(*default),
+            OptionKind::Combo { default, .. } => OptionValue::Combo(default.clone()),
+            OptionKind::Button => OptionValue::Button,
+            OptionKind::String { default } => OptionValue::String(default.clone()),
+        }
+    }
+
+    fn parse_value(&self, value: &str) -> Result<OptionValue, String> {
+        match self {
+            OptionKind::Check { .. } => match value.to_ascii_lowercase().as_str() {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => Err(format!("expected true or false, got '{}'", value)),
+            },
+            OptionKind::Spin { min, max, .. } => {
+                let n = value
+                    .parse::<i64>()
+                    .map_err(|_| format!("expected an integer, got '{}'", value))?;
+                if n < *min || n > *max {
+                    return Err(format!("{} is outside {}..={}", n, min, max));
+                }
+                Ok(OptionValue::Spin(n))
+            }
+            OptionKind::Combo { choices, .. } => choices
+                .iter()
+                .find(|choice| choice.eq_ignore_ascii_case(value))
+                .map(|choice| OptionValue::Combo(choice.clone()))
+                .ok_or_else(|| format!("'{}' is not one of {}", value, choices.join(", "))),
+            OptionKind::Button => Ok(OptionValue::Button),
+            OptionKind::String { .. } => {
+                // GUIs send <empty> for an empty string
+                if value == "<empty>" {
+                    Ok(OptionValue::String(String::new()))
+                } else {
+                    Ok(OptionValue::String(value.to_string()))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UciOption {
+    pub name: String,
+    pub kind: OptionKind,
+    pub value: OptionValue,
+}
+
+// Formats the option the way it is advertised after "uci"
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match &self.kind {
+            OptionKind::Check { default } => write!(f, "check default {}", default),
+            OptionKind::Spin { default, min, max } => {
+                write!(f, "spin default {} min {} max {}", default, min, max)
+            }
+            OptionKind::Combo { default, choices } => {
+                write!(f, "combo default {}", default)?;
+                for choice in choices {
+                    write!(f, " var {}", choice)?;
+                }
+                Ok(())
+            }
+            OptionKind::Button => write!(f, "button"),
+            OptionKind::String { default } => {
+                let default = if default.is_empty() { "<empty>" } else { default };
+                write!(f, "string default {}", default)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OptionsRegistry {
+    options: Vec<UciOption>,
+}
+
+impl OptionsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &str, kind: OptionKind) {
+        let value = kind.default_value();
+        self.options.push(UciOption {
+            name: name.to_string(),
+            kind,
+            value,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UciOption> {
+        self.options.iter()
+    }
+
+    // Option names are matched case-insensitively, as the UCI protocol asks
+    pub fn get(&self, name: &str) -> Option<&OptionValue> {
+        self.find(name).map(|option| &option.value)
+    }
+
+    fn find(&self, name: &str) -> Option<&UciOption> {
+        self.options.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+    }
+
+    // Parses the tokens after "setoption", stores the new value and returns
+    // the option's canonical name with the value to apply
+    pub fn parse_setoption(&mut self, parts: &[&str]) -> Result<(String, OptionValue), String> {
+        if parts.first() != Some(&"name") {
+            return Err("expected 'setoption name <id> [value <x>]'".to_string());
+        }
+        let value_pos = parts.iter().position(|&p| p == "value").unwrap_or(parts.len());
+        let name = parts[1..value_pos].join(" ");
+        let value = parts.get(value_pos + 1..).map(|rest| rest.join(" ")).unwrap_or_default();
+
+        let option = self
+            .options
+            .iter_mut()
+            .find(|option| option.name.eq_ignore_ascii_case(&name))
+            .ok_or_else(|| format!("unknown option '{}'", name))?;
+        if value_pos == parts.len() && option.kind != OptionKind::Button {
+            return Err(format!("missing value for option '{}'", option.name));
+        }
+
+        let parsed = option.kind.parse_value(&value)?;
+        option.value = parsed.clone();
+        Ok((option.name.clone(), parsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> OptionsRegistry {
+        let mut options = OptionsRegistry::new();
+        options.register("Threads", OptionKind::Spin { default: 1, min: 1, max: 64 });
+        options.register("Ponder", OptionKind::Check { default: false });
+        options.register("Clear Hash", OptionKind::Button);
+        options.register(
+            "Style",
+            OptionKind::Combo { default: "Normal".to_string(), choices: vec!["Solid".to_string(), "Normal".to_string()] },
+        );
+        options
+    }
+
+    #[test]
+    fn test_uci_lines() {
+        let options = registry();
+        let lines: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "option name Threads type spin default 1 min 1 max 64",
+                "option name Ponder type check default false",
+                "option name Clear Hash type button",
+                "option name Style type combo default Normal var Solid var Normal",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_setoption() {
+        let mut options = registry();
+
+        let parsed = options.parse_setoption(&["name", "threads", "value", "4"]);
+        assert_eq!(parsed, Ok(("Threads".to_string(), OptionValue::Spin(4))));
+        assert_eq!(options.get("Threads"), Some(&OptionValue::Spin(4)));
+
+        let parsed = options.parse_setoption(&["name", "Clear", "Hash"]);
+        assert_eq!(parsed, Ok(("Clear Hash".to_string(), OptionValue::Button)));
+
+        let parsed = options.parse_setoption(&["name", "Style", "value", "solid"]);
+        assert_eq!(parsed, Ok(("Style".to_string(), OptionValue::Combo("Solid".to_string()))));
+    }
+
+    #[test]
+    fn test_parse_setoption_rejects_bad_input() {
+        let mut options = registry();
+
+        assert!(options.parse_setoption(&["name", "Threads", "value", "0"]).is_err());
+        assert!(options.parse_setoption(&["name", "Threads", "value", "many"]).is_err());
+        assert!(options.parse_setoption(&["name", "Ponder", "value", "maybe"]).is_err());
+        assert!(options.parse_setoption(&["name", "Ponder"]).is_err());
+        assert!(options.parse_setoption(&["name", "Bogus", "value", "1"]).is_err());
+        // Rejected values leave the option unchanged
+        assert_eq!(options.get("Threads"), Some(&OptionValue::Spin(1)));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::board::Board;
+use crate::movegen::{Move, MoveGenerator};
+
+// Counts the leaf nodes of the legal move tree to the given depth
+pub fn perft(board: &Board, generator: &MoveGenerator, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = generator.generate_moves(board);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves {
+        let mut new_board = board.clone();
+        new_board.make_move(mv);
+        nodes += perft(&new_board, generator, depth - 1);
+    }
+
+    nodes
+}
+
+// Perft split by root move, for comparing against a reference engine move by move
+pub fn divide(board: &Board, generator: &MoveGenerator, depth: u32) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+
+    generator
+        .generate_moves(board)
+        .into_iter()
+        .map(|mv| {
+            let mut new_board = board.clone();
+            new_board.make_move(mv);
+            (mv, perft(&new_board, generator, depth - 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Piece;
+
+    #[test]
+    fn test_divide_sums_to_perft() {
+        let board = Board::new();
+        let generator = MoveGenerator::new();
+
+        let split = divide(&board, &generator, 3);
+        assert_eq!(split.len(), 20);
+        assert_eq!(split.iter().map(|(_, nodes)| nodes).sum::<u64>(), perft(&board, &generator, 3));
+    }
+
+    #[test]
+    fn test_perft_with_rook_captures_in_corners() {
+        let generator = MoveGenerator::new();
+        // Rooks face each other down the a- and h-files, so taking one in its
+        // corner at the first ply must cost the victim that side's castling
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(perft(&board, &generator, 1), 26);
+        assert_eq!(perft(&board, &generator, 2), 568);
+        assert_eq!(perft(&board, &generator, 3), 13744);
+        // Kiwipete, the usual stress test for castling, captures and promotions
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(perft(&board, &generator, 2), 2039);
+        assert_eq!(perft(&board, &generator, 3), 97862);
+
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        board.make_move(Move { captured_piece: Some(Piece::Rook), ..Move::new(0, 56, Piece::Rook) });
+        assert_eq!(board.castling_rights, 0b0101);
+        assert!(generator.generate_moves(&board).iter().all(|mv| !(mv.is_castling && mv.to == 58)));
+    }
+}
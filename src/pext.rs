@@ -0,0 +1,102 @@
+// Sliding attacks looked up in tables indexed by PEXT, which gathers the
+// occupancy bits on a piece's rays into a dense index in one instruction.
+// build.rs writes the tables at compile time; they are only used on x86-64
+// CPUs with BMI2, elsewhere the magic tables stand in.
+
+include!(concat!(env!("OUT_DIR"), "/pext_tables.rs"));
+
+struct Table {
+    // The squares whose occupancy matters, edges left out since a ray always reaches them
+    masks: &'static [u64; 64],
+    offsets: &'static [usize; 64],
+    attacks: &'static [u64],
+}
+
+impl Table {
+    #[cfg(target_arch = "x86_64")]
+    fn get(&self, square: u8, occupied: u64) -> u64 {
+        let square = square as usize;
+        // Safe: tables are only handed out once BMI2 has been detected
+        let index = unsafe { pext(occupied, self.masks[square]) } as usize;
+        self.attacks[self.offsets[square] + index]
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn get(&self, _square: u8, _occupied: u64) -> u64 {
+        unreachable!("PEXT tables are only used on x86-64")
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn pext(value: u64, mask: u64) -> u64 {
+    core::arch::x86_64::_pext_u64(value, mask)
+}
+
+pub struct PextTables {
+    bishop: Table,
+    rook: Table,
+}
+
+static TABLES: PextTables = PextTables {
+    bishop: Table { masks: &BISHOP_MASKS, offsets: &BISHOP_OFFSETS, attacks: &BISHOP_ATTACKS },
+    rook: Table { masks: &ROOK_MASKS, offsets: &ROOK_OFFSETS, attacks: &ROOK_ATTACKS },
+};
+
+impl PextTables {
+    pub fn bishop_attacks(&self, square: u8, occupied: u64) -> u64 {
+        self.bishop.get(square, occupied)
+    }
+
+    pub fn rook_attacks(&self, square: u8, occupied: u64) -> u64 {
+        self.rook.get(square, occupied)
+    }
+}
+
+// Whether this CPU has PEXT. Compiling with -C target-feature=+bmi2 turns the
+// check into a constant; without std that is the only way to turn it on.
+pub fn available() -> bool {
+    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    {
+        std::is_x86_feature_detected!("bmi2")
+    }
+    #[cfg(not(all(target_arch = "x86_64", feature = "std")))]
+    {
+        cfg!(target_feature = "bmi2")
+    }
+}
+
+// The tables, or None without BMI2. The CPU is checked once per process.
+pub fn tables() -> Option<&'static PextTables> {
+    #[cfg(feature = "std")]
+    {
+        static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+        AVAILABLE.get_or_init(available).then_some(&TABLES)
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        available().then_some(&TABLES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attacks::{ray_attacks, BISHOP_DIRECTIONS, ROOK_DIRECTIONS};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_tables_match_rays() {
+        let Some(tables) = tables() else {
+            return;
+        };
+        let mut rng = StdRng::seed_from_u64(965);
+        for _ in 0..2000 {
+            // Sparse and dense boards both
+            let occupied = rng.gen::<u64>() & rng.gen::<u64>() & if rng.gen() { rng.gen() } else { u64::MAX };
+            let square = rng.gen_range(0..64);
+            assert_eq!(tables.bishop_attacks(square, occupied), ray_attacks(square, occupied, &BISHOP_DIRECTIONS));
+            assert_eq!(tables.rook_attacks(square, occupied), ray_attacks(square, occupied, &ROOK_DIRECTIONS));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::game::Game;
+
+// A game as written in a PGN file: its tags, the moves as SAN text and the result
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+// Reads every game in a PGN text. Comments, NAGs and variations are skipped.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                // Tags after movetext belong to the next game
+                if !game.moves.is_empty() {
+                    games.push(std::mem::take(&mut game));
+                }
+                let name: String = chars.by_ref().take_while(|c| !c.is_whitespace()).collect();
+                let mut value = String::new();
+                let mut quoted = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => quoted = !quoted,
+                        '\\' if quoted => value.extend(chars.next()),
+                        ']' if !quoted => break,
+                        c if quoted => value.push(c),
+                        _ => {}
+                    }
+                }
+                game.tags.push((name, value));
+            }
+            '{' => chars.by_ref().take_while(|&c| c != '}').for_each(drop),
+            ';' => chars.by_ref().take_while(|&c| c != '\n').for_each(drop),
+            '(' => {
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "[{;(".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+
+                if RESULTS.contains(&token.as_str()) {
+                    game.result = token;
+                    games.push(std::mem::take(&mut game));
+                } else if !token.starts_with('$') {
+                    // Move numbers may be glued to the move: "1.e4", "12...Nf6"
+                    let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                    if !san.is_empty() {
+                        game.moves.push(san.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    if !game.moves.is_empty() || !game.tags.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+// Reads every game in a PGN text and replays its moves; a game with an
+// unreadable move comes back as an error naming that move
+pub fn read_games(text: &str) -> Vec<Result<Game, String>> {
+    parse_pgn(text).iter().map(Game::from_pgn).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pgn() {
+        let text = r#"[Event "Casual"]
+[White "A \"B\" [C]"]
+[Result "1-0"]
+
+1. e4 e5 {best by test} 2. Nf3 (2. f4 exf4) Nc6 $1 3.Bb5 a6?! ; the Morphy
+4. Ba4 1-0
+
+[Event "Second"]
+1. d4 d5 *
+"#;
+        let games = parse_pgn(text);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("White"), Some("A \"B\" [C]"));
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6?!", "Ba4"]);
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].moves, ["d4", "d5"]);
+        assert_eq!(games[1].result, "*");
+
+        let games = read_games(text);
+        assert_eq!(games[0].as_ref().unwrap().moves.len(), 7);
+        assert_eq!(games[1].as_ref().unwrap().moves.len(), 2);
+        assert!(read_games("1. e4 e4 *")[0].is_err());
+    }
+}
@@ -0,0 +1,38 @@
+// The types most programs using the engine need, for a single glob import:
+//
+//     use three_salmons::prelude::*;
+//
+// Positions are Boards, moves come from a MoveGenerator, and an Engine keeps
+// a game and searches it; Evaluator and Search are the parts underneath for
+// those who want them on their own.
+
+pub use crate::board::{Board, Color, Piece, Square};
+#[cfg(feature = "engine")]
+pub use crate::engine::{Engine, SearchResult};
+pub use crate::evaluation::Evaluator;
+pub use crate::movegen::{GameState, MaterialRule, Move, MoveGenerator, MoveList};
+#[cfg(feature = "search")]
+pub use crate::search::{Search, SearchLimits};
+
+#[cfg(all(test, feature = "engine"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prelude_plays_a_game() {
+        let mut engine = Engine::new();
+        engine.set_position(None, &["e2e4", "e7e5"]).unwrap();
+        let board: Board = engine.board().clone();
+        assert_eq!(board.side_to_move, Color::White);
+        let moves = MoveGenerator::new().generate_moves(&board);
+        assert!(moves.iter().any(|mv| mv.piece == Piece::Queen));
+        assert_eq!(engine.game_state(), GameState::Ongoing);
+
+        let result: SearchResult = engine.search(SearchLimits { depth: 2, ..SearchLimits::default() }).unwrap();
+        let square: Square = result.best_move.to;
+        assert!(square < 64);
+        let evaluator = Evaluator::new();
+        assert_eq!(evaluator.evaluate(&board), evaluator.trace(&board).total);
+        assert_eq!(Search::new().get_nodes_searched(), 0);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::board::{DirtyPieces, Piece};
+
+// Material and piece-square values, the part of the evaluation that only
+// depends on which piece stands where. Boards keep running totals of them
+// per side, updated as pieces come and go, so the evaluator can read them
+// off instead of visiting every piece.
+
+// In bitboard order
+pub const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 20000];
+
+// Pawn position bonuses (encourages central control and advancement)
+pub const PAWN_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [10, 10, 20, 30, 30, 20, 10, 10],
+    [5, 5, 10, 25, 25, 10, 5, 5],
+    [0, 0, 0, 20, 20, 0, 0, 0],
+    [5, -5, -10, 0, 0, -10, -5, 5],
+    [5, 10, 10, -20, -20, 10, 10, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+// Knight position bonuses (encourages central control)
+pub const KNIGHT_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20, 0, 0, 0, 0, -20, -40],
+    [-30, 0, 10, 15, 15, 10, 0, -30],
+    [-30, 5, 15, 20, 20, 15, 5, -30],
+    [-30, 0, 15, 20, 20, 15, 0, -30],
+    [-30, 5, 10, 15, 15, 10, 5, -30],
+    [-40, -20, 0, 5, 5, 0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+// Bishop position bonuses (encourages central control and long diagonals)
+pub const BISHOP_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 5, 5, 10, 10, 5, 5, -10],
+    [-10, 0, 10, 10, 10, 10, 0, -10],
+    [-10, 10, 10, 10, 10, 10, 10, -10],
+    [-10, 5, 0, 0, 0, 0, 5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+// Rook position bonuses (encourages open files and central control)
+pub const ROOK_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 10, 10, 10, 10, 10, 10, 5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [0, 0, 0, 5, 5, 0, 0, 0],
+];
+
+// Queen position bonuses (encourages central control and mobility)
+pub const QUEEN_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [0, 0, 5, 5, 5, 5, 0, -5],
+    [-10, 5, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 0, 0, 0, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+// King position bonuses (encourages safety in opening/middlegame)
+pub const KING_TABLE: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [20, 20, 0, 0, 0, 0, 20, 20],
+    [20, 30, 10, 0, 0, 10, 30, 20],
+];
+
+// King position bonuses for endgame (encourages centralization)
+pub const KING_ENDGAME_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10, 0, 0, -10, -20, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -30, 0, 0, 0, 0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+// One side's totals. Only the king's table changes in the endgame, so mg and
+// eg differ by the king alone.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PsqtScore {
+    pub material: i32,
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl PsqtScore {
+    // The totals of the pieces on a side's bitboards
+    pub fn compute(pieces: &[u64; 6]) -> Self {
+        let mut score = Self::default();
+        for (&piece, &bitboard) in Piece::ALL.iter().zip(pieces) {
+            let mut bitboard = bitboard;
+            while bitboard != 0 {
+                score.add(piece, bitboard.trailing_zeros() as u8);
+                bitboard &= bitboard - 1;
+            }
+        }
+        score
+    }
+
+    pub fn add(&mut self, piece: Piece, square: u8) {
+        let (mg, eg) = square_values(piece, square);
+        self.material += PIECE_VALUES[piece as usize];
+        self.mg += mg;
+        self.eg += eg;
+    }
+
+    pub fn remove(&mut self, piece: Piece, square: u8) {
+        let (mg, eg) = square_values(piece, square);
+        self.material -= PIECE_VALUES[piece as usize];
+        self.mg -= mg;
+        self.eg -= eg;
+    }
+}
+
+fn square_values(piece: Piece, square: u8) -> (i32, i32) {
+    let (rank, file) = (square as usize / 8, square as usize % 8);
+    let table = match piece {
+        Piece::Pawn => &PAWN_TABLE,
+        Piece::Knight => &KNIGHT_TABLE,
+        Piece::Bishop => &BISHOP_TABLE,
+        Piece::Rook => &ROOK_TABLE,
+        Piece::Queen => &QUEEN_TABLE,
+        Piece::King => return (KING_TABLE[rank][file], KING_ENDGAME_TABLE[rank][file]),
+    };
+    (table[rank][file], table[rank][file])
+}
+
+// Brings both sides' totals, White's first, past the pieces a move changed
+pub fn update(scores: &mut [PsqtScore; 2], dirty: &DirtyPieces) {
+    for change in dirty.removed() {
+        scores[change.color as usize].remove(change.piece, change.square);
+    }
+    for change in dirty.added() {
+        scores[change.color as usize].add(change.piece, change.square);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::board::Board;
+use crate::engine::Engine;
+use crate::movegen::{GameState, Move, MoveGenerator};
+use crate::pgn::read_games;
+use crate::san::{format_line, parse_san};
+use crate::search::{SearchLimits, MATE_SCORE, MATE_THRESHOLD, MAX_DEPTH};
+use crate::uci::format_move;
+
+// How much deeper the confirmation search looks than the one that found the solution
+const CONFIRMATION_PLIES: u32 = 2;
+// How far ahead of every alternative a move that doesn't mate must stay to be the only solution
+const UNIQUE_MARGIN: i32 = 200;
+
+#[derive(Debug, Clone)]
+pub struct Puzzle {
+    pub id: String,
+    pub board: Board,
+    // The accepted first moves, when the source names them
+    pub best_moves: Vec<Move>,
+}
+
+// Puzzles from a PGN file (each game's start position, its first move being
+// the solution) or from EPD lines with optional "bm" and "id" operations
+pub fn load_puzzles(text: &str) -> Result<Vec<Puzzle>, String> {
+    if text.trim_start().starts_with('[') {
+        return read_games(text)
+            .into_iter()
+            .enumerate()
+            .map(|(i, game)| {
+                let game = game.map_err(|err| format!("game {}: {}", i + 1, err))?;
+                Ok(Puzzle {
+                    id: game.tag("Event").filter(|&event| event != "?").map_or_else(|| format!("#{}", i + 1), str::to_string),
+                    board: game.start.clone(),
+                    best_moves: game.moves.first().copied().into_iter().collect(),
+                })
+            })
+            .collect();
+    }
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .enumerate()
+        .map(|(i, line)| parse_epd(line, i + 1).map_err(|err| format!("line '{}': {}", line, err)))
+        .collect()
+}
+
+fn parse_epd(line: &str, number: usize) -> Result<Puzzle, String> {
+    let fields: Vec<&str> = line.splitn(5, ' ').collect();
+    if fields.len() < 4 {
+        return Err("expected a FEN position".to_string());
+    }
+    // A full FEN works too: its move counters just aren't operations
+    let operations = fields.get(4).copied().unwrap_or_default();
+    let board = match operations.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        [halfmove, fullmove] if halfmove.parse::<u8>().is_ok() && fullmove.parse::<u16>().is_ok() => {
+            Board::from_fen(&format!("{} {} {}", fields[..4].join(" "), halfmove, fullmove))?
+        }
+        _ => Board::from_fen(&fields[..4].join(" "))?,
+    };
+
+    let generator = MoveGenerator::new();
+    let mut puzzle = Puzzle { id: format!("#{}", number), board, best_moves: Vec::new() };
+    for operation in operations.split(';').map(str::trim) {
+        if let Some(moves) = operation.strip_prefix("bm ") {
+            for san in moves.split_whitespace() {
+                puzzle.best_moves.push(parse_san(&puzzle.board, &generator, san)?);
+            }
+        } else if let Some(id) = operation.strip_prefix("id ") {
+            puzzle.id = id.trim_matches('"').to_string();
+        }
+    }
+    Ok(puzzle)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub line: Vec<Move>,
+    // Centipawns for the side to move
+    pub score: i32,
+    // Depth of the confirmation search the solution held up in
+    pub depth: u32,
+}
+
+impl Solution {
+    // Moves to mate for the side to move, when the solution mates
+    pub fn mate_in(&self) -> Option<i32> {
+        (self.score >= MATE_THRESHOLD).then(|| (MATE_SCORE - self.score + 1) / 2)
+    }
+}
+
+// Searches the position to depth for a forced mate or a single clearly best
+// move, then checks the answer with a deeper search: a mate has to still be
+// a mate, and any other move has to stay best by a clear margin.
+pub fn solve(engine: &mut Engine, board: &Board, depth: u32) -> Result<Solution, String> {
+    let fen = board.to_fen();
+    engine.new_game();
+    engine.set_position(Some(&fen), &[])?;
+    let found = engine.search(SearchLimits { depth, ..SearchLimits::default() }).ok_or("the game is already over")?;
+
+    let confirm_depth = (depth + CONFIRMATION_PLIES).min(MAX_DEPTH);
+    let confirmed = engine
+        .search(SearchLimits { depth: confirm_depth, ..SearchLimits::default() })
+        .ok_or("the game is already over")?;
+    let solution = Solution { line: confirmed.pv.clone(), score: confirmed.score, depth: confirm_depth };
+
+    if confirmed.score <= -MATE_THRESHOLD {
+        return Err("the side to move is getting mated".to_string());
+    }
+    if found.score >= MATE_THRESHOLD {
+        if confirmed.score < MATE_THRESHOLD {
+            return Err(format!("the mate found at depth {} does not hold up at depth {}", depth, confirm_depth));
+        }
+        return Ok(solution);
+    }
+    if confirmed.best_move != found.best_move {
+        return Err(format!(
+            "{} at depth {} gives way to {} at depth {}",
+            format_move(&found.best_move),
+            depth,
+            format_move(&confirmed.best_move),
+            confirm_depth
+        ));
+    }
+    if confirmed.score >= MATE_THRESHOLD {
+        return Ok(solution);
+    }
+
+    // Every alternative is searched on its own, as deep as the best move was
+    let generator = MoveGenerator::new();
+    for mv in generator.generate_moves(board) {
+        if mv == confirmed.best_move {
+            continue;
+        }
+        engine.set_position(Some(&fen), &[&format_move(&mv)])?;
+        let score = match engine.search(SearchLimits { depth: confirm_depth - 1, ..SearchLimits::default() }) {
+            Some(reply) => -reply.score,
+            None if matches!(engine.game_state(), GameState::Checkmate(_)) => MATE_SCORE,
+            None => 0,
+        };
+        if confirmed.score - score < UNIQUE_MARGIN {
+            return Err(format!(
+                "{} ({}) is not clearly better than {} ({})",
+                format_move(&confirmed.best_move),
+                confirmed.score,
+                format_move(&mv),
+                score
+            ));
+        }
+    }
+    Ok(solution)
+}
+
+// "1. Qh5+ Kf8 2. Qf7# (mate in 2)" or "23... Rxe3 (+3.15)"
+pub fn describe(board: &Board, solution: &Solution) -> String {
+    let line = format_line(board, &MoveGenerator::new(), &solution.line);
+    match solution.mate_in() {
+        Some(moves) => format!("{} (mate in {})", line, moves),
+        None => format!("{} ({:+.2})", line, solution.score as f64 / 100.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_puzzles() {
+        let epd = "# back rank\n6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; id \"back rank\";\n4k3/8/8/8/8/8/8/4K2R w K - 0 1\n";
+        let puzzles = load_puzzles(epd).unwrap();
+        assert_eq!(puzzles.len(), 2);
+        assert_eq!(puzzles[0].id, "back rank");
+        assert_eq!(puzzles[0].best_moves.iter().map(format_move).collect::<Vec<_>>(), vec!["a1a8"]);
+        assert_eq!(puzzles[1].id, "#2");
+        assert!(puzzles[1].best_moves.is_empty());
+        assert!(load_puzzles("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Rb9;").is_err());
+
+        let pgn = "[Event \"Scholar\"]\n[FEN \"r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 0 1\"]\n\n1. Qxf7# *\n";
+        let puzzles = load_puzzles(pgn).unwrap();
+        assert_eq!(puzzles[0].id, "Scholar");
+        assert_eq!(format_move(&puzzles[0].best_moves[0]), "f3f7");
+    }
+
+    #[test]
+    fn test_solve_mate() {
+        let mut engine = Engine::new();
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let solution = solve(&mut engine, &board, 2).unwrap();
+        assert_eq!(solution.mate_in(), Some(1));
+        assert_eq!(describe(&board, &solution), "1. Ra8# (mate in 1)");
+    }
+
+    #[test]
+    fn test_solve_rejects_positions_without_a_clear_answer() {
+        let mut engine = Engine::new();
+        // The start position has plenty of equally good moves
+        assert!(solve(&mut engine, &Board::new(), 2).is_err());
+
+        // Taking the hanging queen is the only good move
+        let board = Board::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let solution = solve(&mut engine, &board, 2).unwrap();
+        assert_eq!(format_move(&solution.line[0]), "d2d5");
+        assert_eq!(solution.mate_in(), None);
+    }
+}
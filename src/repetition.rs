@@ -0,0 +1,177 @@
+use crate::board::{Board, Piece};
+use crate::movegen::Move;
+use alloc::vec::Vec;
+
+// The hashes of a game's positions, for spotting repetitions. A pawn move,
+// capture or lost castling right can never be undone, so no position before
+// one can come back: each entry remembers where its reversible stretch
+// began and only that tail is scanned.
+#[derive(Debug, Clone, Default)]
+pub struct RepetitionHistory {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    hash: u64,
+    // Index of the first position since the last irreversible move
+    start: usize,
+}
+
+// Whether the position before mv, which had the given castling rights, can
+// never occur again after it
+pub fn is_irreversible(castling_before: u8, mv: &Move, after: &Board) -> bool {
+    mv.piece == Piece::Pawn || mv.captured_piece.is_some() || castling_before != after.castling_rights
+}
+
+impl RepetitionHistory {
+    // A history holding only the starting position
+    pub fn new(start: &Board) -> Self {
+        let mut history = Self::default();
+        history.push(start.hash, true);
+        history
+    }
+
+    // The history of a game given as each position with the move played from
+    // it, ending at current
+    pub fn from_moves(moves: &[(Board, Move)], current: &Board) -> Self {
+        let Some((first, _)) = moves.first() else {
+            return Self::new(current);
+        };
+        let mut history = Self::new(first);
+        for (i, (before, mv)) in moves.iter().enumerate() {
+            let after = moves.get(i + 1).map_or(current, |(board, _)| board);
+            history.push(after.hash, is_irreversible(before.castling_rights, mv, after));
+        }
+        history
+    }
+
+    pub fn push(&mut self, hash: u64, irreversible: bool) {
+        let start = match self.entries.last() {
+            Some(last) if !irreversible => last.start,
+            _ => self.entries.len(),
+        };
+        self.entries.push(Entry { hash, start });
+    }
+
+    pub fn pop(&mut self) {
+        self.entries.pop();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The hash of the latest position
+    pub fn current(&self) -> Option<u64> {
+        self.entries.last().map(|entry| entry.hash)
+    }
+
+    // Indices of the earlier positions equal to the latest one, latest first.
+    // Only positions with the same side to move, every other one, can match.
+    fn earlier_occurrences(&self) -> impl Iterator<Item = usize> + '_ {
+        let (hash, start, len) = match self.entries.last() {
+            Some(last) => (last.hash, last.start, self.entries.len()),
+            None => (0, 0, 0),
+        };
+        (start..len.saturating_sub(1)).rev().skip(1).step_by(2).filter(move |&i| self.entries[i].hash == hash)
+    }
+
+    // How many times the latest position has occurred, itself included
+    pub fn count(&self) -> usize {
+        self.earlier_occurrences().count() + usize::from(!self.is_empty())
+    }
+
+    pub fn is_threefold(&self) -> bool {
+        self.count() >= 3
+    }
+
+    // Whether a search whose root is the position at index root should score
+    // the latest position a draw. A repetition within the search's own line is
+    // enough, as whatever was best the first time will be again; against the
+    // game before the root the full threefold is needed.
+    pub fn is_search_draw(&self, root: usize) -> bool {
+        let mut before_root = 0;
+        for i in self.earlier_occurrences() {
+            if i >= root {
+                return true;
+            }
+            before_root += 1;
+            if before_root >= 2 {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGenerator;
+
+    fn play(history: &mut RepetitionHistory, board: &mut Board, moves: &[&str]) {
+        let generator = MoveGenerator::new();
+        for move_str in moves {
+            let mv = generator.parse_uci_move(board, move_str).unwrap();
+            let undo = board.make_move(mv);
+            history.push(board.hash, is_irreversible(undo.castling_rights, &mv, board));
+        }
+    }
+
+    #[test]
+    fn test_repetitions_within_the_reversible_tail() {
+        let mut board = Board::new();
+        let mut history = RepetitionHistory::new(&board);
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        play(&mut history, &mut board, &shuffle);
+        assert_eq!(history.count(), 2);
+        play(&mut history, &mut board, &shuffle);
+        assert!(history.is_threefold());
+
+        // A pawn move cuts the history: the same shuffle starts counting afresh
+        play(&mut history, &mut board, &["e2e3", "e7e6"]);
+        play(&mut history, &mut board, &shuffle);
+        assert_eq!(history.count(), 2);
+        assert_eq!(history.len(), 15);
+        history.pop();
+        assert_eq!(history.count(), 1);
+    }
+
+    #[test]
+    fn test_search_draws() {
+        let mut board = Board::new();
+        let mut history = RepetitionHistory::new(&board);
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        play(&mut history, &mut board, &shuffle);
+        // Seen once before the root: not yet a draw for a search started here
+        let root = history.len() - 1;
+        assert!(!history.is_search_draw(root));
+        // Once more inside the search's line is
+        play(&mut history, &mut board, &["b1c3", "b8c6", "c3b1", "c6b8"]);
+        assert!(history.is_search_draw(root));
+        // Twice before the root is the threefold
+        assert!(history.is_search_draw(history.len() - 1));
+        assert!(!RepetitionHistory::from_moves(&[], &board).is_search_draw(0));
+    }
+
+    #[test]
+    fn test_from_moves_matches_pushes() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::new();
+        let mut moves = Vec::new();
+        for move_str in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3"] {
+            let mv = generator.parse_uci_move(&board, move_str).unwrap();
+            moves.push((board.clone(), mv));
+            board.make_move(mv);
+        }
+        let history = RepetitionHistory::from_moves(&moves, &board);
+        assert_eq!(history.len(), 6);
+        assert_eq!(history.current(), Some(board.hash));
+        assert_eq!(history.count(), 2);
+    }
+}
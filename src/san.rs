@@ -0,0 +1,235 @@
+use crate::board::{parse_square, Board, Color, Piece};
+use crate::movegen::{Move, MoveGenerator};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+fn piece_letter(piece: Piece) -> Option<char> {
+    match piece {
+        Piece::Pawn => None,
+        Piece::Knight => Some('N'),
+        Piece::Bishop => Some('B'),
+        Piece::Rook => Some('R'),
+        Piece::Queen => Some('Q'),
+        Piece::King => Some('K'),
+    }
+}
+
+fn piece_from_letter(c: char) -> Option<Piece> {
+    match c {
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+// Resolves standard algebraic notation (Nf3, exd5, O-O, e8=Q+) to a legal move
+pub fn parse_san(board: &Board, generator: &MoveGenerator, san: &str) -> Result<Move, String> {
+    let invalid = || format!("invalid move '{}'", san);
+    // Check, mate and annotation marks carry no information we need
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal = generator.generate_moves(board);
+
+    if matches!(text, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+        let kingside = text.len() == 3;
+        return legal
+            .into_iter()
+            .find(|mv| mv.is_castling && (mv.to > mv.from) == kingside)
+            .ok_or_else(|| format!("illegal move '{}'", san));
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+    let piece = match chars.first().and_then(|&c| piece_from_letter(c)) {
+        Some(piece) => {
+            chars.remove(0);
+            piece
+        }
+        None => Piece::Pawn,
+    };
+
+    // Promotion: e8=Q, or e8Q from sloppier writers
+    let mut promotion = None;
+    if let Some(&last) = chars.last() {
+        if let Some(promoted) = piece_from_letter(last).filter(|&p| p != Piece::King) {
+            promotion = Some(promoted);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return Err(invalid());
+    }
+    let to_text: String = chars[chars.len() - 2..].iter().collect();
+    let to = parse_square(&to_text).ok_or_else(invalid)?;
+
+    // Whatever is left between the piece and the destination disambiguates the origin
+    let mut from_file = None;
+    let mut from_rank = None;
+    for &c in &chars[..chars.len() - 2] {
+        match c {
+            'a'..='h' => from_file = Some(c as u8 - b'a'),
+            '1'..='8' => from_rank = Some(c as u8 - b'1'),
+            'x' | ':' | '-' => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    let mut candidates = legal.into_iter().filter(|mv| {
+        mv.piece == piece
+            && mv.to == to
+            && mv.promotion == promotion
+            && from_file.is_none_or(|file| mv.from % 8 == file)
+            && from_rank.is_none_or(|rank| mv.from / 8 == rank)
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some(mv), None) => Ok(mv),
+        (Some(_), Some(_)) => Err(format!("ambiguous move '{}'", san)),
+        (None, _) => Err(format!("illegal move '{}'", san)),
+    }
+}
+
+// Formats a legal move in standard algebraic notation, with check and mate marks
+pub fn to_san(board: &Board, generator: &MoveGenerator, mv: &Move) -> String {
+    let mut san = String::new();
+
+    if mv.is_castling {
+        san.push_str(if mv.to > mv.from { "O-O" } else { "O-O-O" });
+    } else {
+        let square_name = |square: u8| format!("{}{}", (b'a' + square % 8) as char, (b'1' + square / 8) as char);
+        match piece_letter(mv.piece) {
+            Some(letter) => {
+                san.push(letter);
+                // Name the origin file, rank or square if another piece of the same kind could go there
+                let others: Vec<Move> = generator
+                    .generate_moves(board)
+                    .into_iter()
+                    .filter(|other| other.piece == mv.piece && other.to == mv.to && other.from != mv.from)
+                    .collect();
+                if !others.is_empty() {
+                    let file = (b'a' + mv.from % 8) as char;
+                    let rank = (b'1' + mv.from / 8) as char;
+                    if others.iter().all(|other| other.from % 8 != mv.from % 8) {
+                        san.push(file);
+                    } else if others.iter().all(|other| other.from / 8 != mv.from / 8) {
+                        san.push(rank);
+                    } else {
+                        san.push(file);
+                        san.push(rank);
+                    }
+                }
+            }
+            None if mv.captured_piece.is_some() => san.push((b'a' + mv.from % 8) as char),
+            None => {}
+        }
+        if mv.captured_piece.is_some() {
+            san.push('x');
+        }
+        san.push_str(&square_name(mv.to));
+        if let Some(promotion) = mv.promotion.and_then(piece_letter) {
+            san.push('=');
+            san.push(promotion);
+        }
+    }
+
+    let mut after = board.clone();
+    after.make_move(*mv);
+    if generator.is_king_in_check(&after, after.side_to_move) {
+        san.push(if generator.generate_moves(&after).is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+// A line of moves from board in numbered SAN, "12... Kxf7 13. Qh5+"
+pub fn format_line(board: &Board, generator: &MoveGenerator, line: &[Move]) -> String {
+    let mut board = board.clone();
+    let mut tokens = Vec::new();
+    for (i, mv) in line.iter().enumerate() {
+        if board.side_to_move == Color::White {
+            tokens.push(format!("{}.", board.fullmove_number));
+        } else if i == 0 {
+            tokens.push(format!("{}...", board.fullmove_number));
+        }
+        tokens.push(to_san(&board, generator, mv));
+        board.make_move(*mv);
+    }
+    tokens.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_san() {
+        let generator = MoveGenerator::new();
+        let board = Board::new();
+
+        let mv = parse_san(&board, &generator, "Nf3").unwrap();
+        assert_eq!((mv.from, mv.to), (6, 21));
+        let mv = parse_san(&board, &generator, "e4").unwrap();
+        assert_eq!((mv.from, mv.to), (12, 28));
+
+        assert!(parse_san(&board, &generator, "e5").is_err());
+        assert!(parse_san(&board, &generator, "Zz9").is_err());
+        assert!(parse_san(&board, &generator, "").is_err());
+
+        // Two rooks can reach d1
+        let board = Board::from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+        assert!(parse_san(&board, &generator, "Rd1").is_err());
+        assert_eq!(parse_san(&board, &generator, "Rad1").unwrap().from, 0);
+        assert_eq!(parse_san(&board, &generator, "Rhd1").unwrap().from, 7);
+    }
+
+    #[test]
+    fn test_parse_san_special_moves() {
+        let generator = MoveGenerator::new();
+        let board = Board::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+
+        assert!(parse_san(&board, &generator, "O-O").unwrap().is_castling);
+        assert_eq!(parse_san(&board, &generator, "O-O-O+").unwrap().to, 2);
+        assert!(parse_san(&board, &generator, "exd6").unwrap().is_en_passant);
+        assert_eq!(parse_san(&board, &generator, "bxa8=Q+").unwrap().promotion, Some(Piece::Queen));
+        assert_eq!(parse_san(&board, &generator, "b8N").unwrap().promotion, Some(Piece::Knight));
+    }
+
+    #[test]
+    fn test_to_san_round_trip() {
+        let generator = MoveGenerator::new();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for mv in generator.generate_moves(&board) {
+                let san = to_san(&board, &generator, &mv);
+                assert_eq!(parse_san(&board, &generator, &san), Ok(mv), "{} in {}", san, fen);
+            }
+        }
+
+        // Mate is marked as such
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let mv = parse_san(&board, &generator, "Ra8").unwrap();
+        assert_eq!(to_san(&board, &generator, &mv), "Ra8#");
+    }
+
+    #[test]
+    fn test_format_line() {
+        let generator = MoveGenerator::new();
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        let mut after = board.clone();
+        let mut line = Vec::new();
+        for san in ["e5", "Nf3", "Nc6"] {
+            let mv = parse_san(&after, &generator, san).unwrap();
+            after.make_move(mv);
+            line.push(mv);
+        }
+        assert_eq!(format_line(&board, &generator, &line), "1... e5 2. Nf3 Nc6");
+    }
+}
@@ -1,23 +1,248 @@
-use crate::board::{Board, Color, Piece};
+use crate::board::{Board, Color, Piece, UndoState};
+use crate::caches::Caches;
 use crate::evaluation::Evaluator;
-use crate::movegen::{Move, MoveGenerator};
-use crate::transposition::{NodeType, TranspositionEntry, TranspositionTable};
+use crate::experience::Experience;
+use crate::mcts;
+use crate::movegen::{GameState, Move, MoveGenerator, MoveList};
+use crate::nnue::{AccumulatorStack, Network};
+use crate::repetition::{self, RepetitionHistory};
+use crate::timeman::TimeBudget;
+use crate::transposition::{NodeType, TtStats};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Score for delivering checkmate at the root; mates further away score lower
+pub const MATE_SCORE: i32 = 30000;
+// Any score beyond this magnitude is a mate score
+pub const MATE_THRESHOLD: i32 = MATE_SCORE - 1000;
+// Beyond every score a search can return, for the bounds of a full window
+pub const INFINITE_SCORE: i32 = MATE_SCORE + 1;
+
+// Deepest iteration a search will go to unless told otherwise
+pub const MAX_DEPTH: u32 = 25;
+// Hard limit on how far a line goes from the root, quiescence included; a
+// node this deep takes the static evaluation. Ply-indexed tables have this
+// many entries.
+pub const MAX_PLY: u32 = 128;
+
+// Root moves are only reported as they are searched once a search has run this long
+const CURRMOVE_DELAY: Duration = Duration::from_millis(1000);
+// Nodes between currline reports, once they have started
+const CURRLINE_INTERVAL: u64 = 1 << 16;
+// Iterations from this depth on search a window this far either side of the
+// last score, doubling it on the side the score falls outside of
+const ASPIRATION_DEPTH: u32 = 4;
+const ASPIRATION_DELTA: i32 = 25;
+
+// Counters describing the most recent search
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub tt: TtStats,
+}
+
+// Flags another thread can use to steer a running search
+#[derive(Debug, Default)]
+pub struct SearchSignals {
+    // Abort as soon as possible and return the best move found so far
+    pub stop: AtomicBool,
+    // The search is pondering: ignore the time limit until this is cleared
+    pub ponder: AtomicBool,
+    // Which optional reports to send, read as each search starts
+    pub show_refutations: AtomicBool,
+    pub show_currline: AtomicBool,
+}
+
+// When a search must stop. Built by the caller (from a UCI "go") and read
+// directly by the search, so there is one place that fixes the units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchLimits {
+    pub depth: u32,
+    // None: no time limit, the search runs until another limit or "stop"
+    pub time: Option<Duration>,
+    // No new iteration is started after this long
+    pub soft_time: Option<Duration>,
+    pub nodes: Option<u64>,
+    // Only these root moves are searched; empty for all of them
+    pub searchmoves: Vec<Move>,
+    // Stop deepening once a mate in this many moves or fewer is found
+    pub mate: Option<u32>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            depth: MAX_DEPTH,
+            time: None,
+            soft_time: None,
+            nodes: None,
+            searchmoves: Vec::new(),
+            mate: None,
+        }
+    }
+}
+
+impl SearchLimits {
+    // These limits with the time ones taken from a budget
+    pub fn with_budget(self, budget: Option<TimeBudget>) -> Self {
+        Self { time: budget.map(|budget| budget.hard), soft_time: budget.map(|budget| budget.soft), ..self }
+    }
+}
+
+// Progress reports a running search sends to its owner
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchInfo {
+    // The root move now being searched, numbered from 1 in search order
+    CurrMove { depth: u32, mv: Move, number: usize },
+    // A root move scoring below the best, then the reply line refuting it
+    Refutation { line: Vec<Move> },
+    // The line from the root to the node being searched
+    CurrLine { line: Vec<Move> },
+    // A completed iteration of the deepening: the best score and its line.
+    // seldepth is the deepest ply reached so far, quiescence and extensions included.
+    Iteration { depth: u32, seldepth: u32, score: i32, nodes: u64, elapsed: Duration, pv: Vec<Move> },
+}
+
+// The order negamax generates and tries a node's moves in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MoveStage {
+    HashMove,
+    Captures,
+    Killers,
+    Quiets,
+}
+
+impl MoveStage {
+    const ALL: [MoveStage; 4] = [MoveStage::HashMove, MoveStage::Captures, MoveStage::Killers, MoveStage::Quiets];
+}
+
+// How the search picks its move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    AlphaBeta,
+    // PUCT tree search; exploration weighs the priors against the mean values
+    Mcts { exploration: f32 },
+}
+
+// Random choice among the root moves scoring less than margin centipawns below
+// the best, each weighted by exp(-loss / temperature). A zero margin always plays
+// the best move; the seed makes the choices repeatable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Variety {
+    pub margin: i32,
+    pub temperature: i32,
+    pub seed: u64,
+}
+
+pub type InfoCallback = Box<dyn FnMut(SearchInfo) + Send>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootMove {
+    pub mv: Move,
+    pub score: i32,
+    pub previous_score: i32,
+    // Principal variation starting with this move, as of the last time it raised alpha
+    pub pv: Vec<Move>,
+}
+
+// The legal moves at the root, kept across iterations and sorted best first
+// after each one, so every iteration starts with the previous best move
+#[derive(Debug, Clone, Default)]
+pub struct RootMoves {
+    moves: Vec<RootMove>,
+}
+
+impl RootMoves {
+    pub fn new(moves: &[Move]) -> Self {
+        Self {
+            moves: moves
+                .iter()
+                .map(|&mv| RootMove { mv, score: -INFINITE_SCORE, previous_score: -INFINITE_SCORE, pv: vec![mv] })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RootMove> {
+        self.moves.iter()
+    }
+
+    pub fn best(&self) -> Option<&RootMove> {
+        self.moves.first()
+    }
+
+    // Start a new iteration: scores move to previous_score
+    fn begin_iteration(&mut self) {
+        for root_move in &mut self.moves {
+            root_move.previous_score = root_move.score;
+            root_move.score = -INFINITE_SCORE;
+        }
+    }
+
+    // Moves left unsearched by an aborted iteration keep their place behind the searched ones
+    fn sort(&mut self) {
+        self.moves.sort_by_key(|root_move| std::cmp::Reverse(root_move.score));
+    }
+}
 
 pub struct Search {
     evaluator: Evaluator,
     move_generator: MoveGenerator,
-    transposition_table: TranspositionTable,
-    max_depth: u32,
-    max_time: Duration,
+    caches: Caches,
+    limits: SearchLimits,
     nodes_searched: u64,
+    seldepth: u32,
     start_time: Instant,
-    // Killer moves: store the best non-capture moves at each depth
-    killer_moves: [[Option<Move>; 2]; 64], // [depth][slot]
+    elapsed: Duration,
+    signals: Arc<SearchSignals>,
+    pondering: bool,
+    // Set once the search has been told to stop or ran out of time
+    stopped: bool,
+    info_callback: Option<InfoCallback>,
+    currmove_delay: Duration,
+    // Copied from the signals as each search starts
+    show_refutations: bool,
+    show_currline: bool,
+    // The moves from the root to the current node, kept while currline is shown
+    current_line: Vec<Move>,
+    root_moves: RootMoves,
+    experience: Option<Experience>,
+    algorithm: Algorithm,
+    variety: Variety,
+    rng: StdRng,
+    // Evaluates in place of the hand-crafted evaluation when set
+    network: Option<Arc<Network>>,
+    // The network's accumulators along the line being searched
+    accumulators: AccumulatorStack,
+    // A move buffer per ply, reused from node to node
+    move_lists: Vec<MoveList>,
+    // The game's positions up to the root, then the line being searched
+    repetitions: RepetitionHistory,
+    root_index: usize,
+    // Killer moves: the quiet moves that last cut off at each ply
+    killer_moves: [[Option<Move>; 2]; MAX_PLY as usize], // [ply][slot]
     // History heuristic: store how often a move has caused a beta cutoff
     history_table: [[i32; 64]; 64], // [from_square][to_square]
+    // Tests search every move with a full window to check principal variation search
+    #[cfg(test)]
+    full_windows: bool,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Search {
@@ -25,122 +250,446 @@ impl Search {
         Self {
             evaluator: Evaluator::new(),
             move_generator: MoveGenerator::new(),
-            transposition_table: TranspositionTable::new(1_000_000), // 1 million entries
-            max_depth: 25,
-            max_time: Duration::from_secs(20),
+            caches: Caches::default(),
+            limits: SearchLimits::default(),
             nodes_searched: 0,
+            seldepth: 0,
             start_time: Instant::now(),
-            killer_moves: [[None; 2]; 64],
+            elapsed: Duration::ZERO,
+            signals: Arc::new(SearchSignals::default()),
+            pondering: false,
+            stopped: false,
+            info_callback: None,
+            currmove_delay: CURRMOVE_DELAY,
+            show_refutations: false,
+            show_currline: false,
+            current_line: Vec::new(),
+            root_moves: RootMoves::default(),
+            experience: None,
+            algorithm: Algorithm::AlphaBeta,
+            variety: Variety::default(),
+            rng: StdRng::seed_from_u64(0),
+            network: None,
+            accumulators: AccumulatorStack::new(),
+            move_lists: Vec::new(),
+            repetitions: RepetitionHistory::default(),
+            root_index: 0,
+            killer_moves: [[None; 2]; MAX_PLY as usize],
             history_table: [[0; 64]; 64],
+            #[cfg(test)]
+            full_windows: false,
         }
     }
 
     pub fn find_best_move(&mut self, board: &Board) -> Option<Move> {
         self.nodes_searched = 0;
+        self.seldepth = 0;
         self.start_time = Instant::now();
-
-        let mut best_move = None;
-        let mut best_score = -i32::MAX;
-        let mut alpha = -i32::MAX;
-        let beta = i32::MAX;
+        self.pondering = self.signals.ponder.load(Ordering::Relaxed);
+        self.show_refutations = self.signals.show_refutations.load(Ordering::Relaxed);
+        self.show_currline = self.signals.show_currline.load(Ordering::Relaxed);
+        self.current_line.clear();
+        self.stopped = false;
+        self.accumulators.reset();
+        self.caches.tt.new_search();
+        self.caches.tt.reset_stats();
+        if self.repetitions.current() != Some(board.hash) {
+            self.repetitions = RepetitionHistory::new(board);
+        }
+        self.root_index = self.repetitions.len() - 1;
 
         // Get all legal moves and order them
         let mut moves = self.move_generator.generate_moves(board);
         if moves.is_empty() {
+            self.elapsed = self.start_time.elapsed();
             return None;
         }
-        self.order_moves(&mut moves, board, None);
+        // Moves outside searchmoves are left out, unless that would leave none
+        if moves.iter().any(|mv| self.limits.searchmoves.contains(mv)) {
+            moves.retain(|mv| self.limits.searchmoves.contains(mv));
+        }
+        self.order_moves(&mut moves, None, 0);
+        self.root_moves = RootMoves::new(&moves);
+        if let Algorithm::Mcts { exploration } = self.algorithm {
+            self.search_mcts(board, exploration);
+            self.elapsed = self.start_time.elapsed();
+            return self.root_moves.best().map(|root_move| root_move.mv);
+        }
+        self.order_root_moves_by_experience(board);
 
-        // Try each move and evaluate the position
-        for mv in moves {
-            let mut board_copy = board.clone();
-            board_copy.make_move(mv);
+        // Iterative deepening: each iteration searches the previous best move first
+        let mut completed_depth = 0;
+        for depth in 1..=self.limits.depth.clamp(1, MAX_PLY - 1) {
+            self.search_aspirated(board, depth);
+            if self.stopped {
+                break;
+            }
+            completed_depth = depth;
+            if let Some(best) = self.root_moves.best() {
+                let (score, pv) = (best.score, best.pv.clone());
+                let (seldepth, nodes, elapsed) = (self.seldepth, self.nodes_searched, self.start_time.elapsed());
+                self.report(SearchInfo::Iteration { depth, seldepth, score, nodes, elapsed, pv });
+            }
+            // The next iteration would likely be cut short by the hard limit
+            if !self.pondering && self.limits.soft_time.is_some_and(|soft| self.start_time.elapsed() >= soft) {
+                break;
+            }
+            // A mate in the moves asked for has been found
+            let mate_plies = self.limits.mate.map(|moves| 2 * moves as i32 - 1);
+            if let (Some(plies), Some(best)) = (mate_plies, self.root_moves.best()) {
+                if best.score >= MATE_SCORE - plies {
+                    break;
+                }
+            }
+        }
+        self.apply_experience(board, completed_depth);
+        self.choose_variety_move();
+
+        self.elapsed = self.start_time.elapsed();
+        self.root_moves.best().map(|root_move| root_move.mv)
+    }
+
+    // The quiescence search on its own, from a full window, with the node
+    // count started over
+    pub fn quiescence(&mut self, board: &Board) -> i32 {
+        self.nodes_searched = 0;
+        self.accumulators.reset();
+        self.quiescence_search(&mut board.clone(), -INFINITE_SCORE, INFINITE_SCORE, 0)
+    }
+
+    // One iteration, in a narrow window around the previous score once the
+    // scores have settled. Variety needs the scores of moves below the best, and
+    // mates jump too far to aim at.
+    fn search_aspirated(&mut self, board: &Board, depth: u32) {
+        let mut delta = ASPIRATION_DELTA;
+        let (mut alpha, mut beta) = match self.root_moves.best() {
+            Some(best) if depth >= ASPIRATION_DEPTH && self.variety.margin <= 0 && best.score.abs() < MATE_THRESHOLD => {
+                (best.score - delta, best.score + delta)
+            }
+            _ => (-INFINITE_SCORE, INFINITE_SCORE),
+        };
+        loop {
+            let previous = self.root_moves.clone();
+            self.search_root(board, depth, alpha, beta);
+            let score = self.root_moves.best().map_or(-INFINITE_SCORE, |best| best.score);
+            if score <= alpha && alpha > -INFINITE_SCORE {
+                // With every move failing low the last iteration's order is the better guess
+                self.root_moves = previous;
+                alpha = (score - delta).max(-INFINITE_SCORE);
+            } else if score >= beta && beta < INFINITE_SCORE {
+                beta = (score + delta).min(INFINITE_SCORE);
+            } else {
+                return;
+            }
+            if self.stopped {
+                return;
+            }
+            delta *= 2;
+        }
+    }
 
-            // Evaluate the position after the move
-            let score = -self.negamax(&board_copy, self.max_depth - 1, -beta, -alpha);
+    // Searches every root move in the window, stopping at the first to reach beta
+    fn search_root(&mut self, board: &Board, depth: u32, mut alpha: i32, beta: i32) {
+        // With variety on, moves within the margin of the best need exact scores
+        let margin = self.variety.margin.max(0);
+        self.root_moves.begin_iteration();
+        let mut board = board.clone();
 
-            if score > best_score {
-                best_score = score;
-                best_move = Some(mv);
+        for i in 0..self.root_moves.len() {
+            let mv = self.root_moves.moves[i].mv;
+            if self.start_time.elapsed() >= self.currmove_delay {
+                self.report(SearchInfo::CurrMove { depth, mv, number: i + 1 });
             }
 
+            let undo = self.push_move(&mut board, mv);
+            let mut child_pv = Vec::new();
+            let floor = (alpha - margin).max(-INFINITE_SCORE);
+            let score = -self.negamax(&mut board, depth - 1, 1, -beta, -floor, &mut child_pv);
+            self.pop_move(&mut board, &undo);
+
+            // The score of an interrupted search can't be trusted
+            if self.stopped {
+                break;
+            }
+            let root_move = &mut self.root_moves.moves[i];
+            root_move.score = score;
+            if score > floor {
+                root_move.pv.truncate(1);
+                root_move.pv.extend_from_slice(&child_pv);
+            } else if self.show_refutations && !child_pv.is_empty() {
+                // The reply that beat the bound leads the child's line
+                let line = [mv].into_iter().chain(child_pv).collect();
+                self.report(SearchInfo::Refutation { line });
+            }
             alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
 
-            // Check if we've exceeded the time limit
-            if self.start_time.elapsed() > self.max_time {
+            // Check if we've exceeded the time limit or were told to stop
+            if self.should_stop() {
                 break;
             }
         }
 
-        best_move
+        self.root_moves.sort();
+    }
+
+    // Puts a random move within the variety margin of the best first. Mates
+    // are left alone, as is a search that never finished an iteration.
+    fn choose_variety_move(&mut self) {
+        let Some(best) = self.root_moves.best().map(|root_move| root_move.score) else {
+            return;
+        };
+        if self.variety.margin <= 0 || best.abs() >= MATE_THRESHOLD {
+            return;
+        }
+        let temperature = self.variety.temperature.max(1) as f64;
+        let weights: Vec<f64> = self
+            .root_moves
+            .iter()
+            // A score right at the margin is only a bound from the narrowed window
+            .take_while(|root_move| best - root_move.score < self.variety.margin)
+            .map(|root_move| (-(best - root_move.score) as f64 / temperature).exp())
+            .collect();
+        let mut pick = self.rng.gen::<f64>() * weights.iter().sum::<f64>();
+        let index = weights.iter().position(|&weight| {
+            pick -= weight;
+            pick < 0.0
+        });
+        let chosen = self.root_moves.moves.remove(index.unwrap_or(0));
+        self.root_moves.moves.insert(0, chosen);
+    }
+
+    // Monte Carlo tree search over the root moves, which end up ordered by
+    // visits. Playouts stop at the usual limits; a depth limit allows 2^depth.
+    fn search_mcts(&mut self, board: &Board, exploration: f32) {
+        let moves: Vec<Move> = self.root_moves.iter().map(|root_move| root_move.mv).collect();
+        let mut tree = mcts::Tree::new(&moves, exploration);
+        let max_playouts = 1u32 << self.limits.depth.min(31);
+        while tree.playouts() < max_playouts && !self.should_stop() {
+            // Playouts replay moves the accumulators never saw, so each leaf is refreshed
+            let nodes = tree.playout(board, &mut |position| {
+                self.accumulators.invalidate();
+                self.evaluate(position)
+            });
+            self.nodes_searched += nodes;
+        }
+
+        self.root_moves.moves = tree
+            .root_stats()
+            .into_iter()
+            .map(|stats| {
+                let score = match stats.terminal {
+                    Some(value) if value > 0.0 => MATE_SCORE - 1,
+                    Some(value) if value < 0.0 => -MATE_SCORE + 1,
+                    Some(_) => 0,
+                    None => mcts::to_centipawns(stats.value),
+                };
+                RootMove { mv: stats.mv, score, previous_score: score, pv: stats.pv }
+            })
+            .collect();
+    }
+
+    // Makes mv on board and follows it in the accumulators and the repetition
+    // history, handing back what pop_move needs to take it back
+    fn push_move(&mut self, board: &mut Board, mv: Move) -> UndoState {
+        let undo = board.make_move(mv);
+        self.repetitions.push(board.hash, repetition::is_irreversible(undo.castling_rights, &mv, board));
+        self.accumulators.push(undo.dirty);
+        if self.show_currline {
+            self.current_line.push(mv);
+        }
+        undo
     }
 
-    fn negamax(&mut self, board: &Board, depth: u32, alpha: i32, beta: i32) -> i32 {
+    fn pop_move(&mut self, board: &mut Board, undo: &UndoState) {
+        board.unmake_move(undo);
+        self.repetitions.pop();
+        self.accumulators.pop();
+        if self.show_currline {
+            self.current_line.pop();
+        }
+    }
+
+    fn report(&mut self, info: SearchInfo) {
+        if let Some(callback) = self.info_callback.as_mut() {
+            callback(info);
+        }
+    }
+
+    // Fills pv with the best line from this node whenever a move lands inside the window
+    fn negamax(&mut self, board: &mut Board, depth: u32, ply: u32, alpha: i32, beta: i32, pv: &mut Vec<Move>) -> i32 {
+        pv.clear();
+        if self.stopped {
+            return 0;
+        }
         self.nodes_searched += 1;
+        if self.show_currline
+            && self.nodes_searched.is_multiple_of(CURRLINE_INTERVAL)
+            && self.start_time.elapsed() >= self.currmove_delay
+        {
+            self.report(SearchInfo::CurrLine { line: self.current_line.clone() });
+        }
+        self.seldepth = self.seldepth.max(ply);
+        if ply >= MAX_PLY {
+            return self.evaluate(board);
+        }
 
-        // Check transposition table
-        let hash = self.get_position_hash(board);
-        if let Some(score) = self.transposition_table.probe(hash, depth, alpha, beta) {
-            return score;
+        if self.repetitions.is_search_draw(self.root_index) {
+            return 0;
+        }
+        // A hundred halfmoves without a capture or pawn move draw, unless the last of them mated
+        if board.halfmove_clock >= 100 {
+            if self.move_generator.generate_moves(board).is_empty() {
+                return self.game_over_score(board, ply);
+            }
+            return 0;
         }
 
-        // Check if we've reached the maximum depth or if the game is over
-        if depth == 0 || self.is_game_over(board) {
-            return self.quiescence_search(board, alpha, beta);
+        // Mate distance pruning: no line from here mates sooner than this node
+        // allows, so a window beyond that can't be reached
+        let alpha = alpha.max(-MATE_SCORE + ply as i32);
+        let beta = beta.min(MATE_SCORE - ply as i32);
+        if alpha >= beta {
+            return alpha;
         }
 
-        // Get all legal moves and order them
-        let mut moves = self.move_generator.generate_moves(board);
-        if moves.is_empty() {
-            return self.evaluator.evaluate(board);
+        // A check is looked at one ply further, so the horizon can't hide what follows it
+        let depth = if self.move_generator.is_king_in_check(board, board.side_to_move) { depth + 1 } else { depth };
+
+        // Check transposition table
+        let hash = board.hash;
+        let tt_hit = self.caches.tt.probe(hash, ply);
+        if let Some(score) = tt_hit.and_then(|hit| hit.cutoff(depth, alpha, beta)) {
+            self.caches.tt.record_cutoff();
+            return score;
         }
 
-        self.order_moves(&mut moves, board, self.transposition_table.get_best_move(hash));
+        // At the horizon the quiescence search takes over, unless the game is over
+        let mut moves = std::mem::take(self.move_list(ply));
+        if depth == 0 {
+            self.move_generator.generate_moves_into(board, &mut moves);
+            let game_over = moves.is_empty();
+            *self.move_list(ply) = moves;
+            if game_over {
+                return self.game_over_score(board, ply);
+            }
+            return self.quiescence_search(board, alpha, beta, ply);
+        }
 
+        let original_alpha = alpha;
         let mut alpha = alpha;
-        let mut best_score = -i32::MAX;
+        let mut best_score = -INFINITE_SCORE;
         let mut best_move = None;
+        let mut child_pv = Vec::new();
 
-        for mv in moves {
-            let mut board_copy = board.clone();
-            board_copy.make_move(mv);
-
-            // Recursively evaluate the position
-            let score = -self.negamax(&board_copy, depth - 1, -beta, -alpha);
+        // The hash move is tried first even when the stored score couldn't cut off
+        let hash_move = tt_hit.and_then(|hit| hit.best_move).and_then(|packed| self.move_generator.decode_move(board, packed));
+        let killers = self.killer_moves[ply as usize];
+        let mut searched = 0;
 
-            if score > best_score {
-                best_score = score;
-                best_move = Some(mv);
+        // Each stage's moves are only generated if no earlier move cut off
+        'stages: for stage in MoveStage::ALL {
+            moves.clear();
+            match stage {
+                MoveStage::HashMove => {
+                    if let Some(mv) = hash_move {
+                        moves.push(mv);
+                    }
+                }
+                MoveStage::Captures => {
+                    self.move_generator.generate_captures_into(board, &mut moves);
+                    self.order_moves(&mut moves, None, ply);
+                }
+                MoveStage::Killers => {
+                    for killer in killers.into_iter().flatten() {
+                        if !moves.contains(&killer) && self.move_generator.is_legal(board, &killer) {
+                            moves.push(killer);
+                        }
+                    }
+                }
+                MoveStage::Quiets => {
+                    self.move_generator.generate_quiets_into(board, &mut moves);
+                    self.order_moves(&mut moves, None, ply);
+                }
             }
 
-            alpha = alpha.max(score);
+            for &mv in moves.iter() {
+                // Tried in an earlier stage
+                let repeated = match stage {
+                    MoveStage::HashMove => false,
+                    MoveStage::Quiets => hash_move == Some(mv) || killers.contains(&Some(mv)),
+                    _ => hash_move == Some(mv),
+                };
+                if repeated {
+                    continue;
+                }
+                searched += 1;
+                let undo = self.push_move(board, mv);
 
-            // Alpha-beta pruning
-            if alpha >= beta {
-                // Update killer moves
-                if mv.captured_piece.is_none() && mv.promotion.is_none() {
-                    let depth_idx = depth as usize;
-                    if depth_idx < 64 {
+                // Principal variation search: the first move gets the full window; the
+                // rest only have to be shown no better than alpha, and are searched again
+                // properly when one turns out better after all
+                let full_window = searched == 1;
+                #[cfg(test)]
+                let full_window = full_window || self.full_windows;
+                let mut score = if full_window {
+                    -self.negamax(board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv)
+                } else {
+                    -self.negamax(board, depth - 1, ply + 1, -alpha - 1, -alpha, &mut child_pv)
+                };
+                if searched > 1 && score > alpha && score < beta {
+                    score = -self.negamax(board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+                }
+                self.pop_move(board, &undo);
+
+                if score > best_score {
+                    best_score = score;
+                    best_move = Some(mv);
+                }
+                if score > alpha {
+                    pv.clear();
+                    pv.push(mv);
+                    pv.extend_from_slice(&child_pv);
+                }
+
+                alpha = alpha.max(score);
+
+                // Alpha-beta pruning
+                if alpha >= beta {
+                    // Update killer moves
+                    if mv.captured_piece.is_none() && mv.promotion.is_none() {
+                        let killers = &mut self.killer_moves[ply as usize];
                         // Shift existing killer moves
-                        self.killer_moves[depth_idx][1] = self.killer_moves[depth_idx][0];
-                        self.killer_moves[depth_idx][0] = Some(mv);
+                        killers[1] = killers[0];
+                        killers[0] = Some(mv);
                     }
+
+                    // Update history heuristic
+                    let depth_squared = (depth * depth) as i32;
+                    self.history_table[mv.from as usize][mv.to as usize] += depth_squared;
+                    break 'stages;
                 }
 
-                // Update history heuristic
-                let depth_squared = (depth * depth) as i32;
-                self.history_table[mv.from as usize][mv.to as usize] += depth_squared;
-                break;
+                // Check if we've exceeded the time limit or were told to stop
+                if self.should_stop() {
+                    break 'stages;
+                }
             }
+        }
+        *self.move_list(ply) = moves;
+        if searched == 0 {
+            return self.game_over_score(board, ply);
+        }
 
-            // Check if we've exceeded the time limit
-            if self.start_time.elapsed() > self.max_time {
-                break;
-            }
+        // Don't let a search cut short by the clock pollute the table
+        if self.stopped {
+            return best_score;
         }
 
         // Store in transposition table
-        let node_type = if best_score <= alpha {
+        let node_type = if best_score <= original_alpha {
             NodeType::UpperBound
         } else if best_score >= beta {
             NodeType::LowerBound
@@ -148,22 +697,48 @@ impl Search {
             NodeType::Exact
         };
 
-        let entry = TranspositionEntry {
-            hash,
-            depth,
-            score: best_score,
-            node_type,
-            best_move: best_move.map(|mv| self.move_to_u64(mv)),
-        };
-        self.transposition_table.store(hash, entry);
+        self.caches.tt.store(hash, depth, ply, best_score, node_type, best_move);
 
         best_score
     }
 
-    fn quiescence_search(&mut self, board: &Board, mut alpha: i32, beta: i32) -> i32 {
+    // Moves earlier searches rated go first, best rated first, so the first
+    // iterations start from what is already known
+    fn order_root_moves_by_experience(&mut self, board: &Board) {
+        let Some(experience) = &self.experience else {
+            return;
+        };
+        let known = experience.moves(board.hash);
+        let rating = |mv: Move| known.iter().find(|entry| entry.matches(mv)).map(|entry| (entry.depth, entry.score));
+        // Stable, so unrated moves keep their ordering
+        self.root_moves.moves.sort_by_key(|root_move| std::cmp::Reverse(rating(root_move.mv)));
+    }
+
+    // After a search: an earlier search that went deeper overrules this one, and
+    // this one's result is remembered for later games
+    fn apply_experience(&mut self, board: &Board, completed_depth: u32) {
+        let Some(experience) = &mut self.experience else {
+            return;
+        };
+        let key = board.hash;
+        if let Some(entry) = experience.best(key).filter(|entry| entry.depth > completed_depth) {
+            if let Some(index) = self.root_moves.moves.iter().position(|root_move| entry.matches(root_move.mv)) {
+                let root_move = self.root_moves.moves.remove(index);
+                self.root_moves.moves.insert(0, RootMove { score: entry.score, ..root_move });
+            }
+        } else if let Some(best) = self.root_moves.best() {
+            experience.record(key, best.mv, completed_depth, best.score);
+        }
+    }
+
+    fn quiescence_search(&mut self, board: &mut Board, mut alpha: i32, beta: i32, ply: u32) -> i32 {
         self.nodes_searched += 1;
+        self.seldepth = self.seldepth.max(ply);
 
-        let stand_pat = self.evaluator.evaluate(board);
+        let stand_pat = self.evaluate(board);
+        if ply >= MAX_PLY {
+            return stand_pat;
+        }
         if stand_pat >= beta {
             return beta;
         }
@@ -172,48 +747,103 @@ impl Search {
         }
 
         // Only consider captures and promotions
-        let mut moves = self.move_generator.generate_moves(board)
-            .into_iter()
-            .filter(|mv| mv.captured_piece.is_some() || mv.promotion.is_some())
-            .collect::<Vec<_>>();
-
+        let mut moves = std::mem::take(self.move_list(ply));
+        self.move_generator.generate_captures_into(board, &mut moves);
         if moves.is_empty() {
+            *self.move_list(ply) = moves;
             return stand_pat;
         }
 
-        self.order_moves(&mut moves, board, None);
+        self.order_moves(&mut moves, None, ply);
 
-        for mv in moves {
-            let mut board_copy = board.clone();
-            board_copy.make_move(mv);
+        for &mv in moves.iter() {
+            let undo = board.make_move(mv);
+            self.accumulators.push(undo.dirty);
 
-            let score = -self.quiescence_search(&board_copy, -beta, -alpha);
+            let score = -self.quiescence_search(board, -beta, -alpha, ply + 1);
+            self.accumulators.pop();
+            board.unmake_move(&undo);
 
             if score >= beta {
-                return beta;
+                alpha = beta;
+                break;
             }
             if score > alpha {
                 alpha = score;
             }
         }
+        *self.move_list(ply) = moves;
 
         alpha
     }
 
-    fn order_moves(&mut self, moves: &mut Vec<Move>, board: &Board, hash_move: Option<u64>) {
-        // Add some randomness to move ordering in the opening
-        let is_opening = board.white_pieces[0].count_ones() + board.black_pieces[0].count_ones() >= 14;
-        if is_opening {
-            moves.shuffle(&mut thread_rng());
+    // The move buffer of a ply, made on first use
+    fn move_list(&mut self, ply: u32) -> &mut MoveList {
+        let ply = ply as usize;
+        if self.move_lists.len() <= ply {
+            self.move_lists.resize_with(ply + 1, MoveList::new);
+        }
+        &mut self.move_lists[ply]
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.signals.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        } else if self.pondering {
+            if self.signals.ponder.load(Ordering::Relaxed) {
+                return false;
+            }
+            // Ponderhit: the clock for this move starts now
+            self.pondering = false;
+            self.start_time = Instant::now();
+        } else if self.limits.time.is_some_and(|time| self.start_time.elapsed() > time)
+            || self.limits.nodes.is_some_and(|nodes| self.nodes_searched >= nodes)
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    // The score of a position without a legal move, from the side to move's point of view
+    fn game_over_score(&self, board: &Board, ply: u32) -> i32 {
+        match board.variant.result(&self.move_generator, board, false) {
+            Some(GameState::Checkmate(winner) | GameState::VariantWin(winner)) if winner == board.side_to_move => {
+                MATE_SCORE - ply as i32
+            }
+            Some(GameState::Checkmate(_) | GameState::VariantWin(_)) => -MATE_SCORE + ply as i32,
+            _ => 0,
+        }
+    }
+
+    // Static evaluation from the side to move's point of view, via the eval cache
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        if let Some(network) = &self.network {
+            return network.evaluate(self.accumulators.current(&network.transformer, board), board.side_to_move);
+        }
+        let key = board.hash;
+        let score = match self.caches.eval_cache.get(key) {
+            Some(score) => score,
+            None => {
+                let score = self.evaluator.evaluate_cached(board, &mut self.caches.pawn_hash);
+                self.caches.eval_cache.insert(key, score);
+                score
+            }
+        };
+        if board.side_to_move == Color::White {
+            score
+        } else {
+            -score
         }
+    }
 
+    fn order_moves(&self, moves: &mut [Move], hash_move: Option<u16>, ply: u32) {
         moves.sort_by(|a, b| {
             // First try the move from the transposition table
             if let Some(hash) = hash_move {
-                if self.move_to_u64(*a) == hash {
+                if a.to_u16() == hash {
                     return std::cmp::Ordering::Less;
                 }
-                if self.move_to_u64(*b) == hash {
+                if b.to_u16() == hash {
                     return std::cmp::Ordering::Greater;
                 }
             }
@@ -233,17 +863,12 @@ impl Search {
             }
 
             // Then try killer moves
-            let depth = self.max_depth as usize;
-            if depth < 64 {
-                for killer in &self.killer_moves[depth] {
-                    if let Some(killer_move) = killer {
-                        if killer_move.from == a.from && killer_move.to == a.to {
-                            return std::cmp::Ordering::Less;
-                        }
-                        if killer_move.from == b.from && killer_move.to == b.to {
-                            return std::cmp::Ordering::Greater;
-                        }
-                    }
+            for killer_move in self.killer_moves[ply as usize].iter().flatten() {
+                if killer_move.from == a.from && killer_move.to == a.to {
+                    return std::cmp::Ordering::Less;
+                }
+                if killer_move.from == b.from && killer_move.to == b.to {
+                    return std::cmp::Ordering::Greater;
                 }
             }
 
@@ -265,47 +890,104 @@ impl Search {
         }
     }
 
-    fn get_position_hash(&self, board: &Board) -> u64 {
-        // TODO: Implement Zobrist hashing for more accurate position hashing
-        // For now, use a simple hash based on piece positions
-        let mut hash: u64 = 0;
-        for square in 0..64 {
-            if let Some((piece, color)) = board.get_piece_at(square as u8) {
-                let piece_value = self.get_piece_value(piece) as i64;
-                let color_value = if color == Color::White { 1 } else { -1 };
-                hash = hash.wrapping_add((piece_value * color_value) as u64);
-            }
+    // Forget everything learned from previous searches (hash, killers, history)
+    pub fn clear(&mut self) {
+        self.caches.clear();
+        self.killer_moves = [[None; 2]; MAX_PLY as usize];
+        self.history_table = [[0; 64]; 64];
+    }
+
+    // Reallocates the hash tables for a new budget in MB, the main one on huge
+    // pages if asked; everything stored is lost. Says whether huge pages were had.
+    pub fn set_hash(&mut self, hash_mb: usize, huge_pages: bool) -> bool {
+        self.caches.resize(hash_mb, huge_pages);
+        self.caches.tt.huge_pages()
+    }
+
+    // Receives progress reports while a search runs
+    pub fn set_info_callback(&mut self, callback: InfoCallback) {
+        self.info_callback = Some(callback);
+    }
+
+    // Root moves of the last search, best first
+    pub fn root_moves(&self) -> &RootMoves {
+        &self.root_moves
+    }
+
+    // The reply we expect to the best move, to ponder on: the second move of the PV,
+    // or failing that the hash move of the position after the best move
+    pub fn ponder_move(&mut self, board: &Board) -> Option<Move> {
+        let pv = &self.root_moves.best()?.pv;
+        if let Some(&mv) = pv.get(1) {
+            return Some(mv);
         }
-        hash
+
+        let mut board_copy = board.clone();
+        board_copy.make_move(pv[0]);
+        let hash_move = self.caches.tt.probe(board_copy.hash, 1)?.best_move?;
+        self.move_generator
+            .generate_moves(&board_copy)
+            .into_iter()
+            .find(|mv| mv.to_u16() == hash_move)
     }
 
-    fn move_to_u64(&self, mv: Move) -> u64 {
-        // Pack move into a u64: from (6 bits) | to (6 bits) | piece (3 bits) | captured_piece (3 bits) | promotion (3 bits)
-        let from = mv.from as u64;
-        let to = mv.to as u64;
-        let piece = mv.piece as u64;
-        let captured = mv.captured_piece.map(|p| p as u64).unwrap_or(0);
-        let promo = mv.promotion.map(|p| p as u64).unwrap_or(0);
-        
-        (from) | (to << 6) | (piece << 12) | (captured << 15) | (promo << 18)
+    // Shared handle for stopping the search or ending ponder mode from another thread
+    pub fn signals(&self) -> Arc<SearchSignals> {
+        Arc::clone(&self.signals)
+    }
+
+    // The game so far, ending at the position the next search starts from,
+    // so lines that repeat earlier positions are scored as draws
+    pub fn set_history(&mut self, history: RepetitionHistory) {
+        self.repetitions = history;
     }
 
-    fn is_game_over(&self, board: &Board) -> bool {
-        let moves = self.move_generator.generate_moves(board);
-        moves.is_empty()
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.limits = limits;
     }
 
-    pub fn set_max_depth(&mut self, depth: u32) {
-        self.max_depth = depth;
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
     }
 
-    pub fn set_max_time(&mut self, milliseconds: u64) {
-        self.max_time = Duration::from_millis(milliseconds);
+    // Also reseeds, so the choices that follow replay for the same settings
+    pub fn set_variety(&mut self, variety: Variety) {
+        self.variety = variety;
+        self.rng = StdRng::seed_from_u64(variety.seed);
+    }
+
+    // Evaluation weights to search with; scores cached under the old ones are dropped
+    pub fn set_evaluator(&mut self, evaluator: Evaluator) {
+        self.evaluator = evaluator;
+        self.caches.clear();
+    }
+
+    // A network to evaluate with; None goes back to the hand-crafted evaluation.
+    // Scores stored under the other evaluation are dropped.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+        self.caches.clear();
+    }
+
+    pub fn set_experience(&mut self, experience: Option<Experience>) {
+        self.experience = experience;
+    }
+
+    pub fn experience_mut(&mut self) -> Option<&mut Experience> {
+        self.experience.as_mut()
     }
 
     pub fn get_nodes_searched(&self) -> u64 {
         self.nodes_searched
     }
+
+    pub fn stats(&self) -> SearchStats {
+        SearchStats {
+            nodes: self.nodes_searched,
+            elapsed: self.elapsed,
+            tt: self.caches.tt.stats(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -313,16 +995,315 @@ mod tests {
     use super::*;
     use std::time::Duration;
 
+    #[test]
+    fn test_repetition_of_game_positions_is_a_draw() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/R5NK w - - 0 1").unwrap();
+        let mut moves = Vec::new();
+        for move_str in ["g1f3", "e8d8", "f3g1", "d8e8", "g1f3", "e8d8", "f3g1"] {
+            let mv = generator.parse_uci_move(&board, move_str).unwrap();
+            moves.push((board.clone(), mv));
+            board.make_move(mv);
+        }
+        let mut search = Search::new();
+        search.set_limits(SearchLimits { depth: 3, ..SearchLimits::default() });
+
+        // Lost on the board, Black takes the third repetition
+        search.set_history(RepetitionHistory::from_moves(&moves, &board));
+        let best = search.find_best_move(&board).unwrap();
+        assert_eq!((best.from, best.to), (59, 60));
+        assert_eq!(search.root_moves().best().unwrap().score, 0);
+
+        // Without the game, the same position is just lost
+        search.set_history(RepetitionHistory::default());
+        search.find_best_move(&board);
+        assert!(search.root_moves().best().unwrap().score < -300);
+    }
+
+    #[test]
+    fn test_fifty_move_rule_in_the_tree() {
+        let mut search = Search::new();
+        search.set_limits(SearchLimits { depth: 3, ..SearchLimits::default() });
+        // A queen up, but every move runs out the clock
+        let board = Board::from_fen("7k/8/8/8/8/8/8/3QK3 w - - 99 80").unwrap();
+        search.find_best_move(&board);
+        assert_eq!(search.root_moves().best().unwrap().score, 0);
+        // Mate on the hundredth halfmove still counts
+        let board = Board::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 99 80").unwrap();
+        assert_eq!(search.find_best_move(&board).unwrap().to_string(), "a1a8");
+        assert_eq!(search.root_moves().best().unwrap().score, MATE_SCORE - 1);
+    }
+
+    #[test]
+    fn test_root_moves_best_first() {
+        let mut search = Search::new();
+        // Black's queen hangs to the knight
+        let board = Board::from_fen("4k3/8/8/3q4/8/4N3/8/4K3 w - - 0 1").unwrap();
+        search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+
+        let best = search.find_best_move(&board).unwrap();
+        let root_moves = search.root_moves();
+        assert_eq!(root_moves.len(), 11);
+        assert_eq!(root_moves.best().unwrap().mv, best);
+        assert_eq!(best.captured_piece, Some(Piece::Queen));
+        assert!(root_moves.iter().zip(root_moves.iter().skip(1)).all(|(a, b)| a.score >= b.score));
+    }
+
+    #[test]
+    fn test_variety() {
+        let board = Board::new();
+        let choices = |seed| {
+            let mut search = Search::new();
+            search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+            search.set_variety(Variety { margin: 100, temperature: 1000, seed });
+            (0..8).map(|_| search.find_best_move(&board).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(choices(7), choices(7));
+        let mut distinct = choices(7);
+        distinct.dedup();
+        assert!(distinct.len() > 1);
+
+        // A hanging queen is still taken: nothing else comes within the margin
+        let mut search = Search::new();
+        search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+        search.set_variety(Variety { margin: 100, temperature: 1000, seed: 7 });
+        let board = Board::from_fen("4k3/8/8/3q4/8/4N3/8/4K3 w - - 0 1").unwrap();
+        for _ in 0..8 {
+            assert_eq!(search.find_best_move(&board).unwrap().captured_piece, Some(Piece::Queen));
+        }
+    }
+
+    #[test]
+    fn test_pv_is_a_legal_line() {
+        let mut search = Search::new();
+        let mut board = Board::new();
+        search.set_limits(SearchLimits { depth: 4, ..SearchLimits::default() });
+
+        let best = search.find_best_move(&board).unwrap();
+        let pv = search.root_moves().best().unwrap().pv.clone();
+        assert_eq!(pv[0], best);
+        assert!(pv.len() >= 2);
+        for mv in &pv {
+            assert!(search.move_generator.generate_moves(&board).contains(mv));
+            board.make_move(*mv);
+        }
+        assert_eq!(search.ponder_move(&Board::new()), Some(pv[1]));
+    }
+
+    #[test]
+    fn test_aspiration_window_widens_to_the_score() {
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut reference = Search::new();
+        reference.set_limits(SearchLimits { depth: 4, ..SearchLimits::default() });
+        reference.find_best_move(&board);
+        let expected = reference.root_moves().best().unwrap().clone();
+
+        // A last score far off either way: the window misses and is widened until it holds
+        for error in [-400, 400] {
+            let mut search = Search::new();
+            search.set_limits(SearchLimits { depth: 3, ..SearchLimits::default() });
+            search.find_best_move(&board);
+            search.root_moves.moves[0].score = expected.score + error;
+            search.search_aspirated(&board, 4);
+            let best = search.root_moves().best().unwrap();
+            assert_eq!((best.mv, best.score), (expected.mv, expected.score));
+        }
+    }
+
+    #[test]
+    fn test_null_windows_find_what_full_windows_do() {
+        let positions = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+            "r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - 0 1",
+            "2r3k1/pp3ppp/2n5/3Np3/4P3/1P6/P4PPP/3R2K1 b - - 0 1",
+        ];
+        for fen in positions {
+            let board = Board::from_fen(fen).unwrap();
+            let mut results = Vec::new();
+            for full_windows in [false, true] {
+                let mut search = Search::new();
+                search.full_windows = full_windows;
+                search.set_limits(SearchLimits { depth: 4, ..SearchLimits::default() });
+                let mv = search.find_best_move(&board).unwrap();
+                results.push((mv, search.root_moves().best().unwrap().score));
+            }
+            // Re-searching whatever beats alpha makes the null windows exact where it matters
+            assert_eq!(results[0], results[1], "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_checks_extend_and_mates_bound_the_window() {
+        // Qg8+ Rxg8 Nf7# is three plies, but the check lets two plies of search see it
+        let board = Board::from_fen("4r2k/6pp/7N/3Q4/8/8/8/6K1 w - - 0 1").unwrap();
+        let mut search = Search::new();
+        search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+        assert_eq!(search.find_best_move(&board).unwrap().to_string(), "d5g8");
+        assert_eq!(search.root_moves().best().unwrap().score, MATE_SCORE - 3);
+
+        // A mate already found two plies from the root can't be beaten three plies down
+        let mut board = Board::new();
+        let mut pv = Vec::new();
+        search.nodes_searched = 0;
+        assert_eq!(search.negamax(&mut board, 4, 3, MATE_SCORE - 2, INFINITE_SCORE, &mut pv), MATE_SCORE - 2);
+        assert_eq!(search.nodes_searched, 1);
+    }
+
+    #[test]
+    fn test_searchmoves_and_mate_limits() {
+        let generator = MoveGenerator::new();
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut search = Search::new();
+
+        // Only the listed moves are searched, even with a mate elsewhere
+        let searchmoves = vec![generator.parse_uci_move(&board, "g1f1").unwrap()];
+        search.set_limits(SearchLimits { depth: 3, searchmoves, ..SearchLimits::default() });
+        assert_eq!(search.find_best_move(&board).unwrap().to_string(), "g1f1");
+        assert_eq!(search.root_moves().len(), 1);
+
+        // Deepening stops at the first iteration that finds the mate
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        search.set_info_callback(Box::new(move |info| sink.lock().unwrap().push(info)));
+        search.set_limits(SearchLimits { mate: Some(1), ..SearchLimits::default() });
+        assert_eq!(search.find_best_move(&board).unwrap().to_string(), "a1a8");
+        let reports = reports.lock().unwrap();
+        assert!(matches!(reports.last(), Some(SearchInfo::Iteration { depth: 1, .. })));
+    }
+
+    #[test]
+    fn test_soft_time_stops_deepening() {
+        let mut search = Search::new();
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        search.set_info_callback(Box::new(move |info| sink.lock().unwrap().push(info)));
+        search.set_limits(SearchLimits { depth: 6, soft_time: Some(Duration::ZERO), ..SearchLimits::default() });
+
+        // The first iteration always finishes, and is the last one started
+        assert!(search.find_best_move(&Board::new()).is_some());
+        let reports = reports.lock().unwrap();
+        let depths: Vec<u32> = reports
+            .iter()
+            .filter_map(|info| match info {
+                SearchInfo::Iteration { depth, .. } => Some(*depth),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, [1]);
+    }
+
+    #[test]
+    fn test_currmove_reports() {
+        let mut search = Search::new();
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        search.set_info_callback(Box::new(move |info| sink.lock().unwrap().push(info)));
+        search.currmove_delay = Duration::ZERO;
+        search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+
+        search.find_best_move(&Board::new());
+        let mut reports = reports.lock().unwrap();
+        // Every root move, once per iteration, and each iteration's result
+        assert!(matches!(&reports[20], SearchInfo::Iteration { depth: 1, pv, .. } if pv.len() == 1));
+        assert!(matches!(&reports[41], SearchInfo::Iteration { depth: 2, pv, .. } if pv.len() == 2));
+        // Captures looked at past the horizon count towards the selective depth
+        assert!(matches!(&reports[41], SearchInfo::Iteration { seldepth, .. } if *seldepth > 2));
+        reports.retain(|info| matches!(info, SearchInfo::CurrMove { .. }));
+        assert_eq!(reports.len(), 40);
+        assert!(matches!(reports[0], SearchInfo::CurrMove { depth: 1, number: 1, .. }));
+        assert!(matches!(reports[39], SearchInfo::CurrMove { depth: 2, number: 20, .. }));
+    }
+
+    #[test]
+    fn test_refutation_and_currline_reports() {
+        let mut search = Search::new();
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        search.set_info_callback(Box::new(move |info| sink.lock().unwrap().push(info)));
+        search.currmove_delay = Duration::from_secs(3600);
+        search.signals.show_refutations.store(true, Ordering::Relaxed);
+        search.signals.show_currline.store(true, Ordering::Relaxed);
+        search.set_limits(SearchLimits { depth: 3, ..SearchLimits::default() });
+
+        // Everything but taking the queen loses it or leaves it hanging
+        let board = Board::from_fen("4k3/8/8/3q4/8/4N3/8/4K3 w - - 0 1").unwrap();
+        let best = search.find_best_move(&board).unwrap();
+        let reports = reports.lock().unwrap();
+        let refutations: Vec<&Vec<Move>> = reports
+            .iter()
+            .filter_map(|info| match info {
+                SearchInfo::Refutation { line } => Some(line),
+                _ => None,
+            })
+            .collect();
+        assert!(!refutations.is_empty());
+        for line in &refutations {
+            assert!(line.len() >= 2 && line[0] != best);
+            let mut position = board.clone();
+            for mv in line.iter() {
+                assert!(search.move_generator.generate_moves(&position).contains(mv));
+                position.make_move(*mv);
+            }
+        }
+        // Lines only come once the delay has passed
+        assert!(!reports.iter().any(|info| matches!(info, SearchInfo::CurrLine { .. })));
+        assert!(search.current_line.is_empty());
+    }
+
+    #[test]
+    fn test_hash_budget_sizes_every_cache() {
+        let mut search = Search::new();
+        let sizes = |search: &Search| [search.caches.tt.len(), search.caches.pawn_hash.len(), search.caches.eval_cache.len()];
+        let before = sizes(&search);
+
+        // The Hash option's budget shrinks the pawn hash and eval cache along with the main table
+        search.set_hash(2, false);
+        for (after, before) in sizes(&search).into_iter().zip(before) {
+            assert!(after > 0 && after <= before / 8, "{} of {}", after, before);
+        }
+    }
+
     #[test]
     fn test_time_control() {
         let mut search = Search::new();
         
         // Test setting time control
-        search.set_max_time(1000); // 1 second
-        assert_eq!(search.max_time, Duration::from_millis(1000));
+        search.set_limits(SearchLimits { time: Some(Duration::from_secs(1)), ..SearchLimits::default() });
+        assert_eq!(search.limits.time, Some(Duration::from_millis(1000)));
         
-        search.set_max_time(5000); // 5 seconds
-        assert_eq!(search.max_time, Duration::from_millis(5000));
+        search.set_limits(SearchLimits { time: Some(Duration::from_secs(5)), ..SearchLimits::default() });
+        assert_eq!(search.limits.time, Some(Duration::from_millis(5000)));
+    }
+
+    #[test]
+    fn test_max_ply_takes_the_static_evaluation() {
+        let mut search = Search::new();
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let static_eval = search.evaluate(&board);
+        assert_eq!(search.quiescence_search(&mut board, -INFINITE_SCORE, INFINITE_SCORE, MAX_PLY), static_eval);
+        let mut pv = Vec::new();
+        assert_eq!(search.negamax(&mut board, 4, MAX_PLY, -INFINITE_SCORE, INFINITE_SCORE, &mut pv), static_eval);
+        assert!(pv.is_empty());
+        // Just short of the cap the ply-indexed tables still have room, and
+        // every move made on the way down is taken back
+        let (fen, hash) = (board.to_fen(), board.hash);
+        search.negamax(&mut board, 3, MAX_PLY - 2, -INFINITE_SCORE, INFINITE_SCORE, &mut pv);
+        assert_eq!((board.to_fen(), board.hash), (fen, hash));
+
+        search.set_limits(SearchLimits { depth: 1000, nodes: Some(2000), ..SearchLimits::default() });
+        assert!(search.find_best_move(&board).is_some());
+    }
+
+    #[test]
+    fn test_search_respects_node_limit() {
+        let mut search = Search::new();
+        search.set_limits(SearchLimits { nodes: Some(500), ..SearchLimits::default() });
+
+        assert!(search.find_best_move(&Board::new()).is_some());
+        // The limit is checked between moves, so it may overshoot slightly
+        assert!((500..520).contains(&search.get_nodes_searched()));
     }
 
     #[test]
@@ -331,7 +1312,7 @@ mod tests {
         let board = Board::new();
         
         // Set a very short time limit (10ms)
-        search.set_max_time(10);
+        search.set_limits(SearchLimits { time: Some(Duration::from_millis(10)), ..SearchLimits::default() });
         
         // Start the search
         let start_time = Instant::now();
@@ -350,7 +1331,7 @@ mod tests {
         let board = Board::new();
         
         // Set a reasonable time limit (100ms)
-        search.set_max_time(100);
+        search.set_limits(SearchLimits { time: Some(Duration::from_millis(100)), ..SearchLimits::default() });
         
         // Start the search
         let start_time = Instant::now();
@@ -363,4 +1344,68 @@ mod tests {
             "Search only used {}ms of the allocated 100ms",
             elapsed.as_millis());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_experience_overrules_shallow_searches() {
+        let mut search = Search::new();
+        let board = Board::new();
+        let a3 = MoveGenerator::new().generate_moves(&board).into_iter().find(|mv| mv.from == 8 && mv.to == 16).unwrap();
+        let mut experience = Experience::default();
+        experience.record(board.hash, a3, 20, 15);
+        search.set_experience(Some(experience));
+
+        search.set_limits(SearchLimits { depth: 2, ..SearchLimits::default() });
+        assert_eq!(search.find_best_move(&board), Some(a3));
+        assert_eq!(search.root_moves().best().unwrap().score, 15);
+
+        // A search as deep as the minimum is remembered
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        search.set_limits(SearchLimits { depth: crate::experience::MIN_DEPTH, ..SearchLimits::default() });
+        let best = search.find_best_move(&board).unwrap();
+        let entry = search.experience_mut().unwrap().best(board.hash).unwrap();
+        assert!(entry.matches(best));
+    }
+
+    #[test]
+    fn test_mcts_algorithm() {
+        let mut search = Search::new();
+        search.set_algorithm(Algorithm::Mcts { exploration: 1.5 });
+        search.set_limits(SearchLimits { depth: 9, ..SearchLimits::default() });
+        let board = Board::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let best = search.find_best_move(&board).unwrap();
+        assert_eq!((best.from, best.to), (11, 35));
+        assert!(search.root_moves().best().unwrap().score > 300);
+        assert!(search.stats().nodes > 512);
+
+        // A node limit stops the playouts too
+        search.set_limits(SearchLimits { nodes: Some(100), ..SearchLimits::default() });
+        assert!(search.find_best_move(&Board::new()).is_some());
+        assert!(search.stats().nodes < 200);
+    }
+
+    #[test]
+    fn test_network_evaluation() {
+        use crate::nnue::{FeatureTransformer, INPUTS};
+        // Two hidden values counting the perspective's own queens and the other side's
+        let mut weights = vec![0; INPUTS * 2];
+        for square in 0..64 {
+            weights[(4 * 64 + square) * 2] = 100;
+            weights[(384 + 4 * 64 + square) * 2 + 1] = 100;
+        }
+        let transformer = FeatureTransformer::new(2, weights, vec![0, 0]).unwrap();
+        let network = Network::new(transformer, vec![64, -64, 0, 0], 0).unwrap();
+
+        let mut search = Search::new();
+        search.set_network(Some(Arc::new(network)));
+        search.set_limits(SearchLimits { depth: 3, ..SearchLimits::default() });
+        let board = Board::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let best = search.find_best_move(&board).unwrap();
+        assert_eq!((best.from, best.to), (11, 35));
+        assert_eq!(search.root_moves().best().unwrap().score, 0);
+
+        // The hand-crafted evaluation sees the extra rook
+        search.set_network(None);
+        search.find_best_move(&board);
+        assert!(search.root_moves().best().unwrap().score > 300);
+    }
+}
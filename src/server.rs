@@ -0,0 +1,173 @@
+use crate::board::{square_name, Color};
+use crate::engine::Engine;
+use crate::evaluation::{Evaluator, KingDanger};
+use crate::search::{SearchLimits, MATE_SCORE, MATE_THRESHOLD, MAX_DEPTH};
+use crate::uci::format_move;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tiny_http::{Header, Method, Response, Server};
+
+// Depth searched when a request sets no limit at all
+const DEFAULT_DEPTH: u32 = 8;
+// Longest search one request may ask for, so a client can't tie the server up
+const MAX_MOVETIME: Duration = Duration::from_secs(60);
+
+// {"cp": 35} or {"mate": -3}, as in UCI score reports
+fn score_json(score: i32) -> Value {
+    if score.abs() >= MATE_THRESHOLD {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        json!({ "mate": if score > 0 { moves } else { -moves } })
+    } else {
+        json!({ "cp": score })
+    }
+}
+
+// What goes into one side's king safety: the squares around the king, the
+// enemy pieces attacking them, the pawn cover and the resulting score
+fn king_danger_json(danger: &KingDanger) -> Value {
+    let counts = &danger.counts;
+    let [pawn, knight, bishop, rook, queen, king] = counts.attackers;
+    json!({
+        "ring": (0..64).filter(|square| counts.ring >> square & 1 != 0).map(square_name).collect::<Vec<_>>(),
+        "attackers": { "pawn": pawn, "knight": knight, "bishop": bishop, "rook": rook, "queen": queen, "king": king },
+        "attack_units": counts.attack_units(),
+        "shield_pawns": counts.shield_pawns,
+        "open_file": counts.open_file,
+        "semi_open_file": counts.semi_open_file,
+        "score": danger.score,
+    })
+}
+
+// Analyses the position in a request body:
+//   {"fen": "...", "moves": ["e2e4"], "depth": 10, "nodes": 100000, "movetime": 500}
+// Everything is optional; without a fen the start position is used.
+pub fn analyze(engine: &mut Engine, body: &str) -> Result<Value, String> {
+    let request: Value = serde_json::from_str(body).map_err(|err| format!("request is not JSON: {}", err))?;
+    let moves: Vec<&str> = match &request["moves"] {
+        Value::Null => Vec::new(),
+        Value::Array(moves) => moves.iter().map(|mv| mv.as_str().ok_or("moves must be strings")).collect::<Result<_, _>>()?,
+        _ => return Err("moves must be an array".to_string()),
+    };
+    engine.set_position(request["fen"].as_str(), &moves)?;
+
+    let number = |name: &str| match &request[name] {
+        Value::Null => Ok(None),
+        value => value.as_u64().map(Some).ok_or_else(|| format!("{} must be a positive integer", name)),
+    };
+    let (depth, nodes, movetime) = (number("depth")?, number("nodes")?, number("movetime")?);
+    let limits = SearchLimits {
+        depth: match depth {
+            Some(depth) => (depth as u32).clamp(1, MAX_DEPTH),
+            None if nodes.is_none() && movetime.is_none() => DEFAULT_DEPTH,
+            None => MAX_DEPTH,
+        },
+        time: Some(movetime.map_or(MAX_MOVETIME, |ms| Duration::from_millis(ms).min(MAX_MOVETIME))),
+        nodes,
+        ..SearchLimits::default()
+    };
+
+    let evaluator = Evaluator::new();
+    let trace = evaluator.trace(engine.board());
+    let eval = json!({
+        "material": trace.material,
+        "piece_squares": trace.piece_squares,
+        "mobility": trace.mobility,
+        "pawn_structure": trace.pawn_structure,
+        "king_safety": trace.king_safety,
+        "known_endgame": trace.known_endgame,
+        "endgame": trace.is_endgame,
+        "total": trace.total,
+        "king_danger": {
+            "white": king_danger_json(&evaluator.king_danger(engine.board(), Color::White)),
+            "black": king_danger_json(&evaluator.king_danger(engine.board(), Color::Black)),
+        },
+    });
+
+    let fen = engine.board().to_fen();
+    let Some(result) = engine.search(limits) else {
+        return Ok(json!({ "fen": fen, "bestmove": null, "state": engine.game_state().to_string(), "eval": eval }));
+    };
+    Ok(json!({
+        "fen": fen,
+        "bestmove": format_move(&result.best_move),
+        "ponder": result.ponder.as_ref().map(format_move),
+        "score": score_json(result.score),
+        "pv": result.pv.iter().map(format_move).collect::<Vec<_>>(),
+        "nodes": result.nodes,
+        "time_ms": result.elapsed.as_millis() as u64,
+        "eval": eval,
+    }))
+}
+
+// Answers requests one at a time until the process is stopped:
+//   POST /analyze  a position to search, see analyze()
+//   GET  /health   {"status": "ok"}
+pub fn serve(address: &str) -> Result<(), String> {
+    let server = Server::http(address).map_err(|err| format!("cannot listen on {}: {}", address, err))?;
+    let mut engine = Engine::new();
+    let json_header = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+
+    for mut request in server.incoming_requests() {
+        let (status, body) = match (request.method(), request.url()) {
+            (Method::Post, "/analyze") => {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => match analyze(&mut engine, &body) {
+                        Ok(response) => (200, response),
+                        Err(err) => (400, json!({ "error": err })),
+                    },
+                    Err(err) => (400, json!({ "error": err.to_string() })),
+                }
+            }
+            (Method::Get, "/health") => (200, json!({ "status": "ok" })),
+            _ => (404, json!({ "error": "not found" })),
+        };
+        let response = Response::from_string(body.to_string()).with_status_code(status).with_header(json_header.clone());
+        if let Err(err) = request.respond(response) {
+            eprintln!("cannot send response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let mut engine = Engine::new();
+        let response = analyze(&mut engine, r#"{"moves": ["e2e4", "e7e5"], "depth": 2}"#).unwrap();
+
+        assert_eq!(response["fen"], "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+        assert_eq!(response["pv"][0], response["bestmove"]);
+        assert!(response["score"]["cp"].is_i64());
+        assert!(response["eval"]["total"].is_i64());
+        assert_eq!(response["eval"]["king_danger"]["white"]["ring"], json!(["d1", "e1", "f1", "d2", "e2", "f2"]));
+
+        // The queen and rook bear down on the castled king
+        let response = analyze(&mut engine, r#"{"fen": "6k1/5ppp/8/8/7q/8/5PPP/4r1K1 w - - 0 1", "depth": 1}"#).unwrap();
+        let danger = &response["eval"]["king_danger"]["white"];
+        assert_eq!((danger["attackers"]["queen"].as_i64(), danger["attackers"]["rook"].as_i64()), (Some(1), Some(1)));
+        assert_eq!((danger["attack_units"].as_i64(), danger["shield_pawns"].as_i64()), (Some(8), Some(3)));
+        assert!(danger["score"].as_i64() < response["eval"]["king_danger"]["black"]["score"].as_i64());
+
+        let response = analyze(&mut engine, r#"{"fen": "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "depth": 3}"#).unwrap();
+        assert_eq!(response["bestmove"], "a1a8");
+        assert_eq!(response["score"]["mate"], 1);
+    }
+
+    #[test]
+    fn test_analyze_rejects_bad_requests() {
+        let mut engine = Engine::new();
+        assert!(analyze(&mut engine, "depth=3").is_err());
+        assert!(analyze(&mut engine, r#"{"fen": "8/8/8 w - - 0 1"}"#).is_err());
+        assert!(analyze(&mut engine, r#"{"moves": "e2e4"}"#).is_err());
+        assert!(analyze(&mut engine, r#"{"moves": ["e2e5"]}"#).is_err());
+        assert!(analyze(&mut engine, r#"{"depth": -1}"#).is_err());
+
+        let response = analyze(&mut engine, r#"{"fen": "7k/6Q1/6K1/8/8/8/8/8 b - - 0 1"}"#).unwrap();
+        assert_eq!(response["bestmove"], Value::Null);
+        assert_eq!(response["state"], "checkmate, white wins");
+    }
+}
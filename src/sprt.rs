@@ -0,0 +1,125 @@
+use crate::match_runner::MatchScore;
+use std::fmt;
+
+// Sequential probability ratio test between two Elo hypotheses: H0, the
+// change is worth elo0, and H1, it is worth elo1. A match is stopped as soon
+// as the log-likelihood ratio crosses either bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    // Chance of passing a change worth elo0 or less
+    pub alpha: f64,
+    // Chance of failing a change worth elo1 or more
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Self { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    // H1 accepted
+    Pass,
+    // H0 accepted
+    Fail,
+    Continue,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "H1 accepted"),
+            Verdict::Fail => write!(f, "H0 accepted"),
+            Verdict::Continue => write!(f, "continue"),
+        }
+    }
+}
+
+impl Sprt {
+    // "elo0,elo1" with the default error rates
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bound = |s: &str| s.trim().parse::<f64>().map_err(|_| format!("invalid SPRT bounds '{}'", text));
+        match text.split_once(',') {
+            Some((elo0, elo1)) => {
+                let (elo0, elo1) = (bound(elo0)?, bound(elo1)?);
+                if elo0 >= elo1 {
+                    return Err("elo0 must be below elo1".to_string());
+                }
+                Ok(Self { elo0, elo1, ..Self::default() })
+            }
+            None => Err(format!("SPRT bounds should look like 0,5, not '{}'", text)),
+        }
+    }
+
+    // (lower, upper): below lower H0 is accepted, above upper H1 is
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    // Log-likelihood ratio of H1 over H0, using the normal approximation of
+    // the game results (wins, draws and losses scored 1, 0.5 and 0)
+    pub fn llr(&self, score: &MatchScore) -> f64 {
+        let n = score.games() as f64;
+        if score.wins == 0 || score.losses == 0 {
+            // Without both a win and a loss the variance estimate is useless
+            return 0.0;
+        }
+        let mean = score.score();
+        let variance = (score.wins as f64 * (1.0 - mean).powi(2)
+            + score.draws as f64 * (0.5 - mean).powi(2)
+            + score.losses as f64 * mean.powi(2))
+            / n;
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        n * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    pub fn verdict(&self, score: &MatchScore) -> Verdict {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Verdict::Pass
+        } else if llr <= lower {
+            Verdict::Fail
+        } else {
+            Verdict::Continue
+        }
+    }
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds_and_parse() {
+        let (lower, upper) = Sprt::default().bounds();
+        assert!((lower + 2.944).abs() < 0.001);
+        assert!((upper - 2.944).abs() < 0.001);
+
+        assert_eq!(Sprt::parse("-1.5, 3").unwrap(), Sprt { elo0: -1.5, elo1: 3.0, ..Sprt::default() });
+        assert!(Sprt::parse("5,0").is_err());
+        assert!(Sprt::parse("5").is_err());
+    }
+
+    #[test]
+    fn test_verdicts() {
+        let sprt = Sprt::default();
+        assert_eq!(sprt.llr(&MatchScore { wins: 3, draws: 0, losses: 0 }), 0.0);
+        assert_eq!(sprt.verdict(&MatchScore { wins: 10, draws: 10, losses: 10 }), Verdict::Continue);
+
+        // A clearly stronger side passes and a clearly weaker one fails
+        let strong = MatchScore { wins: 600, draws: 800, losses: 400 };
+        assert!(sprt.llr(&strong) > 2.944);
+        assert_eq!(sprt.verdict(&strong), Verdict::Pass);
+        let weak = MatchScore { wins: 400, draws: 800, losses: 600 };
+        assert_eq!(sprt.verdict(&weak), Verdict::Fail);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::board::Color;
+use std::time::Duration;
+
+// Used when "go" gives no clock, movetime or other limit at all
+const DEFAULT_MOVE_TIME_MS: u64 = 5000;
+// Without movestogo, assume the game lasts about this many more moves
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+// Time kept in reserve for GUI and communication lag
+const MOVE_OVERHEAD_MS: u64 = 50;
+// How far past the soft budget an iteration already under way may run
+const HARD_LIMIT_FACTOR: u64 = 4;
+
+// Every parameter of a UCI "go" command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GoParams {
+    pub searchmoves: Vec<String>,
+    pub ponder: bool,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u32>,
+    pub movetime: Option<u64>,
+    pub infinite: bool,
+}
+
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves", "ponder", "wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "mate",
+    "movetime", "infinite",
+];
+
+impl GoParams {
+    // Parses the tokens after "go"; unknown tokens and malformed numbers are skipped
+    pub fn parse(parts: &[&str]) -> Self {
+        let mut params = GoParams::default();
+        let number = |i: usize| parts.get(i + 1).and_then(|s| s.parse::<u64>().ok());
+
+        let mut i = 0;
+        while i < parts.len() {
+            match parts[i] {
+                "searchmoves" => {
+                    while let Some(mv) = parts.get(i + 1).filter(|p| !GO_KEYWORDS.contains(p)) {
+                        params.searchmoves.push(mv.to_string());
+                        i += 1;
+                    }
+                }
+                "ponder" => params.ponder = true,
+                "infinite" => params.infinite = true,
+                "wtime" => params.wtime = number(i),
+                "btime" => params.btime = number(i),
+                "winc" => params.winc = number(i),
+                "binc" => params.binc = number(i),
+                "movestogo" => params.movestogo = number(i),
+                "depth" => params.depth = number(i).map(|d| d as u32),
+                "nodes" => params.nodes = number(i),
+                "mate" => params.mate = number(i).map(|m| m as u32),
+                "movetime" => params.movetime = number(i),
+                _ => {}
+            }
+            i += 1;
+        }
+        params
+    }
+}
+
+// How long to think about a move. No iteration starts once the soft budget
+// is spent; the hard one stops the search wherever it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeBudget {
+    pub soft: Duration,
+    pub hard: Duration,
+}
+
+impl TimeBudget {
+    pub fn fixed(time: Duration) -> Self {
+        Self { soft: time, hard: time }
+    }
+}
+
+pub struct TimeManager;
+
+impl TimeManager {
+    // The budget for this move, or None when only the GUI (or a depth, node or
+    // mate limit) ends the search
+    pub fn allocate(params: &GoParams, side_to_move: Color) -> Option<TimeBudget> {
+        if params.infinite {
+            return None;
+        }
+        if let Some(movetime) = params.movetime {
+            return Some(TimeBudget::fixed(Duration::from_millis(movetime)));
+        }
+
+        let (time, increment) = match side_to_move {
+            Color::White => (params.wtime, params.winc),
+            Color::Black => (params.btime, params.binc),
+        };
+        let time = match time {
+            Some(time) => time,
+            None if params.depth.is_some() || params.nodes.is_some() || params.mate.is_some() => return None,
+            None => return Some(TimeBudget::fixed(Duration::from_millis(DEFAULT_MOVE_TIME_MS))),
+        };
+
+        // Spread the clock over the moves left until the next time control,
+        // and spend most of the increment we get back
+        let moves_to_go = params.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let budget = time / moves_to_go + increment.unwrap_or(0) * 3 / 4;
+
+        // Never plan to use more than is actually on the clock
+        let limit = time.saturating_sub(MOVE_OVERHEAD_MS).max(1);
+        Some(TimeBudget {
+            soft: Duration::from_millis(budget.min(limit)),
+            hard: Duration::from_millis(budget.saturating_mul(HARD_LIMIT_FACTOR).min(limit)),
+        })
+    }
+
+    // With the nodestime option set, a time budget becomes a budget of that many
+    // nodes per millisecond, so results don't depend on how busy the machine is
+    pub fn nodes_for(time: Duration, nodestime: u64) -> u64 {
+        (time.as_millis() as u64).saturating_mul(nodestime).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_go_params() {
+        let params = GoParams::parse(&[
+            "wtime", "60000", "btime", "55000", "winc", "1000", "binc", "500", "movestogo", "20",
+        ]);
+        assert_eq!(params.wtime, Some(60000));
+        assert_eq!(params.btime, Some(55000));
+        assert_eq!(params.winc, Some(1000));
+        assert_eq!(params.binc, Some(500));
+        assert_eq!(params.movestogo, Some(20));
+        assert!(!params.infinite);
+
+        let params = GoParams::parse(&["searchmoves", "e2e4", "d2d4", "depth", "6", "ponder"]);
+        assert_eq!(params.searchmoves, vec!["e2e4", "d2d4"]);
+        assert_eq!(params.depth, Some(6));
+        assert!(params.ponder);
+
+        let params = GoParams::parse(&["infinite", "nodes", "x"]);
+        assert!(params.infinite);
+        assert_eq!(params.nodes, None);
+    }
+
+    #[test]
+    fn test_allocate_from_clock() {
+        let millis = |soft, hard| Some(TimeBudget { soft: Duration::from_millis(soft), hard: Duration::from_millis(hard) });
+        let params = GoParams::parse(&["wtime", "60000", "btime", "30000", "winc", "1000", "binc", "0"]);
+        assert_eq!(TimeManager::allocate(&params, Color::White), millis(2750, 11000));
+        assert_eq!(TimeManager::allocate(&params, Color::Black), millis(1000, 4000));
+
+        // With one move to the time control, use nearly all of it
+        let params = GoParams::parse(&["wtime", "10000", "movestogo", "1"]);
+        assert_eq!(TimeManager::allocate(&params, Color::White), millis(9950, 9950));
+
+        // Never more than what is left
+        let params = GoParams::parse(&["wtime", "100", "winc", "5000"]);
+        assert_eq!(TimeManager::allocate(&params, Color::White), millis(50, 50));
+        let params = GoParams::parse(&["wtime", "6000", "movestogo", "2"]);
+        assert_eq!(TimeManager::allocate(&params, Color::White), millis(3000, 5950));
+    }
+
+    #[test]
+    fn test_nodes_for_time() {
+        assert_eq!(TimeManager::nodes_for(Duration::from_millis(2750), 400), 1_100_000);
+        assert_eq!(TimeManager::nodes_for(Duration::ZERO, 400), 1);
+    }
+
+    #[test]
+    fn test_allocate_fixed_and_unbounded() {
+        let params = GoParams::parse(&["movetime", "1500", "wtime", "60000"]);
+        assert_eq!(TimeManager::allocate(&params, Color::White), Some(TimeBudget::fixed(Duration::from_millis(1500))));
+
+        assert_eq!(TimeManager::allocate(&GoParams::parse(&["infinite"]), Color::White), None);
+        assert_eq!(TimeManager::allocate(&GoParams::parse(&["depth", "8"]), Color::Black), None);
+        assert_eq!(
+            TimeManager::allocate(&GoParams::parse(&[]), Color::White),
+            Some(TimeBudget::fixed(Duration::from_millis(DEFAULT_MOVE_TIME_MS)))
+        );
+    }
+}
@@ -1,65 +1,497 @@
-use std::collections::HashMap;
+use crate::movegen::Move;
+use crate::search::MATE_THRESHOLD;
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NodeType {
     Exact,
     LowerBound,
     UpperBound,
 }
 
-#[derive(Debug, Clone)]
+const BOUND_MASK: u8 = 0b11;
+const AGE_MASK: u8 = 0b11_1111;
+
+// Entries a position may go in: one cache line's worth
+const BUCKET_SIZE: usize = 8;
+// How many plies of depth a search's worth of age outweighs when picking the entry to replace
+const AGE_WEIGHT: i32 = 8;
+
+// Packed entry: key16 | move16 | score16 | depth8 | age6 + bound2.
+// The upper 16 bits of the hash are kept to reject index collisions on probe.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, align(8))]
 pub struct TranspositionEntry {
-    pub hash: u64,
-    pub depth: u32,
+    key: u16,
+    best_move: u16,
+    score: i16,
+    depth: u8,
+    age_bound: u8,
+}
+
+const _: () = assert!(std::mem::size_of::<TranspositionEntry>() == 8);
+
+impl TranspositionEntry {
+    fn is_empty(&self) -> bool {
+        self.age_bound & BOUND_MASK == 0
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth as u32
+    }
+
+    pub fn score(&self) -> i32 {
+        self.score as i32
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        match self.age_bound & BOUND_MASK {
+            1 => NodeType::Exact,
+            2 => NodeType::LowerBound,
+            _ => NodeType::UpperBound,
+        }
+    }
+
+    pub fn best_move(&self) -> Option<u16> {
+        if self.best_move == 0 {
+            None
+        } else {
+            Some(self.best_move)
+        }
+    }
+
+    pub fn age(&self) -> u8 {
+        self.age_bound >> 2
+    }
+
+    // How much the entry is worth keeping at the given table age: deep entries
+    // from the current search most, anything empty least
+    fn worth(&self, age: u8) -> i32 {
+        if self.is_empty() {
+            return i32::MIN;
+        }
+        let searches_ago = (age.wrapping_sub(self.age()) & AGE_MASK) as i32;
+        self.depth as i32 - AGE_WEIGHT * searches_ago
+    }
+}
+
+// Everything a probe found for a position, with the score already adjusted to the probing ply
+#[derive(Debug, Clone, Copy)]
+pub struct TtHit {
     pub score: i32,
-    pub node_type: NodeType,
-    pub best_move: Option<u64>,
+    pub bound: NodeType,
+    pub depth: u32,
+    pub best_move: Option<u16>,
+}
+
+impl TtHit {
+    // The stored score, if it is deep enough and its bound settles this window
+    pub fn cutoff(&self, depth: u32, alpha: i32, beta: i32) -> Option<i32> {
+        if self.depth < depth {
+            return None;
+        }
+        match self.bound {
+            NodeType::Exact => Some(self.score),
+            NodeType::LowerBound if self.score >= beta => Some(self.score),
+            NodeType::UpperBound if self.score <= alpha => Some(self.score),
+            _ => None,
+        }
+    }
+}
+
+// Mate scores are stored relative to the node rather than the root, so a hit
+// reached through a different path still reports the right mate distance
+fn score_to_tt(score: i32, ply: u32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score + ply as i32
+    } else if score <= -MATE_THRESHOLD {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: u32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score - ply as i32
+    } else if score <= -MATE_THRESHOLD {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// Snapshot of the table's usage counters since the last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TtStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub cutoffs: u64,
+    // Probes that missed in a bucket holding other positions
+    pub collisions: u64,
+    // Stores that evicted a different position
+    pub overwrites: u64,
+}
+
+// Relaxed atomics keep counting cheap and usable from &self probes
+#[derive(Debug, Default)]
+struct TtCounters {
+    probes: AtomicU64,
+    hits: AtomicU64,
+    cutoffs: AtomicU64,
+    collisions: AtomicU64,
+    overwrites: AtomicU64,
+}
+
+impl TtCounters {
+    fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+const CACHE_LINE: usize = 64;
+const HUGE_PAGE: usize = 2 * 1024 * 1024;
+
+// The entries as one zeroed block starting on a cache line, so the four
+// entries sharing a line never straddle two. With huge pages the block is
+// rounded to whole 2 MB pages and, on Linux, the kernel is asked to back it
+// with them, which saves most of the TLB misses of a big table. A zeroed
+// entry is an empty one.
+struct Entries {
+    ptr: NonNull<TranspositionEntry>,
+    len: usize,
+    layout: Layout,
+    huge_pages: bool,
+}
+
+// The block is owned like a Vec's buffer
+unsafe impl Send for Entries {}
+unsafe impl Sync for Entries {}
+
+impl Entries {
+    fn new(len: usize, huge_pages: bool) -> Self {
+        let len = (len / BUCKET_SIZE).max(1) * BUCKET_SIZE;
+        let bytes = len * std::mem::size_of::<TranspositionEntry>();
+        let layout = if huge_pages {
+            Layout::from_size_align(bytes.next_multiple_of(HUGE_PAGE), HUGE_PAGE)
+        } else {
+            Layout::from_size_align(bytes, CACHE_LINE)
+        }
+        .expect("transposition table too large");
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr as *mut TranspositionEntry) else {
+            alloc::handle_alloc_error(layout);
+        };
+        let huge_pages = huge_pages && advise_huge_pages(ptr.as_ptr() as *mut u8, layout.size());
+        Self { ptr, len, layout, huge_pages }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(ptr: *mut u8, bytes: usize) -> bool {
+    unsafe { libc::madvise(ptr as *mut libc::c_void, bytes, libc::MADV_HUGEPAGE) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_ptr: *mut u8, _bytes: usize) -> bool {
+    false
+}
+
+impl Deref for Entries {
+    type Target = [TranspositionEntry];
+
+    fn deref(&self) -> &[TranspositionEntry] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Entries {
+    fn deref_mut(&mut self) -> &mut [TranspositionEntry] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Entries {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr() as *mut u8, self.layout) }
+    }
 }
 
 pub struct TranspositionTable {
-    table: HashMap<u64, TranspositionEntry>,
-    size: usize,
+    table: Entries,
+    age: u8,
+    counters: TtCounters,
 }
 
 impl TranspositionTable {
     pub fn new(size: usize) -> Self {
+        Self::with_huge_pages(size, false)
+    }
+
+    pub fn with_huge_pages(size: usize, huge_pages: bool) -> Self {
         Self {
-            table: HashMap::with_capacity(size),
-            size,
+            table: Entries::new(size, huge_pages),
+            age: 0,
+            counters: TtCounters::default(),
+        }
+    }
+
+    // Whether the system agreed to back the table with huge pages
+    pub fn huge_pages(&self) -> bool {
+        self.table.huge_pages
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(TranspositionEntry::default());
+        self.age = 0;
+    }
+
+    // Called once per search so entries from earlier searches can be told apart
+    pub fn new_search(&mut self) {
+        self.age = (self.age + 1) & AGE_MASK;
+    }
+
+    // The entries of the bucket a position belongs in
+    fn bucket(&self, hash: u64) -> std::ops::Range<usize> {
+        let buckets = (self.table.len() / BUCKET_SIZE) as u64;
+        let start = (hash % buckets) as usize * BUCKET_SIZE;
+        start..start + BUCKET_SIZE
+    }
+
+    fn key(hash: u64) -> u16 {
+        (hash >> 48) as u16
+    }
+
+    fn entry(&self, hash: u64) -> Option<&TranspositionEntry> {
+        let key = Self::key(hash);
+        self.table[self.bucket(hash)].iter().find(|entry| !entry.is_empty() && entry.key == key)
+    }
+
+    pub fn stats(&self) -> TtStats {
+        TtStats {
+            probes: self.counters.probes.load(Ordering::Relaxed),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            cutoffs: self.counters.cutoffs.load(Ordering::Relaxed),
+            collisions: self.counters.collisions.load(Ordering::Relaxed),
+            overwrites: self.counters.overwrites.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.counters = TtCounters::default();
+    }
+
+    // The search reports when a hit's score ended the node
+    pub fn record_cutoff(&self) {
+        TtCounters::bump(&self.counters.cutoffs);
+    }
+
+    pub fn store(&mut self, hash: u64, depth: u32, ply: u32, score: i32, node_type: NodeType, best_move: Option<Move>) {
+        let bound = match node_type {
+            NodeType::Exact => 1,
+            NodeType::LowerBound => 2,
+            NodeType::UpperBound => 3,
+        };
+        let key = Self::key(hash);
+        let age = self.age;
+        let range = self.bucket(hash);
+        let bucket = &mut self.table[range];
+        // The position's own entry if it has one, otherwise the one least worth keeping
+        let slot = match bucket.iter().position(|entry| !entry.is_empty() && entry.key == key) {
+            Some(slot) => slot,
+            None => (0..BUCKET_SIZE).min_by_key(|&slot| bucket[slot].worth(age)).unwrap_or(0),
+        };
+        let old = bucket[slot];
+        if !old.is_empty() && old.key != key {
+            TtCounters::bump(&self.counters.overwrites);
+        }
+        // A store without a move keeps the one the position had
+        let best_move = match best_move {
+            Some(mv) => mv.to_u16(),
+            None if old.key == key => old.best_move,
+            None => 0,
+        };
+        bucket[slot] = TranspositionEntry {
+            key,
+            best_move,
+            score: score_to_tt(score, ply).clamp(-(i16::MAX as i32), i16::MAX as i32) as i16,
+            depth: depth.min(u8::MAX as u32) as u8,
+            age_bound: (self.age << 2) | bound,
+        };
+    }
+
+    pub fn probe(&self, hash: u64, ply: u32) -> Option<TtHit> {
+        TtCounters::bump(&self.counters.probes);
+        let entry = self.entry(hash);
+        if entry.is_none() && self.table[self.bucket(hash)].iter().any(|entry| !entry.is_empty()) {
+            TtCounters::bump(&self.counters.collisions);
+        }
+
+        let hit = entry.map(|entry| TtHit {
+            score: score_from_tt(entry.score(), ply),
+            bound: entry.node_type(),
+            depth: entry.depth(),
+            best_move: entry.best_move(),
+        });
+        if hit.is_some() {
+            TtCounters::bump(&self.counters.hits);
         }
+        hit
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Piece;
+
+    #[test]
+    fn test_store_and_probe() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+        let mv = Move::new(12, 28, Piece::Pawn);
+
+        tt.store(hash, 5, 0, 37, NodeType::Exact, Some(mv));
 
-    pub fn store(&mut self, hash: u64, entry: TranspositionEntry) {
-        if self.table.len() >= self.size {
-            // Remove oldest entry if table is full
-            let oldest_key = *self.table.keys().next().unwrap();
-            self.table.remove(&oldest_key);
+        let hit = tt.probe(hash, 0).unwrap();
+        assert_eq!(hit.score, 37);
+        assert_eq!(hit.bound, NodeType::Exact);
+        assert_eq!(hit.depth, 5);
+        assert_eq!(hit.best_move, Some(mv.to_u16()));
+        assert_eq!(hit.cutoff(5, -100, 100), Some(37));
+        assert_eq!(hit.cutoff(6, -100, 100), None);
+    }
+
+    #[test]
+    fn test_aligned_allocation() {
+        for huge_pages in [false, true] {
+            let mut tt = TranspositionTable::with_huge_pages(100_000, huge_pages);
+            assert_eq!(tt.len(), 100_000);
+            assert_eq!(tt.table.as_ptr() as usize % if huge_pages { HUGE_PAGE } else { CACHE_LINE }, 0);
+            // Starts empty, and clearing empties it again
+            assert!(tt.table.iter().all(|entry| entry.is_empty()));
+            tt.store(99_999, 3, 0, 10, NodeType::Exact, None);
+            assert_eq!(tt.probe(99_999, 0).unwrap().score, 10);
+            tt.clear();
+            assert!(tt.probe(99_999, 0).is_none());
         }
-        self.table.insert(hash, entry);
-    }
-
-    pub fn probe(&self, hash: u64, depth: u32, alpha: i32, beta: i32) -> Option<i32> {
-        if let Some(entry) = self.table.get(&hash) {
-            if entry.depth >= depth {
-                match entry.node_type {
-                    NodeType::Exact => return Some(entry.score),
-                    NodeType::LowerBound => {
-                        if entry.score >= beta {
-                            return Some(entry.score);
-                        }
-                    }
-                    NodeType::UpperBound => {
-                        if entry.score <= alpha {
-                            return Some(entry.score);
-                        }
-                    }
-                }
-            }
+        assert!(!TranspositionTable::new(16).huge_pages());
+    }
+
+    #[test]
+    fn test_bounds_only_cut_outside_the_window() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+
+        tt.store(hash, 5, 0, 50, NodeType::LowerBound, None);
+        let hit = tt.probe(hash, 0).unwrap();
+        assert_eq!(hit.cutoff(5, -100, 40), Some(50));
+        assert_eq!(hit.cutoff(5, -100, 100), None);
+
+        tt.store(hash, 5, 0, -50, NodeType::UpperBound, None);
+        let hit = tt.probe(hash, 0).unwrap();
+        assert_eq!(hit.cutoff(5, -40, 100), Some(-50));
+        assert_eq!(hit.cutoff(5, -100, 100), None);
+    }
+
+    #[test]
+    fn test_index_collision_is_rejected() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+        // Same table index, different upper bits
+        let colliding = 0x1234_0000_0000_0042;
+
+        tt.store(hash, 5, 0, 37, NodeType::Exact, None);
+
+        assert!(tt.probe(colliding, 0).is_none());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+        let colliding = 0x1234_0000_0000_0042;
+
+        tt.probe(hash, 0);
+        tt.store(hash, 5, 0, 37, NodeType::Exact, None);
+        tt.probe(hash, 0);
+        tt.record_cutoff();
+        tt.probe(colliding, 0);
+        // Only once the bucket is full does a store evict another position
+        for top in 1..BUCKET_SIZE as u64 {
+            tt.store(top << 56 | 0x42, 5, 0, 37, NodeType::Exact, None);
+        }
+        tt.store(colliding, 5, 0, 37, NodeType::Exact, None);
+
+        assert_eq!(
+            tt.stats(),
+            TtStats { probes: 3, hits: 1, cutoffs: 1, collisions: 1, overwrites: 1 }
+        );
+
+        tt.reset_stats();
+        assert_eq!(tt.stats(), TtStats::default());
+    }
+
+    #[test]
+    fn test_replacement_prefers_shallow_and_old_entries() {
+        let mut tt = TranspositionTable::new(1024);
+        let in_bucket = |top: u64| top << 48 | 0x42;
+        for (top, depth) in [(1, 9), (2, 3), (3, 7), (4, 5), (5, 8), (6, 6), (7, 4), (8, 10)] {
+            tt.store(in_bucket(top), depth, 0, 0, NodeType::Exact, None);
         }
-        None
+        // The shallowest entry goes first
+        tt.store(in_bucket(9), 1, 0, 0, NodeType::Exact, None);
+        assert!(tt.probe(in_bucket(2), 0).is_none());
+        assert!(tt.probe(in_bucket(9), 0).is_some());
+
+        // A search later, the deepest of the old entries is worth less than a shallow new one
+        tt.new_search();
+        tt.store(in_bucket(10), 2, 0, 0, NodeType::Exact, None);
+        tt.store(in_bucket(11), 2, 0, 0, NodeType::Exact, None);
+        assert!(tt.probe(in_bucket(8), 0).is_some());
+        assert!(tt.probe(in_bucket(7), 0).is_none() && tt.probe(in_bucket(9), 0).is_none());
+
+        // Storing the same position again keeps its move unless given a new one
+        let mv = Move::new(12, 28, Piece::Pawn);
+        tt.store(in_bucket(10), 4, 0, 0, NodeType::Exact, Some(mv));
+        tt.store(in_bucket(10), 5, 0, 0, NodeType::LowerBound, None);
+        assert_eq!(tt.probe(in_bucket(10), 0).unwrap().best_move, Some(mv.to_u16()));
+        assert_eq!(tt.table.iter().filter(|entry| !entry.is_empty()).count(), BUCKET_SIZE);
     }
 
-    pub fn get_best_move(&self, hash: u64) -> Option<u64> {
-        self.table.get(&hash).and_then(|entry| entry.best_move)
+    #[test]
+    fn test_clear() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+
+        tt.store(hash, 5, 0, 37, NodeType::Exact, None);
+        tt.clear();
+
+        assert!(tt.probe(hash, 0).is_none());
+    }
+
+    #[test]
+    fn test_mate_scores_are_ply_relative() {
+        use crate::search::MATE_SCORE;
+
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xABCD_0000_0000_0042;
+
+        // Mate found 3 plies below a node at ply 4 (mate in 7 from the root)
+        tt.store(hash, 3, 4, MATE_SCORE - 7, NodeType::Exact, None);
+
+        // The same node reached at ply 2 is a mate in 5 from the root
+        assert_eq!(tt.probe(hash, 2).unwrap().score, MATE_SCORE - 5);
     }
-} 
\ No newline at end of file
+}
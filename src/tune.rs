@@ -0,0 +1,140 @@
+use crate::board::Board;
+use crate::evaluation::Evaluator;
+
+// A quiet position and the result of its game, for White: 1, 0.5 or 0
+#[derive(Debug, Clone)]
+pub struct TuningPosition {
+    pub board: Board,
+    pub result: f64,
+}
+
+type Weight = fn(&mut Evaluator) -> &mut i32;
+
+// The evaluation weights the tuner adjusts. Piece-square tables are left
+// alone: 448 more weights need far more data than a quick datagen run gives.
+pub const WEIGHTS: [(&str, Weight); 17] = [
+    ("pawn_value", |e| &mut e.pawn_value),
+    ("knight_value", |e| &mut e.knight_value),
+    ("bishop_value", |e| &mut e.bishop_value),
+    ("rook_value", |e| &mut e.rook_value),
+    ("queen_value", |e| &mut e.queen_value),
+    ("knight_mobility_weight", |e| &mut e.knight_mobility_weight),
+    ("bishop_mobility_weight", |e| &mut e.bishop_mobility_weight),
+    ("rook_mobility_weight", |e| &mut e.rook_mobility_weight),
+    ("queen_mobility_weight", |e| &mut e.queen_mobility_weight),
+    ("doubled_pawn_penalty", |e| &mut e.doubled_pawn_penalty),
+    ("isolated_pawn_penalty", |e| &mut e.isolated_pawn_penalty),
+    ("passed_pawn_bonus", |e| &mut e.passed_pawn_bonus),
+    ("connected_pawn_bonus", |e| &mut e.connected_pawn_bonus),
+    ("pawn_shield_bonus", |e| &mut e.pawn_shield_bonus),
+    ("open_file_penalty", |e| &mut e.open_file_penalty),
+    ("semi_open_file_penalty", |e| &mut e.semi_open_file_penalty),
+    ("king_attack_bonus", |e| &mut e.king_attack_bonus),
+];
+
+// Step sizes tried in turn: a pass that improves nothing moves on to the next
+const STEPS: [i32; 4] = [8, 4, 2, 1];
+
+// Reads datagen output, "<fen> | <score> | <result>" per line
+pub fn load_positions(text: &str) -> Result<Vec<TuningPosition>, String> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            let [fen, _, result] = fields[..] else {
+                return Err(format!("expected '<fen> | <score> | <result>', got '{}'", line));
+            };
+            let result = result.parse().ok().filter(|result| [0.0, 0.5, 1.0].contains(result));
+            Ok(TuningPosition { board: Board::from_fen(fen)?, result: result.ok_or_else(|| format!("bad result in '{}'", line))? })
+        })
+        .collect()
+}
+
+// Expected score for White of an evaluation, k scaling centipawns to odds
+fn sigmoid(eval: i32, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval as f64 / 400.0))
+}
+
+pub fn mean_error(evaluator: &Evaluator, positions: &[TuningPosition], k: f64) -> f64 {
+    let total: f64 = positions
+        .iter()
+        .map(|position| (position.result - sigmoid(evaluator.evaluate(&position.board), k)).powi(2))
+        .sum();
+    total / positions.len().max(1) as f64
+}
+
+// The scaling that fits the untuned evaluation best, searched to two decimals
+pub fn best_k(evaluator: &Evaluator, positions: &[TuningPosition]) -> f64 {
+    let mut best = 1.0;
+    for step in [0.1, 0.01] {
+        let candidates = (-9..=9).map(|i| best + i as f64 * step).filter(|&k| k > 0.0);
+        best = candidates
+            .map(|k| (k, mean_error(evaluator, positions, k)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(best, |(k, _)| k);
+    }
+    best
+}
+
+// Texel tuning: nudges each weight up or down while that lowers the mean
+// squared error between the evaluation's predicted and the actual results.
+// on_pass hears the error after every pass; the final error is returned.
+pub fn tune(
+    evaluator: &mut Evaluator,
+    positions: &[TuningPosition],
+    k: f64,
+    max_passes: usize,
+    mut on_pass: impl FnMut(usize, f64),
+) -> f64 {
+    // The board's running totals only know the default piece values
+    evaluator.use_board_psqt = false;
+    let mut error = mean_error(evaluator, positions, k);
+    let mut steps = STEPS.iter();
+    let mut step = *steps.next().unwrap();
+    for pass in 1..=max_passes {
+        let mut improved = false;
+        for (_, weight) in WEIGHTS {
+            for delta in [step, -step] {
+                *weight(evaluator) += delta;
+                let new_error = mean_error(evaluator, positions, k);
+                if new_error < error {
+                    error = new_error;
+                    improved = true;
+                    break;
+                }
+                *weight(evaluator) -= delta;
+            }
+        }
+        on_pass(pass, error);
+        if !improved {
+            match steps.next() {
+                Some(&smaller) => step = smaller,
+                None => break,
+            }
+        }
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_tune() {
+        let data = "4k3/8/8/8/8/8/3Q4/4K3 w - - 0 1 | 900 | 1.0\n\
+                    4k3/8/8/8/8/8/3q4/4K3 w - - 0 1 | -900 | 0.0\n\
+                    4k3/8/8/8/8/8/8/4K3 w - - 0 1 | 0 | 0.5\n";
+        let positions = load_positions(data).unwrap();
+        assert_eq!(positions.len(), 3);
+        assert!(load_positions("4k3/8/8/8/8/8/8/4K3 w - - 0 1 | 0 | 2").is_err());
+
+        let mut evaluator = Evaluator::new();
+        let k = best_k(&evaluator, &positions);
+        let before = mean_error(&evaluator, &positions, k);
+        // A lone queen always won, so valuing it higher can only fit better
+        let after = tune(&mut evaluator, &positions, k, 3, |_, _| {});
+        assert!(after <= before);
+        assert!(evaluator.queen_value >= Evaluator::new().queen_value);
+    }
+}
@@ -1,63 +1,218 @@
-use crate::board::{Board, Color, Piece};
-use crate::movegen::{MoveGenerator, Move};
+use crate::board::{Board, Color};
+use crate::book::{self, BookPolicy, BookSelection, PolyglotBook};
+use crate::caches::DEFAULT_HASH_MB;
 use crate::evaluation::Evaluator;
-use crate::search::Search;
+use crate::experience::Experience;
+use crate::handicap::Handicap;
+use crate::level::Level;
+use crate::movegen::{MoveGenerator, Move};
+use crate::options::{OptionKind, OptionValue, OptionsRegistry};
+use crate::repetition::RepetitionHistory;
+use crate::perft;
+use crate::search::{Algorithm, Search, SearchInfo, SearchLimits, SearchSignals, Variety, MATE_SCORE, MATE_THRESHOLD, MAX_DEPTH};
+use crate::timeman::{GoParams, TimeManager};
+use crate::tune;
+use crate::variant::{self, Variant, STANDARD};
 use anyhow::Result;
+use std::fs::{File, OpenOptions};
 use std::io::{self, BufRead, Write};
-use std::str::FromStr;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How many books can be layered, BookFile first
+const BOOK_SLOTS: usize = 3;
+
+// "BookFile", "BookFile2", ... for the book in each slot
+fn book_option(name: &str, slot: usize) -> String {
+    match slot {
+        0 => name.to_string(),
+        _ => format!("{}{}", name, slot + 1),
+    }
+}
+
+// Engine output, optionally mirrored to the debug log file; shared with the search thread
+struct Output<W: Write> {
+    writer: W,
+    log: Option<File>,
+}
+
+impl<W: Write> Output<W> {
+    fn send(&mut self, text: &str) {
+        // Nothing useful can be done if the GUI has gone away
+        let _ = self.writer.write_all(text.as_bytes());
+        let _ = self.writer.flush();
+        for line in text.lines() {
+            self.log("<<", line);
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+        if let Some(log) = self.log.as_mut() {
+            let _ = log.flush();
+        }
+    }
+
+    // One timestamped line per command received (>>) or line sent (<<)
+    fn log(&mut self, direction: &str, line: &str) {
+        if let Some(log) = self.log.as_mut() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let _ = writeln!(log, "[{}.{:03}] {} {}", now.as_secs(), now.subsec_millis(), direction, line);
+        }
+    }
+}
+
+fn emit<W: Write>(output: &Mutex<Output<W>>, line: &str) {
+    output.lock().unwrap().send(&format!("{}\n", line));
+}
+
+// What the command loop acts on next
+enum Input {
+    Line(String),
+    // End of input, or the read error that ended it
+    Closed(Option<io::Error>),
+    // A request to exit from outside the command stream, such as Ctrl-C
+    Interrupt,
+}
+
+// Makes a running command loop shut down as if it had been sent "quit"
+#[derive(Clone)]
+pub struct Interrupter(Sender<Input>);
+
+impl Interrupter {
+    pub fn interrupt(&self) {
+        let _ = self.0.send(Input::Interrupt);
+    }
+}
+
+fn read_input<R: BufRead>(mut input: R, inputs: Sender<Input>) {
+    loop {
+        let mut line = String::new();
+        let next = match input.read_line(&mut line) {
+            Ok(0) => Input::Closed(None),
+            Ok(_) => Input::Line(line),
+            Err(err) => Input::Closed(Some(err)),
+        };
+        let closed = matches!(next, Input::Closed(_));
+        if inputs.send(next).is_err() || closed {
+            return;
+        }
+    }
+}
 
-pub struct UciHandler {
+// Speaks UCI over any pair of streams; stdin and stdout for a real GUI
+pub struct UciHandler<W: Write + Send + 'static = io::Stdout> {
     board: Board,
+    // Positions and moves that led to the current board, for repetition detection
+    history: Vec<(Board, Move)>,
     move_generator: MoveGenerator,
-    search: Search,
+    // Shared with the worker thread that runs "go"
+    search: Arc<Mutex<Search>>,
+    signals: Arc<SearchSignals>,
+    search_thread: Option<JoinHandle<()>>,
+    // Whether the running search was started by "go infinite", and so only ends on "stop"
+    infinite: bool,
+    options: OptionsRegistry,
+    // Changes to what the search owns, held back while a search runs and has it locked
+    pending_options: Vec<(String, OptionValue)>,
+    // The Hash budget in MB and LargePages setting the tables were last allocated for
+    hash_allocation: (usize, bool),
+    // Set by "debug on"; enables the "info string" diagnostics
+    debug: bool,
+    // Loaded from BookFile, BookFile2 and so on, in that priority; only
+    // consulted when OwnBook is on
+    books: [Option<PolyglotBook>; BOOK_SLOTS],
+    output: Arc<Mutex<Output<W>>>,
+    // Lines read by run's input thread, and interrupts
+    inputs: Receiver<Input>,
+    input_sender: Sender<Input>,
+}
+
+impl Default for UciHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UciHandler {
     pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+}
+
+impl<W: Write + Send + 'static> UciHandler<W> {
+    pub fn with_output(output: W) -> Self {
+        let output = Arc::new(Mutex::new(Output { writer: output, log: None }));
+        let mut search = Search::new();
+        let info_output = Arc::clone(&output);
+        search.set_info_callback(Box::new(move |info| emit(&info_output, &format_info(&info))));
+        let (input_sender, inputs) = mpsc::channel();
         UciHandler {
             board: Board::new(),
+            history: Vec::new(),
             move_generator: MoveGenerator::new(),
-            search: Search::new(),
+            signals: search.signals(),
+            search: Arc::new(Mutex::new(search)),
+            search_thread: None,
+            infinite: false,
+            options: Self::default_options(),
+            pending_options: Vec::new(),
+            hash_allocation: (DEFAULT_HASH_MB, false),
+            debug: false,
+            books: Default::default(),
+            output,
+            inputs,
+            input_sender,
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
-        let mut reader = stdin.lock();
-        let mut line = String::new();
+    pub fn interrupter(&self) -> Interrupter {
+        Interrupter(self.input_sender.clone())
+    }
 
-        while reader.read_line(&mut line).unwrap() > 0 {
-            let command = line.trim();
-            
-            match command {
-                "quit" => break,
-                "uci" => {
-                    println!("id name Three Salmons");
-                    println!("id author Magnus Torvund");
-                    println!("uciok");
-                }
-                "isready" => println!("readyok"),
-                "ucinewgame" => {
-                    self.board = Board::new();
-                }
-                cmd if cmd.starts_with("position") => {
-                    let parts: Vec<&str> = cmd.split_whitespace().collect();
-                    self.handle_position(&parts[1..]);
+    // Reads commands until "quit", the end of input or an interrupt. Input is
+    // read on a thread of its own so an interrupt gets through while a read
+    // blocks; whichever way the loop ends, a running search is stopped or
+    // finished and its bestmove sent before returning.
+    pub fn run<R: BufRead + Send + 'static>(&mut self, input: R) -> Result<()> {
+        let inputs = self.input_sender.clone();
+        thread::spawn(move || read_input(input, inputs));
+
+        let result = loop {
+            let (command, read_error) = match self.inputs.recv() {
+                Ok(Input::Line(line)) => {
+                    let command = line.trim().to_string();
+                    if !command.is_empty() {
+                        self.output.lock().unwrap().log(">>", &command);
+                    }
+                    (command, None)
                 }
-                cmd if cmd.starts_with("go") => {
-                    let parts: Vec<&str> = cmd.split_whitespace().collect();
-                    let response = self.handle_go(&parts[1..]);
-                    print!("{}", response);
+                Ok(Input::Interrupt) => ("quit".to_string(), None),
+                Ok(Input::Closed(err)) => {
+                    // Let a running search finish, but don't wait on one that
+                    // can't end by itself: a ponder or an infinite search
+                    self.signals.ponder.store(false, Ordering::Relaxed);
+                    if self.infinite {
+                        self.signals.stop.store(true, Ordering::Relaxed);
+                    }
+                    self.wait_for_search();
+                    ("quit".to_string(), err)
                 }
-                _ => {}
+                Err(_) => ("quit".to_string(), None),
+            };
+            let response = self.handle_command(&command)?;
+            if !response.is_empty() {
+                self.output.lock().unwrap().send(&response);
             }
-            
-            stdout.flush()?;
-            line.clear();
-        }
-        Ok(())
+            if command == "quit" {
+                break read_error.map_or(Ok(()), |err| Err(err.into()));
+            }
+        };
+        self.output.lock().unwrap().flush();
+        result
     }
 
     pub fn handle_command(&mut self, command: &str) -> Result<String> {
@@ -66,222 +221,1173 @@ impl UciHandler {
             return Ok("".to_string());
         }
 
-        match parts[0] {
+        // Options held back for a search apply once it has ended, so before a new one starts
+        if parts[0] == "go" {
+            self.wait_for_search();
+        }
+        let mut response = if self.search_running() { "".to_string() } else { self.apply_pending_options() };
+        let handled: Result<String> = match parts[0] {
             "uci" => Ok(self.handle_uci()),
+            "debug" => Ok(self.handle_debug(&parts[1..])),
             "isready" => Ok("readyok\n".to_string()),
             "ucinewgame" => Ok(self.handle_ucinewgame()),
+            "setoption" => Ok(self.handle_setoption(&parts[1..])),
             "position" => Ok(self.handle_position(&parts[1..])),
+            "go" if parts.get(1) == Some(&"perft") => Ok(self.handle_perft(&parts[2..])),
             "go" => Ok(self.handle_go(&parts[1..])),
-            "quit" => Ok("".to_string()),
+            "perft" => Ok(self.handle_perft(&parts[1..])),
+            "d" => Ok(self.handle_display()),
+            "eval" => Ok(self.handle_eval()),
+            "see" => Ok(self.handle_see(&parts[1..])),
+            "stop" => Ok(self.handle_stop()),
+            "ponderhit" => Ok(self.handle_ponderhit()),
+            "quit" => Ok(self.save_experience()),
             _ => Ok("".to_string()),
+        };
+        response.push_str(&handled?);
+        if !self.search_running() {
+            response.push_str(&self.apply_pending_options());
         }
+        Ok(response)
+    }
+
+    fn search_running(&self) -> bool {
+        self.search_thread.as_ref().is_some_and(|thread| !thread.is_finished())
+    }
+
+    fn default_options() -> OptionsRegistry {
+        let mut options = OptionsRegistry::new();
+        options.register("Ponder", OptionKind::Check { default: false });
+        options.register("Hash", OptionKind::Spin { default: DEFAULT_HASH_MB as i64, min: 1, max: 65536 });
+        options.register("Clear Hash", OptionKind::Button);
+        // Backs the main hash table with huge pages where the system allows
+        options.register("LargePages", OptionKind::Check { default: false });
+        options.register("OwnBook", OptionKind::Check { default: false });
+        for slot in 0..BOOK_SLOTS {
+            options.register(&book_option("BookFile", slot), OptionKind::String { default: String::new() });
+            options.register(&book_option("BookDepth", slot), OptionKind::Spin { default: 20, min: 1, max: 200 });
+        }
+        options.register("BookVariety", OptionKind::Spin { default: 0, min: 0, max: 100 });
+        options.register(
+            "BookPolicy",
+            OptionKind::Combo {
+                default: "Weighted".to_string(),
+                choices: vec!["Best".to_string(), "Weighted".to_string(), "TopN".to_string()],
+            },
+        );
+        options.register("BookTopN", OptionKind::Spin { default: 3, min: 1, max: 50 });
+        // In hundredths, so 100 keeps the book's weights as they are
+        options.register("BookTemperature", OptionKind::Spin { default: 100, min: 1, max: 1000 });
+        options.register("BookMinWeight", OptionKind::Spin { default: 0, min: 0, max: 65535 });
+        options.register("BookMinScore", OptionKind::Spin { default: 0, min: 0, max: 100 });
+        #[cfg(feature = "online-tablebase")]
+        {
+            options.register("OnlineTablebase", OptionKind::Check { default: false });
+            options.register("OnlineTablebaseCache", OptionKind::String { default: String::new() });
+        }
+        #[cfg(feature = "online-explorer")]
+        {
+            options.register("OnlineExplorer", OptionKind::Check { default: false });
+            options.register("OnlineExplorerCache", OptionKind::String { default: String::new() });
+            options.register("OnlineExplorerMinGames", OptionKind::Spin { default: 100, min: 1, max: 1_000_000 });
+        }
+        options.register(
+            "SearchAlgorithm",
+            OptionKind::Combo { default: "AlphaBeta".to_string(), choices: vec!["AlphaBeta".to_string(), "MCTS".to_string()] },
+        );
+        // PUCT exploration constant in hundredths
+        options.register("MCTSExploration", OptionKind::Spin { default: 150, min: 1, max: 1000 });
+        // Centipawns a random root move may trail the best by, 0 for none; the
+        // temperature, also in centipawns, flattens the odds as it rises
+        options.register("Variety", OptionKind::Spin { default: 0, min: 0, max: 500 });
+        options.register("VarietyTemperature", OptionKind::Spin { default: 50, min: 1, max: 1000 });
+        options.register("VarietySeed", OptionKind::Spin { default: 0, min: 0, max: i32::MAX as i64 });
+        // Weaker play for beginners; Club brings its own variety
+        options.register(
+            "Level",
+            OptionKind::Combo {
+                default: Level::Full.to_string(),
+                choices: Level::ALL.iter().map(Level::to_string).collect(),
+            },
+        );
+        // Search results remembered across games; empty for none
+        options.register("ExperienceFile", OptionKind::String { default: String::new() });
+        options.register("UCI_ShowRefutations", OptionKind::Check { default: false });
+        options.register("UCI_ShowCurrLine", OptionKind::Check { default: false });
+        options.register(
+            "UCI_Variant",
+            OptionKind::Combo {
+                default: STANDARD.name().to_string(),
+                choices: variant::all().iter().map(|variant| variant.name().to_string()).collect(),
+            },
+        );
+        // Material odds for "position startpos" in standard chess
+        options.register(
+            "Handicap",
+            OptionKind::Combo {
+                default: Handicap::None.to_string(),
+                choices: Handicap::ALL.iter().map(Handicap::to_string).collect(),
+            },
+        );
+        options.register(
+            "HandicapSide",
+            OptionKind::Combo { default: "White".to_string(), choices: vec!["White".to_string(), "Black".to_string()] },
+        );
+        #[cfg(feature = "eval-tune")]
+        {
+            let mut evaluator = Evaluator::new();
+            for (name, weight) in tune::WEIGHTS {
+                let default = *weight(&mut evaluator) as i64;
+                let range = (default.abs() * 2).max(100);
+                options.register(name, OptionKind::Spin { default, min: -range, max: range });
+            }
+        }
+        options.register("nodestime", OptionKind::Spin { default: 0, min: 0, max: 10000 });
+        options.register("Debug Log File", OptionKind::String { default: String::new() });
+        options
     }
 
     fn handle_uci(&self) -> String {
-        "id name Three Salmons\nid author Magnus Torvund\nuciok\n".to_string()
+        let mut response = "id name Three Salmons\nid author Magnus Torvund\n".to_string();
+        for option in self.options.iter() {
+            response.push_str(&format!("{}\n", option));
+        }
+        response.push_str("uciok\n");
+        response
     }
 
-    fn handle_ucinewgame(&mut self) -> String {
-        self.board = Board::new();
+    fn handle_debug(&mut self, parts: &[&str]) -> String {
+        match parts.first() {
+            Some(&"on") => self.debug = true,
+            Some(&"off") => self.debug = false,
+            _ => {}
+        }
         "".to_string()
     }
 
-    fn handle_position(&mut self, parts: &[&str]) -> String {
-        if parts.is_empty() {
-            return "".to_string();
+    // Formats a diagnostic as an "info string" line, or nothing when debug is off
+    fn debug_info(&self, message: &str) -> String {
+        if self.debug {
+            format!("info string {}\n", message)
+        } else {
+            "".to_string()
         }
+    }
 
-        match parts[0] {
-            "startpos" => {
-                self.board = Board::new();
-                if parts.len() > 1 && parts[1] == "moves" {
-                    for move_str in &parts[2..] {
-                        if let Some(mv) = self.parse_move(move_str) {
-                            self.board.make_move(mv);
-                        }
-                    }
-                }
+    fn handle_ucinewgame(&mut self) -> String {
+        // A search from the old game is moot, and holds the tables we're about to
+        // clear; what it learned is saved once it stops
+        let response = self.save_experience();
+        self.board = self.start_position();
+        self.history.clear();
+        self.search.lock().unwrap().clear();
+        response
+    }
+
+    fn handle_setoption(&mut self, parts: &[&str]) -> String {
+        // Unknown options and invalid values are ignored, as the protocol expects
+        match self.options.parse_setoption(parts) {
+            Ok((name, value)) => {
+                let mut response = self.apply_option(&name, &value);
+                response.push_str(&self.debug_info(&format!("option {} set to {}", name, value)));
+                response
             }
-            "fen" => {
-                if parts.len() > 1 {
-                    let fen = parts[1..].join(" ");
-                    if let Ok(board) = Board::from_fen(&fen) {
-                        self.board = board;
-                        if parts.len() > 6 && parts[6] == "moves" {
-                            for move_str in &parts[7..] {
-                                if let Some(mv) = self.parse_move(move_str) {
-                                    self.board.make_move(mv);
-                                }
-                            }
-                        }
-                    }
-                }
+            Err(err) => self.debug_info(&format!("setoption ignored: {}", err)),
+        }
+    }
+
+    // Hands a validated option change to the component that owns it. Whatever
+    // needs the search waits for a running one to finish, as it holds the lock.
+    fn apply_option(&mut self, name: &str, value: &OptionValue) -> String {
+        let owned_by_search = matches!(
+            name,
+            "Clear Hash" | "Hash" | "LargePages" | "ExperienceFile" | "Variety" | "VarietyTemperature"
+                | "VarietySeed" | "Level"
+        ) || tune::WEIGHTS.iter().any(|(weight, _)| *weight == name);
+        if owned_by_search && self.search_running() {
+            self.pending_options.push((name.to_string(), value.clone()));
+            return self.debug_info(&format!("option {} applies once the search ends", name));
+        }
+
+        match (name, value) {
+            ("Clear Hash", OptionValue::Button) => self.search.lock().unwrap().clear(),
+            ("Hash", _) => return self.resize_hash(),
+            ("LargePages", _) => return self.resize_hash(),
+            ("UCI_ShowRefutations", OptionValue::Check(show)) => {
+                self.signals.show_refutations.store(*show, Ordering::Relaxed)
+            }
+            ("UCI_ShowCurrLine", OptionValue::Check(show)) => self.signals.show_currline.store(*show, Ordering::Relaxed),
+            ("Debug Log File", OptionValue::String(path)) => return self.open_log(path),
+            (name, OptionValue::String(path)) if name.starts_with("BookFile") => {
+                let slot = (0..BOOK_SLOTS).find(|&slot| book_option("BookFile", slot) == name).unwrap_or(0);
+                return self.open_book(slot, path);
+            }
+            ("ExperienceFile", OptionValue::String(path)) => return self.open_experience(path),
+            (name, OptionValue::Spin(_)) if tune::WEIGHTS.iter().any(|(weight, _)| *weight == name) => {
+                let evaluator = self.evaluator();
+                self.search.lock().unwrap().set_evaluator(evaluator);
+            }
+            ("Variety" | "VarietyTemperature" | "VarietySeed" | "Level", _) => {
+                let variety = self.variety();
+                self.search.lock().unwrap().set_variety(variety);
             }
             _ => {}
         }
         "".to_string()
     }
 
-    fn parse_move(&self, move_str: &str) -> Option<Move> {
-        if move_str.len() != 4 && move_str.len() != 5 {
-            return None;
+    fn apply_pending_options(&mut self) -> String {
+        let pending = std::mem::take(&mut self.pending_options);
+        pending.iter().map(|(name, value)| self.apply_option(name, value)).collect()
+    }
+
+    // An empty path turns logging off; otherwise the session is appended to the file
+    fn open_log(&mut self, path: &str) -> String {
+        let log = if path.is_empty() {
+            None
+        } else {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(file),
+                Err(err) => return format!("info string cannot open log file {}: {}\n", path, err),
+            }
+        };
+        self.output.lock().unwrap().log = log;
+        "".to_string()
+    }
+
+    fn open_book(&mut self, slot: usize, path: &str) -> String {
+        self.books[slot] = None;
+        if path.is_empty() {
+            return "".to_string();
+        }
+        match PolyglotBook::open(path) {
+            Ok(book) => {
+                let response = self.debug_info(&format!("loaded {} book entries from {}", book.len(), path));
+                self.books[slot] = Some(book);
+                response
+            }
+            Err(err) => format!("info string cannot load book: {}\n", err),
+        }
+    }
+
+    // Whatever the previous file learned is written back before switching
+    fn open_experience(&mut self, path: &str) -> String {
+        let mut response = self.save_experience();
+        let mut search = self.search.lock().unwrap();
+        search.set_experience(None);
+        if path.is_empty() {
+            return response;
+        }
+        match Experience::open(path) {
+            Ok(experience) => {
+                response.push_str(&self.debug_info(&format!("loaded {} experience entries from {}", experience.len(), path)));
+                search.set_experience(Some(experience));
+            }
+            Err(err) => response.push_str(&format!("info string cannot load experience: {}\n", err)),
+        }
+        response
+    }
+
+    fn save_experience(&mut self) -> String {
+        self.handle_stop();
+        match self.search.lock().unwrap().experience_mut().map(Experience::save) {
+            Some(Err(err)) => format!("info string {}\n", err),
+            _ => "".to_string(),
+        }
+    }
+
+    // Reallocates the tables when the budget or page kind changed; setting
+    // the same size again keeps what they hold
+    fn resize_hash(&mut self) -> String {
+        let hash_mb = match self.options.get("Hash") {
+            Some(OptionValue::Spin(mb)) => *mb as usize,
+            _ => DEFAULT_HASH_MB,
+        };
+        let large_pages = self.options.get("LargePages") == Some(&OptionValue::Check(true));
+        if self.hash_allocation == (hash_mb, large_pages) {
+            return "".to_string();
+        }
+        self.hash_allocation = (hash_mb, large_pages);
+        let huge_pages = self.search.lock().unwrap().set_hash(hash_mb, large_pages);
+        if large_pages && !huge_pages {
+            return "info string large pages unavailable, using normal pages\n".to_string();
+        }
+        "".to_string()
+    }
+
+    fn variant(&self) -> &'static dyn Variant {
+        match self.options.get("UCI_Variant") {
+            Some(OptionValue::Combo(name)) => variant::by_name(name).unwrap_or(&STANDARD),
+            _ => &STANDARD,
+        }
+    }
+
+    // The variant's start position, less any handicap material
+    fn start_position(&self) -> Board {
+        let variant = self.variant();
+        let handicap = match self.options.get("Handicap") {
+            Some(OptionValue::Combo(name)) => Handicap::from_name(name).unwrap_or(Handicap::None),
+            _ => Handicap::None,
+        };
+        if variant.name() != STANDARD.name() || handicap == Handicap::None {
+            return variant::start_position(variant);
+        }
+        let giver = match self.options.get("HandicapSide") {
+            Some(OptionValue::Combo(side)) if side == "Black" => Color::Black,
+            _ => Color::White,
+        };
+        handicap.start_position(giver)
+    }
+
+    // The evaluation with any weights changed through options
+    fn evaluator(&self) -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        for (name, weight) in tune::WEIGHTS {
+            if let Some(OptionValue::Spin(value)) = self.options.get(name) {
+                *weight(&mut evaluator) = *value as i32;
+            }
         }
+        evaluator.use_board_psqt = evaluator.has_default_piece_values();
+        evaluator
+    }
 
-        let from_file = move_str.chars().nth(0)? as u8 - b'a';
-        let from_rank = move_str.chars().nth(1)? as u8 - b'1';
-        let to_file = move_str.chars().nth(2)? as u8 - b'a';
-        let to_rank = move_str.chars().nth(3)? as u8 - b'1';
+    fn level(&self) -> Level {
+        match self.options.get("Level") {
+            Some(OptionValue::Combo(name)) => Level::from_name(name).unwrap_or_default(),
+            _ => Level::Full,
+        }
+    }
 
-        let from = (from_rank * 8 + from_file) as u8;
-        let to = (to_rank * 8 + to_file) as u8;
+    // The level's variety if it has one, otherwise the Variety options'
+    fn variety(&self) -> Variety {
+        if let Some(variety) = self.level().variety() {
+            return variety;
+        }
+        let spin = |name: &str| match self.options.get(name) {
+            Some(OptionValue::Spin(value)) => *value,
+            _ => 0,
+        };
+        Variety { margin: spin("Variety") as i32, temperature: spin("VarietyTemperature") as i32, seed: spin("VarietySeed") as u64 }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match (self.options.get("SearchAlgorithm"), self.options.get("MCTSExploration")) {
+            (Some(OptionValue::Combo(name)), Some(OptionValue::Spin(exploration))) if name == "MCTS" => {
+                Algorithm::Mcts { exploration: *exploration as f32 / 100.0 }
+            }
+            _ => Algorithm::AlphaBeta,
+        }
+    }
 
-        let (piece, color) = self.board.get_piece_at(from)?;
-        
-        // Check if the piece belongs to the side to move
-        if color != self.board.side_to_move {
+    // A book move for the current position, if the book is on and still applies
+    fn book_move(&self) -> Option<Move> {
+        // Polyglot books are standard chess only
+        if self.options.get("OwnBook") != Some(&OptionValue::Check(true)) || self.variant().name() != STANDARD.name() {
             return None;
         }
+        let layers: Vec<(&PolyglotBook, u16)> = (0..BOOK_SLOTS)
+            .filter_map(|slot| {
+                let depth = match self.options.get(&book_option("BookDepth", slot)) {
+                    Some(OptionValue::Spin(depth)) => *depth as u16,
+                    _ => u16::MAX,
+                };
+                self.books[slot].as_ref().map(|book| (book, depth))
+            })
+            .collect();
+        book::pick_layered(&layers, &self.board, &self.move_generator, &self.book_selection(), &mut rand::thread_rng())
+    }
 
-        let captured_piece = if let Some((piece, _)) = self.board.get_piece_at(to) {
-            Some(piece)
-        } else {
-            None
+    fn book_selection(&self) -> BookSelection {
+        let spin = |name: &str| match self.options.get(name) {
+            Some(OptionValue::Spin(value)) => *value,
+            _ => 0,
         };
+        let policy = match self.options.get("BookPolicy") {
+            Some(OptionValue::Combo(policy)) if policy == "Best" => BookPolicy::Best,
+            Some(OptionValue::Combo(policy)) if policy == "TopN" => BookPolicy::TopN {
+                n: spin("BookTopN") as usize,
+                temperature: spin("BookTemperature") as f64 / 100.0,
+            },
+            _ => BookPolicy::Weighted { variety: spin("BookVariety") as u32 },
+        };
+        BookSelection {
+            policy,
+            min_weight: spin("BookMinWeight") as u16,
+            min_score: spin("BookMinScore") as f64 / 100.0,
+        }
+    }
 
-        let mut mv = Move::new(from, to, piece);
-        mv.captured_piece = captured_piece;
-
-        // Handle promotions
-        if move_str.len() == 5 {
-            mv.promotion = match move_str.chars().nth(4)? {
-                'q' => Some(Piece::Queen),
-                'r' => Some(Piece::Rook),
-                'b' => Some(Piece::Bishop),
-                'n' => Some(Piece::Knight),
-                _ => None,
-            };
+    // The tablebase server's best move at the root, plus diagnostics; a failed
+    // probe just means searching as usual
+    #[cfg(feature = "online-tablebase")]
+    fn online_tablebase_move(&self) -> (Option<String>, String) {
+        use crate::online_tb::OnlineTablebase;
+
+        if self.options.get("OnlineTablebase") != Some(&OptionValue::Check(true))
+            || !OnlineTablebase::applies(&self.board)
+        {
+            return (None, "".to_string());
         }
+        let cache_dir = match self.options.get("OnlineTablebaseCache") {
+            Some(OptionValue::String(dir)) if !dir.is_empty() => dir.into(),
+            _ => std::env::temp_dir().join("three-salmons-tablebase"),
+        };
 
-        // Validate the move
-        if self.move_generator.is_move_valid(&self.board, &mv) {
-            Some(mv)
-        } else {
-            None
+        match OnlineTablebase::new(cache_dir).best_move(&self.board) {
+            Ok(Some(mv)) => {
+                let response = self.debug_info(&format!("tablebase hit: {} ({:?})", mv.uci, mv.outcome));
+                (Some(mv.uci), response)
+            }
+            Ok(None) => (None, "".to_string()),
+            Err(err) => (None, self.debug_info(&err)),
+        }
+    }
+
+    // A move from the Lichess opening explorer once the book has none, plus
+    // diagnostics; a failed lookup just means searching as usual
+    #[cfg(feature = "online-explorer")]
+    fn online_explorer_move(&self) -> (Option<Move>, String) {
+        use crate::online_explorer::OpeningExplorer;
+
+        if self.options.get("OnlineExplorer") != Some(&OptionValue::Check(true)) || self.variant().name() != STANDARD.name() {
+            return (None, "".to_string());
+        }
+        let cache_dir = match self.options.get("OnlineExplorerCache") {
+            Some(OptionValue::String(dir)) if !dir.is_empty() => dir.into(),
+            _ => std::env::temp_dir().join("three-salmons-explorer"),
+        };
+        let mut explorer = OpeningExplorer::new(cache_dir);
+        if let Some(OptionValue::Spin(min_games)) = self.options.get("OnlineExplorerMinGames") {
+            explorer.min_games = *min_games as u64;
+        }
+
+        match explorer.pick_move(&self.board, &mut rand::thread_rng()) {
+            Ok(Some(mv)) => {
+                let response = self.debug_info(&format!("explorer move {}", format_move(&mv)));
+                (Some(mv), response)
+            }
+            Ok(None) => (None, "".to_string()),
+            Err(err) => (None, self.debug_info(&err)),
         }
     }
 
+    fn handle_position(&mut self, parts: &[&str]) -> String {
+        let moves_pos = parts.iter().position(|&p| p == "moves").unwrap_or(parts.len());
+
+        let board = match parts.first() {
+            Some(&"startpos") => Ok(self.start_position()),
+            Some(&"fen") => Board::from_variant_fen(&parts[1..moves_pos].join(" "), self.variant()),
+            _ => Err("expected 'startpos' or 'fen'".to_string()),
+        };
+        let mut board = match board {
+            Ok(board) => board,
+            // Keep the previous position rather than searching a half-parsed one
+            Err(err) => return format!("info string invalid position: {}\n", err),
+        };
+
+        // Apply the moves up to the first one that isn't legal
+        let moves = parts.get(moves_pos + 1..).unwrap_or_default();
+        let (history, response) = match board.apply_uci_moves(moves) {
+            Ok(history) => (history, "".to_string()),
+            Err(err) => {
+                let response = format!("info string {}\n", err);
+                (err.played, response)
+            }
+        };
+        self.board = board;
+        self.history = history;
+        response
+    }
+
     fn handle_go(&mut self, parts: &[&str]) -> String {
-        // Parse search parameters
-        let mut max_time = Duration::from_secs(5); // Default 5 seconds
-        let mut increment = 0; // Default increment
-
-        for i in 0..parts.len() {
-            match parts[i] {
-                "wtime" | "btime" => {
-                    if let Some(time) = parts.get(i + 1).and_then(|s| s.parse::<u64>().ok()) {
-                        // Use 1/10th of the remaining time, but ensure we have at least 1 second
-                        max_time = Duration::from_millis(std::cmp::max(time / 10, 1000));
-                    }
-                }
-                "winc" | "binc" => {
-                    if let Some(inc) = parts.get(i + 1).and_then(|s| s.parse::<u64>().ok()) {
-                        increment = inc;
-                    }
-                }
-                "movetime" => {
-                    if let Some(time) = parts.get(i + 1).and_then(|s| s.parse::<u64>().ok()) {
-                        max_time = Duration::from_millis(time);
-                    }
-                }
-                "depth" => {
-                    if let Some(depth) = parts.get(i + 1).and_then(|s| s.parse::<u32>().ok()) {
-                        self.search.set_max_depth(depth);
-                    }
-                }
-                _ => {}
+        // Only one search runs at a time
+        self.wait_for_search();
+
+        let params = GoParams::parse(parts);
+
+        // Play from the book without searching, unless the GUI wants analysis
+        if !params.infinite && !params.ponder && params.searchmoves.is_empty() {
+            if let Some(mv) = self.book_move() {
+                let mut response = self.debug_info(&format!("book move {}", format_move(&mv)));
+                response.push_str(&format!("bestmove {}\n", format_move(&mv)));
+                return response;
             }
         }
 
-        // Add increment to the time if available
-        if increment > 0 {
-            max_time += Duration::from_millis(increment);
+        #[cfg(feature = "online-explorer")]
+        if !params.infinite && !params.ponder && params.searchmoves.is_empty() {
+            let (mv, mut response) = self.online_explorer_move();
+            if let Some(mv) = mv {
+                response.push_str(&format!("bestmove {}\n", format_move(&mv)));
+                return response;
+            }
         }
 
-        // Configure search parameters
-        self.search.set_max_time(max_time.as_millis() as u64);
+        #[cfg(feature = "online-tablebase")]
+        if !params.infinite && !params.ponder && params.searchmoves.is_empty() {
+            let (mv, mut response) = self.online_tablebase_move();
+            if let Some(mv) = mv {
+                response.push_str(&format!("bestmove {}\n", mv));
+                return response;
+            }
+        }
 
-        // Use the search engine to find the best move
-        if let Some(best_move) = self.search.find_best_move(&self.board) {
-            format!("bestmove {}\n", self.format_move(&best_move))
-        } else {
-            "bestmove (none)\n".to_string()
+        if !params.infinite && !params.ponder && params.searchmoves.is_empty() {
+            if let Some(mv) = self.level().quick_move(&self.board, &mut rand::thread_rng()) {
+                return format!("bestmove {}\n", format_move(&mv));
+            }
+        }
+
+        let level_depth = self.level().max_depth().unwrap_or(MAX_DEPTH);
+        let mut limits = SearchLimits {
+            depth: params.depth.unwrap_or(MAX_DEPTH).min(level_depth),
+            nodes: params.nodes,
+            // Moves that aren't legal here are ignored
+            searchmoves: params
+                .searchmoves
+                .iter()
+                .filter_map(|move_str| self.move_generator.parse_uci_move(&self.board, move_str).ok())
+                .collect(),
+            mate: params.mate,
+            ..SearchLimits::default()
+        }
+        .with_budget(TimeManager::allocate(&params, self.board.side_to_move));
+        if let (Some(OptionValue::Spin(nodestime)), Some(time)) = (self.options.get("nodestime"), limits.soft_time) {
+            if *nodestime > 0 {
+                let budget = TimeManager::nodes_for(time, *nodestime as u64);
+                limits.nodes = Some(limits.nodes.map_or(budget, |nodes| nodes.min(budget)));
+                limits = limits.with_budget(None);
+            }
+        }
+        let ponder = params.ponder;
+        self.infinite = params.infinite;
+        let report_ponder_move = self.options.get("Ponder") == Some(&OptionValue::Check(true));
+        {
+            let mut search = self.search.lock().unwrap();
+            search.set_limits(limits.clone());
+            search.set_history(RepetitionHistory::from_moves(&self.history, &self.board));
+            search.set_algorithm(self.algorithm());
+        }
+
+        let mut response = match (limits.soft_time, limits.time) {
+            (Some(soft), Some(hard)) => {
+                self.debug_info(&format!("time limit {} ms, {} ms at most", soft.as_millis(), hard.as_millis()))
+            }
+            _ => self.debug_info("no time limit"),
+        };
+        if let Some(depth) = params.depth {
+            response.push_str(&self.debug_info(&format!("depth limit {}", depth)));
         }
+        if let Some(nodes) = limits.nodes {
+            response.push_str(&self.debug_info(&format!("node limit {}", nodes)));
+        }
+        if ponder {
+            response.push_str(&self.debug_info("pondering, clock starts on ponderhit"));
+        }
+
+        // The time limit only starts counting once a ponder search gets a ponderhit
+        self.signals.stop.store(false, Ordering::Relaxed);
+        self.signals.ponder.store(ponder, Ordering::Relaxed);
+
+        let search = Arc::clone(&self.search);
+        let signals = Arc::clone(&self.signals);
+        let output = Arc::clone(&self.output);
+        let board = self.board.clone();
+        self.search_thread = Some(thread::spawn(move || {
+            let mut search = search.lock().unwrap();
+            let best_move = search.find_best_move(&board);
+            let ponder_move = if report_ponder_move { search.ponder_move(&board) } else { None };
+            drop(search);
+
+            // While pondering, bestmove may only be sent after ponderhit or stop
+            while signals.ponder.load(Ordering::Relaxed) && !signals.stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            match (best_move, ponder_move) {
+                (Some(mv), Some(reply)) => {
+                    emit(&output, &format!("bestmove {} ponder {}", format_move(&mv), format_move(&reply)))
+                }
+                (Some(mv), None) => emit(&output, &format!("bestmove {}", format_move(&mv))),
+                (None, _) => emit(&output, "bestmove (none)"),
+            }
+        }));
+        response
     }
 
-    fn  format_move(&self, mv: &Move) -> String {
-        let from_file = (mv.from % 8) as u8;
-        let from_rank = (mv.from / 8) as u8;
-        let to_file = (mv.to % 8) as u8;
-        let to_rank = (mv.to / 8) as u8;
+    // Non-standard "d": the board, its FEN and hash key, and whether the game is over
+    fn handle_display(&self) -> String {
+        let state = self.move_generator.get_game_state(&self.board, &self.history);
+        format!(
+            "{}\nFen: {}\nKey: {:016X}\nState: {}\n",
+            self.board,
+            self.board.to_fen(),
+            self.board.hash,
+            state
+        )
+    }
 
-        let mut result = String::new();
-        result.push((b'a' + from_file) as char);
-        result.push((b'1' + from_rank) as char);
-        result.push((b'a' + to_file) as char);
-        result.push((b'1' + to_rank) as char);
+    // Non-standard "eval": the static evaluation of the current position, term by term
+    fn handle_eval(&self) -> String {
+        format!("{}\n", self.evaluator().trace(&self.board))
+    }
 
-        if let Some(promotion) = mv.promotion {
-            result.push(match promotion {
-                Piece::Queen => 'q',
-                Piece::Rook => 'r',
-                Piece::Bishop => 'b',
-                Piece::Knight => 'n',
-                _ => ' ',
-            });
+    // Non-standard "see <move>": the static exchange the move starts, capture
+    // by capture with the running balance, then its SEE score
+    fn handle_see(&self, parts: &[&str]) -> String {
+        let Some(move_str) = parts.first() else {
+            return "info string usage: see <move>\n".to_string();
+        };
+        let mv = match parse_uci_move(&self.board, &self.move_generator, move_str) {
+            Ok(mv) => mv,
+            Err(err) => return format!("info string {}\n", err),
+        };
+        let (score, steps) = self.move_generator.exchange(&self.board, &mv);
+        let mut response = String::new();
+        for step in &steps {
+            response.push_str(&format!("{:?}, balance {:+}\n", step.capture, step.balance));
         }
+        response.push_str(&format!("SEE: {:+}\n", score));
+        response
+    }
 
-        result
+    // Prints perft divide output for the current position, one line per root move
+    fn handle_perft(&self, parts: &[&str]) -> String {
+        let depth = match parts.first().and_then(|s| s.parse::<u32>().ok()) {
+            Some(depth) => depth,
+            None => return "info string usage: perft <depth>\n".to_string(),
+        };
+
+        let start = std::time::Instant::now();
+        let split = perft::divide(&self.board, &self.move_generator, depth);
+        let nodes: u64 = split.iter().map(|(_, nodes)| nodes).sum();
+
+        let mut response = String::new();
+        for (mv, count) in &split {
+            response.push_str(&format!("{}: {}\n", format_move(mv), count));
+        }
+        response.push_str(&format!("\nNodes searched: {}\n", nodes));
+        response.push_str(&self.debug_info(&format!("perft {} took {} ms", depth, start.elapsed().as_millis())));
+        response
+    }
+
+    fn handle_stop(&mut self) -> String {
+        self.signals.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+        "".to_string()
+    }
+
+    fn handle_ponderhit(&mut self) -> String {
+        // The opponent played the expected move: keep searching, now on our own clock
+        self.signals.ponder.store(false, Ordering::Relaxed);
+        "".to_string()
+    }
+
+    fn wait_for_search(&mut self) {
+        if let Some(handle) = self.search_thread.take() {
+            let _ = handle.join();
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Square {
-    A1, B1, C1, D1, E1, F1, G1, H1,
-    A2, B2, C2, D2, E2, F2, G2, H2,
-    A3, B3, C3, D3, E3, F3, G3, H3,
-    A4, B4, C4, D4, E4, F4, G4, H4,
-    A5, B5, C5, D5, E5, F5, G5, H5,
-    A6, B6, C6, D6, E6, F6, G6, H6,
-    A7, B7, C7, D7, E7, F7, G7, H7,
-    A8, B8, C8, D8, E8, F8, G8, H8,
+fn format_info(info: &SearchInfo) -> String {
+    match info {
+        SearchInfo::CurrMove { depth, mv, number } => {
+            format!("info depth {} currmove {} currmovenumber {}", depth, format_move(mv), number)
+        }
+        SearchInfo::Refutation { line } => format!("info refutation {}", format_line(line)),
+        SearchInfo::CurrLine { line } => format!("info currline {}", format_line(line)),
+        SearchInfo::Iteration { depth, seldepth, score, nodes, elapsed, pv } => {
+            let nps = *nodes as u128 * 1000 / elapsed.as_millis().max(1);
+            format!(
+                "info depth {} seldepth {} score {} nodes {} nps {} time {} pv {}",
+                depth,
+                seldepth,
+                format_score(*score),
+                nodes,
+                nps,
+                elapsed.as_millis(),
+                format_line(pv)
+            )
+        }
+    }
+}
+
+// Centipawns, or moves to mate with the losing side's negative
+fn format_score(score: i32) -> String {
+    if score.abs() < MATE_THRESHOLD {
+        return format!("cp {}", score);
+    }
+    let moves = (MATE_SCORE - score.abs() + 1) / 2;
+    format!("mate {}", if score > 0 { moves } else { -moves })
 }
 
-impl FromStr for Square {
-    type Err = String;
+// Resolves a move in coordinate notation (e2e4, e7e8q) to one of the legal moves,
+// which carries the castling, en passant and capture details
+pub fn parse_uci_move(board: &Board, generator: &MoveGenerator, move_str: &str) -> Result<Move, String> {
+    generator.parse_uci_move(board, move_str)
+}
+
+pub fn format_move(mv: &Move) -> String {
+    mv.to_string()
+}
+
+fn format_line(line: &[Move]) -> String {
+    line.iter().map(format_move).collect::<Vec<_>>().join(" ")
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 2 {
-            return Err("Invalid square format".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Piece;
+
+    // An output stream the test can read back after the handler is done with it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
         }
-        
-        let file = match s.chars().nth(0).unwrap() {
-            'a' => 0, 'b' => 1, 'c' => 2, 'd' => 3,
-            'e' => 4, 'f' => 5, 'g' => 6, 'h' => 7,
-            _ => return Err("Invalid file".to_string()),
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Feeds a scripted session to the handler and returns every line it wrote
+    fn run_script_with_iterations(commands: &[&str]) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut uci = UciHandler::with_output(buffer.clone());
+        uci.run(io::Cursor::new(commands.join("\n"))).unwrap();
+
+        let output = buffer.0.lock().unwrap();
+        String::from_utf8_lossy(&output).lines().map(str::to_string).collect()
+    }
+
+    // The same without the report of each finished iteration
+    fn run_script(commands: &[&str]) -> Vec<String> {
+        let mut lines = run_script_with_iterations(commands);
+        lines.retain(|line| !(line.starts_with("info depth ") && line.contains(" pv")));
+        lines
+    }
+
+    #[test]
+    fn test_script_handshake() {
+        let lines = run_script(&["uci", "isready", "quit"]);
+        assert_eq!(lines[0], "id name Three Salmons");
+        assert!(lines.iter().any(|line| line == "option name Ponder type check default false"));
+        assert_eq!(&lines[lines.len() - 2..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn test_script_go_reports_bestmove() {
+        // Only one legal move: Kg1
+        let lines = run_script(&["position fen 6k1/8/8/8/8/8/r7/7K w - - 0 1", "go depth 2"]);
+        assert_eq!(lines, ["bestmove h1g1"]);
+
+        let lines = run_script(&["position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", "go depth 3"]);
+        assert_eq!(lines, ["bestmove (none)"]);
+    }
+
+    #[test]
+    fn test_script_searchmoves_and_mate() {
+        let lines = run_script(&["position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "go depth 3 searchmoves g1f1 g1h1"]);
+        assert!(lines == ["bestmove g1f1"] || lines == ["bestmove g1h1"], "{:?}", lines);
+        // With no other limit, the search ends once the mate is found
+        let lines = run_script(&["position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "go mate 1"]);
+        assert_eq!(lines, ["bestmove a1a8"]);
+    }
+
+    #[test]
+    fn test_script_reports_each_iteration() {
+        let lines = run_script_with_iterations(&["position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "go depth 3"]);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("info depth 1 seldepth "));
+        assert!(lines[2].starts_with("info depth 3 seldepth ") && lines[2].contains(" score mate 1 nodes "));
+        assert!(lines[2].ends_with(" pv a1a8"));
+        assert_eq!(lines[3], "bestmove a1a8");
+
+        assert_eq!(format_score(-35), "cp -35");
+        assert_eq!(format_score(-MATE_SCORE + 4), "mate -2");
+    }
+
+    #[test]
+    fn test_script_bestmove_with_ponder_move() {
+        let lines = run_script(&["position startpos", "go depth 3"]);
+        assert_eq!(lines[0].split(' ').count(), 2);
+
+        let lines = run_script(&["setoption name Ponder value true", "position startpos", "go depth 3"]);
+        let words: Vec<&str> = lines[0].split(' ').collect();
+        assert_eq!(words.len(), 4);
+        assert_eq!((words[0], words[2]), ("bestmove", "ponder"));
+    }
+
+    #[test]
+    fn test_nodestime_turns_time_into_nodes() {
+        let mut uci = UciHandler::with_output(io::sink());
+        uci.handle_command("setoption name nodestime value 2").unwrap();
+        uci.handle_command("debug on").unwrap();
+
+        let response = uci.handle_command("go wtime 3000 btime 3000").unwrap();
+        uci.handle_command("stop").unwrap();
+        assert_eq!(response, "info string no time limit\ninfo string node limit 200\n");
+    }
+
+    #[test]
+    fn test_script_book_moves() {
+        // A one-entry book: d2d4 from the start position
+        let path = std::env::temp_dir().join(format!("three-salmons-book-{}.bin", std::process::id()));
+        let mut bytes = crate::polyglot::key(&Board::new()).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(27u16 | 11 << 6).to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0, 0, 0, 0]);
+        std::fs::write(&path, bytes).unwrap();
+        let set_book = format!("setoption name BookFile value {}", path.display());
+
+        let lines = run_script(&[&set_book, "setoption name OwnBook value true", "position startpos", "go depth 1"]);
+        assert_eq!(lines, ["bestmove d2d4"]);
+
+        // Off by default, and out of book after the first move
+        let lines = run_script(&[&set_book, "position startpos", "go depth 1"]);
+        assert_ne!(lines, ["bestmove d2d4"]);
+        let lines = run_script(&[&set_book, "setoption name OwnBook value true", "position startpos moves e2e4", "go depth 1"]);
+        assert!(lines[0].starts_with("bestmove "));
+
+        // The only book move is too light to pass the weight floor
+        let lines = run_script(&[
+            &set_book,
+            "setoption name OwnBook value true",
+            "setoption name BookPolicy value best",
+            "setoption name BookMinWeight value 2",
+            "position startpos",
+            "go depth 1",
+        ]);
+        assert_ne!(lines, ["bestmove d2d4"]);
+
+        std::fs::remove_file(&path).unwrap();
+        let lines = run_script(&[&set_book]);
+        assert!(lines[0].starts_with("info string cannot load book"));
+    }
+
+    #[test]
+    fn test_script_layered_books() {
+        let write_book = |name: &str, entries: &[(&[&str], u16)]| {
+            let path = std::env::temp_dir().join(format!("three-salmons-{}-{}.bin", name, std::process::id()));
+            let mut bytes = Vec::new();
+            for (moves, mv) in entries {
+                let mut board = Board::new();
+                for move_str in moves.iter() {
+                    board.make_move(parse_uci_move(&board, &MoveGenerator::new(), move_str).unwrap());
+                }
+                bytes.extend_from_slice(&crate::polyglot::key(&board).to_be_bytes());
+                bytes.extend_from_slice(&mv.to_be_bytes());
+                bytes.extend_from_slice(&[0, 1, 0, 0, 0, 0]);
+            }
+            std::fs::write(&path, bytes).unwrap();
+            path
         };
-        
-        let rank = match s.chars().nth(1).unwrap() {
-            '1' => 0, '2' => 1, '3' => 2, '4' => 3,
-            '5' => 4, '6' => 5, '7' => 6, '8' => 7,
-            _ => return Err("Invalid rank".to_string()),
+        // A repertoire of 1. d4 only, and a broader book that also knows 1. e4 e5 2. Nf3
+        let repertoire = write_book("repertoire", &[(&[], 27 | 11 << 6)]);
+        let fallback = write_book("fallback", &[(&[], 28 | 12 << 6), (&["e2e4", "e7e5"], 21 | 6 << 6)]);
+        let set_books = [
+            "setoption name OwnBook value true".to_string(),
+            format!("setoption name BookFile value {}", repertoire.display()),
+            format!("setoption name BookFile2 value {}", fallback.display()),
+        ];
+        let script = |extra: &[&str]| {
+            let commands: Vec<&str> = set_books.iter().map(String::as_str).chain(extra.iter().copied()).collect();
+            run_script(&commands)
         };
-        
-        Ok(Square::from_u8(rank * 8 + file))
+
+        assert_eq!(script(&["position startpos", "go depth 1"]), ["bestmove d2d4"]);
+        assert_eq!(script(&["position startpos moves e2e4 e7e5", "go depth 1"]), ["bestmove g1f3"]);
+        // Each book stops at its own depth
+        let lines = script(&["setoption name BookDepth2 value 1", "position startpos moves e2e4 e7e5", "go depth 1"]);
+        assert_ne!(lines, ["bestmove g1f3"]);
+
+        std::fs::remove_file(repertoire).unwrap();
+        std::fs::remove_file(fallback).unwrap();
     }
-}
 
-impl Square {
-    fn from_u8(value: u8) -> Self {
-        unsafe { std::mem::transmute_copy(&value) }
+    #[test]
+    fn test_script_variant() {
+        let lines = run_script(&["position fen 4k3/8/8/8/8/8/8/P7 w - - 0 1"]);
+        assert!(lines[0].starts_with("info string invalid position"));
+
+        let lines = run_script(&["setoption name UCI_Variant value horde", "position startpos moves a4a5", "d"]);
+        assert!(lines.iter().any(|line| line.contains("rnbqkbnr/pppppppp/8/PPP2PP1/1PPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP b kq")));
+    }
+
+    #[test]
+    fn test_script_handicap() {
+        let lines = run_script(&["setoption name Handicap value Knight", "setoption name HandicapSide value Black", "position startpos", "d"]);
+        assert!(lines.iter().any(|line| line.contains("r1bqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")));
+    }
+
+    #[test]
+    fn test_script_mcts() {
+        let lines = run_script(&["setoption name SearchAlgorithm value mcts", "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "go nodes 2000"]);
+        assert_eq!(lines.last().unwrap(), "bestmove a1a8");
+    }
+
+    #[test]
+    fn test_script_level() {
+        // Greedy takes the rook the pawn defends, and answers at once
+        let position = "position fen 4k3/8/2p5/3r4/8/8/8/3QK3 w - - 0 1";
+        let lines = run_script(&["setoption name Level value greedy", position, "go depth 6"]);
+        assert_eq!(lines, ["bestmove d1d5"]);
+        let lines = run_script(&["setoption name Level value Depth1", position, "go depth 6"]);
+        assert!(lines.iter().all(|line| !line.starts_with("info depth 2")));
+    }
+
+    #[test]
+    fn test_script_isready_during_search() {
+        // The I/O thread answers while the search thread is busy
+        let lines = run_script(&["position startpos", "go infinite", "isready", "stop"]);
+        assert_eq!(lines[0], "readyok");
+        assert!(lines[1].starts_with("bestmove "));
+
+        let lines = run_script(&["position startpos", "go infinite", "ucinewgame", "isready"]);
+        assert!(lines[0].starts_with("bestmove "));
+        assert_eq!(lines[1], "readyok");
+    }
+
+    #[test]
+    fn test_shutdown_ends_running_searches() {
+        // Input ending under an infinite search stops it rather than waiting forever
+        let lines = run_script(&["position startpos", "go infinite"]);
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+        let lines = run_script(&["position startpos", "go infinite", "quit"]);
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+
+        // Input that never ends: only an interrupt gets the loop out
+        struct Endless(io::Cursor<&'static str>);
+        impl io::Read for Endless {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.read(buf)? {
+                    0 => loop {
+                        thread::park();
+                    },
+                    read => Ok(read),
+                }
+            }
+        }
+        let buffer = SharedBuffer::default();
+        let mut uci = UciHandler::with_output(buffer.clone());
+        let interrupter = uci.interrupter();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            interrupter.interrupt();
+        });
+        uci.run(io::BufReader::new(Endless(io::Cursor::new("position startpos\ngo infinite\n")))).unwrap();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.lines().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_script_debug_log_file() {
+        let path = std::env::temp_dir().join(format!("three-salmons-log-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let set_log = format!("setoption name Debug Log File value {}", path.display());
+        run_script(&[&set_log, "isready", "setoption name Debug Log File value <empty>", "uci"]);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().map(|line| line.split_once("] ").unwrap().1).collect();
+        assert_eq!(lines, [">> isready", "<< readyok", ">> setoption name Debug Log File value <empty>"]);
+        assert!(log.starts_with('['));
+    }
+
+    #[test]
+    fn test_hash_option_reallocates_on_change() {
+        let lines = run_script(&["uci"]);
+        assert!(lines.iter().any(|line| line == "option name Hash type spin default 16 min 1 max 65536"));
+
+        // A table that survived makes the repeat search far cheaper
+        let mut uci = UciHandler::with_output(SharedBuffer::default());
+        let nodes = |uci: &mut UciHandler<SharedBuffer>, command: &str| {
+            uci.handle_command(command).unwrap();
+            uci.handle_command("go depth 4").unwrap();
+            uci.wait_for_search();
+            uci.search.lock().unwrap().stats().nodes
+        };
+        let fresh = nodes(&mut uci, "position startpos");
+        let resized = nodes(&mut uci, "setoption name Hash value 2");
+        assert!(resized > fresh / 2);
+        assert!(nodes(&mut uci, "setoption name Hash value 2") < resized / 2);
+        assert!(nodes(&mut uci, "setoption name LargePages value false") < resized / 2);
+    }
+
+    #[test]
+    fn test_options_wait_for_a_running_search() {
+        // The search holds its lock until stopped: setoption mustn't wait on it
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let lines = run_script(&[
+                "debug on",
+                "position startpos",
+                "go infinite",
+                "setoption name Clear Hash",
+                "setoption name Hash value 2",
+                "setoption name UCI_ShowCurrLine value true",
+                "stop",
+                "isready",
+            ]);
+            sender.send(lines).unwrap();
+        });
+        let lines = receiver.recv_timeout(Duration::from_secs(30)).expect("setoption blocked the running search");
+        assert!(lines.iter().any(|line| line == "info string option Hash applies once the search ends"));
+        let bestmove = lines.iter().position(|line| line.starts_with("bestmove ")).unwrap();
+        assert_eq!(lines.last().unwrap(), "readyok");
+        assert!(bestmove < lines.len() - 1);
+    }
+
+    #[test]
+    fn test_script_stop_and_ponderhit() {
+        let lines = run_script(&["position startpos", "go infinite", "stop", "isready"]);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("bestmove "));
+        assert_eq!(lines[1], "readyok");
+
+        // bestmove waits for ponderhit, then the search is on our clock
+        let lines = run_script(&["position startpos", "go ponder movetime 50", "isready", "ponderhit"]);
+        assert_eq!(lines[0], "readyok");
+        assert!(lines[1].starts_with("bestmove "));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_position_fen_with_moves() {
+        let mut uci = UciHandler::new();
+
+        let response = uci
+            .handle_command("position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves e2e4 d7d5 e4d5")
+            .unwrap();
+        assert_eq!(response, "");
+        assert_eq!(uci.board.get_piece_at(35), Some((Piece::Pawn, Color::White)));
+        assert_eq!(uci.board.black_pieces[0].count_ones(), 7);
+
+        // Castling comes through with the rook move
+        uci.handle_command("position fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 moves e1g1").unwrap();
+        assert_eq!(uci.board.get_piece_at(5), Some((Piece::Rook, Color::White)));
+    }
+
+    #[test]
+    fn test_parse_move_special_moves() {
+        let mut uci = UciHandler::new();
+
+        // En passant removes the pawn that was passed
+        uci.handle_command("position startpos moves e2e4 a7a6 e4e5 d7d5 e5d6").unwrap();
+        assert_eq!(uci.board.get_piece_at(43), Some((Piece::Pawn, Color::White)));
+        assert_eq!(uci.board.get_piece_at(35), None);
+        assert!(uci.history.last().unwrap().1.is_en_passant);
+
+        // Black castles queenside: king to c8, rook from a8 to d8
+        uci.handle_command("position fen r3k3/8/8/8/8/8/8/4K3 b q - 0 1 moves e8c8").unwrap();
+        assert_eq!(uci.board.get_piece_at(58), Some((Piece::King, Color::Black)));
+        assert_eq!(uci.board.get_piece_at(59), Some((Piece::Rook, Color::Black)));
+        assert_eq!(uci.board.get_piece_at(56), None);
+        assert_eq!(uci.board.castling_rights, 0);
+
+        // Under-promotion with a capture
+        uci.handle_command("position fen 1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1 moves a7b8n").unwrap();
+        assert_eq!(uci.board.get_piece_at(57), Some((Piece::Knight, Color::White)));
+        assert_eq!(uci.board.black_pieces[3], 0);
+
+        // The promotion piece is part of the move
+        let response = uci.handle_command("position fen 1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1 moves a7a8").unwrap();
+        assert_eq!(response, "info string illegal move 'a7a8', move 1 of the list\n");
+    }
+
+    #[test]
+    fn test_position_errors_are_reported() {
+        let mut uci = UciHandler::new();
+
+        let response = uci.handle_command("position fen 8/8/8/8 w - - 0 1").unwrap();
+        assert!(response.starts_with("info string invalid position"));
+
+        let response = uci.handle_command("position startpos moves e2e4 e7e5 g1f3 e2e4 b8c6").unwrap();
+        assert_eq!(response, "info string illegal move 'e2e4', move 4 of the list\n");
+        // The moves before the bad one are kept
+        assert_eq!(uci.board.side_to_move, Color::Black);
+        assert_eq!(uci.history.len(), 3);
+    }
+
+    #[test]
+    fn test_perft_divide_output() {
+        let mut uci = UciHandler::new();
+        uci.handle_command("position startpos moves e2e4").unwrap();
+
+        let response = uci.handle_command("go perft 1").unwrap();
+        assert_eq!(response.lines().count(), 22);
+        assert!(response.contains("e7e5: 1\n"));
+        assert!(response.ends_with("\nNodes searched: 20\n"));
+
+        assert_eq!(uci.handle_command("perft 1").unwrap(), response);
+    }
+
+    #[test]
+    fn test_display_command() {
+        let mut uci = UciHandler::new();
+        uci.handle_command("position startpos moves f2f3 e7e5 g2g4 d8h4").unwrap();
+
+        let response = uci.handle_command("d").unwrap();
+        assert!(response.starts_with("r n b . k b n r\n"));
+        assert!(response.contains("Fen: rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3\n"));
+        assert!(response.contains(&format!("Key: {:016X}\n", uci.board.hash)));
+        assert!(response.ends_with("State: checkmate, black wins\n"));
+    }
+
+    #[test]
+    fn test_eval_command() {
+        let mut uci = UciHandler::new();
+        uci.handle_command("position fen 4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+
+        let response = uci.handle_command("eval").unwrap();
+        assert!(response.starts_with("Material          500\n"));
+        assert!(response.contains("Phase          endgame\n"));
+        assert!(response.ends_with("(white side)\n"));
+    }
+
+    #[cfg(feature = "eval-tune")]
+    #[test]
+    fn test_eval_weight_options() {
+        let mut uci = UciHandler::new();
+        assert!(uci.handle_command("uci").unwrap().contains("option name queen_value type spin default 900 min -1800 max 1800\n"));
+        uci.handle_command("position fen 4k3/8/8/8/8/8/8/Q3K3 w - - 0 1").unwrap();
+        uci.handle_command("setoption name queen_value value 1000").unwrap();
+        assert!(uci.handle_command("eval").unwrap().starts_with("Material         1000\n"));
+        uci.handle_command("setoption name queen_value value 900").unwrap();
+        assert!(uci.evaluator().use_board_psqt);
+    }
+
+    #[test]
+    fn test_see_command() {
+        let mut uci = UciHandler::new();
+        uci.handle_command("position fen 4k3/8/2p5/3r4/8/8/8/3QK3 w - - 0 1").unwrap();
+
+        let response = uci.handle_command("see d1d5").unwrap();
+        assert_eq!(response, "d1d5 Queen x Rook, balance +500\nc6d5 Pawn x Queen, balance -400\nSEE: -400\n");
+        assert_eq!(uci.handle_command("see").unwrap(), "info string usage: see <move>\n");
+        assert!(uci.handle_command("see d1d7").unwrap().starts_with("info string "));
+    }
+
+    #[test]
+    fn test_debug_info_strings() {
+        let mut uci = UciHandler::new();
+
+        // Silent until debug is switched on
+        let response = uci.handle_command("setoption name Ponder value true").unwrap();
+        assert_eq!(response, "");
+
+        uci.handle_command("debug on").unwrap();
+        let response = uci.handle_command("setoption name Ponder value false").unwrap();
+        assert_eq!(response, "info string option Ponder set to false\n");
+        let response = uci.handle_command("setoption name Bogus value 1").unwrap();
+        assert_eq!(response, "info string setoption ignored: unknown option 'Bogus'\n");
+
+        uci.handle_command("debug off").unwrap();
+        let response = uci.handle_command("setoption name Bogus value 1").unwrap();
+        assert_eq!(response, "");
+    }
+}
@@ -0,0 +1,239 @@
+use crate::board::{Board, Color};
+use crate::evaluation::{EvalTrace, Evaluator};
+use crate::movegen::{GameState, MaterialRule, MoveGenerator};
+use alloc::string::{String, ToString};
+use core::fmt;
+
+const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// The rules a game is played by. Every board carries its variant, so move
+// generation, game-state detection and evaluation all consult it. The
+// provided methods are the rules of standard chess; a variant overrides only
+// what it changes.
+pub trait Variant: fmt::Debug + Send + Sync {
+    // The name used for the UCI_Variant option
+    fn name(&self) -> &'static str;
+
+    fn start_fen(&self) -> &'static str;
+
+    // Checks a position read from a FEN; standard chess wants one king a side
+    fn validate(&self, board: &Board) -> Result<(), String> {
+        if board.white_pieces[5].count_ones() != 1 || board.black_pieces[5].count_ones() != 1 {
+            return Err("each side needs exactly one king".to_string());
+        }
+        Ok(())
+    }
+
+    // Whether a pawn of this colour on this rank (0-7) may advance two squares
+    fn can_double_push(&self, color: Color, rank: u8) -> bool {
+        rank == if color == Color::White { 1 } else { 6 }
+    }
+
+    // Whether a move that turned before into after is allowed: by default the
+    // mover's king must not be left in check
+    fn is_legal(&self, generator: &MoveGenerator, before: &Board, after: &Board) -> bool {
+        !generator.is_king_in_check(after, before.side_to_move)
+    }
+
+    // Whether is_legal is the rule above, which lets MoveGenerator::is_legal
+    // check a single move against pins and checks instead of making it. A
+    // variant that overrides is_legal must return false here.
+    fn standard_legality(&self) -> bool {
+        true
+    }
+
+    // The game's end by the variant's win conditions, or None while it goes on.
+    // has_moves says whether the side to move has a legal move.
+    fn result(&self, generator: &MoveGenerator, board: &Board, has_moves: bool) -> Option<GameState> {
+        if has_moves {
+            None
+        } else if generator.is_king_in_check(board, board.side_to_move) {
+            Some(GameState::Checkmate(board.side_to_move.opposite()))
+        } else {
+            Some(GameState::Stalemate)
+        }
+    }
+
+    // Whether neither side can win any more, under the rule's reading of it
+    fn is_insufficient_material(&self, generator: &MoveGenerator, board: &Board, rule: MaterialRule) -> bool {
+        generator.insufficient_material(board, rule)
+    }
+
+    // Changes to the standard evaluation terms; the total is summed afterwards
+    fn adjust_evaluation(&self, _evaluator: &Evaluator, _board: &Board, _trace: &mut EvalTrace) {}
+
+    // Whether the standard chess endgame knowledge in endgame.rs holds
+    fn uses_endgame_table(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "chess"
+    }
+
+    fn start_fen(&self) -> &'static str {
+        STARTPOS
+    }
+}
+
+pub static STANDARD: Standard = Standard;
+
+// White has 36 pawns and no king, and wins by checkmating Black; Black wins by
+// capturing every white piece. White pawns on the first rank may double push.
+#[derive(Debug)]
+pub struct Horde;
+
+impl Variant for Horde {
+    fn name(&self) -> &'static str {
+        "horde"
+    }
+
+    fn start_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+    }
+
+    fn validate(&self, board: &Board) -> Result<(), String> {
+        if board.white_pieces[5] != 0 || board.black_pieces[5].count_ones() != 1 {
+            return Err("the horde has no king and Black needs exactly one".to_string());
+        }
+        Ok(())
+    }
+
+    fn can_double_push(&self, color: Color, rank: u8) -> bool {
+        match color {
+            Color::White => rank <= 1,
+            Color::Black => rank == 6,
+        }
+    }
+
+    fn result(&self, generator: &MoveGenerator, board: &Board, has_moves: bool) -> Option<GameState> {
+        if board.white_pieces.iter().all(|&pieces| pieces == 0) {
+            return Some(GameState::VariantWin(Color::Black));
+        }
+        STANDARD.result(generator, board, has_moves)
+    }
+
+    // Both sides can always still win: Black by taking the last white piece,
+    // White by promoting. The standard rule would read a lone pawn as a bare king.
+    fn is_insufficient_material(&self, _generator: &MoveGenerator, _board: &Board, _rule: MaterialRule) -> bool {
+        false
+    }
+
+    fn adjust_evaluation(&self, evaluator: &Evaluator, _board: &Board, trace: &mut EvalTrace) {
+        // Material counts a king for Black that White doesn't have; without a
+        // king to lose, the horde shouldn't be scored as if it had lost one.
+        // King safety already only scores kings that exist.
+        trace.material += evaluator.king_value;
+    }
+
+    fn uses_endgame_table(&self) -> bool {
+        false
+    }
+}
+
+pub static HORDE: Horde = Horde;
+
+// Every variant the engine plays, standard chess first
+pub fn all() -> [&'static dyn Variant; 2] {
+    [&STANDARD, &HORDE]
+}
+
+pub fn by_name(name: &str) -> Option<&'static dyn Variant> {
+    all().into_iter().find(|variant| variant.name().eq_ignore_ascii_case(name))
+}
+
+pub fn start_position(variant: &'static dyn Variant) -> Board {
+    Board::from_variant_fen(variant.start_fen(), variant).expect("variant start positions are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    // Kings may be left en prise, as in some beginner variants
+    #[derive(Debug)]
+    struct NoCheckRule;
+
+    impl Variant for NoCheckRule {
+        fn name(&self) -> &'static str {
+            "nocheck"
+        }
+
+        fn start_fen(&self) -> &'static str {
+            STARTPOS
+        }
+
+        fn is_legal(&self, _generator: &MoveGenerator, _before: &Board, _after: &Board) -> bool {
+            true
+        }
+
+        fn standard_legality(&self) -> bool {
+            false
+        }
+    }
+
+    static NO_CHECK_RULE: NoCheckRule = NoCheckRule;
+
+    #[test]
+    fn test_standard_is_the_default() {
+        assert_eq!(Board::new().variant.name(), "chess");
+        assert_eq!(by_name("Chess").unwrap().name(), "chess");
+        assert!(by_name("bughouse").is_none());
+        assert!(Board::from_variant_fen(STANDARD.start_fen(), &HORDE).is_err());
+        assert_eq!(start_position(&STANDARD).to_fen(), Board::new().to_fen());
+    }
+
+    #[test]
+    fn test_variant_rules_reach_the_move_generator() {
+        let generator = MoveGenerator::new();
+        // The e1 king is in check from the e8 rook, which only matters under standard rules
+        let fen = "4r2k/8/8/8/8/8/3P4/4K3 w - - 0 1";
+        let standard = Board::from_fen(fen).unwrap();
+        let relaxed = Board::from_variant_fen(fen, &NO_CHECK_RULE).unwrap();
+
+        assert_eq!(generator.generate_moves(&standard).len(), 3);
+        assert_eq!(generator.generate_moves(&relaxed).len(), 6);
+        assert_eq!(generator.get_game_state(&relaxed, &[]), GameState::Ongoing);
+    }
+
+    #[test]
+    fn test_horde_rules() {
+        let generator = MoveGenerator::new();
+        let board = start_position(&HORDE);
+        assert_eq!(board.white_pieces[0].count_ones(), 36);
+        assert_eq!(board.to_fen(), HORDE.start_fen());
+        // Only pawns with an empty square ahead can move, all of them on the fourth rank or beyond
+        let moves = generator.generate_moves(&board);
+        assert!(moves.iter().all(|mv| mv.from / 8 >= 3));
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::Ongoing);
+
+        // A first-rank pawn with room ahead may double push, without an en passant square
+        let mut board = Board::from_variant_fen("4k3/8/8/8/8/8/8/P7 w - - 0 1", &HORDE).unwrap();
+        let double = moves_from(&generator, &board, 0);
+        assert_eq!(double, vec![8, 16]);
+        board.make_move(generator.parse_uci_move(&board, "a1a3").unwrap());
+        assert_eq!(board.en_passant_square, None);
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::Ongoing);
+
+        // The horde is gone: Black has won
+        let board = Board::from_variant_fen("4k3/8/8/8/8/8/8/8 w - - 0 1", &HORDE).unwrap();
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::VariantWin(Color::Black));
+
+        // White still wins by mate
+        let board = Board::from_variant_fen("7k/6Q1/8/8/8/8/8/6R1 b - - 0 1", &HORDE).unwrap();
+        assert_eq!(generator.get_game_state(&board, &[]), GameState::Checkmate(Color::White));
+    }
+
+    fn moves_from(generator: &MoveGenerator, board: &Board, from: u8) -> Vec<u8> {
+        let mut targets: Vec<u8> = generator.generate_moves(board).iter().filter(|mv| mv.from == from).map(|mv| mv.to).collect();
+        targets.sort();
+        targets
+    }
+}
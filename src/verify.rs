@@ -0,0 +1,126 @@
+use crate::board::{Board, Color, Piece};
+use crate::movegen::{Move, MoveGenerator};
+use crate::uci::format_move;
+use crate::zobrist;
+
+// Self-consistency checks for the board and move generator: run them over
+// many positions after touching movegen, make_move or hashing. Each returns
+// what went wrong, naming the position and move.
+
+// The FEN reads back the same, and the moves is_legal accepts, looked up by
+// squares and promotion, are exactly the generated ones
+pub fn check_position(generator: &MoveGenerator, board: &Board) -> Result<(), String> {
+    let fen = board.to_fen();
+    let reread = Board::from_variant_fen(&fen, board.variant).map_err(|err| format!("{}: FEN doesn't read back: {}", fen, err))?;
+    if reread.to_fen() != fen || !same_position(&reread, board) {
+        return Err(format!("{}: FEN reads back as {}", fen, reread.to_fen()));
+    }
+    let moves = generator.generate_moves(board);
+    for mv in &moves {
+        if !generator.is_legal(board, mv) {
+            return Err(format!("{}: generated {} is rejected by is_legal", fen, format_move(mv)));
+        }
+    }
+    let promotions = [None, Some(Piece::Queen), Some(Piece::Rook), Some(Piece::Bishop), Some(Piece::Knight)];
+    let ours = if board.side_to_move == Color::White { board.white_pieces } else { board.black_pieces };
+    let movers = ours.iter().fold(0u64, |acc, &pieces| acc | pieces);
+    for (from, to) in (0..64).filter(|&from| movers & (1 << from) != 0).flat_map(|from| (0..64).map(move |to| (from, to))) {
+        for promotion in promotions {
+            let generated = moves.iter().find(|mv| mv.from == from && mv.to == to && mv.promotion == promotion);
+            if generator.find_move(board, from, to, promotion).as_ref() != generated {
+                let mv = Move { promotion, ..Move::new(from, to, Piece::Pawn) };
+                return Err(format!("{}: is_legal and generation disagree on {}", fen, format_move(&mv)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Plays mv on board, checking the incremental hash and piece-square totals
+// against full recomputations and that unmaking the move restores the
+// position, and returns the position after it
+pub fn check_move(board: &Board, mv: Move) -> Result<Board, String> {
+    let fen = board.to_fen();
+    let mut after = board.clone();
+    let undo = after.make_move(mv);
+
+    if after.hash != zobrist::hash(&after) {
+        return Err(format!("{} {}: incremental hash differs from the full one", fen, format_move(&mv)));
+    }
+
+    let mut refreshed = after.clone();
+    refreshed.refresh_psqt();
+    if refreshed.psqt != after.psqt {
+        return Err(format!("{} {}: running material and piece-square totals differ from recomputed ones", fen, format_move(&mv)));
+    }
+
+    let mut unmade = after.clone();
+    unmade.unmake_move(&undo);
+    if !same_position(&unmade, board) {
+        return Err(format!("{} {}: unmaking gives {}", fen, format_move(&mv), unmade.to_fen()));
+    }
+    Ok(after)
+}
+
+// Both checks along a line of legal moves, given as indices into each
+// position's generated moves (wrapping around), so any sequence of numbers
+// is a game. Stops early if the game ends.
+pub fn check_line(generator: &MoveGenerator, start: &Board, choices: &[usize]) -> Result<(), String> {
+    let mut board = start.clone();
+    check_position(generator, &board)?;
+    for &choice in choices {
+        let moves = generator.generate_moves(&board);
+        if moves.is_empty() {
+            break;
+        }
+        board = check_move(&board, moves[choice % moves.len()])?;
+        check_position(generator, &board)?;
+    }
+    Ok(())
+}
+
+fn same_position(a: &Board, b: &Board) -> bool {
+    a.white_pieces == b.white_pieces
+        && a.black_pieces == b.black_pieces
+        && a.side_to_move == b.side_to_move
+        && a.castling_rights == b.castling_rights
+        && a.en_passant_square == b.en_passant_square
+        && a.halfmove_clock == b.halfmove_clock
+        && a.fullmove_number == b.fullmove_number
+        && a.psqt == b.psqt
+        && a.hash == b.hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::{Variant, HORDE};
+    use proptest::prelude::*;
+
+    // Starting points with castling, en passant and promotions close at hand
+    const STARTS: [&str; 5] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbqkbnr/pp1ppppp/8/2pP4/8/8/PPP1PPPP/RNBQKBNR w KQkq c6 0 2",
+    ];
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(48))]
+
+        #[test]
+        fn random_games_stay_consistent(start in 0..STARTS.len(), choices in prop::collection::vec(any::<usize>(), 0..40)) {
+            let board = Board::from_fen(STARTS[start]).unwrap();
+            let result = check_line(&MoveGenerator::new(), &board, &choices);
+            prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+        }
+
+        #[test]
+        fn random_horde_games_stay_consistent(choices in prop::collection::vec(any::<usize>(), 0..40)) {
+            let board = Board::from_variant_fen(HORDE.start_fen(), &HORDE).unwrap();
+            let result = check_line(&MoveGenerator::new(), &board, &choices);
+            prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::board::{Board, Color, UndoState};
+
+// Random keys for piece/square/side/castling/en-passant, generated at compile time
+// from a fixed seed so hashes are stable between runs
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+pub struct ZobristKeys {
+    pub pieces: [[[u64; 64]; 6]; 2], // [color][piece][square]
+    pub side_to_move: u64,
+    pub castling: [u64; 16],
+    pub en_passant_file: [u64; 8],
+}
+
+const fn generate_keys() -> ZobristKeys {
+    let mut keys = ZobristKeys {
+        pieces: [[[0; 64]; 6]; 2],
+        side_to_move: 0,
+        castling: [0; 16],
+        en_passant_file: [0; 8],
+    };
+    let mut state = 0x5A1E_0F7E_3CA1_B5ED;
+
+    let mut color = 0;
+    while color < 2 {
+        let mut piece = 0;
+        while piece < 6 {
+            let mut square = 0;
+            while square < 64 {
+                let (next, key) = splitmix64(state);
+                state = next;
+                keys.pieces[color][piece][square] = key;
+                square += 1;
+            }
+            piece += 1;
+        }
+        color += 1;
+    }
+
+    let (next, key) = splitmix64(state);
+    state = next;
+    keys.side_to_move = key;
+
+    let mut i = 0;
+    while i < 16 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.castling[i] = key;
+        i += 1;
+    }
+
+    let mut file = 0;
+    while file < 8 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.en_passant_file[file] = key;
+        file += 1;
+    }
+
+    keys
+}
+
+pub static KEYS: ZobristKeys = generate_keys();
+
+fn hash_pieces(pieces: &[u64; 6], color: usize, piece_range: core::ops::Range<usize>) -> u64 {
+    let mut hash = 0;
+    for piece in piece_range {
+        let mut bb = pieces[piece];
+        while bb != 0 {
+            let square = bb.trailing_zeros() as usize;
+            hash ^= KEYS.pieces[color][piece][square];
+            bb &= bb - 1;
+        }
+    }
+    hash
+}
+
+// Full hash of a position, computed from scratch
+pub fn hash(board: &Board) -> u64 {
+    let mut hash = hash_pieces(&board.white_pieces, 0, 0..6) ^ hash_pieces(&board.black_pieces, 1, 0..6);
+    if board.side_to_move == Color::Black {
+        hash ^= KEYS.side_to_move;
+    }
+    hash ^= KEYS.castling[(board.castling_rights & 0b1111) as usize];
+    if let Some(ep_square) = board.en_passant_square {
+        hash ^= KEYS.en_passant_file[(ep_square % 8) as usize];
+    }
+    hash
+}
+
+// The hash after a move, from the hash before it and what make_move changed
+pub fn update(hash: u64, undo: &UndoState, after: &Board) -> u64 {
+    let mut hash = hash ^ KEYS.side_to_move;
+    for change in undo.dirty.removed().chain(undo.dirty.added()) {
+        hash ^= KEYS.pieces[change.color as usize][change.piece as usize][change.square as usize];
+    }
+    hash ^= KEYS.castling[(undo.castling_rights & 0b1111) as usize] ^ KEYS.castling[(after.castling_rights & 0b1111) as usize];
+    for ep_square in [undo.en_passant_square, after.en_passant_square].into_iter().flatten() {
+        hash ^= KEYS.en_passant_file[(ep_square % 8) as usize];
+    }
+    hash
+}
+
+// Hash of the pawn placement only, used to key the pawn hash table
+pub fn pawn_hash(board: &Board) -> u64 {
+    hash_pieces(&board.white_pieces, 0, 0..1) ^ hash_pieces(&board.black_pieces, 1, 0..1)
+}